@@ -0,0 +1,175 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use htty_core::config::{Size, StyleMode};
+use htty_core::command::Command;
+use htty_core::pty;
+use htty_core::runtime::RuntimeDir;
+use htty_core::session::{Event, Session};
+use htty_core::shutdown::Shutdown;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+const SIZES: [(usize, usize); 3] = [(80, 24), (200, 50), (400, 100)];
+
+/// Colorful, line-oriented output in the style of a build log.
+fn sample_output(bytes: usize) -> String {
+    let mut output = String::with_capacity(bytes + 128);
+    let mut i = 0;
+
+    while output.len() < bytes {
+        output.push_str(&format!(
+            "\x1b[1;3{}m[{i:>6}]\x1b[0m compiling \x1b[4mcrate-{i}\x1b[0m v0.{}.{} ... \x1b[32mok\x1b[0m\r\n",
+            i % 8,
+            i % 10,
+            i % 100
+        ));
+        i += 1;
+    }
+
+    output
+}
+
+fn session_output(c: &mut Criterion) {
+    let output = sample_output(1024 * 1024);
+    let chunks: Vec<String> = output
+        .as_bytes()
+        .chunks(4096)
+        .map(|c| String::from_utf8_lossy(c).into_owned())
+        .collect();
+
+    let mut group = c.benchmark_group("session_output");
+    group.throughput(Throughput::Bytes(output.len() as u64));
+
+    for (cols, rows) in SIZES {
+        group.bench_function(BenchmarkId::from_parameter(format!("{cols}x{rows}")), |b| {
+            b.iter(|| {
+                let mut session = Session::new(cols, rows);
+
+                for chunk in &chunks {
+                    session.output(chunk.clone());
+                }
+
+                session.flush_output();
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn snapshot(c: &mut Criterion) {
+    let output = sample_output(64 * 1024);
+    let mut group = c.benchmark_group("snapshot");
+
+    for style_mode in [StyleMode::Plain, StyleMode::Styled] {
+        for (cols, rows) in SIZES {
+            let mut session = Session::new(cols, rows);
+            session.set_style_mode(style_mode);
+            session.output(output.clone());
+
+            let id = BenchmarkId::new(format!("{style_mode:?}").to_lowercase(), format!("{cols}x{rows}"));
+            group.bench_function(id, |b| b.iter(|| session.snapshot()));
+        }
+    }
+
+    group.finish();
+}
+
+fn json(c: &mut Criterion) {
+    let mut group = c.benchmark_group("json");
+
+    let output = Event::Output(0.0, sample_output(4096));
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("output", |b| b.iter(|| output.to_json()));
+
+    for (cols, rows) in SIZES {
+        let text = sample_output(cols * rows);
+        let snapshot = Event::Snapshot(0.0, cols, rows, text.clone(), text, None);
+
+        group.bench_function(BenchmarkId::new("snapshot", format!("{cols}x{rows}")), |b| {
+            b.iter(|| snapshot.to_json())
+        });
+    }
+
+    group.finish();
+}
+
+/// Run `head -c BYTES` in a PTY and time how long it takes to read everything it printed.
+///
+/// Shutting the PTY down afterwards (signalling wait-exit and waiting for the task to notice
+/// the closed command channel) isn't part of the measurement.
+async fn read_through_pty(bytes: usize) -> Duration {
+    let runtime_dir = RuntimeDir::create().unwrap();
+    let (_input_tx, input_rx) = mpsc::channel(1024);
+    let (_control_tx, control_rx) = mpsc::channel(1024);
+    let (output_tx, mut output_rx) = mpsc::channel(1024);
+    let (stderr_tx, _stderr_rx) = mpsc::channel(1024);
+    let (pid_tx, _pid_rx) = mpsc::channel(1);
+    let (exit_code_tx, _exit_code_rx) = mpsc::channel(1);
+    let (command_tx, mut command_rx) = mpsc::channel(1024);
+
+    let options = pty::Options {
+        wait_exit_helper: Some(PathBuf::from(env!("CARGO_BIN_EXE_ht"))),
+        ..pty::Options::default()
+    };
+
+    let start = Instant::now();
+
+    let pty = tokio::spawn(
+        pty::spawn(
+            pty::Program::Shell(format!("head -c {bytes} /dev/zero | tr '\\0' x")),
+            runtime_dir.fifo_path(),
+            &Size::new(80, 24),
+            options,
+            input_rx,
+            control_rx,
+            output_tx,
+            stderr_tx,
+            pid_tx,
+            exit_code_tx,
+            command_tx,
+            Shutdown::new(),
+        )
+        .unwrap(),
+    );
+
+    let mut received = 0;
+
+    while received < bytes {
+        received += output_rx.recv().await.expect("output ended early").len();
+    }
+
+    let elapsed = start.elapsed();
+
+    while let Some(command) = command_rx.recv().await {
+        if let Command::Completed(fifo_path) = command {
+            let mut fifo = std::fs::OpenOptions::new().write(true).open(fifo_path).unwrap();
+            writeln!(fifo, "exit").unwrap();
+            break;
+        }
+    }
+
+    drop(command_rx);
+    pty.await.unwrap().unwrap();
+
+    elapsed
+}
+
+fn pty_read(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let bytes = 16 * 1024 * 1024;
+
+    let mut group = c.benchmark_group("pty_read");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(bytes as u64));
+
+    group.bench_function("16MiB", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| runtime.block_on(read_through_pty(bytes))).sum())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, session_output, snapshot, json, pty_read);
+criterion_main!(benches);
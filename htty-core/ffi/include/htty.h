@@ -0,0 +1,46 @@
+/* Generated from src/lib.rs by `HTTY_FFI_BLESS=1 cargo test -p htty-ffi`; do not edit. */
+
+#ifndef HTTY_H
+#define HTTY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// A terminal, and once spawned, the command running in it.
+typedef struct HttySession HttySession;
+
+// Create a `cols`x`rows` terminal to spawn a command in. Returns NULL if either is 0.
+HttySession *htty_session_new(uint16_t cols, uint16_t rows);
+
+// Run `command` with `/bin/sh -c` in the session's terminal. A session runs one command.
+int htty_spawn(HttySession *session, const char *command);
+
+// Send `count` keys, named as for ht's sendKeys, returning once they've been written.
+int htty_send_keys(HttySession *session, const char *const *keys, size_t count);
+
+// The session's next event as JSON, as ht prints it, waiting up to `timeout_ms` for one.
+// Returns NULL if none came by then, or the session has ended.
+char *htty_poll_event(HttySession *session, uint64_t timeout_ms);
+
+// The text on the screen, one line per row. Returns NULL on failure.
+char *htty_snapshot(HttySession *session);
+
+// End the session, hanging up on the command if it's still running, and free it.
+void htty_session_free(HttySession *session);
+
+// Free a string returned by one of these functions.
+void htty_string_free(char *string);
+
+// What went wrong in the last call on this thread that failed, or NULL. The string belongs
+// to htty, and lasts until the next failure on the thread.
+const char *htty_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,316 @@
+//! C API for running commands in headless terminals, for test rigs written in C, C++, Zig
+//! and the like, which can't use the Rust or Python APIs.
+//!
+//! The declarations are in `include/htty.h`, which is generated from this file: after
+//! changing a signature or its doc comment, run `HTTY_FFI_BLESS=1 cargo test -p htty-ffi`.
+//!
+//! ```c
+//! HttySession *session = htty_session_new(80, 24);
+//! htty_spawn(session, "vim");
+//! const char *keys[] = {"ihello", "Escape"};
+//! htty_send_keys(session, keys, 2);
+//! char *text = htty_snapshot(session);
+//! htty_string_free(text);
+//! htty_session_free(session);
+//! ```
+//!
+//! Functions returning `int` return 0 on success, and -1 on failure, after which
+//! `htty_last_error` says what went wrong.
+//!
+//! # Safety
+//!
+//! Sessions must come from `htty_session_new`, and not be used once freed, nor from two
+//! threads at once. Strings passed in must be NUL-terminated UTF-8, and strings handed out
+//! must be freed with `htty_string_free`, once.
+
+#![allow(clippy::missing_safety_doc)]
+
+use htty_core::blocking::{self, Events, Handle};
+use htty_core::Htty;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+use std::time::Duration;
+
+/// A terminal, and once spawned, the command running in it.
+pub struct HttySession {
+    cols: u16,
+    rows: u16,
+    // Subscribed to on spawning, so that no events are missed before the first poll
+    running: Option<(Handle, Events)>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Create a `cols`x`rows` terminal to spawn a command in. Returns NULL if either is 0.
+#[no_mangle]
+pub extern "C" fn htty_session_new(cols: u16, rows: u16) -> *mut HttySession {
+    if cols == 0 || rows == 0 {
+        set_error("the terminal needs at least one column and one row");
+        return ptr::null_mut();
+    }
+
+    Box::into_raw(Box::new(HttySession { cols, rows, running: None }))
+}
+
+/// Run `command` with `/bin/sh -c` in the session's terminal. A session runs one command.
+#[no_mangle]
+pub unsafe extern "C" fn htty_spawn(session: *mut HttySession, command: *const c_char) -> c_int {
+    status(spawn(session, command))
+}
+
+/// Send `count` keys, named as for ht's sendKeys, returning once they've been written.
+#[no_mangle]
+pub unsafe extern "C" fn htty_send_keys(session: *mut HttySession, keys: *const *const c_char, count: usize) -> c_int {
+    status(send_keys(session, keys, count))
+}
+
+/// The session's next event as JSON, as ht prints it, waiting up to `timeout_ms` for one.
+/// Returns NULL if none came by then, or the session has ended.
+#[no_mangle]
+pub unsafe extern "C" fn htty_poll_event(session: *mut HttySession, timeout_ms: u64) -> *mut c_char {
+    let result = running(session).map(|(_, events)| events.next_timeout(Duration::from_millis(timeout_ms)));
+
+    match result {
+        Ok(Some(event)) => into_c_string(event.to_json()),
+        Ok(None) => ptr::null_mut(),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// The text on the screen, one line per row. Returns NULL on failure.
+#[no_mangle]
+pub unsafe extern "C" fn htty_snapshot(session: *mut HttySession) -> *mut c_char {
+    let result = running(session).and_then(|(handle, _)| handle.snapshot().map_err(|e| e.to_string()));
+
+    match result {
+        Ok(snapshot) => into_c_string(snapshot.text),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// End the session, hanging up on the command if it's still running, and free it.
+#[no_mangle]
+pub unsafe extern "C" fn htty_session_free(session: *mut HttySession) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+/// Free a string returned by one of these functions.
+#[no_mangle]
+pub unsafe extern "C" fn htty_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// What went wrong in the last call on this thread that failed, or NULL. The string belongs
+/// to htty, and lasts until the next failure on the thread.
+#[no_mangle]
+pub extern "C" fn htty_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| error.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+unsafe fn spawn(session: *mut HttySession, command: *const c_char) -> Result<(), String> {
+    let session = session.as_mut().ok_or("session is NULL")?;
+    let command = str_arg(command, "command")?;
+
+    if session.running.is_some() {
+        return Err("the session is already running a command".to_string());
+    }
+
+    let builder = Htty::builder().size(session.cols, session.rows).command([command]);
+    let handle = blocking::spawn(builder).map_err(|e| e.to_string())?;
+    let events = handle.events().map_err(|e| e.to_string())?;
+    session.running = Some((handle, events));
+
+    Ok(())
+}
+
+unsafe fn send_keys(session: *mut HttySession, keys: *const *const c_char, count: usize) -> Result<(), String> {
+    let (handle, _) = running(session)?;
+
+    if keys.is_null() && count > 0 {
+        return Err("keys is NULL".to_string());
+    }
+
+    let keys = (0..count).map(|i| str_arg(*keys.add(i), "key")).collect::<Result<Vec<_>, _>>()?;
+
+    handle.send_keys(keys).map_err(|e| e.to_string())
+}
+
+unsafe fn running<'a>(session: *mut HttySession) -> Result<&'a (Handle, Events), String> {
+    let session = session.as_ref().ok_or("session is NULL")?;
+
+    session.running.as_ref().ok_or_else(|| "no command has been spawned in the session".to_string())
+}
+
+unsafe fn str_arg<'a>(string: *const c_char, name: &str) -> Result<&'a str, String> {
+    if string.is_null() {
+        return Err(format!("{name} is NULL"));
+    }
+
+    CStr::from_ptr(string).to_str().map_err(|_| format!("{name} is not valid UTF-8"))
+}
+
+fn status(result: Result<(), String>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(e);
+            -1
+        }
+    }
+}
+
+fn set_error(message: impl Into<String>) {
+    let message = CString::new(message.into().replace('\0', " ")).expect("NULs are replaced");
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+}
+
+fn into_c_string(string: String) -> *mut c_char {
+    CString::new(string.replace('\0', " ")).expect("NULs are replaced").into_raw()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    fn take_string(string: *mut c_char) -> String {
+        assert!(!string.is_null(), "{:?}", last_error());
+        let text = unsafe { CStr::from_ptr(string) }.to_str().unwrap().to_string();
+        unsafe { htty_string_free(string) };
+        text
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(htty_last_error()) }.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn round_trip() {
+        let session = htty_session_new(20, 5);
+        let command = CString::new("cat").unwrap();
+        assert_eq!(unsafe { htty_spawn(session, command.as_ptr()) }, 0);
+
+        let init = take_string(unsafe { htty_poll_event(session, 5000) });
+        assert!(init.starts_with(r#"{"type":"init""#), "{init}");
+
+        let keys = [CString::new("hi").unwrap(), CString::new("Enter").unwrap()];
+        let key_ptrs: Vec<_> = keys.iter().map(|k| k.as_ptr()).collect();
+        assert_eq!(unsafe { htty_send_keys(session, key_ptrs.as_ptr(), key_ptrs.len()) }, 0);
+
+        let mut text = String::new();
+
+        for _ in 0..500 {
+            text = take_string(unsafe { htty_snapshot(session) });
+
+            if text.lines().map(str::trim_end).eq(["hi", "hi", "", "", ""]) {
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(text.starts_with("hi"), "{text:?}");
+        unsafe { htty_session_free(session) };
+    }
+
+    #[test]
+    fn errors() {
+        assert!(htty_session_new(0, 24).is_null());
+
+        let session = htty_session_new(20, 5);
+        assert!(unsafe { htty_snapshot(session) }.is_null());
+        assert_eq!(last_error(), "no command has been spawned in the session");
+
+        assert_eq!(unsafe { htty_spawn(session, ptr::null()) }, -1);
+        assert_eq!(last_error(), "command is NULL");
+
+        unsafe { htty_session_free(session) };
+    }
+
+    /// `include/htty.h`, as generated from the exported functions in this file, whose
+    /// signatures are each kept on one line for it.
+    fn header() -> String {
+        let source = include_str!("lib.rs");
+        let mut out = String::from(
+            "/* Generated from src/lib.rs by `HTTY_FFI_BLESS=1 cargo test -p htty-ffi`; do not edit. */\n\n\
+             #ifndef HTTY_H\n#define HTTY_H\n\n#include <stddef.h>\n#include <stdint.h>\n\n\
+             #ifdef __cplusplus\nextern \"C\" {\n#endif\n\n\
+             // A terminal, and once spawned, the command running in it.\n\
+             typedef struct HttySession HttySession;\n",
+        );
+        let mut docs = Vec::new();
+
+        for line in source.lines().take_while(|line| !line.starts_with("#[cfg(test)]")) {
+            if let Some(doc) = line.strip_prefix("///") {
+                docs.push(doc.trim());
+                continue;
+            }
+
+            if let Some(signature) = line.strip_prefix("pub extern \"C\" fn ").or_else(|| line.strip_prefix("pub unsafe extern \"C\" fn ")) {
+                let (name, rest) = signature.split_once('(').unwrap();
+                let (args, ret) = rest.trim_end_matches(" {").split_once(')').unwrap();
+                let ret = ret.strip_prefix(" -> ").map_or("void", c_type);
+                let args: Vec<_> = args.split(", ").filter(|arg| !arg.is_empty()).map(|arg| arg.split_once(": ").unwrap()).map(|(name, ty)| declare(c_type(ty), name)).collect();
+
+                out.push('\n');
+                docs.iter().for_each(|doc| out.push_str(&format!("// {doc}\n")));
+                let args = if args.is_empty() { "void".to_string() } else { args.join(", ") };
+                out.push_str(&format!("{}({args});\n", declare(ret, name)));
+            }
+
+            if !line.starts_with("#[") {
+                docs.clear();
+            }
+        }
+
+        out.push_str("\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n");
+        out
+    }
+
+    fn declare(ty: &str, name: &str) -> String {
+        if ty.ends_with('*') {
+            format!("{ty}{name}")
+        } else {
+            format!("{ty} {name}")
+        }
+    }
+
+    fn c_type(ty: &str) -> &'static str {
+        match ty {
+            "u16" => "uint16_t",
+            "u64" => "uint64_t",
+            "usize" => "size_t",
+            "c_int" => "int",
+            "*mut HttySession" => "HttySession *",
+            "*const c_char" => "const char *",
+            "*mut c_char" => "char *",
+            "*const *const c_char" => "const char *const *",
+            _ => panic!("no C type for {ty}"),
+        }
+    }
+
+    #[test]
+    fn header_is_current() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("include/htty.h");
+
+        if std::env::var_os("HTTY_FFI_BLESS").is_some() {
+            std::fs::write(&path, header()).unwrap();
+        }
+
+        let current = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(current == header(), "{} is out of date; run HTTY_FFI_BLESS=1 cargo test -p htty-ffi", path.display());
+    }
+}
@@ -0,0 +1,11 @@
+//! Arbitrary key names to send a terminal, one per line. `ht fuzz --input
+//! fuzz/artifacts/session_input` replays what this turns up.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::collections::HashMap;
+
+fuzz_target!(|data: &[u8]| {
+    htty_core::fuzz::input_for(data, 80, 24, &HashMap::new());
+});
@@ -0,0 +1,12 @@
+//! Arbitrary bytes written to a terminal by a command, read in chunks of whatever size the
+//! first byte picks. `ht fuzz fuzz/artifacts/session_output` replays what this turns up.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some((&chunk, data)) = data.split_first() {
+        htty_core::fuzz::screen_after(data, 80, 24, usize::from(chunk) + 1);
+    }
+});
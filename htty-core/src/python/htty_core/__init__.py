@@ -2,9 +2,34 @@
 htty-core: A thin wrapper around a forked [ht](https://github.com/andyk/ht) binary for use with [htty](https://matrixmanatyrservice.github.io/htty/htty.html).
 """
 
-from .core import Cols, Command, HtArgs, HtEvent, Rows, StyleMode, find_ht_binary, run
+from .core import (
+    Cols,
+    Command,
+    HtArgs,
+    HtEvent,
+    HtmlTheme,
+    LogLevel,
+    Rows,
+    StyleFormat,
+    StyleMode,
+    find_ht_binary,
+    run,
+)
 
-__all__ = ["HtArgs", "HtEvent", "find_ht_binary", "run", "Command", "Rows", "Cols", "StyleMode", "__version__"]
+__all__ = [
+    "HtArgs",
+    "HtEvent",
+    "find_ht_binary",
+    "run",
+    "Command",
+    "Rows",
+    "Cols",
+    "StyleMode",
+    "StyleFormat",
+    "HtmlTheme",
+    "LogLevel",
+    "__version__",
+]
 # [[[cog
 # import os
 # cog.out(f'__version__ = "{os.environ["HTTY_VERSION"]}"')
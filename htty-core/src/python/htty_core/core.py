@@ -1,4 +1,5 @@
 import os
+import shlex
 import subprocess
 import sys
 import sysconfig
@@ -26,6 +27,34 @@ class StyleMode(StrEnum):
     STYLED = "styled"
 
 
+class StyleFormat(StrEnum):
+    """Encoding of the styleMap in styled snapshots."""
+
+    CELLS = "cells"
+    """One style id per cell."""
+    RLE = "rle"
+    """Each row as a list of [style id, count] runs."""
+
+
+class HtmlTheme(StrEnum):
+    """Default colors of snapshots rendered as HTML, for cells that don't set their own."""
+
+    DARK = "dark"
+    """Light grey text on black, like xterm."""
+    LIGHT = "light"
+    """Black text on white."""
+
+
+class LogLevel(StrEnum):
+    """How much of ht's own diagnostics is written to its stderr."""
+
+    ERROR = "error"
+    WARN = "warn"
+    INFO = "info"
+    DEBUG = "debug"
+    TRACE = "trace"
+
+
 # Type aliases for common parameters
 Command = Annotated[Union[str, list[str]], "run this command (as a subprocess of ht)"]
 Rows = Annotated[
@@ -57,10 +86,31 @@ class HtEvent(StrEnum):
 
     Events added by `htty`:
 
+    - inputWritten
+    - inputDiscarded
+    - flushed
     - pid
     - exitCode
     - debug
+    - error
     - completed
+    - stderr
+    - rawOutput
+    - respawn
+    - stopped
+    - continued
+    - idle
+    - active
+    - idleTimeout
+    - state
+
+    When ht runs with `--pane`, output, resize, snapshot, pid, exitCode and commandCompleted events from the
+    additional panes carry a `pane` field (the pane's id, starting at 1) in their data.
+
+    A subscriber that reads too slowly can fall behind and miss events. It's then sent a `dropped` event (whether or
+    not it subscribed to anything in particular) whose data has a `count` of the events it missed, followed by a
+    fresh `init` event if it subscribed to those. Anything reconstructed from earlier events should be discarded in
+    favor of that `init`.
     """
 
     INIT = "init"
@@ -71,15 +121,48 @@ class HtEvent(StrEnum):
 
     SNAPSHOT = "snapshot"
     """
-    Terminal window snapshot. Sent when the terminal snapshot is taken with the takeSnapshot command.
+    Terminal window snapshot. Sent when the terminal snapshot is taken with the takeSnapshot command, and only to
+    the client which sent it (other subscribers, such as WebSocket clients, don't see it).
 
     Event data is an object with the following fields:
 
+    - time - seconds since the session started, when the snapshot was taken (orders it among recorded events)
     - cols - current terminal width, number of columns
     - rows - current terminal height, number of rows
     - text - plain text snapshot as multi-line string, where each line represents a terminal row
     - seq - a raw sequence of characters, which when printed to a blank terminal puts it in the same state as
       ht's virtual terminal
+    - html - the screen as a `<pre>` element with inline styles, if the takeSnapshot command had an `htmlTheme`
+      (`dark` or `light`, see `HtmlTheme`)
+    """
+
+    INPUT_WRITTEN = "inputWritten"
+    """
+    Input has been written to the terminal. Sent in answer to an input or sendKeys command with `"ack": true`, and
+    only to the client which sent it, once all of that command's input is in the PTY (which doesn't mean the
+    application has read it yet). It can't be subscribed to.
+
+    Event data is an object with the following fields:
+
+    - bytes - how many bytes of input the command sent
+    """
+
+    FLUSHED = "flushed"
+    """
+    Every command sent before a flush command has been seen to: its input written to the terminal, and the snapshot it
+    asked for sent. Sent in answer to the flush, only to the client which sent it, and after anything else it asked
+    for beforehand. It can't be subscribed to.
+    """
+
+    INPUT_DISCARDED = "inputDiscarded"
+    """
+    Input arrived after the command exited (and before any respawn), so it was dropped rather than written. Sent
+    instead of inputWritten to the client which asked for an acknowledgement, and otherwise to every client. It
+    can't be subscribed to.
+
+    Event data is an object with the following fields:
+
+    - bytes - how many bytes of input were discarded
     """
 
     OUTPUT = "output"
@@ -92,9 +175,33 @@ class HtEvent(StrEnum):
       (colors, cursor positioning, etc.)
     """
 
+    RAW_OUTPUT = "rawOutput"
+    """
+    The exact bytes behind output events, for commands that write binary data or split escape sequences and
+    characters across writes, which the text of an output event can't carry faithfully. Only sent to subscribers of
+    rawOutput, and alongside output events rather than instead of them. Output from additional panes isn't included.
+
+    Event data is an object with the following fields:
+
+    - bytes - the bytes written to the terminal, base64-encoded
+    """
+
+    STDERR = "stderr"
+    """
+    Output the application wrote to stderr. Only sent when ht runs with `--separate-stderr`, in which case the
+    child's stderr is connected to a pipe instead of the terminal and never appears in snapshots.
+
+    Event data is an object with the following fields:
+
+    - seq - the raw characters written to stderr
+    """
+
     RESIZE = "resize"
     """
-    Terminal resize. Send when the terminal is resized with the resize command.
+    Terminal resize. Send when the terminal is resized with the resize command, once the command's PTY has taken the
+    new size and the command has been sent SIGWINCH (not sent if the size is unchanged, as the kernel only signals a
+    change). If the PTY refuses the size, or takes a different one, an error event is sent instead (followed, in the
+    latter case, by a resize event with the size it took).
 
     Event data is an object with the following fields:
 
@@ -112,6 +219,15 @@ class HtEvent(StrEnum):
     """
     htty modified ht to stay open even after the command has completed.
     This event indicates the exit code of the underlying command.
+
+    Event data is an object with the following fields:
+
+    - exitCode - the exit status; signal deaths are reported as `128 + signal number`
+    - signal - name of the terminating signal (e.g. `"SIGKILL"`), or null
+    - coreDumped - whether a core dump was produced (not known when a shell wrapper relayed the status)
+    - terminatedBy - `"command"` if the command ended, either exec'd directly or with a shell wrapper relaying its
+      status (in which case `signal` is inferred from the `128 + n` convention), `"shell"` if the wrapper itself
+      was terminated
     """
 
     COMMAND_COMPLETED = "commandCompleted"
@@ -137,11 +253,114 @@ class HtEvent(StrEnum):
     This event indicates when the command completed, exitCode appears when the shell exits.
     """
 
+    RESPAWN = "respawn"
+    """
+    The command exited with a nonzero status and is being relaunched in the same session. Only sent when ht runs
+    with `--respawn[=N]`. Each relaunch is followed by a new pid event, and eventually a new exitCode event.
+
+    Event data is an object with the following fields:
+
+    - attempt - which relaunch this is, starting at 1
+    - delayMs - how long ht waits before relaunching (doubles with each attempt, up to 5 seconds)
+    """
+
+    STOPPED = "stopped"
+    """
+    The command was stopped by a signal, and won't run (or read input) until it's continued.
+
+    Only ht's own child is watched: the command, or the shell wrapping it. That's stopped by
+    signalling its process group (e.g. `kill -STOP -<pid>`), but not by Ctrl-Z, as the kernel
+    discards terminal stop signals for a process group whose parent is outside its session, as
+    ht is. Jobs an interactive shell starts can be stopped with Ctrl-Z, but aren't ht's children,
+    so that's only seen in what the shell prints.
+
+    Event data is an object with the following fields:
+
+    - signal - the name of the signal that stopped it, e.g. SIGSTOP or SIGTSTP
+    """
+
+    CONTINUED = "continued"
+    """
+    The command was continued (SIGCONT) after a stopped event.
+    """
+
+    IDLE = "idle"
+    """
+    The application hasn't produced any output for a while (1 second unless ht runs with `--idle-after <MS>`).
+    Sent once per quiet period.
+
+    Event data is an object with the following fields:
+
+    - quietMs - milliseconds since the last output
+    """
+
+    ACTIVE = "active"
+    """
+    Output resumed after an idle event.
+
+    Event data is an object with the following fields:
+
+    - quietMs - how long the quiet period lasted, in milliseconds
+    """
+
+    IDLE_TIMEOUT = "idleTimeout"
+    """
+    Neither output nor commands arrived for the duration given with `--idle-timeout <SECS>`, so ht is shutting
+    down. It exits with status 124 shortly after sending this.
+
+    Event data is an object with the following fields:
+
+    - quietMs - milliseconds since the last output or command
+    """
+
+    STATE = "state"
+    """
+    The session moved on to another stage of its life, which decides the commands it still acts on. A new subscriber is
+    also told the current state, right after init, without the others hearing of it again.
+
+    Event data is an object with the following fields:
+
+    - state - one of:
+      - `starting` - the command is being launched; input is held until it can read it
+      - `running`
+      - `childExited` - the command has exited, and ht is kept open (see `no_exit`) for snapshots; input and termios
+        changes are discarded
+      - `draining` - ht took an exit command (or timed out), and is seeing to the commands sent before it; input and
+        further exit commands are discarded
+      - `closed` - ht takes no more commands
+    """
+
     DEBUG = "debug"
     """
     These events contain messages that might be helpful for debugging `ht`.
     """
 
+    ERROR = "error"
+    """
+    A command was refused, e.g. a resize to fewer than 2 or more than 10000 columns or rows. Sent to every client.
+    A line on stdin that isn't a command ht knows (invalid JSON, an unknown type, missing fields) is also answered
+    with one, sent on stdout only. It can't be subscribed to.
+
+    Event data is an object with the following fields:
+
+    - message - why the command was refused
+    - line - the line that couldn't be taken as a command, for errors about one
+    - id - the `id` field of that line, if it had one
+    """
+
+    BACKLOG = "backlog"
+    """
+    One of ht's internal channels is three quarters full, so whatever feeds it is about to be held up (or, for the
+    broadcast channel, the slowest subscriber is about to miss events). Sent to every client, once per backlog: it's
+    sent again only after the channel has drained to half. It can't be subscribed to.
+
+    Event data is an object with the following fields:
+
+    - channel - `output` (from the command), `command` (from clients) or `broadcast` (events to subscribers)
+    - queued - how many items were waiting in the channel
+    - capacity - how many items the channel holds (see `--channel-capacity` and `--broadcast-capacity`)
+    """
+
 
 class HtArgs:
     """
@@ -155,12 +374,37 @@ class HtArgs:
         rows: Rows = None,
         cols: Cols = None,
         style_mode: Optional[StyleMode] = None,
+        style_format: Optional[StyleFormat] = None,
+        log_level: Optional[LogLevel] = None,
+        log_file: Optional[str] = None,
+        no_exit: bool = False,
+        start_on_output: bool = False,
+        propagate_exit_code: bool = False,
+        idle_timeout: Optional[int] = None,
+        final_snapshot: Union[bool, str] = False,
+        profile: Optional[str] = None,
+        manifest: Optional[str] = None,
+        env: Optional[dict[str, str]] = None,
+        cwd: Optional[Union[str, "os.PathLike[str]"]] = None,
     ) -> None:
         self.command = command
         self.subscribes = subscribes or []
         self.rows = rows
         self.cols = cols
         self.style_mode = style_mode
+        self.style_format = style_format
+        self.log_level = log_level
+        self.log_file = log_file
+        self.no_exit = no_exit
+        self.start_on_output = start_on_output
+        self.propagate_exit_code = propagate_exit_code
+        self.idle_timeout = idle_timeout
+        self.final_snapshot = final_snapshot
+        self.profile = profile
+        self.manifest = manifest
+        # Not options of ht's, but where and with what environment it's started, which the command inherits
+        self.env = env
+        self.cwd = cwd
 
     def get_command(self, ht_binary: Optional[str] = None) -> list[str]:
         """Build the command line arguments for running ht.
@@ -189,11 +433,57 @@ class HtArgs:
         if self.style_mode is not None:
             cmd_args.extend(["--style-mode", self.style_mode])
 
+        # Add style format if specified
+        if self.style_format is not None:
+            cmd_args.extend(["--style-format", self.style_format])
+
+        # Add log level if specified
+        if self.log_level is not None:
+            cmd_args.extend(["--log-level", self.log_level])
+
+        # Add log file if specified
+        if self.log_file is not None:
+            cmd_args.extend(["--log-file", self.log_file])
+
+        # Keep serving after the command exits, until an exit command
+        if self.no_exit:
+            cmd_args.append("--no-exit")
+
+        # Hold back the init event until the command's first output
+        if self.start_on_output:
+            cmd_args.append("--start-on-output")
+
+        # Have ht exit with the command's exit status
+        if self.propagate_exit_code:
+            cmd_args.append("--propagate-exit-code")
+
+        # Shut down after this many seconds without output or commands
+        if self.idle_timeout is not None:
+            cmd_args.extend(["--idle-timeout", str(self.idle_timeout)])
+
+        # Snapshot the screen just before ht shuts down, as an event (True) or into the named file
+        if self.final_snapshot is True:
+            cmd_args.append("--final-snapshot")
+        elif self.final_snapshot:
+            cmd_args.append(f"--final-snapshot={self.final_snapshot}")
+
+        # Apply a named profile from the config file
+        if self.profile is not None:
+            cmd_args.extend(["--profile", self.profile])
+
+        # Write the manifest of artifacts somewhere other than next to them
+        if self.manifest is not None:
+            cmd_args.extend(["--manifest", self.manifest])
+
         # Add separator and the command to run
         cmd_args.append("--")
         if isinstance(self.command, str):
-            cmd_args.extend(self.command.split())
+            # A single argument is a command line for ht's shell
+            cmd_args.append(self.command)
+        elif len(self.command) == 1:
+            cmd_args.append(shlex.quote(self.command[0]))
         else:
+            # Several are a program and its arguments, which ht passes on untouched
             cmd_args.extend(self.command)
 
         return cmd_args
@@ -248,4 +538,6 @@ def run(args: HtArgs) -> subprocess.Popen[str]:
         stderr=subprocess.PIPE,
         text=True,
         bufsize=1,
+        env=args.env,
+        cwd=args.cwd,
     )
@@ -1,6 +1,8 @@
-use super::Subscription;
+use crate::config::Subscription;
+use crate::message::{self, Message};
 use crate::session;
-use anyhow::Result;
+use crate::timing::Timing;
+use anyhow::{anyhow, Result};
 use axum::{
     extract::{connect_info::ConnectInfo, ws, Query, State},
     http::{header, StatusCode, Uri},
@@ -8,7 +10,7 @@ use axum::{
     routing::get,
     Router,
 };
-use futures_util::{sink, stream, StreamExt};
+use futures_util::{sink, stream, Sink, SinkExt, StreamExt};
 use rust_embed::RustEmbed;
 use serde::Deserialize;
 use serde_json::json;
@@ -16,27 +18,49 @@ use std::borrow::Cow;
 use std::future::{self, Future, IntoFuture};
 use std::io;
 use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tracing::info;
 
 #[derive(RustEmbed)]
 #[folder = "assets/"]
 struct Assets;
 
+/// The session end every WebSocket handler subscribes through.
+#[derive(Clone)]
+struct Clients {
+    tx: mpsc::Sender<session::Client>,
+    timing: Timing,
+}
+
+impl Clients {
+    async fn stream(&self) -> Result<impl stream::Stream<Item = session::Event> + Unpin> {
+        Ok(session::stream(&self.tx, self.timing.subscription_timeout).await?)
+    }
+
+    async fn stream_for(&self, sub: &Subscription) -> Result<impl stream::Stream<Item = session::Event> + Unpin> {
+        Ok(session::stream_for(&self.tx, self.timing.subscription_timeout, sub).await?)
+    }
+}
+
 pub async fn start(
     listener: TcpListener,
     clients_tx: mpsc::Sender<session::Client>,
+    timing: Timing,
 ) -> Result<impl Future<Output = io::Result<()>>> {
     listener.set_nonblocking(true)?;
     let listener = tokio::net::TcpListener::from_std(listener)?;
     let addr = listener.local_addr().unwrap();
-    eprintln!("HTTP server listening on {addr}");
-    eprintln!("live preview available at http://{addr}");
+    info!("HTTP server listening on {addr}");
+    info!("live preview available at http://{addr}");
 
     let app: Router<()> = Router::new()
         .route("/ws/alis", get(alis_handler))
         .route("/ws/events", get(event_stream_handler))
-        .with_state(clients_tx)
+        .with_state(Clients {
+            tx: clients_tx,
+            timing,
+        })
         .fallback(static_handler);
 
     Ok(axum::serve(
@@ -53,26 +77,26 @@ pub async fn start(
 async fn alis_handler(
     ws: ws::WebSocketUpgrade,
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
-    State(clients_tx): State<mpsc::Sender<session::Client>>,
+    State(clients): State<Clients>,
 ) -> impl IntoResponse {
     ws.on_upgrade(move |socket| async move {
-        let _ = handle_alis_socket(socket, clients_tx).await;
+        let _ = handle_alis_socket(socket, clients).await;
     })
 }
 
 async fn handle_alis_socket(
     socket: ws::WebSocket,
-    clients_tx: mpsc::Sender<session::Client>,
+    clients: Clients,
 ) -> Result<()> {
     let (sink, stream) = socket.split();
     let drainer = tokio::spawn(stream.map(Ok).forward(sink::drain()));
 
-    let result = session::stream(&clients_tx)
+    let messages = clients.stream()
         .await?
         .filter_map(alis_message)
-        .chain(stream::once(future::ready(Ok(close_message()))))
-        .forward(sink)
-        .await;
+        .chain(stream::once(future::ready(Ok(close_message()))));
+
+    let result = send_all(sink, messages, clients.timing.write_timeout).await;
 
     drainer.abort();
     result?;
@@ -81,43 +105,103 @@ async fn handle_alis_socket(
 }
 
 async fn alis_message(
-    event: Result<session::Event, BroadcastStreamRecvError>,
+    event: session::Event,
 ) -> Option<Result<ws::Message, axum::Error>> {
     use session::Event::*;
 
     match event {
-        Ok(Init(time, cols, rows, _pid, seq, _text, _)) => Some(Ok(json_message(json!({
+        Init(time, cols, rows, _pid, seq, _text, _) => Some(Ok(json_message(json!({
             "time": time,
             "cols": cols,
             "rows": rows,
             "init": seq,
         })))),
 
-        Ok(Output(time, data)) => Some(Ok(json_message(json!([time, "o", data])))),
+        Output(time, data) => Some(Ok(json_message(json!([time, "o", data])))),
+
+        RawOutput(_, _) => None,
+
+        Stderr(_, _) => None,
 
-        Ok(Resize(time, cols, rows)) => Some(Ok(json_message(json!([
+        Resize(time, cols, rows) => Some(Ok(json_message(json!([
             time,
             "r",
             format!("{cols}x{rows}")
         ])))),
 
-        Ok(Snapshot(_, _, _, _, _)) => None,
+        Snapshot(..) => None,
+
+        InputWritten(_) => None,
+
+        InputDiscarded(_) => None,
+
+        Flushed => None,
 
-        Ok(Pid(_, _)) => None,
+        Pid(_, _) => None,
 
-        Ok(ExitCode(_, _)) => None,
+        ExitCode(_, _) => None,
 
-        Ok(Debug(_, _)) => None,
+        Debug(_, _) => None,
 
-        Ok(Completed(_)) => None,
+        Error(_, _) => None,
 
-        Err(e) => Some(Err(axum::Error::new(e))),
+        Completed(_) => None,
+
+        Respawn(_, _, _) => None,
+
+        Stopped(_, _) => None,
+
+        Continued(_) => None,
+
+        Idle(_, _) => None,
+
+        Active(_, _) => None,
+
+        IdleTimeout(_, _) => None,
+
+        State(_, _) => None,
+
+        Pane(_, _) => None,
+
+        // The stream carries on with a fresh init, which resets the player
+        Dropped(_, _) => None,
+
+        Backlog(..) => None,
     }
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct EventsParams {
     sub: Option<String>,
+    batch_ms: Option<u64>,
+    batch_size: Option<usize>,
+}
+
+/// How long a batch may wait after its first event when only `batchSize` is given...
+const DEFAULT_BATCH_DELAY: Duration = Duration::from_millis(16);
+
+/// ...and how many events it may hold when only `batchMs` is given.
+const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// Server-side batching of events for one event stream client.
+#[derive(Debug, Clone, Copy)]
+struct Batching {
+    delay: Duration,
+    size: usize,
+}
+
+impl EventsParams {
+    fn batching(&self) -> Option<Batching> {
+        if self.batch_ms.is_none() && self.batch_size.is_none() {
+            return None;
+        }
+
+        Some(Batching {
+            delay: self.batch_ms.map(Duration::from_millis).unwrap_or(DEFAULT_BATCH_DELAY),
+            size: self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1),
+        })
+    }
 }
 
 /// Event stream handler
@@ -125,33 +209,47 @@ struct EventsParams {
 /// This endpoint allows the client to subscribe to selected events and have them delivered as they occur.
 /// Query param `sub` should be set to a comma-separated list desired of events.
 /// See above for a list of supported events.
+///
+/// With `batchMs` and/or `batchSize`, events are instead gathered into `batch` frames, each
+/// holding the events received within `batchMs` milliseconds of its first one (default 16), or
+/// `batchSize` events (default 256), whichever comes first.
 async fn event_stream_handler(
     ws: ws::WebSocketUpgrade,
     Query(params): Query<EventsParams>,
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
-    State(clients_tx): State<mpsc::Sender<session::Client>>,
+    State(clients): State<Clients>,
 ) -> impl IntoResponse {
+    let batching = params.batching();
     let sub: Subscription = params.sub.unwrap_or_default().parse().unwrap_or_default();
 
     ws.on_upgrade(move |socket| async move {
-        let _ = handle_event_stream_socket(socket, clients_tx, sub).await;
+        let _ = handle_event_stream_socket(socket, clients, sub, batching).await;
     })
 }
 
 async fn handle_event_stream_socket(
     socket: ws::WebSocket,
-    clients_tx: mpsc::Sender<session::Client>,
+    clients: Clients,
     sub: Subscription,
+    batching: Option<Batching>,
 ) -> Result<()> {
     let (sink, stream) = socket.split();
     let drainer = tokio::spawn(stream.map(Ok).forward(sink::drain()));
 
-    let result = session::stream(&clients_tx)
+    let events = clients.stream_for(&sub)
         .await?
-        .filter_map(move |e| event_stream_message(e, sub))
-        .chain(stream::once(future::ready(Ok(close_message()))))
-        .forward(sink)
-        .await;
+        .filter(move |e| future::ready(sub.includes(e)));
+
+    let messages = match batching {
+        Some(batching) => tokio_stream::StreamExt::chunks_timeout(events, batching.size, batching.delay)
+            .map(|events| Ok(batch_message(&events)))
+            .boxed(),
+
+        None => events.map(|e| Ok(ws::Message::Text(e.to_json()))).boxed(),
+    };
+
+    let messages = messages.chain(stream::once(future::ready(Ok(close_message()))));
+    let result = send_all(sink, messages, clients.timing.write_timeout).await;
 
     drainer.abort();
     result?;
@@ -159,24 +257,34 @@ async fn handle_event_stream_socket(
     Ok(())
 }
 
-async fn event_stream_message(
-    event: Result<session::Event, BroadcastStreamRecvError>,
-    sub: Subscription,
-) -> Option<Result<ws::Message, axum::Error>> {
-    use session::Event::*;
+/// Send `messages` to a client, giving up on it if a send fails or takes longer than `timeout`,
+/// as one that has stopped reading would otherwise hold its socket open for good.
+async fn send_all(
+    mut sink: impl Sink<ws::Message, Error = axum::Error> + Unpin,
+    messages: impl stream::Stream<Item = Result<ws::Message, axum::Error>>,
+    timeout: Duration,
+) -> Result<()> {
+    let mut messages = std::pin::pin!(messages);
 
-    match event {
-        Ok(e @ Init(..)) if sub.init => Some(Ok(json_message(e.to_json()))),
-        Ok(e @ Output(_, _)) if sub.output => Some(Ok(json_message(e.to_json()))),
-        Ok(e @ Resize(_, _, _)) if sub.resize => Some(Ok(json_message(e.to_json()))),
-        Ok(e @ Snapshot(_, _, _, _, _)) if sub.snapshot => Some(Ok(json_message(e.to_json()))),
-        Ok(e @ Pid(_, _)) if sub.pid => Some(Ok(json_message(e.to_json()))),
-        Ok(e @ ExitCode(_, _)) if sub.exit_code => Some(Ok(json_message(e.to_json()))),
-        Ok(e @ Debug(_, _)) if sub.debug => Some(Ok(json_message(e.to_json()))),
-        Ok(e @ Completed(_)) if sub.command_completed => Some(Ok(json_message(e.to_json()))),
-        Ok(_) => None,
-        Err(e) => Some(Err(axum::Error::new(e))),
+    while let Some(message) = messages.next().await {
+        tokio::time::timeout(timeout, sink.send(message?))
+            .await
+            .map_err(|_| anyhow!("client not reading for {timeout:?}"))??;
     }
+
+    tokio::time::timeout(timeout, sink.close())
+        .await
+        .map_err(|_| anyhow!("client not reading for {timeout:?}"))??;
+
+    Ok(())
+}
+
+fn batch_message(events: &[session::Event]) -> ws::Message {
+    let batch = Message::Batch(message::Batch {
+        events: events.iter().map(session::Event::message).collect(),
+    });
+
+    ws::Message::Text(serde_json::to_string(&batch).expect("events always serialize"))
 }
 
 fn json_message(value: serde_json::Value) -> ws::Message {
@@ -207,3 +315,20 @@ async fn static_handler(uri: Uri) -> impl IntoResponse {
         None => (StatusCode::NOT_FOUND, "404").into_response(),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{json_message, send_all};
+    use futures_util::{future, sink, stream};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn gives_up_on_client_not_reading() {
+        let stalled = sink::unfold((), |(), _| future::pending::<Result<(), axum::Error>>());
+        let messages = stream::iter([Ok(json_message(serde_json::json!([0.0, "o", "a"])))]);
+
+        let result = send_all(Box::pin(stalled), messages, Duration::from_millis(10)).await;
+
+        assert!(result.unwrap_err().to_string().contains("not reading"));
+    }
+}
@@ -1,13 +1,35 @@
-use super::Subscription;
-use crate::cli::StyleMode;
-use crate::command::{self, Command, InputSeq};
+use crate::config::Subscription;
+use crate::config::HtmlTheme;
+use crate::config::{StyleFormat, StyleMode};
+use crate::command::{self, Command};
+use crate::keys::{key_seqs, standard_key};
+use crate::message::{self, Message};
+use crate::render;
 use crate::session;
+use crate::shutdown::Shutdown;
+use crate::timing::Timing;
 use anyhow::Result;
+use futures_util::stream::FuturesOrdered;
 use serde::{de::DeserializeOwned, Deserialize};
-use std::io;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
 use std::thread;
-use tokio::sync::mpsc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
 use tokio_stream::StreamExt;
+use tracing::error;
+
+/// How many lines may wait to be written to stdout while the reader catches up.
+const STDOUT_BACKLOG: usize = 64;
+
+/// What a client asked for in reply to a command, besides the command itself.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct ReplyOptions {
+    /// The theme to render a snapshot as HTML in, from takeSnapshot's `htmlTheme`.
+    html_theme: Option<HtmlTheme>,
+    /// Whether to acknowledge input once it has been written, from input and sendKeys's `ack`.
+    ack: bool,
+}
 
 #[derive(Debug, Deserialize)]
 struct InputArgs {
@@ -30,101 +52,320 @@ struct SetStyleModeArgs {
     mode: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct SetStyleFormatArgs {
+    format: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetTermiosArgs {
+    settings: String,
+}
+
+/// The reply to a flush, held back until the snapshots and acknowledgements asked for before
+/// it have been printed.
+struct Fence {
+    /// How many of those are still to be printed.
+    snapshots: usize,
+    acks: usize,
+    reply: oneshot::Receiver<session::Event>,
+}
+
+impl Fence {
+    fn is_due(&self) -> bool {
+        self.snapshots == 0 && self.acks == 0
+    }
+}
+
+/// Where events and replies are printed: stdout, written from a thread of its own so that a
+/// reader which stops reading holds up only that thread, never the event loop.
+struct Stdout {
+    /// Gone once stdout has failed, after which nothing more is printed.
+    lines: Option<mpsc::Sender<String>>,
+    /// How the writer thread ended, sent as it does.
+    written: oneshot::Receiver<io::Result<()>>,
+    timeout: Duration,
+    failure: Option<io::Error>,
+}
+
+impl Stdout {
+    fn new(timeout: Duration) -> Self {
+        Self::to(io::stdout(), timeout)
+    }
+
+    /// Print to `out` instead of stdout.
+    fn to(out: impl Write + Send + 'static, timeout: Duration) -> Self {
+        let (lines_tx, lines_rx) = mpsc::channel(STDOUT_BACKLOG);
+        let (written_tx, written_rx) = oneshot::channel();
+        thread::spawn(|| write_stdout(out, lines_rx, written_tx));
+
+        Self {
+            lines: Some(lines_tx),
+            written: written_rx,
+            timeout,
+            failure: None,
+        }
+    }
+
+    /// Queue `line` to be printed, giving up on stdout if the writer thread has hit an error
+    /// (a closed pipe, most likely) or the backlog hasn't moved within the timeout.
+    async fn print(&mut self, line: String) {
+        let Some(lines) = &self.lines else {
+            return;
+        };
+
+        let failure = match tokio::time::timeout(self.timeout, lines.send(line)).await {
+            Ok(Ok(())) => return,
+
+            Ok(Err(_)) => match self.written.try_recv() {
+                Ok(Err(e)) => e,
+                _ => io::Error::from(io::ErrorKind::BrokenPipe),
+            },
+
+            Err(_) => io::Error::new(io::ErrorKind::TimedOut, format!("not read for {:?}", self.timeout)),
+        };
+
+        self.lines = None;
+        self.failure = Some(failure);
+    }
+
+    /// Why stdout was given up on, the first time this is asked after it was.
+    fn take_failure(&mut self) -> Option<io::Error> {
+        self.failure.take()
+    }
+
+    /// Wait for what's been queued to be written, for as long as stdout keeps being read.
+    async fn finish(mut self) {
+        if self.lines.take().is_some() {
+            let _ = tokio::time::timeout(self.timeout, self.written).await;
+        }
+    }
+}
+
+fn write_stdout(mut out: impl Write, mut lines: mpsc::Receiver<String>, written: oneshot::Sender<io::Result<()>>) {
+    let result = (|| {
+        while let Some(line) = lines.blocking_recv() {
+            writeln!(out, "{line}")?;
+            out.flush()?;
+        }
+
+        Ok(())
+    })();
+
+    let _ = written.send(result);
+}
+
 pub async fn start(
     command_tx: mpsc::Sender<Command>,
     clients_tx: mpsc::Sender<session::Client>,
     sub: Subscription,
+    keys: HashMap<String, String>,
+    keep_child: bool,
+    timing: Timing,
+    shutdown: Shutdown,
 ) -> Result<()> {
     let (input_tx, mut input_rx) = mpsc::unbounded_channel();
     thread::spawn(|| read_stdin(input_tx));
-    let mut events = session::stream(&clients_tx).await?;
+    let mut stdout = Stdout::new(timing.write_timeout);
+    let mut events = session::stream_for(&clients_tx, timing.subscription_timeout, &sub).await?;
+    let mut replies = FuturesOrdered::new();
+    let mut html_themes = VecDeque::new();
+    // Kept apart from snapshots, which shouldn't wait on input the child isn't reading
+    let mut acks = FuturesOrdered::new();
+    let mut fences = VecDeque::new();
+    let mut stdin_open = true;
+    let mut events_open = true;
 
     loop {
+        // Once the session has ended, see out the replies it already sent or dropped
+        if !events_open && replies.is_empty() && acks.is_empty() && fences.is_empty() {
+            break;
+        }
+
         tokio::select! {
-            line = input_rx.recv() => {
+            line = input_rx.recv(), if stdin_open => {
                 match line {
+                    Some(line) if line.trim().is_empty() => (),
+
                     Some(line) => {
-                        match parse_line(&line) {
-                            Ok(command) => command_tx.send(command).await?,
-                            Err(e) => eprintln!("command parse error: {e}"),
+                        match parse_line(&line, &keys) {
+                            Ok((command, options)) => {
+                                let command = with_reply(command, options, &mut replies, &mut html_themes, &mut acks, &mut fences);
+
+                                if let Err(e) = command_tx.send(command).await {
+                                    // A command arriving as ht shuts down has nowhere to go
+                                    if !shutdown.is_triggered() {
+                                        return Err(e.into());
+                                    }
+
+                                    stdin_open = false;
+                                }
+                            }
+                            Err(e) => stdout.print(protocol_error(&line, e)).await,
                         }
                     }
 
                     None => {
                         // stdin closed - drain any remaining commands in the channel
                         while let Ok(line) = input_rx.try_recv() {
-                            match parse_line(&line) {
-                                Ok(command) => {
+                            if line.trim().is_empty() {
+                                continue;
+                            }
+
+                            match parse_line(&line, &keys) {
+                                Ok((command, options)) => {
+                                    let command = with_reply(command, options, &mut replies, &mut html_themes, &mut acks, &mut fences);
                                     if command_tx.send(command).await.is_err() {
                                         break; // command channel closed, time to exit
                                     }
                                 },
-                                Err(e) => eprintln!("command parse error: {e}"),
+                                Err(e) => stdout.print(protocol_error(&line, e)).await,
                             }
                         }
                         
-                        // stdin is closed but keep the API task alive and command channel open
-                        // Wait for the main event loop to close the command channel when it's truly done
-                        // This prevents race conditions where commands are dropped due to stdin closure
-                        loop {
-                            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                            // Check if command channel is closed (main event loop is shutting down)
-                            if command_tx.is_closed() {
-                                break;
-                            }
-                        }
-                        break;
+                        // stdin is closed but keep the command channel open, and keep passing on
+                        // events until the session ends, so none are lost while ht shuts down
+                        stdin_open = false;
                     }
                 }
             }
 
-            event = events.next() => {
-                use session::Event::*;
+            _ = shutdown.triggered(), if stdin_open => {
+                // Take no more commands, but keep passing on events until the session ends
+                stdin_open = false;
+            }
 
+            event = events.next(), if events_open => {
                 match event {
-                    Some(Ok(e @ Init(..))) if sub.init => {
-                        println!("{}", e.to_json());
+                    Some(e) if sub.includes(&e) => {
+                        stdout.print(e.to_json()).await;
                     }
 
-                    Some(Ok(e @ Output(_, _))) if sub.output => {
-                        println!("{}", e.to_json());
-                    }
+                    Some(_) => (),
 
-                    Some(Ok(e @ Resize(_, _, _))) if sub.resize => {
-                        println!("{}", e.to_json());
-                    }
+                    None => events_open = false,
+                }
+            }
 
-                    Some(Ok(e @ Snapshot(_, _, _, _, _))) if sub.snapshot => {
-                        println!("{}", e.to_json());
-                    }
+            Some(reply) = replies.next(), if !replies.is_empty() => {
+                let theme = html_themes.pop_front().flatten();
 
-                    Some(Ok(e @ Pid(_, _))) if sub.pid => {
-                        println!("{}", e.to_json());
+                if let Ok(e) = reply {
+                    if sub.includes(&e) {
+                        stdout.print(reply_json(&e, theme)).await;
                     }
+                }
 
-                    Some(Ok(e @ ExitCode(_, _))) if sub.exit_code => {
-                        println!("{}", e.to_json());
-                    }
+                fences.iter_mut().for_each(|fence| fence.snapshots = fence.snapshots.saturating_sub(1));
+            }
 
-                    Some(Ok(e @ Debug(_, _))) if sub.debug => {
-                        println!("{}", e.to_json());
-                    }
+            Some(ack) = acks.next(), if !acks.is_empty() => {
+                if let Ok(e) = ack {
+                    stdout.print(e.to_json()).await;
+                }
 
-                    Some(Ok(e @ Completed(_))) if sub.command_completed => {
-                        println!("{}", e.to_json());
-                    }
+                fences.iter_mut().for_each(|fence| fence.acks = fence.acks.saturating_sub(1));
+            }
 
-                    Some(_) => (),
+            flushed = async { (&mut fences.front_mut().expect("checked").reply).await }, if fences.front().is_some_and(Fence::is_due) => {
+                fences.pop_front();
 
-                    None => break
+                if let Ok(e) = flushed {
+                    stdout.print(e.to_json()).await;
                 }
             }
         }
+
+        if let Some(e) = stdout.take_failure() {
+            // Events are still taken from the session, so it can see the end of this stream as
+            // usual, but they go nowhere
+            if keep_child {
+                error!("cannot write to stdout ({e}), no longer printing events");
+            } else {
+                error!("cannot write to stdout ({e}), shutting down");
+                shutdown.trigger();
+            }
+        }
     }
 
+    stdout.finish().await;
+
     Ok(())
 }
 
+/// Have snapshots and flushes we ask for (and input acknowledgements, if asked for) come
+/// straight back to us instead of going out to every client, remembering which theme (if
+/// any) each snapshot is to be rendered as HTML in.
+fn with_reply(
+    command: Command,
+    options: ReplyOptions,
+    replies: &mut FuturesOrdered<oneshot::Receiver<session::Event>>,
+    html_themes: &mut VecDeque<Option<HtmlTheme>>,
+    acks: &mut FuturesOrdered<oneshot::Receiver<session::Event>>,
+    fences: &mut VecDeque<Fence>,
+) -> Command {
+    match command {
+        Command::Snapshot(None) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            replies.push_back(reply_rx);
+            html_themes.push_back(options.html_theme);
+            Command::Snapshot(Some(reply_tx))
+        }
+
+        Command::Input(seqs, None) if options.ack => {
+            let (ack_tx, ack_rx) = oneshot::channel();
+            acks.push_back(ack_rx);
+            Command::Input(seqs, Some(ack_tx))
+        }
+
+        Command::Flush(None) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+
+            fences.push_back(Fence {
+                snapshots: replies.len(),
+                acks: acks.len(),
+                reply: reply_rx,
+            });
+
+            Command::Flush(Some(reply_tx))
+        }
+
+        Command::Pane(id, command) => {
+            Command::Pane(id, Box::new(with_reply(*command, options, replies, html_themes, acks, fences)))
+        }
+
+        command => command,
+    }
+}
+
+/// The wire form of a snapshot we asked for, with the screen rendered as HTML if `theme` is set.
+fn reply_json(event: &session::Event, theme: Option<HtmlTheme>) -> String {
+    let mut message = event.message();
+
+    if let (Some(theme), Message::Snapshot(data)) = (theme, &mut message) {
+        data.html = Some(render::html(data.cols, data.rows, &data.seq, theme).into());
+    }
+
+    serde_json::to_string(&message).expect("events always serialize")
+}
+
+/// The error event telling the client why `line` wasn't taken as a command, with the `id` it
+/// gave (if any) so the client can tell which of its requests went wrong.
+fn protocol_error(line: &str, reason: String) -> String {
+    let id = serde_json::from_str::<serde_json::Value>(line)
+        .ok()
+        .and_then(|mut value| value.get_mut("id").map(serde_json::Value::take));
+
+    let message = Message::Error(message::Error {
+        message: reason.into(),
+        line: Some(line.into()),
+        id,
+        pane: None,
+    });
+
+    serde_json::to_string(&message).expect("events always serialize")
+}
+
 fn read_stdin(input_tx: mpsc::UnboundedSender<String>) -> Result<()> {
     for line in io::stdin().lines() {
         input_tx.send(line?)?;
@@ -133,23 +374,69 @@ fn read_stdin(input_tx: mpsc::UnboundedSender<String>) -> Result<()> {
     Ok(())
 }
 
-fn parse_line(line: &str) -> Result<command::Command, String> {
-    serde_json::from_str::<serde_json::Value>(line)
-        .map_err(|e| e.to_string())
-        .and_then(build_command)
+/// The command on `line`, and what it asks for in reply.
+fn parse_line(line: &str, keys: &HashMap<String, String>) -> Result<(command::Command, ReplyOptions), String> {
+    let value = serde_json::from_str::<serde_json::Value>(line).map_err(|e| e.to_string())?;
+    let options = ReplyOptions {
+        html_theme: html_theme(&value)?,
+        ack: ack(&value)?,
+    };
+
+    Ok((build_routed_command(value, keys)?, options))
+}
+
+fn html_theme(value: &serde_json::Value) -> Result<Option<HtmlTheme>, String> {
+    match value.get("htmlTheme") {
+        Some(theme) if value["type"] == "takeSnapshot" => theme
+            .as_str()
+            .ok_or_else(|| format!("invalid html theme: {theme}"))?
+            .parse()
+            .map(Some),
+
+        _ => Ok(None),
+    }
+}
+
+fn ack(value: &serde_json::Value) -> Result<bool, String> {
+    match value.get("ack") {
+        Some(ack) if value["type"] == "input" || value["type"] == "sendKeys" => {
+            ack.as_bool().ok_or_else(|| format!("invalid ack: {ack}"))
+        }
+
+        _ => Ok(false),
+    }
 }
 
-fn build_command(value: serde_json::Value) -> Result<Command, String> {
+fn build_routed_command(value: serde_json::Value, keys: &HashMap<String, String>) -> Result<Command, String> {
+    let pane = match value.get("pane") {
+        Some(pane) => pane
+            .as_u64()
+            .ok_or_else(|| format!("invalid pane id: {pane}"))? as usize,
+
+        None => 0,
+    };
+
+    match (pane, build_command(value, keys)?) {
+        (0, command) => Ok(command),
+
+        (_, Command::Exit | Command::Flush(_) | Command::SetStyleMode(_) | Command::SetStyleFormat(_)) => {
+            Err("exit, flush, setStyleMode and setStyleFormat apply to the whole session and can't target a pane".to_string())
+        }
+
+        (pane, command) => Ok(Command::Pane(pane, Box::new(command))),
+    }
+}
+
+fn build_command(value: serde_json::Value, keys: &HashMap<String, String>) -> Result<Command, String> {
     match value["type"].as_str() {
         Some("input") => {
             let args: InputArgs = args_from_json_value(value)?;
-            Ok(Command::Input(vec![standard_key(args.payload)]))
+            Ok(Command::Input(vec![standard_key(args.payload)], None))
         }
 
         Some("sendKeys") => {
             let args: SendKeysArgs = args_from_json_value(value)?;
-            let seqs = args.keys.into_iter().map(parse_key).collect();
-            Ok(Command::Input(seqs))
+            Ok(Command::Input(key_seqs(args.keys, keys), None))
         }
 
         Some("resize") => {
@@ -157,7 +444,7 @@ fn build_command(value: serde_json::Value) -> Result<Command, String> {
             Ok(Command::Resize(args.cols, args.rows))
         }
 
-        Some("takeSnapshot") => Ok(Command::Snapshot),
+        Some("takeSnapshot") => Ok(Command::Snapshot(None)),
 
         Some("setStyleMode") => {
             let args: SetStyleModeArgs = args_from_json_value(value)?;
@@ -166,6 +453,23 @@ fn build_command(value: serde_json::Value) -> Result<Command, String> {
             Ok(Command::SetStyleMode(style_mode))
         }
 
+        Some("setStyleFormat") => {
+            let args: SetStyleFormatArgs = args_from_json_value(value)?;
+            let style_format = args.format.parse::<StyleFormat>()
+                .map_err(|e| format!("invalid style format: {}", e))?;
+            Ok(Command::SetStyleFormat(style_format))
+        }
+
+        Some("setTermios") => {
+            let args: SetTermiosArgs = args_from_json_value(value)?;
+            Ok(Command::SetTermios(args.settings.parse()?))
+        }
+
+        Some("closeStdin") => Ok(Command::CloseStdin),
+
+        Some("flush") => Ok(Command::Flush(None)),
+
+        // Every exit is fenced now, so a "fence" left over from when that was optional is ignored
         Some("exit") => Ok(Command::Exit),
 
         other => Err(format!("invalid command type: {other:?}")),
@@ -179,170 +483,27 @@ where
     serde_json::from_value(value).map_err(|e| e.to_string())
 }
 
-fn standard_key<S: ToString>(seq: S) -> InputSeq {
-    InputSeq::Standard(seq.to_string())
-}
-
-fn cursor_key<S: ToString>(seq1: S, seq2: S) -> InputSeq {
-    InputSeq::Cursor(seq1.to_string(), seq2.to_string())
-}
-
-fn parse_key(key: String) -> InputSeq {
-    let seq = match key.as_str() {
-        "C-@" | "C-Space" | "^@" => "\x00",
-        "C-[" | "Escape" | "^[" => "\x1b",
-        "C-\\" | "^\\" => "\x1c",
-        "C-]" | "^]" => "\x1d",
-        "C-^" | "C-/" => "\x1e",
-        "C--" | "C-_" => "\x1f",
-        "Tab" => "\x09",   // same as C-i
-        "Enter" => "\x0d", // same as C-m
-        "Backspace" => "\x7f", // DEL character
-        "Space" => " ",
-        "Left" => return cursor_key("\x1b[D", "\x1bOD"),
-        "Right" => return cursor_key("\x1b[C", "\x1bOC"),
-        "Up" => return cursor_key("\x1b[A", "\x1bOA"),
-        "Down" => return cursor_key("\x1b[B", "\x1bOB"),
-        "C-Left" => "\x1b[1;5D",
-        "C-Right" => "\x1b[1;5C",
-        "S-Left" => "\x1b[1;2D",
-        "S-Right" => "\x1b[1;2C",
-        "C-Up" => "\x1b[1;5A",
-        "C-Down" => "\x1b[1;5B",
-        "S-Up" => "\x1b[1;2A",
-        "S-Down" => "\x1b[1;2B",
-        "A-Left" => "\x1b[1;3D",
-        "A-Right" => "\x1b[1;3C",
-        "A-Up" => "\x1b[1;3A",
-        "A-Down" => "\x1b[1;3B",
-        "C-S-Left" | "S-C-Left" => "\x1b[1;6D",
-        "C-S-Right" | "S-C-Right" => "\x1b[1;6C",
-        "C-S-Up" | "S-C-Up" => "\x1b[1;6A",
-        "C-S-Down" | "S-C-Down" => "\x1b[1;6B",
-        "C-A-Left" | "A-C-Left" => "\x1b[1;7D",
-        "C-A-Right" | "A-C-Right" => "\x1b[1;7C",
-        "C-A-Up" | "A-C-Up" => "\x1b[1;7A",
-        "C-A-Down" | "A-C-Down" => "\x1b[1;7B",
-        "A-S-Left" | "S-A-Left" => "\x1b[1;4D",
-        "A-S-Right" | "S-A-Right" => "\x1b[1;4C",
-        "A-S-Up" | "S-A-Up" => "\x1b[1;4A",
-        "A-S-Down" | "S-A-Down" => "\x1b[1;4B",
-        "C-A-S-Left" | "C-S-A-Left" | "A-C-S-Left" | "S-C-A-Left" | "A-S-C-Left" | "S-A-C-Left" => {
-            "\x1b[1;8D"
-        }
-        "C-A-S-Right" | "C-S-A-Right" | "A-C-S-Right" | "S-C-A-Right" | "A-S-C-Right"
-        | "S-A-C-Right" => "\x1b[1;8C",
-        "C-A-S-Up" | "C-S-A-Up" | "A-C-S-Up" | "S-C-A-Up" | "A-S-C-Up" | "S-A-C-Up" => "\x1b[1;8A",
-        "C-A-S-Down" | "C-S-A-Down" | "A-C-S-Down" | "S-C-A-Down" | "A-S-C-Down" | "S-A-C-Down" => {
-            "\x1b[1;8B"
-        }
-        "F1" => "\x1bOP",
-        "F2" => "\x1bOQ",
-        "F3" => "\x1bOR",
-        "F4" => "\x1bOS",
-        "F5" => "\x1b[15~",
-        "F6" => "\x1b[17~",
-        "F7" => "\x1b[18~",
-        "F8" => "\x1b[19~",
-        "F9" => "\x1b[20~",
-        "F10" => "\x1b[21~",
-        "F11" => "\x1b[23~",
-        "F12" => "\x1b[24~",
-        "C-F1" => "\x1b[1;5P",
-        "C-F2" => "\x1b[1;5Q",
-        "C-F3" => "\x1b[1;5R",
-        "C-F4" => "\x1b[1;5S",
-        "C-F5" => "\x1b[15;5~",
-        "C-F6" => "\x1b[17;5~",
-        "C-F7" => "\x1b[18;5~",
-        "C-F8" => "\x1b[19;5~",
-        "C-F9" => "\x1b[20;5~",
-        "C-F10" => "\x1b[21;5~",
-        "C-F11" => "\x1b[23;5~",
-        "C-F12" => "\x1b[24;5~",
-        "S-F1" => "\x1b[1;2P",
-        "S-F2" => "\x1b[1;2Q",
-        "S-F3" => "\x1b[1;2R",
-        "S-F4" => "\x1b[1;2S",
-        "S-F5" => "\x1b[15;2~",
-        "S-F6" => "\x1b[17;2~",
-        "S-F7" => "\x1b[18;2~",
-        "S-F8" => "\x1b[19;2~",
-        "S-F9" => "\x1b[20;2~",
-        "S-F10" => "\x1b[21;2~",
-        "S-F11" => "\x1b[23;2~",
-        "S-F12" => "\x1b[24;2~",
-        "A-F1" => "\x1b[1;3P",
-        "A-F2" => "\x1b[1;3Q",
-        "A-F3" => "\x1b[1;3R",
-        "A-F4" => "\x1b[1;3S",
-        "A-F5" => "\x1b[15;3~",
-        "A-F6" => "\x1b[17;3~",
-        "A-F7" => "\x1b[18;3~",
-        "A-F8" => "\x1b[19;3~",
-        "A-F9" => "\x1b[20;3~",
-        "A-F10" => "\x1b[21;3~",
-        "A-F11" => "\x1b[23;3~",
-        "A-F12" => "\x1b[24;3~",
-        "Home" => return cursor_key("\x1b[H", "\x1bOH"),
-        "C-Home" => "\x1b[1;5H",
-        "S-Home" => "\x1b[1;2H",
-        "A-Home" => "\x1b[1;3H",
-        "End" => return cursor_key("\x1b[F", "\x1bOF"),
-        "C-End" => "\x1b[1;5F",
-        "S-End" => "\x1b[1;2F",
-        "A-End" => "\x1b[1;3F",
-        "PageUp" => "\x1b[5~",
-        "C-PageUp" => "\x1b[5;5~",
-        "S-PageUp" => "\x1b[5;2~",
-        "A-PageUp" => "\x1b[5;3~",
-        "PageDown" => "\x1b[6~",
-        "C-PageDown" => "\x1b[6;5~",
-        "S-PageDown" => "\x1b[6;2~",
-        "A-PageDown" => "\x1b[6;3~",
-
-        k => {
-            let chars: Vec<char> = k.chars().collect();
-
-            match chars.as_slice() {
-                ['C', '-', k @ 'a'..='z'] => {
-                    return standard_key((*k as u8 - 0x60) as char);
-                }
-
-                ['C', '-', k @ 'A'..='Z'] => {
-                    return standard_key((*k as u8 - 0x40) as char);
-                }
-
-                ['^', k @ 'a'..='z'] => {
-                    return standard_key((*k as u8 - 0x60) as char);
-                }
-
-                ['^', k @ 'A'..='Z'] => {
-                    return standard_key((*k as u8 - 0x40) as char);
-                }
-
-                ['A', '-', k] => {
-                    return standard_key(format!("\x1b{}", k));
-                }
-
-                _ => &key,
-            }
-        }
-    };
-
-    standard_key(seq)
-}
-
 #[cfg(test)]
 mod test {
-    use super::{cursor_key, parse_line, standard_key, Command};
-    use crate::cli::StyleMode;
+    use super::{Command, ReplyOptions, Stdout, STDOUT_BACKLOG};
+    use crate::config::HtmlTheme;
+    use crate::config::{StyleFormat, StyleMode};
     use crate::command::InputSeq;
+    use crate::keys::{cursor_key, standard_key};
+    use crate::termios;
+    use std::collections::HashMap;
+    use std::io::{self, Write};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn parse_line(line: &str) -> Result<Command, String> {
+        super::parse_line(line, &HashMap::new()).map(|(command, _options)| command)
+    }
 
     #[test]
     fn parse_input() {
         let command = parse_line(r#"{ "type": "input", "payload": "hello" }"#).unwrap();
-        assert!(matches!(command, Command::Input(input) if input == vec![standard_key("hello")]));
+        assert!(matches!(command, Command::Input(input, None) if input == vec![standard_key("hello")]));
     }
 
     #[test]
@@ -465,6 +626,15 @@ mod test {
             ["C-PageDown", "\x1b[6;5~"],
             ["S-PageDown", "\x1b[6;2~"],
             ["A-PageDown", "\x1b[6;3~"],
+            ["Insert", "\x1b[2~"],
+            ["C-Insert", "\x1b[2;5~"],
+            ["S-Insert", "\x1b[2;2~"],
+            ["A-Insert", "\x1b[2;3~"],
+            ["Delete", "\x1b[3~"],
+            ["C-Delete", "\x1b[3;5~"],
+            ["S-Delete", "\x1b[3;2~"],
+            ["A-Delete", "\x1b[3;3~"],
+            ["S-Tab", "\x1b[Z"],
         ];
 
         for [key, chars] in examples {
@@ -473,7 +643,7 @@ mod test {
             ))
             .unwrap();
 
-            assert!(matches!(command, Command::Input(input) if input == vec![standard_key(chars)]));
+            assert!(matches!(command, Command::Input(input, None) if input == vec![standard_key(chars)]));
         }
 
         let command = parse_line(
@@ -482,7 +652,7 @@ mod test {
         .unwrap();
 
         assert!(
-            matches!(command, Command::Input(input) if input == vec![standard_key("hello"), standard_key("\x0d"), standard_key("\x03"), standard_key("\x1b^"), cursor_key("\x1b[D", "\x1bOD")])
+            matches!(command, Command::Input(input, None) if input == vec![standard_key("hello"), standard_key("\x0d"), standard_key("\x03"), standard_key("\x1b^"), cursor_key("\x1b[D", "\x1bOD")])
         );
     }
 
@@ -503,7 +673,7 @@ mod test {
             ))
             .unwrap();
 
-            if let Command::Input(seqs) = command {
+            if let Command::Input(seqs, _) = command {
                 if let InputSeq::Cursor(seq3, seq4) = &seqs[0] {
                     if seq1 == seq3 && seq2 == seq4 {
                         continue;
@@ -536,7 +706,15 @@ mod test {
     #[test]
     fn parse_take_snapshot() {
         let command = parse_line(r#"{ "type": "takeSnapshot" }"#).unwrap();
-        assert!(matches!(command, Command::Snapshot));
+        assert!(matches!(command, Command::Snapshot(None)));
+    }
+
+    #[test]
+    fn parse_send_keys_custom() {
+        let keys = HashMap::from([("Save".to_string(), "\x13".to_string())]);
+        let (command, _options) = super::parse_line(r#"{ "type": "sendKeys", "keys": ["Save", "Enter"] }"#, &keys).unwrap();
+
+        assert!(matches!(command, Command::Input(input, None) if input == vec![standard_key("\x13"), standard_key("\x0d")]));
     }
 
     #[test]
@@ -554,14 +732,186 @@ mod test {
         parse_line(r#"{ "type": "setStyleMode" }"#).expect_err("should fail");
     }
 
+    #[test]
+    fn parse_set_style_format() {
+        let command = parse_line(r#"{ "type": "setStyleFormat", "format": "rle" }"#).unwrap();
+        assert!(matches!(command, Command::SetStyleFormat(StyleFormat::Rle)));
+
+        let command = parse_line(r#"{ "type": "setStyleFormat", "format": "cells" }"#).unwrap();
+        assert!(matches!(command, Command::SetStyleFormat(StyleFormat::Cells)));
+
+        parse_line(r#"{ "type": "setStyleFormat", "format": "zip" }"#).expect_err("should fail");
+    }
+
+    #[test]
+    fn parse_set_termios() {
+        let command = parse_line(r#"{ "type": "setTermios", "settings": "raw,-echo,ixon" }"#).unwrap();
+        assert!(matches!(
+            command,
+            Command::SetTermios(termios::Settings {
+                mode: Some(termios::Mode::Raw),
+                echo: Some(false),
+                isig: None,
+                ixon: Some(true),
+                ixoff: None,
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_set_termios_invalid() {
+        parse_line(r#"{ "type": "setTermios", "settings": "-raw" }"#).expect_err("should fail");
+        parse_line(r#"{ "type": "setTermios", "settings": "bogus" }"#).expect_err("should fail");
+        parse_line(r#"{ "type": "setTermios" }"#).expect_err("should fail");
+    }
+
+    #[test]
+    fn parse_close_stdin() {
+        let command = parse_line(r#"{ "type": "closeStdin" }"#).unwrap();
+        assert!(matches!(command, Command::CloseStdin));
+    }
+
+    #[test]
+    fn parse_pane() {
+        let command = parse_line(r#"{ "type": "takeSnapshot", "pane": 0 }"#).unwrap();
+        assert!(matches!(command, Command::Snapshot(None)));
+
+        let command = parse_line(r#"{ "type": "resize", "cols": 40, "rows": 10, "pane": 2 }"#).unwrap();
+        assert!(matches!(command, Command::Pane(2, c) if matches!(*c, Command::Resize(40, 10))));
+    }
+
+    #[test]
+    fn parse_pane_invalid() {
+        parse_line(r#"{ "type": "takeSnapshot", "pane": -1 }"#).expect_err("should fail");
+        parse_line(r#"{ "type": "takeSnapshot", "pane": "left" }"#).expect_err("should fail");
+        parse_line(r#"{ "type": "exit", "pane": 1 }"#).expect_err("should fail");
+    }
+
+    #[test]
+    fn parse_snapshot_html_theme() {
+        let parse = |line| super::parse_line(line, &HashMap::new());
+
+        assert!(matches!(
+            parse(r#"{ "type": "takeSnapshot" }"#),
+            Ok((Command::Snapshot(None), ReplyOptions { html_theme: None, .. }))
+        ));
+        assert!(matches!(
+            parse(r#"{ "type": "takeSnapshot", "htmlTheme": "light" }"#),
+            Ok((Command::Snapshot(None), ReplyOptions { html_theme: Some(HtmlTheme::Light), .. }))
+        ));
+        assert!(matches!(
+            parse(r#"{ "type": "takeSnapshot", "htmlTheme": "dark", "pane": 1 }"#),
+            Ok((Command::Pane(1, _), ReplyOptions { html_theme: Some(HtmlTheme::Dark), .. }))
+        ));
+
+        parse(r#"{ "type": "takeSnapshot", "htmlTheme": "solarized" }"#).expect_err("should fail");
+        parse(r#"{ "type": "takeSnapshot", "htmlTheme": 1 }"#).expect_err("should fail");
+    }
+
+    #[test]
+    fn parse_input_ack() {
+        let parse = |line| super::parse_line(line, &HashMap::new()).map(|(_command, options)| options.ack);
+
+        assert_eq!(parse(r#"{ "type": "sendKeys", "keys": ["a"] }"#), Ok(false));
+        assert_eq!(parse(r#"{ "type": "sendKeys", "keys": ["a"], "ack": true }"#), Ok(true));
+        assert_eq!(parse(r#"{ "type": "input", "payload": "a", "ack": true, "pane": 1 }"#), Ok(true));
+        assert_eq!(parse(r#"{ "type": "takeSnapshot", "ack": true }"#), Ok(false));
+
+        parse(r#"{ "type": "input", "payload": "a", "ack": "yes" }"#).expect_err("should fail");
+    }
+
     #[test]
     fn parse_exit() {
         let command = parse_line(r#"{ "type": "exit" }"#).unwrap();
         assert!(matches!(command, Command::Exit));
+
+        let command = parse_line(r#"{ "type": "exit", "fence": true }"#).unwrap();
+        assert!(matches!(command, Command::Exit));
+    }
+
+    #[test]
+    fn parse_flush() {
+        let command = parse_line(r#"{ "type": "flush" }"#).unwrap();
+        assert!(matches!(command, Command::Flush(None)));
+
+        parse_line(r#"{ "type": "flush", "pane": 1 }"#).expect_err("should fail");
     }
 
     #[test]
     fn parse_invalid_json() {
         parse_line("{").expect_err("should fail");
     }
+
+    #[test]
+    fn protocol_error() {
+        let line = r#"{ "type": "bogus", "id": 7 }"#;
+        let error = parse_line(line).expect_err("should fail");
+        let json: serde_json::Value = serde_json::from_str(&super::protocol_error(line, error)).unwrap();
+
+        assert_eq!(json["type"], "error");
+        assert_eq!(json["data"]["message"], r#"invalid command type: Some("bogus")"#);
+        assert_eq!(json["data"]["line"], line);
+        assert_eq!(json["data"]["id"], 7);
+
+        let json: serde_json::Value = serde_json::from_str(&super::protocol_error("{", "bad".to_string())).unwrap();
+        assert_eq!(json["data"]["line"], "{");
+        assert!(json["data"].get("id").is_none());
+    }
+
+    /// Stands in for a stdout whose reader has gone away, or stopped reading until `release`
+    /// sends (or is dropped).
+    enum Reader {
+        Gone,
+        Stuck(mpsc::Receiver<()>),
+    }
+
+    impl Write for Reader {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self {
+                Reader::Gone => Err(io::ErrorKind::BrokenPipe.into()),
+                Reader::Stuck(release) => {
+                    let _ = release.recv();
+                    Ok(buf.len())
+                }
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn stdout_gone() {
+        let mut stdout = Stdout::to(Reader::Gone, Duration::from_secs(5));
+
+        // The first line is queued before the writer finds out, the next one isn't
+        stdout.print("first".to_string()).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stdout.print("second".to_string()).await;
+
+        let failure = stdout.take_failure().expect("stdout should have failed");
+        assert_eq!(failure.kind(), io::ErrorKind::BrokenPipe);
+        assert!(stdout.take_failure().is_none());
+
+        // Nothing more is printed, or reported
+        stdout.print("third".to_string()).await;
+        assert!(stdout.take_failure().is_none());
+    }
+
+    #[tokio::test]
+    async fn stdout_not_read() {
+        let (_release, stuck) = mpsc::channel();
+        let mut stdout = Stdout::to(Reader::Stuck(stuck), Duration::from_millis(50));
+
+        // One line for the writer to get stuck on, and a backlog's worth behind it
+        for i in 0..=STDOUT_BACKLOG {
+            stdout.print(i.to_string()).await;
+            assert!(stdout.take_failure().is_none(), "gave up after {i} lines");
+        }
+
+        stdout.print("one too many".to_string()).await;
+        let failure = stdout.take_failure().expect("stdout should have timed out");
+        assert_eq!(failure.kind(), io::ErrorKind::TimedOut);
+    }
 }
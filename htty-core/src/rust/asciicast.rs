@@ -0,0 +1,281 @@
+//! Recordings in the [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) format.
+//!
+//! A recording is a header line followed by one `[time, code, data]` line per event, where
+//! `time` is in seconds since the start of the session.
+
+use crate::session::{Event, Session};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Lines, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Builds a recording of a session from the events it's given, writing each line out to `W`
+/// as it goes: to memory by default, or to a file with `SessionRecorder::create`.
+///
+/// The recording's size is taken from the first init event, and events before it are
+/// ignored. A later init (after a subscriber fell behind) redraws the screen the dropped
+/// events led to.
+#[derive(Debug, Clone, Default)]
+pub struct SessionRecorder<W = Vec<u8>> {
+    header: Option<(usize, usize, u64)>,
+    out: W,
+}
+
+impl SessionRecorder {
+    #[allow(dead_code)] // ht records to a file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `session` from now on, through `Session::on_event`.
+    #[allow(dead_code)] // ht records through a subscription instead
+    pub fn attach(session: &mut Session) -> Arc<Mutex<Self>> {
+        let recorder = Arc::new(Mutex::new(Self::new()));
+        let shared = recorder.clone();
+
+        session.on_event(move |event| {
+            if let Ok(mut recorder) = shared.lock() {
+                // Writing to memory can't fail
+                let _ = recorder.record(event);
+            }
+        });
+
+        recorder
+    }
+
+    /// The recording as asciicast v2: a header line, then a line per event.
+    #[allow(dead_code)] // ht records to a file
+    pub fn to_json(&self) -> Result<String> {
+        if self.header.is_none() {
+            bail!("nothing recorded yet, the session hasn't sent its init event");
+        }
+
+        Ok(String::from_utf8(self.out.clone())?)
+    }
+}
+
+impl SessionRecorder<PartialFile> {
+    /// Record to `path`, by way of a `.partial` file beside it that `finish` renames into place,
+    /// so that a recording cut short (by ht being killed) never turns up under the final name.
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        let file = File::create(&partial).with_context(|| format!("cannot create {}", partial.display()))?;
+
+        Ok(Self {
+            header: None,
+            out: PartialFile {
+                file,
+                partial,
+                path: path.to_path_buf(),
+            },
+        })
+    }
+
+    /// Move the recording into place, or remove it if the session never got as far as its init
+    /// event.
+    pub fn finish(self) -> Result<()> {
+        let PartialFile { partial, path, .. } = self.out;
+
+        let finished = match self.header {
+            Some(_) => fs::rename(&partial, &path).with_context(|| format!("cannot write {}", path.display())),
+            None => Err(anyhow::anyhow!("nothing recorded, the session never sent its init event")),
+        };
+
+        if finished.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+
+        finished
+    }
+}
+
+impl<W: Write> SessionRecorder<W> {
+    pub fn record(&mut self, event: &Event) -> io::Result<()> {
+        match (event, &self.header) {
+            (Event::Init(time, cols, rows, _pid, seq, _text, _), header) => {
+                if header.is_none() {
+                    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                    self.header = Some((*cols, *rows, timestamp));
+
+                    let header = json!({
+                        "version": 2,
+                        "width": cols,
+                        "height": rows,
+                        "timestamp": timestamp,
+                        "env": { "TERM": "xterm-256color" },
+                    });
+
+                    self.out.write_all(format!("{header}\n").as_bytes())?;
+                }
+
+                if !seq.is_empty() {
+                    self.write_entry(*time, "o", seq)?;
+                }
+            }
+
+            (Event::Output(time, seq), Some(_)) => self.write_entry(*time, "o", seq)?,
+
+            (Event::Resize(time, cols, rows), Some(_)) => self.write_entry(*time, "r", &format!("{cols}x{rows}"))?,
+
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn write_entry(&mut self, time: f64, code: &str, data: &str) -> io::Result<()> {
+        // A whole line per write, so the file never ends partway through one if ht is killed
+        self.out.write_all(format!("{}\n", json!([time, code, data])).as_bytes())
+    }
+}
+
+/// The file a recording is written to until it's finished, see `SessionRecorder::create`.
+#[derive(Debug)]
+pub struct PartialFile {
+    file: File,
+    partial: PathBuf,
+    path: PathBuf,
+}
+
+impl Write for PartialFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// One `[time, code, data]` line of a recording, e.g. `[1.5, "o", "hello"]`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Entry(pub f64, pub String, pub String);
+
+pub struct Reader {
+    lines: Lines<BufReader<File>>,
+    line: usize,
+}
+
+impl Reader {
+    /// Open the recording at `path` and read its header.
+    pub fn open(path: &Path) -> Result<(Header, Self)> {
+        let file = File::open(path).with_context(|| format!("cannot open {}", path.display()))?;
+        let mut reader = Self {
+            lines: BufReader::new(file).lines(),
+            line: 0,
+        };
+
+        let header: Header = match reader.next_line()? {
+            Some(line) => serde_json::from_str(&line).context("invalid asciicast header")?,
+            None => bail!("{} is empty", path.display()),
+        };
+
+        if header.version != 2 {
+            bail!("unsupported asciicast version {}, expected 2", header.version);
+        }
+
+        Ok((header, reader))
+    }
+
+    fn next_line(&mut self) -> Result<Option<String>> {
+        loop {
+            self.line += 1;
+
+            match self.lines.next().transpose()? {
+                Some(line) if line.trim().is_empty() => continue,
+                line => return Ok(line),
+            }
+        }
+    }
+}
+
+impl Iterator for Reader {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.next_line().transpose()?;
+        let number = self.line;
+
+        Some(line.and_then(|line| {
+            serde_json::from_str(&line).with_context(|| format!("invalid asciicast event on line {number}"))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SessionRecorder;
+    use crate::session::Session;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn records_session() {
+        let mut session = Session::new(10, 2);
+        let recorder = SessionRecorder::attach(&mut session);
+        session.output("hello".to_string());
+        session.resize(20, 4);
+
+        let json = recorder.lock().unwrap().to_json().unwrap();
+        let lines: Vec<serde_json::Value> = json.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+
+        assert_eq!((lines[0]["width"].as_u64(), lines[0]["height"].as_u64()), (Some(10), Some(2)));
+        // After the header, the init event's redraw of the (blank) screen
+        let [.., output, resize] = &lines[..] else { panic!("{json}") };
+        assert_eq!((&output[1], &output[2]), (&"o".into(), &"hello".into()));
+        assert_eq!((&resize[1], &resize[2]), (&"r".into(), &"20x4".into()));
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn streams_recording_to_file() {
+        let mut session = Session::new(10, 2);
+        let dir = std::env::temp_dir().join(format!("htty-asciicast-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.cast");
+        let recorder = Arc::new(Mutex::new(Some(SessionRecorder::create(&path).unwrap())));
+        let shared = recorder.clone();
+
+        session.on_event(move |event| {
+            shared.lock().unwrap().as_mut().unwrap().record(event).unwrap();
+        });
+
+        session.output("hello".to_string());
+        session.flush_output();
+
+        // What's been recorded so far is already in the file, though not yet under its name
+        let partial = std::fs::read_to_string(dir.join("session.cast.partial")).unwrap();
+        assert_eq!(partial.lines().count(), 3, "{partial}");
+        assert!(partial.ends_with(",\"o\",\"hello\"]\n"), "{partial}");
+        assert!(!path.exists());
+
+        session.resize(20, 4);
+        recorder.lock().unwrap().take().unwrap().finish().unwrap();
+        let finished = std::fs::read_to_string(&path).unwrap();
+        assert!(finished.starts_with(&partial) && finished.ends_with(",\"r\",\"20x4\"]\n"), "{finished}");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Nothing is left behind by a recording that never started
+        SessionRecorder::create(&dir.join("empty.cast")).unwrap().finish().unwrap_err();
+        assert!(SessionRecorder::create(&dir.join("missing/session.cast")).is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nothing_recorded() {
+        assert!(SessionRecorder::new().to_json().is_err());
+    }
+}
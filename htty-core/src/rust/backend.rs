@@ -0,0 +1,267 @@
+//! The terminal emulator a session feeds output to and reads screens back from.
+//!
+//! Sessions use avt unless given another backend with `Session::with_backend`, which can
+//! be a different emulator, or a test double recording what it was fed.
+
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Color {
+    /// One of the 256 palette colors: the 16 basic ones, the 6x6x6 cube, then 24 greys.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// Colors and attributes a cell is drawn with. The default is the terminal's default
+/// rendition: default colors, no attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Style {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub bold: bool,
+    pub faint: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub blink: bool,
+    pub inverse: bool,
+}
+
+/// One character position on the screen.
+///
+/// A wide character takes up two cells: one with `width` 2 holding it, followed by one
+/// with `width` 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub width: usize,
+    pub style: Style,
+}
+
+/// What a terminal is showing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Screen {
+    pub cols: usize,
+    pub rows: usize,
+    /// The rows, top to bottom.
+    pub lines: Vec<Vec<Cell>>,
+    pub cursor: Cursor,
+}
+
+impl Screen {
+    /// The characters on the screen, one line per row.
+    pub fn text(&self) -> String {
+        self.lines.iter().map(|line| text(line)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// The characters in a row of cells.
+pub fn text(line: &[Cell]) -> String {
+    line.iter().filter(|cell| cell.width > 0).map(|cell| cell.ch).collect()
+}
+
+/// Where the cursor is, and whether it's shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cursor {
+    pub col: usize,
+    pub row: usize,
+    pub visible: bool,
+}
+
+pub trait TerminalBackend: Send {
+    /// Process output from the program running in the terminal.
+    fn feed_str(&mut self, data: &str);
+
+    fn resize(&mut self, cols: usize, rows: usize);
+
+    /// `(cols, rows)`
+    fn size(&self) -> (usize, usize);
+
+    /// The visible rows, top to bottom.
+    fn view(&self) -> Vec<Vec<Cell>>;
+
+    /// Escape sequences which, fed to a fresh terminal of the same size, reproduce this one.
+    fn dump(&self) -> String;
+
+    fn cursor(&self) -> Cursor;
+
+    /// Whether the program asked for cursor keys in application mode (DECCKM).
+    fn cursor_key_app_mode(&self) -> bool;
+
+    fn screen(&self) -> Screen {
+        let (cols, rows) = self.size();
+
+        Screen {
+            cols,
+            rows,
+            lines: self.view(),
+            cursor: self.cursor(),
+        }
+    }
+}
+
+/// Builds a `cols`x`rows` terminal, for the main screen, each pane, and snapshots.
+pub type Factory = Arc<dyn Fn(usize, usize) -> Box<dyn TerminalBackend> + Send + Sync>;
+
+pub fn avt() -> Factory {
+    Arc::new(build_avt)
+}
+
+pub fn build_avt(cols: usize, rows: usize) -> Box<dyn TerminalBackend> {
+    Box::new(avt::Vt::builder().size(cols, rows).build())
+}
+
+impl TerminalBackend for avt::Vt {
+    fn feed_str(&mut self, data: &str) {
+        avt::Vt::feed_str(self, data);
+    }
+
+    fn resize(&mut self, cols: usize, rows: usize) {
+        avt::Vt::resize(self, cols, rows);
+    }
+
+    fn size(&self) -> (usize, usize) {
+        avt::Vt::size(self)
+    }
+
+    fn view(&self) -> Vec<Vec<Cell>> {
+        avt::Vt::view(self)
+            .iter()
+            .map(|line| line.cells().iter().map(Cell::from).collect())
+            .collect()
+    }
+
+    fn dump(&self) -> String {
+        avt::Vt::dump(self)
+    }
+
+    fn cursor(&self) -> Cursor {
+        let cursor = avt::Vt::cursor(self);
+
+        Cursor {
+            col: cursor.col,
+            row: cursor.row,
+            visible: cursor.visible,
+        }
+    }
+
+    fn cursor_key_app_mode(&self) -> bool {
+        avt::Vt::cursor_key_app_mode(self)
+    }
+}
+
+impl From<&avt::Cell> for Cell {
+    fn from(cell: &avt::Cell) -> Self {
+        Self {
+            ch: cell.char(),
+            width: cell.width(),
+            style: Style::from(cell.pen()),
+        }
+    }
+}
+
+impl From<&avt::Pen> for Style {
+    fn from(pen: &avt::Pen) -> Self {
+        Self {
+            foreground: pen.foreground().map(Color::from),
+            background: pen.background().map(Color::from),
+            bold: pen.is_bold(),
+            faint: pen.is_faint(),
+            italic: pen.is_italic(),
+            underline: pen.is_underline(),
+            strikethrough: pen.is_strikethrough(),
+            blink: pen.is_blink(),
+            inverse: pen.is_inverse(),
+        }
+    }
+}
+
+impl From<avt::Color> for Color {
+    fn from(color: avt::Color) -> Self {
+        match color {
+            avt::Color::Indexed(i) => Color::Indexed(i),
+            avt::Color::RGB(rgb) => Color::Rgb(rgb.r, rgb.g, rgb.b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cell, Color, Cursor, Style, TerminalBackend};
+    use crate::session::Session;
+    use std::sync::{Arc, Mutex};
+
+    /// avt, keeping a log of everything it's fed.
+    struct Recorder {
+        vt: avt::Vt,
+        fed: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TerminalBackend for Recorder {
+        fn feed_str(&mut self, data: &str) {
+            self.fed.lock().unwrap().push(data.to_string());
+            self.vt.feed_str(data);
+        }
+
+        fn resize(&mut self, cols: usize, rows: usize) {
+            TerminalBackend::resize(&mut self.vt, cols, rows);
+        }
+
+        fn size(&self) -> (usize, usize) {
+            self.vt.size()
+        }
+
+        fn view(&self) -> Vec<Vec<Cell>> {
+            TerminalBackend::view(&self.vt)
+        }
+
+        fn dump(&self) -> String {
+            self.vt.dump()
+        }
+
+        fn cursor(&self) -> Cursor {
+            TerminalBackend::cursor(&self.vt)
+        }
+
+        fn cursor_key_app_mode(&self) -> bool {
+            self.vt.cursor_key_app_mode()
+        }
+    }
+
+    #[test]
+    fn session_uses_given_backend() {
+        let fed = Arc::new(Mutex::new(Vec::new()));
+        let log = fed.clone();
+
+        let mut session = Session::new(10, 2).with_backend(move |cols, rows| {
+            Box::new(Recorder {
+                vt: avt::Vt::builder().size(cols, rows).build(),
+                fed: log.clone(),
+            })
+        });
+
+        session.output("hello".to_string());
+        session.output("\x1b[?1h".to_string());
+
+        assert_eq!(*fed.lock().unwrap(), ["hello", "\x1b[?1h"]);
+        assert!(session.cursor_key_app_mode());
+    }
+
+    #[test]
+    fn avt_screen() {
+        let mut vt = avt::Vt::builder().size(4, 2).build();
+        TerminalBackend::feed_str(&mut vt, "\x1b[1;31ma\x1b[0m\u{4e16}");
+        let screen = TerminalBackend::screen(&vt);
+
+        let red = Style {
+            foreground: Some(Color::Indexed(1)),
+            bold: true,
+            ..Style::default()
+        };
+
+        assert_eq!(screen.lines[0][0], Cell { ch: 'a', width: 1, style: red });
+        assert_eq!((screen.lines[0][1].ch, screen.lines[0][1].width, screen.lines[0][2].width), ('\u{4e16}', 2, 0));
+        assert_eq!(screen.text(), "a\u{4e16} \n    ");
+        assert_eq!((screen.cursor.col, screen.cursor.row), (3, 0));
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// Tally of the output that went through the session, reported on exit by `--benchmark`.
+///
+/// The clock starts when the first chunk arrives from the PTY and stops once the last one
+/// has been fed to the virtual terminal, so startup and shutdown don't skew the rate.
+#[derive(Debug, Default)]
+pub struct Meter {
+    bytes: u64,
+    chunks: u64,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Meter {
+    /// Count a chunk of `bytes` which was received at `received` and has just been processed.
+    pub fn record(&mut self, bytes: usize, received: Instant) {
+        self.bytes += bytes as u64;
+        self.chunks += 1;
+        self.first.get_or_insert(received);
+        self.last = Some(Instant::now());
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => last - first,
+            _ => Duration::ZERO,
+        }
+    }
+
+    pub fn megabytes_per_second(&self) -> f64 {
+        let seconds = self.elapsed().as_secs_f64();
+
+        if seconds > 0.0 {
+            self.bytes as f64 / 1_000_000.0 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Display for Meter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "benchmark: {} bytes in {} chunks over {:.3}s ({:.1} MB/s)",
+            self.bytes,
+            self.chunks,
+            self.elapsed().as_secs_f64(),
+            self.megabytes_per_second()
+        )
+    }
+}
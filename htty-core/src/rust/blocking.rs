@@ -0,0 +1,173 @@
+//! A synchronous version of [`crate::embed`], for programs (test harnesses, mostly) that
+//! don't have an async runtime of their own.
+//!
+//! ```no_run
+//! # fn example() -> anyhow::Result<()> {
+//! use htty_core::{blocking, Htty};
+//! use std::time::Duration;
+//!
+//! let htty = blocking::spawn(Htty::builder().size(80, 24).command(["vim"]))?;
+//! htty.send_keys(["ihello", "Escape"])?;
+//! htty.expect("hello", Duration::from_secs(5))?;
+//! htty.send_keys([":q!", "Enter"])?;
+//! let status = htty.wait_exit();
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::embed::{self, HttyBuilder, Snapshot};
+use crate::error::Result;
+use crate::pty::ExitStatus;
+use crate::session::Event;
+use futures_util::StreamExt;
+use regex::Regex;
+use std::sync::mpsc;
+use std::time::Duration;
+use tokio::runtime::{self, Runtime};
+
+/// Start the command configured by `builder`, on a runtime of its own.
+pub fn spawn(builder: HttyBuilder) -> Result<Handle> {
+    // A worker thread keeps the terminal up to date between calls
+    let runtime = runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .map_err(|e| builder.spawn_failed(e.into()))?;
+
+    let inner = {
+        let _guard = runtime.enter();
+        builder.spawn()?
+    };
+
+    Ok(Handle {
+        runtime,
+        inner: Some(inner),
+    })
+}
+
+/// A running command and its terminal. See [`embed::Handle`], whose methods these block on.
+pub struct Handle {
+    runtime: Runtime,
+    // Only taken by `close` and `drop`
+    inner: Option<embed::Handle>,
+}
+
+impl Handle {
+    pub fn send_keys<I, S>(&self, keys: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.runtime.block_on(self.inner().send_keys(keys))
+    }
+
+    pub fn expect(&self, text: &str, timeout: Duration) -> Result<()> {
+        self.runtime.block_on(self.inner().expect(text, timeout))
+    }
+
+    pub fn expect_re(&self, pattern: &Regex, timeout: Duration) -> Result<()> {
+        self.runtime.block_on(self.inner().expect_re(pattern, timeout))
+    }
+
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        self.runtime.block_on(self.inner().snapshot())
+    }
+
+    /// Subscribe to the session's events, starting with `Event::Init`.
+    pub fn events(&self) -> Result<Events> {
+        let mut events = self.runtime.block_on(self.inner().events())?;
+        let (tx, rx) = mpsc::channel();
+
+        // Passed on as they come, so none are dropped while the caller isn't looking
+        self.runtime.spawn(async move {
+            while let Some(event) = events.next().await {
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Events(rx))
+    }
+
+    pub fn wait_exit(&self) -> Option<ExitStatus> {
+        self.runtime.block_on(self.inner().wait_exit())
+    }
+
+    pub fn shutdown(&self) {
+        self.inner().shutdown();
+    }
+
+    pub fn close(mut self) -> Result<Option<ExitStatus>> {
+        let inner = self.inner.take().expect("handle is only closed once");
+        self.runtime.block_on(inner.close())
+    }
+
+    fn inner(&self) -> &embed::Handle {
+        self.inner.as_ref().expect("handle is open")
+    }
+}
+
+/// The events of a session, from [`Handle::events`]. Iterating blocks until the next event,
+/// and ends with the session.
+pub struct Events(mpsc::Receiver<Event>);
+
+impl Events {
+    /// The next event, waiting up to `timeout` for one. `None` if there was none by then, or
+    /// the session has ended.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<Event> {
+        self.0.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for Events {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        self.0.recv().ok()
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // End the session while the runtime is still there to hang up on the command
+        if let Some(inner) = self.inner.take() {
+            let _ = self.runtime.block_on(inner.close());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::spawn;
+    use crate::session::Event;
+    use crate::Htty;
+    use regex::Regex;
+    use std::time::Duration;
+
+    #[test]
+    fn expect() {
+        let htty = spawn(Htty::builder().size(20, 5).command(["cat"])).unwrap();
+        htty.send_keys(["hello", "Enter"]).unwrap();
+        htty.expect("hello", Duration::from_secs(5)).unwrap();
+        htty.expect_re(&Regex::new("(?m)^hello\\s*\\nhello").unwrap(), Duration::from_secs(5)).unwrap();
+
+        let error = htty.expect("goodbye", Duration::from_millis(100)).unwrap_err();
+        assert!(error.to_string().starts_with("expected the screen to contain \"goodbye\""));
+
+        htty.send_keys(["C-d"]).unwrap();
+        assert_eq!(htty.wait_exit().map(|s| s.code), Some(0));
+        assert!(htty.snapshot().unwrap().text.starts_with("hello"));
+    }
+
+    #[test]
+    fn events() {
+        let htty = spawn(Htty::builder().size(20, 5).command(["cat"])).unwrap();
+        let mut events = htty.events().unwrap();
+        assert!(matches!(events.next(), Some(Event::Init(..))));
+
+        htty.send_keys(["C-d"]).unwrap();
+        let mut events = std::iter::from_fn(|| events.next_timeout(Duration::from_secs(5)));
+        assert!(events.any(|event| matches!(event, Event::ExitCode(..))));
+    }
+}
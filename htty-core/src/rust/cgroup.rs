@@ -0,0 +1,293 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Resource limits applied to the child's cgroup. `None` leaves the inherited value in place.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub memory_max: Option<Bytes>,
+    pub cpu_max: Option<Cpus>,
+}
+
+/// A byte count, parsed from a number with an optional `K`, `M` or `G` suffix (powers of 1024).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bytes(pub u64);
+
+impl FromStr for Bytes {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (digits, multiplier) = match s.trim().to_ascii_uppercase() {
+            s if s.ends_with('K') => (s[..s.len() - 1].to_string(), 1 << 10),
+            s if s.ends_with('M') => (s[..s.len() - 1].to_string(), 1 << 20),
+            s if s.ends_with('G') => (s[..s.len() - 1].to_string(), 1 << 30),
+            s => (s, 1),
+        };
+
+        match digits.parse::<u64>().ok().filter(|n| *n > 0).and_then(|n| n.checked_mul(multiplier)) {
+            Some(bytes) => Ok(Bytes(bytes)),
+            None => bail!("invalid memory size: {s}. Expected e.g. 512M"),
+        }
+    }
+}
+
+/// CPU bandwidth as a (possibly fractional) number of CPUs, e.g. `0.5` or `2`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cpus(pub f64);
+
+impl FromStr for Cpus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().parse::<f64>() {
+            Ok(n) if n.is_finite() && n > 0.0 => Ok(Cpus(n)),
+            _ => bail!("invalid CPU limit: {s}. Expected a positive number of CPUs, e.g. 0.5"),
+        }
+    }
+}
+
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// A freshly created cgroup v2 directory which the child joins before exec.
+///
+/// Controllers can only be enabled for the children of a cgroup without processes of its
+/// own, so ht first moves itself out of the cgroup it started in, to a `supervisor` leaf of a
+/// new `htty-*` cgroup, and the child's `command` cgroup, with the limits, goes beside it.
+///
+/// Dropping it kills whatever is still running inside and removes the directory,
+/// so even deeply forking commands can't outlive the session.
+pub struct Cgroup {
+    path: PathBuf,
+    root: PathBuf,
+    origin: PathBuf,
+}
+
+impl Cgroup {
+    pub fn create(limits: Limits) -> Result<Self> {
+        let origin = current_cgroup()?;
+        remove_stale(&origin);
+
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        let root = origin.join(format!("htty-{}-{nanos:09}", std::process::id()));
+        fs::create_dir(&root).with_context(|| format!("cannot create cgroup {}", root.display()))?;
+
+        // From here on, dropping it removes whatever has been made so far
+        let cgroup = Self {
+            path: root.join("command"),
+            root,
+            origin,
+        };
+
+        let supervisor = cgroup.root.join("supervisor");
+        fs::create_dir(&supervisor).with_context(|| format!("cannot create cgroup {}", supervisor.display()))?;
+        join(&supervisor).with_context(|| format!("cannot move ht into cgroup {}", supervisor.display()))?;
+
+        for (controller, wanted) in [("memory", limits.memory_max.is_some()), ("cpu", limits.cpu_max.is_some())] {
+            if wanted {
+                enable_controller(&cgroup.origin, controller)?;
+                enable_controller(&cgroup.root, controller)?;
+            }
+        }
+
+        fs::create_dir(&cgroup.path)
+            .with_context(|| format!("cannot create cgroup {}", cgroup.path.display()))?;
+
+        if let Some(Bytes(bytes)) = limits.memory_max {
+            cgroup.write("memory.max", &bytes.to_string())?;
+        }
+
+        if let Some(Cpus(cpus)) = limits.cpu_max {
+            let quota = ((cpus * CPU_PERIOD_USEC as f64) as u64).max(1000);
+            cgroup.write("cpu.max", &format!("{quota} {CPU_PERIOD_USEC}"))?;
+        }
+
+        Ok(cgroup)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<()> {
+        let path = self.path.join(file);
+        fs::write(&path, value).with_context(|| format!("cannot write {value:?} to {}", path.display()))
+    }
+
+    fn is_populated(&self) -> bool {
+        fs::read_to_string(self.path.join("cgroup.events"))
+            .map(|events| events.lines().any(|l| l == "populated 1"))
+            .unwrap_or(false)
+    }
+
+    fn kill(&self) {
+        // cgroup.kill needs Linux 5.14; fall back to signalling every member ourselves.
+        if self.write("cgroup.kill", "1").is_ok() {
+            return;
+        }
+
+        if let Ok(procs) = fs::read_to_string(self.path.join("cgroup.procs")) {
+            for pid in procs.lines().filter_map(|l| l.parse().ok()) {
+                let _ = nix::sys::signal::kill(
+                    nix::unistd::Pid::from_raw(pid),
+                    nix::sys::signal::Signal::SIGKILL,
+                );
+            }
+        }
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        let deadline = Instant::now() + Duration::from_secs(1);
+
+        while self.is_populated() && Instant::now() < deadline {
+            self.kill();
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        match fs::remove_dir(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => warn!("cannot remove cgroup {}: {e}", self.path.display()),
+            _ => (),
+        }
+
+        // ht can't go back to a cgroup it has enabled controllers in, and then stays in its
+        // leaf until it exits; the next session to start removes what's left
+        if join(&self.origin).is_ok() {
+            remove_tree(&self.root);
+        }
+    }
+}
+
+/// Move the calling process into the cgroup at `path`. Meant to run in the forked child before exec.
+pub fn join(path: &Path) -> std::io::Result<()> {
+    fs::write(path.join("cgroup.procs"), "0")
+}
+
+/// Directory of the cgroup v2 hierarchy this process currently belongs to.
+fn current_cgroup() -> Result<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").context("cannot read /proc/self/mountinfo")?;
+
+    // Fields: id parent major:minor root mount-point options... - fstype source super-options
+    let mount_point = mountinfo
+        .lines()
+        .find_map(|line| {
+            let (fields, rest) = line.split_once(" - ")?;
+            (rest.split(' ').next()? == "cgroup2").then(|| fields.split(' ').nth(4).map(PathBuf::from))?
+        })
+        .context("no cgroup v2 hierarchy is mounted")?;
+
+    let cgroups = fs::read_to_string("/proc/self/cgroup").context("cannot read /proc/self/cgroup")?;
+
+    let relative = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .context("this process is not in a cgroup v2 hierarchy")?;
+
+    Ok(mount_point.join(relative.trim_start_matches('/')))
+}
+
+/// Remove the cgroups left in `origin` by sessions whose ht has exited without removing them
+/// (see `Cgroup::drop`). Any still populated are left alone.
+fn remove_stale(origin: &Path) {
+    let Ok(entries) = fs::read_dir(origin) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let pid = name.to_str().and_then(|n| n.strip_prefix("htty-")).and_then(|n| n.split('-').next());
+
+        if pid.is_some_and(|pid| !Path::new("/proc").join(pid).exists()) {
+            remove_tree(&entry.path());
+        }
+    }
+}
+
+/// Remove an `htty-*` cgroup and the leaves in it, as far as they're empty.
+fn remove_tree(root: &Path) {
+    for leaf in ["command", "supervisor"] {
+        let _ = fs::remove_dir(root.join(leaf));
+    }
+
+    let _ = fs::remove_dir(root);
+}
+
+fn enable_controller(parent: &Path, controller: &str) -> Result<()> {
+    let available = fs::read_to_string(parent.join("cgroup.controllers")).unwrap_or_default();
+
+    if !available.split_whitespace().any(|c| c == controller) {
+        bail!(
+            "the {controller} controller is not available in {} (is it delegated to this user?)",
+            parent.display()
+        );
+    }
+
+    let subtree_control = parent.join("cgroup.subtree_control");
+
+    fs::write(&subtree_control, format!("+{controller}")).with_context(|| {
+        format!(
+            "cannot enable the {controller} controller in {} (the parent cgroup must not contain processes itself)",
+            subtree_control.display()
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{remove_stale, Bytes, Cpus};
+    use std::fs;
+
+    #[test]
+    fn bytes() {
+        assert_eq!("4096".parse::<Bytes>().unwrap(), Bytes(4096));
+        assert_eq!("512k".parse::<Bytes>().unwrap(), Bytes(512 << 10));
+        assert_eq!(" 512M ".parse::<Bytes>().unwrap(), Bytes(512 << 20));
+        assert_eq!("2G".parse::<Bytes>().unwrap(), Bytes(2 << 30));
+
+        for invalid in ["", "G", "0", "0M", "-1K", "1.5G", "12T", "99999999999999G"] {
+            assert!(invalid.parse::<Bytes>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn cpus() {
+        assert_eq!("0.5".parse::<Cpus>().unwrap(), Cpus(0.5));
+        assert_eq!(" 2 ".parse::<Cpus>().unwrap(), Cpus(2.0));
+
+        for invalid in ["", "0", "-1", "inf", "NaN", "two"] {
+            assert!(invalid.parse::<Cpus>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn removes_stale_cgroups() {
+        let origin = std::env::temp_dir().join(format!("htty-cgroup-test-{}", std::process::id()));
+        let stale = origin.join("htty-1073741823-000000001");
+        let live = origin.join(format!("htty-{}-000000001", std::process::id()));
+        let populated = origin.join("htty-1073741822-000000001");
+
+        for dir in [&stale, &live, &populated] {
+            fs::create_dir_all(dir.join("command")).unwrap();
+            fs::create_dir_all(dir.join("supervisor")).unwrap();
+        }
+
+        // Stands in for a process still in a cgroup, which keeps its directory from being removed
+        fs::write(populated.join("supervisor/cgroup.procs"), "1").unwrap();
+        fs::create_dir(origin.join("other")).unwrap();
+
+        remove_stale(&origin);
+        let mut left: Vec<_> = fs::read_dir(&origin).unwrap().map(|e| e.unwrap().path()).collect();
+        let mut kept = vec![populated.clone(), live, origin.join("other")];
+        left.sort();
+        kept.sort();
+        assert_eq!(left, kept);
+        assert!(!populated.join("command").exists());
+
+        fs::remove_dir_all(&origin).unwrap();
+    }
+}
@@ -1,27 +1,79 @@
-use crate::api::Subscription;
-use anyhow::{bail, Result};
-use nix::pty;
-use std::{fmt::Display, net::SocketAddr, ops::Deref, str::FromStr, path::PathBuf, env};
+use crate::cgroup;
+use crate::config::{Config, Geometry, Size, StyleFormat, StyleMode, Subscription};
+use crate::exec::Script;
+use crate::locale;
+use crate::namespace::Namespaces;
+use crate::pane;
+use crate::pty::{self as child, Respawn};
+use crate::termios;
+use crate::timing::Timing;
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser, Subcommand};
+use regex::Regex;
+use std::collections::HashMap;
+use std::{fs, io};
+use std::{net::SocketAddr, str::FromStr, path::{Path, PathBuf}, env, time::Duration};
 
-#[derive(Debug, Clone, Copy, Default)]
-pub enum StyleMode {
+/// How much of ht's own diagnostics is written to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LogLevel {
+    Error,
+    Warn,
     #[default]
-    Plain,
-    Styled,
+    Info,
+    Debug,
+    Trace,
 }
 
-impl FromStr for StyleMode {
+impl FromStr for LogLevel {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
-            "plain" => Ok(StyleMode::Plain),
-            "styled" => Ok(StyleMode::Styled),
-            _ => Err(format!("invalid style mode: {s}. Valid options: plain, styled")),
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(format!("invalid log level: {s}. Valid options: error, warn, info, debug, trace")),
         }
     }
 }
 
+/// How `ht snapshot` prints the final screen.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ScreenFormat {
+    /// Plain text, one line per row.
+    #[default]
+    Text,
+    /// Text with SGR sequences reproducing the colors and attributes.
+    Ansi,
+    /// A `<pre>` element with the colors and attributes as inline styles.
+    Html,
+}
+
+impl FromStr for ScreenFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(ScreenFormat::Text),
+            "ansi" => Ok(ScreenFormat::Ansi),
+            "html" => Ok(ScreenFormat::Html),
+            _ => Err(format!("invalid screen format: {s}. Valid options: text, ansi, html")),
+        }
+    }
+}
+
+/// Where `--final-snapshot` puts the screen ht shuts down with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FinalSnapshot {
+    /// Broadcast as a snapshot event to every subscriber.
+    Event,
+    /// Written to the file as plain text.
+    File(PathBuf),
+}
+
 #[derive(Debug)]
 pub struct Cli {
     pub command: Option<Commands>,
@@ -30,191 +82,732 @@ pub struct Cli {
     pub listen: Option<SocketAddr>,
     pub subscribe: Option<Subscription>,
     pub style_mode: StyleMode,
+    pub style_format: StyleFormat,
+    pub login: bool,
+    pub no_setsid: bool,
+    pub no_ctty: bool,
+    pub job_control: bool,
+    pub separate_stderr: bool,
+    pub termios: termios::Settings,
+    pub main_pane: Option<Geometry>,
+    pub panes: Vec<pane::Spec>,
+    pub wait_exit_helper: Option<PathBuf>,
+    pub cgroup: Option<cgroup::Limits>,
+    pub unshare: Namespaces,
+    pub respawn: Respawn,
+    /// Keep serving after the command exits, until an exit command arrives.
+    pub no_exit: bool,
+    /// Keep the command running when stdout's reader goes away, rather than shutting down.
+    pub keep_child: bool,
+    /// Hold back init events until the command's first output.
+    pub start_on_output: bool,
+    /// Exit with the command's exit status rather than 0.
+    pub propagate_exit_code: bool,
+    pub idle_after: Duration,
+    /// Shut down after this long without output or commands.
+    pub idle_timeout: Option<Duration>,
+    pub final_snapshot: Option<FinalSnapshot>,
+    /// Environment variables set for the command, from the config file.
+    pub env: HashMap<String, String>,
+    /// What to do when ht is started in a locale that isn't ASCII or UTF-8.
+    pub locale_policy: locale::Policy,
+    /// Where to write the manifest of artifacts, rather than next to them.
+    pub manifest: Option<PathBuf>,
+    pub benchmark: bool,
+    pub log_level: LogLevel,
+    /// File receiving ht's diagnostics as JSON lines, down to debug level.
+    pub log_file: Option<PathBuf>,
+    pub buffers: Buffers,
+    pub timing: Timing,
+    /// Extra key names for `sendKeys`, mapped to the sequences they send.
+    pub keys: HashMap<String, String>,
 }
 
-#[derive(Debug)]
-pub enum Commands {
+// [[[cog
+// import os
+// cog.out(f'const VERSION: &str = "{os.environ["HTTY_VERSION_INFO_HT"].removeprefix("ht ")}";')
+// ]]]
+const VERSION: &str = "0.2.30 (unknown)";
+// [[[end]]]
+
+const ENVIRONMENT_HELP: &str = "\
+Delays and polling intervals can be overridden in milliseconds through the environment:
+HTTY_FIFO_MONITORING_INTERVAL_MS, HTTY_SUBSCRIPTION_TIMEOUT_MS, HTTY_WRITE_TIMEOUT_MS and
+HTTY_WAIT_EXIT_TIMEOUT_MS";
+
+/// Headless terminal: run a command in a virtual terminal and drive it with JSON messages.
+#[derive(Debug, Parser)]
+#[command(name = "ht", version = VERSION, args_conflicts_with_subcommands = true, after_help = ENVIRONMENT_HELP)]
+struct Args {
+    #[command(flatten)]
+    run: RunArgs,
+
+    #[command(subcommand)]
+    command: Option<Subcommands>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Subcommands {
+    /// Run a command in the terminal (what ht does when no subcommand is given)
+    Run(Box<RunArgs>),
+    /// Run a command without any API and save its recording, final screen and exit code to a directory
+    Record {
+        /// Directory receiving session.cast, snapshot.txt and exit-code (created if missing)
+        #[arg(short, long, value_name = "DIR")]
+        output: PathBuf,
+
+        #[command(flatten)]
+        run: Box<RunArgs>,
+    },
+    /// Run a command without any API, print its final screen and exit with its exit code
+    Snapshot {
+        /// Take the snapshot once the terminal has produced no output for this many milliseconds
+        #[arg(long, value_name = "MILLIS")]
+        until_quiet: Option<u64>,
+
+        /// Take the snapshot once the screen text matches this regular expression
+        #[arg(long, value_name = "REGEX")]
+        until: Option<Regex>,
+
+        /// How to print the screen: text, ansi or html
+        #[arg(long, value_name = "FORMAT", default_value = "text")]
+        format: ScreenFormat,
+
+        #[command(flatten)]
+        run: Box<RunArgs>,
+    },
+    /// Run a command without any API, driving it with a script of inputs and expectations
+    Exec {
+        /// Script to run, one step per line: type TEXT, send KEY..., wait MILLIS, expect TEXT,
+        /// expect-re REGEX or snapshot
+        #[arg(short = 'f', long, value_name = "FILE")]
+        script: PathBuf,
+
+        /// How long expect and expect-re wait for the screen to match, in milliseconds
+        #[arg(long, value_name = "MILLIS", default_value_t = 5000)]
+        timeout: u64,
+
+        #[command(flatten)]
+        run: Box<RunArgs>,
+    },
+    /// List the key names sendKeys understands, or show the bytes sent for the given keys
+    Keys {
+        /// Keys to show, e.g. Up C-a
+        keys: Vec<String>,
+
+        /// Read custom keys from PATH [default: ~/.config/htty/config.toml]
+        #[arg(long, value_name = "PATH")]
+        config: Option<PathBuf>,
+
+        /// Don't read a config file
+        #[arg(long, conflicts_with = "config")]
+        no_config: bool,
+
+        /// Also use the keys of this profile in the config file
+        #[arg(long, value_name = "NAME", conflicts_with = "no_config")]
+        profile: Option<String>,
+    },
+    /// Play an asciicast recording (such as one made by record) back in this terminal
+    Replay {
+        /// The .cast file to play
+        file: PathBuf,
+
+        /// Playback speed relative to the recording, e.g. 2 for twice as fast
+        #[arg(long, value_name = "FACTOR", default_value_t = 1.0, value_parser = parse_speed)]
+        speed: f64,
+
+        /// Print the recording's events as ht's JSON messages instead of playing it
+        #[arg(long)]
+        to_json: bool,
+    },
+    /// Feed each file in a corpus to a terminal as a command's output (or with --input, as keys
+    /// to send it), with no command run, printing the screen it leaves (or the bytes the keys
+    /// encode to, or the panic it causes) as a JSON line per file
+    Fuzz {
+        /// Directory of files to feed, or a single file
+        corpus: PathBuf,
+
+        /// Terminal size: COLSxROWS, or a preset (vt100 is 80x24, wide is 200x50)
+        #[arg(long, value_name = "COLSxROWS", default_value = "120x40")]
+        size: Size,
+
+        /// Feed each file this many bytes at a time, so that sequences get split between reads
+        #[arg(long, value_name = "BYTES", default_value_t = 4096, value_parser = clap::value_parser!(u64).range(1..))]
+        chunk: u64,
+
+        /// Feed each file as key names, one per line, as sendKeys takes them, instead of as output
+        #[arg(long, conflicts_with = "chunk")]
+        input: bool,
+    },
+    /// Print a shell completion script, e.g. ht completions bash > /etc/bash_completion.d/ht
+    Completions {
+        shell: clap_complete::Shell,
+    },
+    /// Print ht's man page, or write pages for ht and each of its subcommands to a directory
+    Man {
+        /// Directory receiving ht.1, ht-run.1 and so on, instead of printing ht.1
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+    },
+    /// Wait for ht to release a finished command, through a FIFO created at SIGNAL_FILE
     WaitExit {
         signal_file: PathBuf,
+
+        /// Give up with an error after this many milliseconds, in case ht went away [default: wait
+        /// indefinitely]
+        #[arg(long, value_name = "MILLIS")]
+        timeout: Option<u64>,
     },
 }
 
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0.0 => Ok(speed),
+        _ => Err(format!("invalid speed: {s}. Expected a positive number")),
+    }
+}
+
+/// What ht was asked to do instead of serving the stdio API.
+#[derive(Debug)]
+pub enum Commands {
+    Record { output: PathBuf },
+    Snapshot { until_quiet: Option<Duration>, until: Option<Regex>, format: ScreenFormat },
+    Exec { script: Script, timeout: Duration },
+    Keys { keys: Vec<String> },
+    Replay { file: PathBuf, speed: f64, to_json: bool },
+    Fuzz { corpus: PathBuf, size: Size, chunk: usize, input: bool },
+    Completions { shell: clap_complete::Shell },
+    Man { output_dir: Option<PathBuf> },
+    WaitExit { signal_file: PathBuf, timeout: Option<Duration> },
+}
+
+/// Options for running a command, which override the config file, which overrides the defaults.
+#[derive(Debug, clap::Args)]
+struct RunArgs {
+    /// Terminal size: COLSxROWS, auto for the size of the terminal ht runs in, or a preset
+    /// (vt100 is 80x24, wide is 200x50) [default: 120x40]
+    #[arg(long, value_name = "COLSxROWS")]
+    size: Option<Size>,
+
+    /// Enable HTTP server [default address: 127.0.0.1:0]
+    #[arg(short, long, value_name = "LISTEN_ADDR", num_args = 0..=1, default_missing_value = "127.0.0.1:0")]
+    listen: Option<SocketAddr>,
+
+    /// Subscribe to events, e.g. output,snapshot
+    #[arg(long, value_name = "EVENTS")]
+    subscribe: Option<Subscription>,
+
+    /// Style mode for snapshots: plain, styled [default: plain]
+    #[arg(short, long, value_name = "MODE")]
+    style_mode: Option<StyleMode>,
+
+    /// Encoding of styleMap in styled snapshots: cells, rle [default: cells]
+    #[arg(long, value_name = "FORMAT")]
+    style_format: Option<StyleFormat>,
+
+    /// Run the shell as a login shell (sources profile files)
+    #[arg(long)]
+    login: bool,
+
+    /// Don't make the child a session leader (implies --no-ctty)
+    #[arg(long)]
+    no_setsid: bool,
+
+    /// Don't make the PTY the child's controlling terminal
+    #[arg(long)]
+    no_ctty: bool,
+
+    /// Enable job control in the shell (sh -m)
+    #[arg(long)]
+    job_control: bool,
+
+    /// Deliver the child's stderr as stderr events instead of via the PTY
+    #[arg(long)]
+    separate_stderr: bool,
+
+    /// PTY line discipline, e.g. raw,-echo,ixon (raw, cooked, [-]echo, [-]isig, [-]ixon, [-]ixoff)
+    #[arg(long, value_name = "SETTINGS")]
+    termios: Option<termios::Settings>,
+
+    /// Run COMMAND in an additional pane, e.g. 60x40+60+0:'tail -f log' (repeatable)
+    #[arg(long = "pane", value_name = "GEOM:COMMAND")]
+    panes: Vec<pane::Spec>,
+
+    /// Area of the screen used by SHELL_COMMAND when panes are present [default: whole screen]
+    #[arg(long, value_name = "GEOM")]
+    main_pane: Option<Geometry>,
+
+    /// Binary run as `<PATH> wait-exit <FIFO>` after the command [default: this executable]
+    #[arg(long, value_name = "PATH")]
+    wait_exit_helper: Option<PathBuf>,
+
+    /// Run the child in a fresh cgroup v2, killing everything left in it on exit
+    #[arg(long)]
+    cgroup: bool,
+
+    /// Memory limit for the cgroup, e.g. 512M (implies --cgroup)
+    #[arg(long, value_name = "SIZE")]
+    memory_max: Option<cgroup::Bytes>,
+
+    /// CPU limit for the cgroup in CPUs, e.g. 0.5 (implies --cgroup)
+    #[arg(long, value_name = "CPUS")]
+    cpu_max: Option<cgroup::Cpus>,
+
+    /// Run the child in new Linux namespaces, e.g. net,pid (net, pid, ipc, uts, mount, user)
+    #[arg(long, value_name = "NAMESPACES")]
+    unshare: Option<Namespaces>,
+
+    /// Relaunch the command (up to N times) with backoff when it exits nonzero
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true)]
+    respawn: Option<Option<u32>>,
+
+    /// Keep running after the command exits, so snapshots can still be taken, until an exit
+    /// command arrives. The terminal is also held open, so output written to it after a hangup
+    /// (by a program that closes and reopens it) is still captured
+    #[arg(long)]
+    no_exit: bool,
+
+    /// If whatever reads ht's stdout goes away or stops reading, keep the command running
+    /// (driven through stdin and --listen) rather than shutting down
+    #[arg(long)]
+    keep_child: bool,
+
+    /// Send the init event once the command first prints something (or exits), with that output
+    /// on screen, rather than straight away
+    #[arg(long)]
+    start_on_output: bool,
+
+    /// Exit with the command's exit code, or 128 + N if it was killed by signal N (1 if its status
+    /// is unknown, 0 if it was still running when ht exited)
+    #[arg(long)]
+    propagate_exit_code: bool,
+
+    /// Emit idle after this long without output, and active when it resumes [default: 1000]
+    #[arg(long, value_name = "MS")]
+    idle_after: Option<u64>,
+
+    /// Shut down, emitting idleTimeout and exiting with status 124, after this long without
+    /// output or commands
+    #[arg(long, value_name = "SECS")]
+    idle_timeout: Option<u64>,
+
+    /// Take a snapshot just before shutting down, sent as a snapshot event or written to PATH
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+    final_snapshot: Option<Option<PathBuf>>,
+
+    /// Write the JSON manifest listing the recording, snapshot and log files produced, along with
+    /// the command, its exit code and timings, to PATH [default: htty-manifest.json next to them]
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Bytes read from the PTY at a time [default: 131072, env: HTTY_READ_BUF_SIZE]
+    #[arg(long, value_name = "BYTES")]
+    read_buffer_size: Option<usize>,
+
+    /// Capacity of the internal channels carrying input and output [default: 1024, env: HTTY_CHANNEL_BUFFER_SIZE]
+    #[arg(long, value_name = "N")]
+    channel_capacity: Option<usize>,
+
+    /// Events a subscriber may fall behind by before missing some [default: 1024, env: HTTY_BROADCAST_CHANNEL_SIZE]
+    #[arg(long, value_name = "N")]
+    broadcast_capacity: Option<usize>,
+
+    /// Report the throughput of the command's output through the session on exit
+    #[arg(long)]
+    benchmark: bool,
+
+    /// When the locale isn't ASCII or UTF-8: set (run the command with LC_ALL=C.UTF-8), warn or strict [default: strict]
+    #[arg(long, value_name = "POLICY")]
+    locale_policy: Option<locale::Policy>,
+
+    /// Diagnostics written to stderr: error, warn, info, debug, trace [default: info]
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<LogLevel>,
+
+    /// Also write diagnostics (down to debug level) to PATH as JSON lines
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Only write errors to stderr (same as --log-level error)
+    #[arg(short, long, conflicts_with = "log_level")]
+    quiet: bool,
+
+    /// Read defaults for these options from PATH [default: ~/.config/htty/config.toml]
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Don't read a config file
+    #[arg(long, conflicts_with = "config")]
+    no_config: bool,
+
+    /// Apply this profile from the config file on top of its other settings
+    #[arg(long, value_name = "NAME", conflicts_with = "no_config")]
+    profile: Option<String>,
+
+    /// Command to run inside the terminal: a program and its arguments, or a single command line for the shell [default: bash]
+    #[arg(trailing_var_arg = true)]
+    shell_command: Vec<String>,
+}
+
 impl Cli {
     pub fn new() -> Result<Self> {
-        let args: Vec<String> = env::args().collect();
-        parse_args(&args)
-    }
-}
-
-fn parse_args(args: &[String]) -> Result<Cli> {
-    let mut cli = Cli {
-        command: None,
-        size: Size::default(),
-        shell_command: vec!["bash".to_string()],
-        listen: None,
-        subscribe: None,
-        style_mode: StyleMode::default(),
-    };
-
-    let mut i = 1; // Skip program name
-    
-    while i < args.len() {
-        match args[i].as_str() {
-            "--help" | "-h" => {
-                print_help(&args[0]);
-                std::process::exit(0);
-            }
-            "--version" | "-V" => {
-                // [[[cog
-                // import os
-                // cog.out(f'println!("{os.environ["HTTY_VERSION_INFO_HT"]}");')
-                // ]]]
-                println!("ht 0.2.30 (unknown)");
-                // [[[end]]]
-                std::process::exit(0);
-            }
-            "--size" => {
-                if i + 1 >= args.len() {
-                    bail!("--size requires a value");
-                }
-                i += 1;
-                cli.size = args[i].parse()?;
-            }
-            "--listen" | "-l" => {
-                // Handle optional value
-                if i + 1 < args.len() && !args[i + 1].starts_with('-') {
-                    i += 1;
-                    cli.listen = Some(args[i].parse()?);
-                } else {
-                    cli.listen = Some("127.0.0.1:0".parse()?);
-                }
-            }
-            "--subscribe" => {
-                if i + 1 >= args.len() {
-                    bail!("--subscribe requires a value");
-                }
-                i += 1;
-                cli.subscribe = Some(args[i].parse().map_err(|e: String| anyhow::anyhow!(e))?);
-            }
-            "--style-mode" | "-s" => {
-                if i + 1 >= args.len() {
-                    bail!("--style-mode requires a value");
-                }
-                i += 1;
-                cli.style_mode = args[i].parse().map_err(|e: String| anyhow::anyhow!(e))?;
-            }
-            "wait-exit" => {
-                if i + 1 >= args.len() {
-                    bail!("wait-exit requires a signal file path");
+        Self::from_args(Args::parse())
+    }
+
+    fn from_args(args: Args) -> Result<Self> {
+        match args.command {
+            Some(Subcommands::Run(run)) => Self::run(*run),
+
+            Some(Subcommands::Record { output, run }) => {
+                let cli = Self::run(*run)?;
+
+                if cli.respawn != Respawn::Never {
+                    bail!("record stops at the command's first exit, so it can't be combined with --respawn");
                 }
-                i += 1;
-                cli.command = Some(Commands::WaitExit {
-                    signal_file: PathBuf::from(&args[i]),
-                });
-                break; // No more parsing after subcommand
+
+                // Like snapshot and exec, record takes the final screen after the command exits
+                // and then tells ht to exit itself
+                Ok(Self {
+                    command: Some(Commands::Record { output }),
+                    no_exit: true,
+                    ..cli
+                })
             }
-            "--" => {
-                // Everything after -- is the shell command
-                i += 1;
-                if i < args.len() {
-                    cli.shell_command = args[i..].to_vec();
+
+            Some(Subcommands::Snapshot { until_quiet, until, format, run }) => {
+                let cli = Self::run(*run)?;
+
+                if cli.respawn != Respawn::Never {
+                    bail!("snapshot stops at the command's first exit, so it can't be combined with --respawn");
                 }
-                break;
+
+                Ok(Self {
+                    command: Some(Commands::Snapshot {
+                        until_quiet: until_quiet.map(Duration::from_millis),
+                        until,
+                        format,
+                    }),
+                    no_exit: true,
+                    propagate_exit_code: true,
+                    ..cli
+                })
             }
-            arg if arg.starts_with('-') => {
-                bail!("Unknown option: {}", arg);
+
+            Some(Subcommands::Exec { script, timeout, run }) => {
+                let script = Script::load(&script)?;
+
+                Ok(Self {
+                    command: Some(Commands::Exec {
+                        script,
+                        timeout: Duration::from_millis(timeout),
+                    }),
+                    no_exit: true,
+                    ..Self::run(*run)?
+                })
             }
-            _ => {
-                // Positional arguments are shell command
-                cli.shell_command = args[i..].to_vec();
-                break;
+
+            Some(Subcommands::Keys { keys, config, no_config, profile }) => Ok(Self {
+                command: Some(Commands::Keys { keys }),
+                ..Self::configured(config.as_deref(), no_config, profile.as_deref())?
+            }),
+
+            Some(Subcommands::Replay { file, speed, to_json }) => Ok(Self {
+                command: Some(Commands::Replay { file, speed, to_json }),
+                ..Self::defaults()?
+            }),
+
+            Some(Subcommands::Fuzz { corpus, size, chunk, input }) => Ok(Self {
+                command: Some(Commands::Fuzz { corpus, size, chunk: chunk as usize, input }),
+                ..Self::defaults()?
+            }),
+
+            Some(Subcommands::Completions { shell }) => Ok(Self {
+                command: Some(Commands::Completions { shell }),
+                ..Self::defaults()?
+            }),
+
+            Some(Subcommands::Man { output_dir }) => Ok(Self {
+                command: Some(Commands::Man { output_dir }),
+                ..Self::defaults()?
+            }),
+
+            Some(Subcommands::WaitExit { signal_file, timeout }) => Ok(Self {
+                command: Some(Commands::WaitExit {
+                    signal_file,
+                    timeout: timeout.map(Duration::from_millis),
+                }),
+                ..Self::defaults()?
+            }),
+
+            None => Self::run(args.run),
+        }
+    }
+
+    pub(crate) fn defaults() -> Result<Self> {
+        Ok(Self {
+            command: None,
+            size: Size::default(),
+            shell_command: vec!["bash".to_string()],
+            listen: None,
+            subscribe: None,
+            style_mode: StyleMode::default(),
+            style_format: StyleFormat::default(),
+            login: false,
+            no_setsid: false,
+            no_ctty: false,
+            job_control: false,
+            separate_stderr: false,
+            termios: termios::Settings::default(),
+            main_pane: None,
+            panes: Vec::new(),
+            wait_exit_helper: None,
+            cgroup: None,
+            unshare: Namespaces::default(),
+            respawn: Respawn::default(),
+            no_exit: false,
+            keep_child: false,
+            start_on_output: false,
+            propagate_exit_code: false,
+            idle_after: Duration::from_millis(1000),
+            idle_timeout: None,
+            final_snapshot: None,
+            env: HashMap::new(),
+            locale_policy: locale::Policy::default(),
+            manifest: None,
+            benchmark: false,
+            log_level: LogLevel::default(),
+            log_file: None,
+            buffers: Buffers::default(),
+            timing: Timing::from_env()?,
+            keys: HashMap::new(),
+        })
+    }
+
+    /// Write the completion script for `shell` to stdout.
+    pub fn print_completions(shell: clap_complete::Shell) {
+        clap_complete::generate(shell, &mut Args::command(), "ht", &mut io::stdout());
+    }
+
+    /// Write the man page to stdout, or pages for ht and each subcommand to `output_dir`.
+    pub fn print_man(output_dir: Option<&Path>) -> Result<()> {
+        match output_dir {
+            Some(dir) => {
+                fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
+                clap_mangen::generate_to(Args::command(), dir)?;
             }
+
+            None => clap_mangen::Man::new(Args::command()).render(&mut io::stdout())?,
         }
-        i += 1;
+
+        Ok(())
     }
 
-    Ok(cli)
-}
+    /// The defaults, overridden by the config file (and `profile` in it) and the environment.
+    fn configured(config: Option<&Path>, no_config: bool, profile: Option<&str>) -> Result<Self> {
+        let config = if no_config {
+            Config::default()
+        } else {
+            Config::for_path(config)?
+        };
 
-fn print_help(program_name: &str) {
-    println!("Usage: {} [OPTIONS] [SHELL_COMMAND]... [COMMAND]", program_name);
-    println!();
-    println!("Commands:");
-    println!("  wait-exit  Wait for a signal file to be deleted before exiting");
-    println!("  help       Print this message or the help of the given subcommand(s)");
-    println!();
-    println!("Arguments:");
-    println!("  [SHELL_COMMAND]...  Command to run inside the terminal [default: bash]");
-    println!();
-    println!("Options:");
-    println!("      --size <COLSxROWS>        Terminal size [default: 120x40]");
-    println!("  -l, --listen [<LISTEN_ADDR>]  Enable HTTP server");
-    println!("      --subscribe <EVENTS>      Subscribe to events");
-    println!("  -s, --style-mode <MODE>       Style mode for snapshots [default: plain]");
-    println!("  -h, --help                    Print help");
-    println!("  -V, --version                 Print version");
-}
+        let mut cli = Self::defaults()?;
+        config.apply(&mut cli, profile)?;
+        cli.buffers.apply_env()?;
+        Ok(cli)
+    }
+
+    fn run(args: RunArgs) -> Result<Self> {
+        let mut cli = Self::configured(args.config.as_deref(), args.no_config, args.profile.as_deref())?;
+        args.apply(&mut cli);
+
+        let main_pane = cli.main_pane.iter().map(|g| ("--main-pane", g));
+        let panes = cli.panes.iter().map(|p| ("--pane", &p.geometry));
+
+        for (flag, geometry) in main_pane.chain(panes) {
+            if !geometry.fits_within(&cli.size) {
+                bail!("{flag} {geometry} does not fit within a terminal of size {}", cli.size);
+            }
+        }
 
-#[derive(Debug, Clone)]
-pub struct Size(pty::Winsize);
+        cli.buffers.validate()?;
 
-impl Size {
-    pub fn cols(&self) -> usize {
-        self.0.ws_col as usize
+        Ok(cli)
     }
+}
+
+impl RunArgs {
+    fn apply(self, cli: &mut Cli) {
+        if let Some(size) = self.size {
+            cli.size = size;
+        }
+
+        if !self.shell_command.is_empty() {
+            cli.shell_command = self.shell_command;
+        }
+
+        cli.listen = self.listen.or(cli.listen);
+        cli.subscribe = self.subscribe.or(cli.subscribe);
+        cli.style_mode = self.style_mode.unwrap_or(cli.style_mode);
+        cli.style_format = self.style_format.unwrap_or(cli.style_format);
+        cli.login |= self.login;
+        cli.no_setsid |= self.no_setsid;
+        cli.no_ctty |= self.no_ctty;
+        cli.job_control |= self.job_control;
+        cli.separate_stderr |= self.separate_stderr;
+        cli.termios = self.termios.unwrap_or(cli.termios);
+        cli.main_pane = self.main_pane.or(cli.main_pane);
+        cli.panes = self.panes;
+        cli.wait_exit_helper = self.wait_exit_helper.or(cli.wait_exit_helper.take());
+
+        if self.cgroup || self.memory_max.is_some() || self.cpu_max.is_some() {
+            let limits = cli.cgroup.get_or_insert_with(cgroup::Limits::default);
+            limits.memory_max = self.memory_max.or(limits.memory_max);
+            limits.cpu_max = self.cpu_max.or(limits.cpu_max);
+        }
+
+        cli.unshare = self.unshare.unwrap_or(cli.unshare);
+
+        cli.respawn = match self.respawn {
+            Some(Some(limit)) => Respawn::Limited(limit),
+            Some(None) => Respawn::Unlimited,
+            None => cli.respawn,
+        };
+
+        cli.no_exit |= self.no_exit;
+        cli.keep_child |= self.keep_child;
+        cli.start_on_output |= self.start_on_output;
+        cli.propagate_exit_code |= self.propagate_exit_code;
+
+        if let Some(millis) = self.idle_after {
+            cli.idle_after = Duration::from_millis(millis);
+        }
+
+        cli.idle_timeout = self.idle_timeout.map(Duration::from_secs).or(cli.idle_timeout);
+
+        cli.final_snapshot = match self.final_snapshot {
+            Some(Some(path)) => Some(FinalSnapshot::File(path)),
+            Some(None) => Some(FinalSnapshot::Event),
+            None => cli.final_snapshot.take(),
+        };
+
+        cli.manifest = self.manifest.or(cli.manifest.take());
 
-    pub fn rows(&self) -> usize {
-        self.0.ws_row as usize
+        cli.buffers.read_size = self.read_buffer_size.unwrap_or(cli.buffers.read_size);
+        cli.buffers.channel_capacity = self.channel_capacity.unwrap_or(cli.buffers.channel_capacity);
+        cli.buffers.broadcast_capacity = self.broadcast_capacity.unwrap_or(cli.buffers.broadcast_capacity);
+        cli.benchmark |= self.benchmark;
+        cli.locale_policy = self.locale_policy.unwrap_or(cli.locale_policy);
+
+        cli.log_level = if self.quiet {
+            LogLevel::Error
+        } else {
+            self.log_level.unwrap_or(cli.log_level)
+        };
+
+        cli.log_file = self.log_file.or(cli.log_file.take());
     }
 }
 
-impl Default for Size {
+/// Buffer sizes and channel capacities, tunable for high-output workloads.
+///
+/// Each one defaults to its compiled-in value, can be overridden through the environment,
+/// and is in turn overridden by its command line flag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Buffers {
+    /// Bytes read from the PTY (and the stderr pipe) at a time.
+    pub read_size: usize,
+    /// Capacity of the channels between the PTY tasks, the APIs and the event loop.
+    pub channel_capacity: usize,
+    /// How many events a subscriber may fall behind by before it starts missing them.
+    pub broadcast_capacity: usize,
+}
+
+const MAX_READ_SIZE: usize = 16 * 1024 * 1024;
+const MAX_CAPACITY: usize = 1 << 20;
+
+impl Default for Buffers {
     fn default() -> Self {
-        Size(pty::Winsize {
-            ws_col: 120,
-            ws_row: 40,
-            ws_xpixel: 0,
-            ws_ypixel: 0,
-        })
+        Self {
+            read_size: child::DEFAULT_READ_BUF_SIZE,
+            channel_capacity: 1024,
+            broadcast_capacity: 1024,
+        }
     }
 }
 
-impl FromStr for Size {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.split_once('x') {
-            Some((cols, rows)) => {
-                let cols: u16 = cols.parse()?;
-                let rows: u16 = rows.parse()?;
-
-                let winsize = pty::Winsize {
-                    ws_col: cols,
-                    ws_row: rows,
-                    ws_xpixel: 0,
-                    ws_ypixel: 0,
-                };
-
-                Ok(Size(winsize))
+impl Buffers {
+    fn apply_env(&mut self) -> Result<()> {
+        for (var, value) in [
+            ("HTTY_READ_BUF_SIZE", &mut self.read_size),
+            ("HTTY_CHANNEL_BUFFER_SIZE", &mut self.channel_capacity),
+            ("HTTY_BROADCAST_CHANNEL_SIZE", &mut self.broadcast_capacity),
+        ] {
+            if let Ok(s) = env::var(var) {
+                *value = parse_count(var, &s)?;
             }
+        }
 
-            None => {
-                bail!("invalid size format: {s}");
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !(1024..=MAX_READ_SIZE).contains(&self.read_size) {
+            bail!("read buffer size must be between 1024 and {MAX_READ_SIZE} bytes, got {}", self.read_size);
+        }
+
+        for (name, capacity) in [("channel", self.channel_capacity), ("broadcast", self.broadcast_capacity)] {
+            if !(1..=MAX_CAPACITY).contains(&capacity) {
+                bail!("{name} capacity must be between 1 and {MAX_CAPACITY}, got {capacity}");
             }
         }
+
+        Ok(())
     }
 }
 
-impl Deref for Size {
-    type Target = pty::Winsize;
+fn parse_count(name: &str, s: &str) -> Result<usize> {
+    s.trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid value for {name}: {s}. Expected a positive integer"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Args, Cli};
+    use clap::{CommandFactory, Parser};
+    use std::io::Write;
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    #[test]
+    fn verify_args() {
+        Args::command().debug_assert();
     }
-}
 
-impl Display for Size {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}x{}", self.0.ws_col, self.0.ws_row)
+    #[test]
+    fn precedence() {
+        let mut config = tempfile::NamedTempFile::new().unwrap();
+        let settings = "read-buffer-size = 2048\nchannel-capacity = 8\nbroadcast-capacity = 8\nsize = \"90x30\"\n";
+        write!(config, "{settings}[profile.big]\nchannel-capacity = 16\nbroadcast-capacity = 16\n").unwrap();
+        let config = config.path().to_str().unwrap();
+
+        let cli = |flags: &[&str]| {
+            let args = ["ht", "--config", config, "--profile", "big"].into_iter().chain(flags.iter().copied());
+            Cli::from_args(Args::try_parse_from(args).unwrap()).unwrap()
+        };
+
+        // Nothing else in the tests reads this one
+        std::env::set_var("HTTY_BROADCAST_CHANNEL_SIZE", "32");
+        let configured = cli(&[]);
+        let flagged = cli(&["--broadcast-capacity", "64", "--size", "100x40"]);
+        std::env::remove_var("HTTY_BROADCAST_CHANNEL_SIZE");
+
+        // The config file overrides the defaults, its profile the file, the environment the
+        // profile, and flags everything
+        assert_eq!(configured.buffers.read_size, 2048);
+        assert_eq!(configured.buffers.channel_capacity, 16);
+        assert_eq!(configured.buffers.broadcast_capacity, 32);
+        assert_eq!(configured.size.to_string(), "90x30");
+        assert_eq!(flagged.buffers.broadcast_capacity, 64);
+        assert_eq!(flagged.size.to_string(), "100x40");
+        assert!(!configured.no_exit);
     }
 }
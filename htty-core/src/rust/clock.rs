@@ -0,0 +1,56 @@
+//! The clock a [`Session`](crate::Session) times its events by.
+//!
+//! That's `std::time::Instant`, except on wasm32-unknown-unknown, where there's no clock to
+//! read and `Instant::now` panics. There the time stands still, at zero, until the host moves
+//! it on with [`set`].
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+pub use host::{set, Instant};
+
+#[cfg(target_arch = "wasm32")]
+mod host {
+    use std::ops::{Add, Sub};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    /// Nanoseconds since the host started counting.
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    /// Move the clock on to `since_start`. It never goes back, so an earlier time is ignored.
+    pub fn set(since_start: Duration) {
+        NOW.fetch_max(since_start.as_nanos().try_into().unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    /// A moment on the host's clock.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Instant(Duration);
+
+    impl Instant {
+        pub fn now() -> Self {
+            Self(Duration::from_nanos(NOW.load(Ordering::Relaxed)))
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            Self::now() - *self
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Self;
+
+        fn add(self, duration: Duration) -> Self {
+            Self(self.0 + duration)
+        }
+    }
+
+    impl Sub for Instant {
+        type Output = Duration;
+
+        fn sub(self, earlier: Self) -> Duration {
+            self.0.saturating_sub(earlier.0)
+        }
+    }
+}
@@ -1,14 +1,63 @@
-use crate::cli::StyleMode;
+#[cfg(feature = "pty")]
+use crate::config::{Size, StyleFormat, StyleMode};
+#[cfg(feature = "pty")]
+use crate::pty;
+#[cfg(feature = "pty")]
+use crate::session::{Event, Reply, Session, SessionState};
+#[cfg(feature = "pty")]
+use crate::termios;
+#[cfg(feature = "pty")]
+use futures_util::future::{BoxFuture, FutureExt};
+#[cfg(feature = "pty")]
+use tokio::sync::{mpsc, oneshot};
 
+#[cfg(feature = "pty")]
 #[derive(Debug)]
 pub enum Command {
-    Input(Vec<InputSeq>),
-    Snapshot,
+    /// Send input to the command, acknowledging it on the given reply channel (if there is
+    /// one) once it has all been written to the PTY.
+    Input(Vec<InputSeq>, Option<Reply>),
+    /// Take a snapshot, sending it only to the given reply channel if there is one, or
+    /// broadcasting it to every subscriber otherwise.
+    Snapshot(Option<Reply>),
     Resize(usize, usize),
     SetStyleMode(StyleMode),
+    SetStyleFormat(StyleFormat),
+    SetTermios(termios::Settings),
+    CloseStdin,
+    /// Send `Event::Flushed` once every command sent before this one has been seen to, with
+    /// its input written to the PTY, to the given reply channel if there is one, or to every
+    /// subscriber otherwise.
+    Flush(Option<Reply>),
+    /// End the session, as soon as the commands sent before have been seen to (as for `Flush`).
     Exit,
     Debug(String),
     Completed(std::path::PathBuf),
+    Respawn(u32, std::time::Duration),
+    /// The command was stopped by the named signal.
+    Stopped(&'static str),
+    Continued,
+    Pane(usize, Box<Command>),
+}
+
+#[cfg(feature = "pty")]
+impl Command {
+    /// Whether a session in `state` acts on this command. Those ht sends itself are always
+    /// taken, and a pane's input is left to the pane's own driver unless the session is ending.
+    pub fn valid_in(&self, state: SessionState) -> bool {
+        match self {
+            Command::Input(..) | Command::SetTermios(_) | Command::CloseStdin => state.takes_input(),
+            Command::Exit => !state.is_ending(),
+            Command::Debug(_) | Command::Completed(_) | Command::Respawn(..) | Command::Stopped(_) | Command::Continued => true,
+            Command::Pane(_, command) => match **command {
+                Command::Input(..) | Command::SetTermios(_) | Command::CloseStdin => !state.is_ending(),
+                _ => state != SessionState::Closed,
+            },
+            Command::Snapshot(_) | Command::Flush(_) | Command::Resize(..) | Command::SetStyleMode(_) | Command::SetStyleFormat(_) => {
+                state != SessionState::Closed
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -34,3 +83,122 @@ fn seq_as_bytes(seq: &InputSeq, app_mode: bool) -> &[u8] {
         (InputSeq::Cursor(_seq1, seq2), true) => seq2.as_bytes(),
     }
 }
+
+/// Turn down a command that a session in `state` doesn't act on, reporting input as
+/// discarded, and anything else with a debug event.
+#[cfg(feature = "pty")]
+pub fn reject(session: &mut Session, command: Command, state: SessionState) {
+    match command {
+        Command::Input(seqs, reply) => {
+            let bytes = session.encode_input(&seqs).len();
+            session.emit_input_discarded(bytes, reply);
+        }
+
+        Command::Pane(id, command) => match *command {
+            Command::Input(seqs, reply) => {
+                let bytes = session.pane_encode_input(id, &seqs).len();
+                session.emit_pane_input_discarded(id, bytes, reply);
+            }
+
+            command => session.emit_debug_event(&format!("commandIgnored:{state}:pane{id}:{command:?}")),
+        },
+
+        command => session.emit_debug_event(&format!("commandIgnored:{state}:{command:?}")),
+    }
+}
+
+/// Ask each of these PTY drivers to say when the input already handed to it has been
+/// written, returning a future that completes once they all have (or have stopped).
+///
+/// Everything else a command does happens on the event loop as it takes the command, so
+/// this is all that a flush has to wait for.
+#[cfg(feature = "pty")]
+pub async fn fence<'a>(control_txs: impl IntoIterator<Item = &'a mpsc::Sender<pty::Control>>) -> BoxFuture<'static, ()> {
+    let mut written = Vec::new();
+
+    for control_tx in control_txs {
+        let (written_tx, written_rx) = oneshot::channel();
+
+        // A driver that has stopped has nothing left to write
+        if control_tx.send(pty::Control::NotifyWritten(written_tx)).await.is_ok() {
+            written.push(written_rx);
+        }
+    }
+
+    async move {
+        for written_rx in written {
+            let _ = written_rx.await;
+        }
+    }
+    .boxed()
+}
+
+/// Ask a PTY driver to resize its terminal to `size`, returning a future with the size the
+/// PTY reports afterwards, or why it couldn't be resized.
+///
+/// A driver that has stopped has no terminal left to resize, so only the screen needs to
+/// change, and the future has `size` as it is.
+#[cfg(feature = "pty")]
+pub async fn resize(control_tx: &mpsc::Sender<pty::Control>, size: Size) -> BoxFuture<'static, Result<Size, String>> {
+    let (resized_tx, resized_rx) = oneshot::channel();
+    let sent = control_tx.send(pty::Control::Resize(size, resized_tx)).await.is_ok();
+
+    async move {
+        if !sent {
+            return Ok(size);
+        }
+
+        match resized_rx.await {
+            Ok(resized) => resized.map_err(|e| e.to_string()),
+            Err(_) => Ok(size),
+        }
+    }
+    .boxed()
+}
+
+/// Input that a PTY driver had stopped taking, to be reported with `Event::InputDiscarded`.
+#[cfg(feature = "pty")]
+#[derive(Debug)]
+pub struct Discarded {
+    pub bytes: usize,
+    pub reply: Option<Reply>,
+}
+
+/// Hand `data` to a PTY driver, and once it has been written to the PTY send
+/// `Event::InputWritten` (passed through `wrap`) to `reply`, if there is one.
+///
+/// Fails if the driver has stopped. If it stops after taking `data` but before writing it,
+/// `reply` gets `Event::InputDiscarded` instead.
+#[cfg(feature = "pty")]
+pub async fn send_input(
+    input_tx: &mpsc::Sender<Vec<u8>>,
+    control_tx: &mpsc::Sender<pty::Control>,
+    data: Vec<u8>,
+    reply: Option<Reply>,
+    wrap: impl FnOnce(Event) -> Event + Send + 'static,
+) -> Result<(), Discarded> {
+    let bytes = data.len();
+
+    if input_tx.send(data).await.is_err() {
+        return Err(Discarded { bytes, reply });
+    }
+
+    if let Some(reply) = reply {
+        let (written_tx, written_rx) = oneshot::channel();
+
+        if control_tx.send(pty::Control::NotifyWritten(written_tx)).await.is_err() {
+            return Err(Discarded { bytes, reply: Some(reply) });
+        }
+
+        tokio::spawn(async move {
+            let event = match written_rx.await {
+                Ok(()) => Event::InputWritten(bytes),
+                Err(_) => Event::InputDiscarded(bytes),
+            };
+
+            let _ = reply.send(wrap(event));
+        });
+    }
+
+    Ok(())
+}
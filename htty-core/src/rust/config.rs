@@ -0,0 +1,658 @@
+//! Settings: the defaults file, and the types describing a session's terminal and what
+//! clients get from it, shared by the command line and the library.
+
+#[cfg(feature = "pty")]
+use crate::cli::{Cli, FinalSnapshot};
+use crate::session::Event;
+#[cfg(feature = "pty")]
+use anyhow::{anyhow, Context};
+use anyhow::{bail, Result};
+#[cfg(feature = "pty")]
+use nix::libc;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "pty")]
+use std::collections::HashMap;
+use std::fmt::Display;
+#[cfg(feature = "pty")]
+use std::os::fd::AsRawFd;
+#[cfg(feature = "pty")]
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+#[cfg(feature = "pty")]
+use std::time::Duration;
+#[cfg(feature = "pty")]
+use std::{env, fs};
+
+/// Defaults read from a TOML file, applied before (and so overridden by) command line flags.
+///
+/// Keys are named after the flags they stand in for:
+///
+/// ```toml
+/// size = "200x50" # or "auto", "vt100", "wide"
+/// subscribe = ["output", "snapshot"]
+/// style-mode = "styled"
+/// listen = "127.0.0.1:8080"
+/// idle-after = 500 # ms
+/// idle-timeout = 600 # s
+/// final-snapshot = true # or a path
+///
+/// [env] # set for the command
+/// LANG = "C.UTF-8"
+///
+/// [keys]
+/// Save = "\u0013" # sendKeys name => sequence sent to the terminal
+///
+/// [profile.demo] # the same keys again, applied on top with --profile demo
+/// size = "wide"
+/// ```
+#[cfg(feature = "pty")]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    size: Option<String>,
+    subscribe: Option<Vec<String>>,
+    style_mode: Option<String>,
+    style_format: Option<String>,
+    listen: Option<String>,
+    idle_after: Option<u64>,
+    idle_timeout: Option<u64>,
+    final_snapshot: Option<FinalSnapshotSetting>,
+    no_exit: Option<bool>,
+    keep_child: Option<bool>,
+    start_on_output: Option<bool>,
+    propagate_exit_code: Option<bool>,
+    read_buffer_size: Option<usize>,
+    channel_capacity: Option<usize>,
+    broadcast_capacity: Option<usize>,
+    locale_policy: Option<String>,
+    log_level: Option<String>,
+    log_file: Option<PathBuf>,
+    env: HashMap<String, String>,
+    keys: HashMap<String, String>,
+    profile: HashMap<String, Config>,
+}
+
+/// `final-snapshot = true` for a snapshot event, or the path of the file to write it to.
+#[cfg(feature = "pty")]
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FinalSnapshotSetting {
+    Enabled(bool),
+    File(PathBuf),
+}
+
+#[cfg(feature = "pty")]
+impl Config {
+    /// Load the file named by `--config`, or the default one if it exists.
+    pub fn for_path(explicit: Option<&Path>) -> Result<Self> {
+        match explicit {
+            Some(path) => Self::load(path),
+            None => match default_path() {
+                Some(path) if path.exists() => Self::load(&path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("cannot read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// Apply the top-level settings to `cli`, then those of `profile` if one was picked.
+    pub fn apply(mut self, cli: &mut Cli, profile: Option<&str>) -> Result<()> {
+        let picked = profile.map(|name| self.take_profile(name)).transpose()?;
+        self.apply_settings(cli)?;
+
+        match (profile, picked) {
+            (Some(name), Some(picked)) => picked.apply_settings(cli).with_context(|| format!("in profile {name}")),
+            _ => Ok(()),
+        }
+    }
+
+    fn take_profile(&mut self, name: &str) -> Result<Self> {
+        let Some(profile) = self.profile.remove(name) else {
+            let mut names: Vec<_> = self.profile.keys().map(String::as_str).collect();
+            names.sort();
+
+            return Err(match names.as_slice() {
+                [] => anyhow!("unknown profile: {name}. The config file defines no profiles"),
+                names => anyhow!("unknown profile: {name}. Profiles in the config file: {}", names.join(", ")),
+            });
+        };
+
+        if !profile.profile.is_empty() {
+            bail!("profile {name} can't define profiles of its own");
+        }
+
+        Ok(profile)
+    }
+
+    fn apply_settings(self, cli: &mut Cli) -> Result<()> {
+        if let Some(size) = self.size {
+            cli.size = size.parse().context("invalid size in config file")?;
+        }
+
+        if let Some(events) = self.subscribe {
+            cli.subscribe = Some(events.join(",").parse().map_err(|e: String| anyhow::anyhow!(e))?);
+        }
+
+        if let Some(mode) = self.style_mode {
+            cli.style_mode = mode.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+        }
+
+        if let Some(format) = self.style_format {
+            cli.style_format = format.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+        }
+
+        if let Some(listen) = self.listen {
+            cli.listen = Some(listen.parse().with_context(|| format!("invalid listen address in config file: {listen}"))?);
+        }
+
+        if let Some(millis) = self.idle_after {
+            cli.idle_after = Duration::from_millis(millis);
+        }
+
+        if let Some(secs) = self.idle_timeout {
+            cli.idle_timeout = Some(Duration::from_secs(secs));
+        }
+
+        match self.final_snapshot {
+            Some(FinalSnapshotSetting::Enabled(true)) => cli.final_snapshot = Some(FinalSnapshot::Event),
+            Some(FinalSnapshotSetting::Enabled(false)) => cli.final_snapshot = None,
+            Some(FinalSnapshotSetting::File(path)) => cli.final_snapshot = Some(FinalSnapshot::File(path)),
+            None => (),
+        }
+
+        cli.no_exit = self.no_exit.unwrap_or(cli.no_exit);
+        cli.keep_child = self.keep_child.unwrap_or(cli.keep_child);
+        cli.start_on_output = self.start_on_output.unwrap_or(cli.start_on_output);
+        cli.propagate_exit_code = self.propagate_exit_code.unwrap_or(cli.propagate_exit_code);
+
+        if let Some(size) = self.read_buffer_size {
+            cli.buffers.read_size = size;
+        }
+
+        if let Some(capacity) = self.channel_capacity {
+            cli.buffers.channel_capacity = capacity;
+        }
+
+        if let Some(capacity) = self.broadcast_capacity {
+            cli.buffers.broadcast_capacity = capacity;
+        }
+
+        if let Some(policy) = self.locale_policy {
+            cli.locale_policy = policy.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+        }
+
+        if let Some(level) = self.log_level {
+            cli.log_level = level.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+        }
+
+        if let Some(path) = self.log_file {
+            cli.log_file = Some(path);
+        }
+
+        for (name, seq) in &self.keys {
+            if seq.is_empty() {
+                bail!("key {name} in config file has an empty sequence");
+            }
+        }
+
+        cli.env.extend(self.env);
+        cli.keys.extend(self.keys);
+
+        Ok(())
+    }
+}
+
+/// `$XDG_CONFIG_HOME/htty/config.toml`, falling back to `~/.config/htty/config.toml`.
+#[cfg(feature = "pty")]
+fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(dir.join("htty").join("config.toml"))
+}
+
+/// Whether snapshots come with style data (colors and attributes) or just text.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum StyleMode {
+    #[default]
+    Plain,
+    Styled,
+}
+
+impl FromStr for StyleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "plain" => Ok(StyleMode::Plain),
+            "styled" => Ok(StyleMode::Styled),
+            _ => Err(format!("invalid style mode: {s}. Valid options: plain, styled")),
+        }
+    }
+}
+
+/// How the `styleMap` of a styled snapshot is encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StyleFormat {
+    /// One style id per cell.
+    #[default]
+    Cells,
+    /// Runs of `[styleId, count]` pairs per row.
+    Rle,
+}
+
+impl FromStr for StyleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cells" => Ok(StyleFormat::Cells),
+            "rle" => Ok(StyleFormat::Rle),
+            _ => Err(format!("invalid style format: {s}. Valid options: cells, rle")),
+        }
+    }
+}
+
+/// The fewest columns or rows a terminal may have. With fewer there's no room to move the
+/// cursor, and programs laying out a screen fall over.
+pub const MIN_SIDE: usize = 2;
+
+/// The most columns or rows a terminal may have, which keeps a screen (and each snapshot of
+/// it) small enough to hold in memory.
+pub const MAX_SIDE: usize = 10_000;
+
+/// Terminal dimensions, in columns and rows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Size {
+    cols: u16,
+    rows: u16,
+}
+
+/// Sizes `--size` accepts by name.
+const SIZE_PRESETS: &[(&str, u16, u16)] = &[("vt100", 80, 24), ("wide", 200, 50)];
+
+impl Size {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        Size { cols, rows }
+    }
+
+    /// `cols` by `rows`, unless either is outside [`MIN_SIDE`]..=[`MAX_SIDE`].
+    pub fn checked(cols: usize, rows: usize) -> Result<Self> {
+        let sides = MIN_SIDE..=MAX_SIDE;
+
+        if !sides.contains(&cols) || !sides.contains(&rows) {
+            bail!("invalid size: {cols}x{rows}. Columns and rows must each be between {MIN_SIDE} and {MAX_SIDE}");
+        }
+
+        Ok(Size::new(cols as u16, rows as u16))
+    }
+
+    /// `cols` by `rows`, each brought within [`MIN_SIDE`]..=[`MAX_SIDE`].
+    #[allow(dead_code)] // only the wasm terminal clamps rather than refusing
+    pub fn clamped(cols: usize, rows: usize) -> Self {
+        Size::new(cols.clamp(MIN_SIDE, MAX_SIDE) as u16, rows.clamp(MIN_SIDE, MAX_SIDE) as u16)
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols as usize
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows as usize
+    }
+
+    /// The size of the terminal ht is running in, found through whichever of stdout, stderr,
+    /// stdin and /dev/tty is a terminal first.
+    #[cfg(feature = "pty")]
+    fn of_terminal() -> Result<Self> {
+        let tty = fs::File::open("/dev/tty").ok();
+        let fds = [libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO];
+
+        for fd in fds.into_iter().chain(tty.as_ref().map(|f| f.as_raw_fd())) {
+            let mut winsize = nix::pty::Winsize {
+                ws_col: 0,
+                ws_row: 0,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+
+            if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) } == 0 && winsize.ws_col > 0 && winsize.ws_row > 0 {
+                return Size::checked(winsize.ws_col.into(), winsize.ws_row.into());
+            }
+        }
+
+        bail!("cannot use size auto: ht is not running in a terminal");
+    }
+
+    #[cfg(not(feature = "pty"))]
+    fn of_terminal() -> Result<Self> {
+        bail!("size auto needs htty_core's pty feature");
+    }
+}
+
+impl Default for Size {
+    fn default() -> Self {
+        Size::new(120, 40)
+    }
+}
+
+impl FromStr for Size {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Self::of_terminal();
+        }
+
+        if let Some((_, cols, rows)) = SIZE_PRESETS.iter().find(|(name, _, _)| *name == s) {
+            return Ok(Size::new(*cols, *rows));
+        }
+
+        match s.split_once('x') {
+            Some((cols, rows)) => Size::checked(cols.parse()?, rows.parse()?),
+
+            None => {
+                let presets: Vec<_> = SIZE_PRESETS.iter().map(|(name, _, _)| *name).collect();
+                bail!("invalid size format: {s}. Expected COLSxROWS, auto or one of {}", presets.join(", "));
+            }
+        }
+    }
+}
+
+impl Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.cols, self.rows)
+    }
+}
+
+/// Sub-rectangle of the virtual screen occupied by a pane, written as `COLSxROWS+X+Y`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geometry {
+    pub cols: usize,
+    pub rows: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+impl Geometry {
+    pub fn full(size: &Size) -> Self {
+        Self {
+            cols: size.cols(),
+            rows: size.rows(),
+            x: 0,
+            y: 0,
+        }
+    }
+
+    pub fn size(&self) -> Size {
+        Size::new(self.cols as u16, self.rows as u16)
+    }
+
+    pub fn fits_within(&self, size: &Size) -> bool {
+        self.x + self.cols <= size.cols() && self.y + self.rows <= size.rows()
+    }
+}
+
+impl FromStr for Geometry {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('+');
+        let size: Size = parts.next().unwrap_or_default().parse()?;
+
+        let (x, y) = match (parts.next(), parts.next(), parts.next()) {
+            (None, None, None) => (0, 0),
+            (Some(x), Some(y), None) => (x.parse()?, y.parse()?),
+            _ => bail!("invalid pane geometry: {s}. Expected COLSxROWS+X+Y"),
+        };
+
+        Ok(Self {
+            cols: size.cols(),
+            rows: size.rows(),
+            x,
+            y,
+        })
+    }
+}
+
+impl Display for Geometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.cols, self.rows, self.x, self.y)
+    }
+}
+
+/// Default colors for screens rendered as HTML, for cells that don't set their own.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum HtmlTheme {
+    /// Light grey text on black, like xterm.
+    #[default]
+    Dark,
+    /// Black text on white.
+    Light,
+}
+
+impl FromStr for HtmlTheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dark" => Ok(HtmlTheme::Dark),
+            "light" => Ok(HtmlTheme::Light),
+            _ => Err(format!("invalid html theme: {s}. Valid options: dark, light")),
+        }
+    }
+}
+
+/// Which kinds of events a client gets. The default is none of them.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Subscription {
+    pub init: bool,
+    pub snapshot: bool,
+    pub resize: bool,
+    pub output: bool,
+    pub raw_output: bool,
+    pub stderr: bool,
+    pub pid: bool,
+    pub exit_code: bool,
+    pub debug: bool,
+    pub command_completed: bool,
+    pub respawn: bool,
+    pub stopped: bool,
+    pub continued: bool,
+    pub idle: bool,
+    pub active: bool,
+    pub idle_timeout: bool,
+    pub state: bool,
+}
+
+impl Subscription {
+    /// Every kind of event.
+    #[allow(dead_code)] // ht picks events by name
+    pub fn all() -> Self {
+        Self {
+            init: true,
+            snapshot: true,
+            resize: true,
+            output: true,
+            raw_output: true,
+            stderr: true,
+            pid: true,
+            exit_code: true,
+            debug: true,
+            command_completed: true,
+            respawn: true,
+            stopped: true,
+            continued: true,
+            idle: true,
+            active: true,
+            idle_timeout: true,
+            state: true,
+        }
+    }
+
+    pub fn includes(&self, event: &Event) -> bool {
+        match event {
+            Event::Init(..) => self.init,
+            Event::Output(..) => self.output,
+            Event::RawOutput(..) => self.raw_output,
+            Event::Stderr(..) => self.stderr,
+            Event::Resize(..) => self.resize,
+            Event::Snapshot(..) => self.snapshot,
+            // Only ever sent to the client that asked for it
+            Event::InputWritten(..) => true,
+            // Always delivered: the client's input went nowhere
+            Event::InputDiscarded(..) => true,
+            // Always delivered: a command the client sent was refused
+            Event::Error(..) => true,
+            // Only broadcast if whoever flushed didn't ask for a reply
+            Event::Flushed => true,
+            Event::Pid(..) => self.pid,
+            Event::ExitCode(..) => self.exit_code,
+            Event::Debug(..) => self.debug,
+            Event::Completed(..) => self.command_completed,
+            Event::Respawn(..) => self.respawn,
+            Event::Stopped(..) => self.stopped,
+            Event::Continued(..) => self.continued,
+            Event::Idle(..) => self.idle,
+            Event::Active(..) => self.active,
+            Event::IdleTimeout(..) => self.idle_timeout,
+            Event::State(..) => self.state,
+            Event::Pane(_, event) => self.includes(event),
+            // Always delivered: a client that missed events needs to know its view is stale
+            Event::Dropped(..) => true,
+            // Always delivered too, so a client can tell ht falling behind from a quiet command
+            Event::Backlog(..) => true,
+        }
+    }
+}
+
+impl FromStr for Subscription {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sub = Subscription::default();
+
+        for event in s.split(',') {
+            match event {
+                "init" => sub.init = true,
+                "output" => sub.output = true,
+                "rawOutput" => sub.raw_output = true,
+                "stderr" => sub.stderr = true,
+                "resize" => sub.resize = true,
+                "snapshot" => sub.snapshot = true,
+                "pid" => sub.pid = true,
+                "exitCode" => sub.exit_code = true,
+                "debug" => sub.debug = true,
+                "commandCompleted" => sub.command_completed = true,
+                "respawn" => sub.respawn = true,
+                "stopped" => sub.stopped = true,
+                "continued" => sub.continued = true,
+                "idle" => sub.idle = true,
+                "active" => sub.active = true,
+                "idleTimeout" => sub.idle_timeout = true,
+                "state" => sub.state = true,
+                _ => return Err(format!("invalid event name: {event}")),
+            }
+        }
+
+        Ok(sub)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Size, Subscription};
+    #[cfg(feature = "pty")]
+    use super::Config;
+    #[cfg(feature = "pty")]
+    use crate::cli::Cli;
+    use crate::session::Event;
+
+    #[cfg(feature = "pty")]
+    fn applied(toml: &str, profile: Option<&str>) -> anyhow::Result<Cli> {
+        let config: Config = toml::from_str(toml)?;
+        let mut cli = Cli::defaults()?;
+        config.apply(&mut cli, profile)?;
+        Ok(cli)
+    }
+
+    #[cfg(feature = "pty")]
+    #[test]
+    fn apply_config() {
+        let toml = r#"
+            size = "wide"
+            style-mode = "styled"
+            no-exit = true
+
+            [env]
+            LANG = "C.UTF-8"
+            TZ = "UTC"
+
+            [profile.demo]
+            size = "90x30"
+            env = { TZ = "Europe/Paris" }
+        "#;
+
+        let cli = applied("", None).unwrap();
+        assert_eq!(cli.size.to_string(), Size::default().to_string());
+        assert!(!cli.no_exit && cli.env.is_empty());
+
+        let cli = applied(toml, None).unwrap();
+        assert_eq!(cli.size.to_string(), "200x50");
+        assert!(cli.no_exit && matches!(cli.style_mode, super::StyleMode::Styled));
+        assert_eq!(cli.env["TZ"], "UTC");
+
+        // The profile goes on top, leaving what it doesn't mention alone
+        let cli = applied(toml, Some("demo")).unwrap();
+        assert_eq!(cli.size.to_string(), "90x30");
+        assert!(cli.no_exit);
+        assert_eq!((cli.env["LANG"].as_str(), cli.env["TZ"].as_str()), ("C.UTF-8", "Europe/Paris"));
+    }
+
+    #[cfg(feature = "pty")]
+    #[test]
+    fn apply_config_errors() {
+        for (toml, profile, error) in [
+            ("", Some("demo"), "unknown profile: demo. The config file defines no profiles"),
+            ("[profile.b]\n[profile.a]\n", Some("demo"), "unknown profile: demo. Profiles in the config file: a, b"),
+            ("[profile.demo.profile.inner]\n", Some("demo"), "profile demo can't define profiles of its own"),
+            ("[profile.demo]\nsize = \"huge\"\n", Some("demo"), "in profile demo"),
+            ("size = \"huge\"\n", None, "invalid size in config file"),
+        ] {
+            assert_eq!(applied(toml, profile).unwrap_err().to_string(), error, "{toml:?}");
+        }
+
+        assert!(toml::from_str::<Config>("sise = \"wide\"\n").is_err());
+    }
+
+    #[test]
+    fn subscribe_to_all() {
+        let all = "init,output,rawOutput,stderr,resize,snapshot,pid,exitCode,debug,commandCompleted,respawn,stopped,continued,idle,active,idleTimeout,state";
+        let parsed: Subscription = all.parse().unwrap();
+
+        for event in [Event::Output(0.0, String::new()), Event::Idle(0.0, Default::default()), Event::Debug(0.0, String::new())] {
+            assert!(parsed.includes(&event) && Subscription::all().includes(&event));
+            assert!(!Subscription::default().includes(&event));
+        }
+    }
+
+    #[test]
+    fn parse_size() {
+        for (s, expected) in [("90x30", "90x30"), ("vt100", "80x24"), ("wide", "200x50")] {
+            assert_eq!(s.parse::<Size>().unwrap().to_string(), expected);
+        }
+
+        for s in ["90", "90xabc", "huge", "0x24", "80x1", "10001x10", "80x65536"] {
+            s.parse::<Size>().expect_err(s);
+        }
+
+        assert_eq!(Size::clamped(0, 100_000).to_string(), "2x10000");
+    }
+}
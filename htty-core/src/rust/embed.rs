@@ -0,0 +1,732 @@
+//! Running a command in a headless terminal from within another program, without going
+//! through the `ht` binary and its JSON protocol.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use htty_core::Htty;
+//!
+//! let htty = Htty::builder().size(80, 24).command(["vim"]).spawn()?;
+//! htty.send_keys(["ihello", "Escape"]).await?;
+//! println!("{}", htty.snapshot().await?.text);
+//! htty.send_keys([":q!", "Enter"]).await?;
+//! let status = htty.wait_exit().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::config::{Size, StyleMode};
+use crate::command::{self, Command};
+use crate::error::{HttyError, Result};
+use crate::exec::Follower;
+use crate::keys;
+use crate::pty;
+use crate::runtime::RuntimeDir;
+use crate::session::{self, Backlog, Event, Session, SessionState};
+use crate::shutdown::Shutdown;
+use crate::timing::Timing;
+use crate::utf8;
+use anyhow::anyhow;
+use futures_util::stream::{FuturesOrdered, StreamExt};
+use futures_util::Stream;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Entry point for running a command in an in-process headless terminal.
+pub struct Htty;
+
+impl Htty {
+    pub fn builder() -> HttyBuilder {
+        HttyBuilder::default()
+    }
+}
+
+/// Configures the terminal and the command to run in it. See [`Htty::builder`].
+#[derive(Debug, Clone)]
+pub struct HttyBuilder {
+    cols: u16,
+    rows: u16,
+    command: Vec<String>,
+    style_mode: StyleMode,
+    keys: HashMap<String, String>,
+    options: pty::Options,
+    /// What each spawned session's own shutdown is made a child of, if anything.
+    parent_shutdown: Option<Shutdown>,
+}
+
+impl Default for HttyBuilder {
+    fn default() -> Self {
+        Self {
+            cols: 120,
+            rows: 40,
+            command: Vec::new(),
+            style_mode: StyleMode::default(),
+            keys: HashMap::new(),
+            // The wait-exit helper defaults to the current executable, which here is the
+            // embedding program rather than ht, so it is only used when asked for
+            options: pty::Options {
+                wait_exit: false,
+                ..pty::Options::default()
+            },
+            parent_shutdown: None,
+        }
+    }
+}
+
+impl HttyBuilder {
+    pub fn size(mut self, cols: u16, rows: u16) -> Self {
+        self.cols = cols;
+        self.rows = rows;
+        self
+    }
+
+    /// The command to run, as on ht's command line: a program and its arguments, passed to it
+    /// as they are, or a single command line for `/bin/sh -c` to interpret.
+    pub fn command<I, S>(mut self, command: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.command = command.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set an environment variable for the command, on top of this process's environment.
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.env.insert(name.into(), value.into());
+        self
+    }
+
+    /// Report the command's completion through `<helper> wait-exit`, as ht does, before the
+    /// PTY closes. `helper` is usually the path of the `ht` binary.
+    pub fn wait_exit_helper(mut self, helper: impl Into<PathBuf>) -> Self {
+        self.options.wait_exit = true;
+        self.options.wait_exit_helper = Some(helper.into());
+        self
+    }
+
+    pub fn style_mode(mut self, style_mode: StyleMode) -> Self {
+        self.style_mode = style_mode;
+        self
+    }
+
+    /// Custom key names for `send_keys`, mapping to the text they send.
+    pub fn keys(mut self, keys: HashMap<String, String>) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.options.timing = timing;
+        self
+    }
+
+    /// End the session when `shutdown` is triggered, along with whatever else it was given
+    /// to. Ending the session on its own leaves `shutdown` untriggered.
+    pub fn shutdown(mut self, shutdown: &Shutdown) -> Self {
+        self.parent_shutdown = Some(shutdown.clone());
+        self
+    }
+
+    /// Start the command. Must be called from within a Tokio runtime.
+    pub fn spawn(self) -> Result<Handle> {
+        if self.command.is_empty() {
+            return Err(self.spawn_failed(anyhow!("no command to run")));
+        }
+
+        let size = Size::checked(self.cols.into(), self.rows.into()).map_err(|e| self.spawn_failed(e))?;
+
+        let (input_tx, input_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (control_tx, control_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (output_tx, output_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (stderr_tx, stderr_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (command_tx, command_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (clients_tx, clients_rx) = mpsc::channel(1);
+        let (pid_tx, pid_rx) = mpsc::channel(1);
+        let (exit_code_tx, exit_code_rx) = mpsc::channel(1);
+        let (exit_tx, exit_rx) = watch::channel(None);
+        let runtime_dir = RuntimeDir::create().map_err(|e| self.spawn_failed(e))?;
+        let timing = self.options.timing;
+        let shutdown = self.parent_shutdown.as_ref().map_or_else(Shutdown::new, Shutdown::child);
+
+        let pty = pty::spawn(
+            pty::Program::from_args(self.command.clone()),
+            runtime_dir.fifo_path(),
+            &size,
+            self.options,
+            input_rx,
+            control_rx,
+            output_tx,
+            stderr_tx,
+            pid_tx,
+            exit_code_tx,
+            command_tx.clone(),
+            shutdown.clone(),
+        )?;
+
+        tokio::spawn(pty);
+
+        let mut session = Session::new(self.cols as usize, self.rows as usize);
+        session.set_style_mode(self.style_mode);
+
+        let channels = Channels {
+            output_rx,
+            stderr_rx,
+            input_tx,
+            control_tx,
+            command_rx,
+            clients_rx,
+            pid_rx,
+            exit_code_rx,
+            exit_tx,
+        };
+
+        let task = tokio::spawn(run(session, channels, runtime_dir, shutdown.clone()));
+
+        Ok(Handle {
+            command_tx,
+            clients_tx,
+            exit_rx,
+            keys: self.keys,
+            timing,
+            shutdown,
+            task: Some(task),
+        })
+    }
+}
+
+impl HttyBuilder {
+    pub(crate) fn spawn_failed(&self, source: anyhow::Error) -> HttyError {
+        HttyError::SpawnFailed {
+            command: pty::Program::from_args(self.command.clone()).to_string(),
+            source,
+        }
+    }
+}
+
+/// The screen at the moment a snapshot was taken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub cols: usize,
+    pub rows: usize,
+    /// The screen's text, one line per row.
+    pub text: String,
+    /// Escape sequences redrawing the screen, with colors and attributes.
+    pub seq: String,
+}
+
+/// A running command and its terminal. Dropping it ends the session, hanging up on the
+/// command if it's still running.
+pub struct Handle {
+    command_tx: mpsc::Sender<Command>,
+    clients_tx: mpsc::Sender<session::Client>,
+    exit_rx: watch::Receiver<Option<pty::ExitStatus>>,
+    keys: HashMap<String, String>,
+    timing: Timing,
+    shutdown: Shutdown,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Handle {
+    /// Send keys (as named for ht's `sendKeys`), returning once they have been written to
+    /// the terminal.
+    pub async fn send_keys<I, S>(&self, keys: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let names = keys.into_iter().map(Into::into).collect();
+        let seqs = keys::key_seqs(names, &self.keys);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(Command::Input(seqs, Some(reply_tx))).await?;
+
+        match reply_rx.await.map_err(|_| HttyError::SessionEnded)? {
+            Event::InputWritten(_) => Ok(()),
+            Event::InputDiscarded(bytes) => Err(HttyError::InputDiscarded(bytes)),
+            event => Err(HttyError::ProtocolError(format!("{event:?} in reply to input"))),
+        }
+    }
+
+    pub async fn snapshot(&self) -> Result<Snapshot> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(Command::Snapshot(Some(reply_tx))).await?;
+
+        match reply_rx.await.map_err(|_| HttyError::SessionEnded)? {
+            Event::Snapshot(_, cols, rows, seq, text, _) => Ok(Snapshot { cols, rows, text, seq }),
+            event => Err(HttyError::ProtocolError(format!("{event:?} in reply to a snapshot"))),
+        }
+    }
+
+    /// Wait up to `timeout` for the screen to contain `text`, like `expect` in `ht exec`
+    /// scripts. Gives up early, with `HttyError::ChildExited`, if the command exits.
+    pub async fn expect(&self, text: &str, timeout: Duration) -> Result<()> {
+        self.until(timeout, &format!("contain {text:?}"), |screen| screen.contains(text)).await
+    }
+
+    /// Like `expect`, waiting for the screen to match `pattern`.
+    pub async fn expect_re(&self, pattern: &Regex, timeout: Duration) -> Result<()> {
+        self.until(timeout, &format!("match {:?}", pattern.as_str()), |screen| pattern.is_match(screen)).await
+    }
+
+    /// Follow the screen until it satisfies `done`, failing with what it was `expected` to do
+    /// and what it showed instead if it doesn't in time.
+    async fn until(&self, timeout: Duration, expected: &str, done: impl Fn(&str) -> bool) -> Result<()> {
+        let mut events = self.events().await?;
+        let mut follower = Follower::new(self.command_tx.clone());
+
+        if follower.until(&mut events, Instant::now() + timeout, done).await {
+            return Ok(());
+        }
+
+        let message = format!("expected the screen to {expected}\n{}", follower.screen.text());
+
+        match self.exit_rx.borrow().clone() {
+            Some(status) => Err(HttyError::ChildExited { status, message }),
+            None => Err(HttyError::Timeout(message)),
+        }
+    }
+
+    /// Wait for the command to exit. Returns `None` if the session ended first.
+    pub async fn wait_exit(&self) -> Option<pty::ExitStatus> {
+        let mut exit_rx = self.exit_rx.clone();
+        let status = exit_rx.wait_for(Option::is_some).await.ok()?;
+
+        status.clone()
+    }
+
+    /// Subscribe to the session's events, starting with `Event::Init`.
+    pub async fn events(&self) -> Result<impl Stream<Item = Event> + Unpin> {
+        session::stream(&self.clients_tx, self.timing.subscription_timeout).await
+    }
+
+    /// Start ending the session, hanging up on the command if it's still running, without
+    /// waiting for it to end. Anything waiting on the session returns once it has.
+    pub fn shutdown(&self) {
+        self.shutdown.trigger();
+    }
+
+    /// End the session, hanging up on the command if it's still running, and return its
+    /// exit status if it was seen before then.
+    pub async fn close(mut self) -> Result<Option<pty::ExitStatus>> {
+        self.shutdown.trigger();
+
+        if let Some(task) = self.task.take() {
+            if let Err(e) = task.await {
+                if e.is_panic() {
+                    std::panic::resume_unwind(e.into_panic());
+                }
+            }
+        }
+
+        let status = self.exit_rx.borrow().clone();
+
+        Ok(status)
+    }
+
+    async fn send(&self, command: Command) -> Result<()> {
+        self.command_tx.send(command).await.map_err(|_| HttyError::SessionEnded)
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.shutdown.trigger();
+    }
+}
+
+struct Channels {
+    output_rx: mpsc::Receiver<Vec<u8>>,
+    stderr_rx: mpsc::Receiver<Vec<u8>>,
+    input_tx: mpsc::Sender<Vec<u8>>,
+    control_tx: mpsc::Sender<pty::Control>,
+    command_rx: mpsc::Receiver<Command>,
+    clients_rx: mpsc::Receiver<session::Client>,
+    pid_rx: mpsc::Receiver<i32>,
+    exit_code_rx: mpsc::Receiver<pty::ExitStatus>,
+    exit_tx: watch::Sender<Option<pty::ExitStatus>>,
+}
+
+/// A pared-down version of ht's event loop: there are no panes, API servers or idle
+/// tracking, and the session lives on after the command exits until it's closed.
+async fn run(mut session: Session, mut channels: Channels, runtime_dir: RuntimeDir, shutdown: Shutdown) {
+    let mut output_open = true;
+    // An exit status that arrived while output sent ahead of it was still queued
+    let mut held_exit = None;
+    let mut stderr_open = true;
+    let mut output_decoder = utf8::Decoder::default();
+    let mut stderr_decoder = utf8::Decoder::default();
+    let mut state = SessionState::Starting;
+    session.emit_state(state);
+    let mut flushes = FuturesOrdered::new();
+    let mut resizes = FuturesOrdered::new();
+    let mut output_backlog = Backlog::new("output", CHANNEL_CAPACITY);
+    let mut command_backlog = Backlog::new("command", CHANNEL_CAPACITY);
+
+    loop {
+        let output_flush_deadline = session.output_flush_deadline();
+
+        tokio::select! {
+            data = channels.output_rx.recv(), if output_open => {
+                match data {
+                    Some(data) => {
+                        session.check_backlog(&mut output_backlog, channels.output_rx.len());
+                        let text = output_decoder.decode(&data);
+
+                        if !text.is_empty() {
+                            session.output(text);
+                        }
+
+                        if channels.output_rx.is_empty() {
+                            if let Some(status) = held_exit.take() {
+                                report_exit(&mut session, &mut state, &channels.exit_tx, status);
+                            }
+                        }
+                    }
+
+                    None => {
+                        session.flush_output();
+                        output_open = false;
+
+                        if let Some(status) = held_exit.take() {
+                            report_exit(&mut session, &mut state, &channels.exit_tx, status);
+                        }
+                    }
+                }
+            }
+
+            data = channels.stderr_rx.recv(), if stderr_open => {
+                match data {
+                    Some(data) => {
+                        let text = stderr_decoder.decode(&data);
+
+                        if !text.is_empty() {
+                            session.stderr(text);
+                        }
+                    }
+
+                    None => stderr_open = false,
+                }
+            }
+
+            _ = sleep_until_std(output_flush_deadline), if output_flush_deadline.is_some() => {
+                session.flush_output();
+            }
+
+            Some(reply) = flushes.next(), if !flushes.is_empty() => session.emit_flushed(reply),
+
+            Some((size, reported)) = resizes.next(), if !resizes.is_empty() => session.follow_pty_resize(size, reported),
+
+            Some(pid) = channels.pid_rx.recv() => {
+                session.emit_pid(pid);
+                enter(&mut session, &mut state, SessionState::Running);
+            }
+
+            Some(status) = channels.exit_code_rx.recv() => {
+                // The output the command wrote before exiting was sent first, and comes first
+                if output_open && !channels.output_rx.is_empty() {
+                    held_exit = Some(status);
+                } else {
+                    report_exit(&mut session, &mut state, &channels.exit_tx, status);
+                }
+            }
+
+            command = channels.command_rx.recv() => {
+                session.check_backlog(&mut command_backlog, channels.command_rx.len());
+
+                match command {
+                    Some(command) if !command.valid_in(state) => command::reject(&mut session, command, state),
+
+                    Some(Command::Input(seqs, reply)) => {
+                        let data = session.encode_input(&seqs);
+
+                        if let Err(discarded) = command::send_input(&channels.input_tx, &channels.control_tx, data, reply, |event| event).await {
+                            enter(&mut session, &mut state, SessionState::ChildExited);
+                            session.emit_input_discarded(discarded.bytes, discarded.reply);
+                        }
+                    }
+
+                    Some(Command::Snapshot(Some(reply))) => session.snapshot_for(reply),
+
+                    Some(Command::Snapshot(None)) => session.snapshot(),
+
+                    Some(Command::Resize(cols, rows)) => match Size::checked(cols, rows) {
+                        Ok(size) => {
+                            let resized = command::resize(&channels.control_tx, size).await;
+                            resizes.push_back(async move { (size, resized.await) });
+                        }
+
+                        // Refused, with an error event
+                        Err(_) => session.resize(cols, rows),
+                    },
+
+                    Some(Command::SetStyleMode(style_mode)) => session.set_style_mode(style_mode),
+
+                    Some(Command::SetStyleFormat(style_format)) => session.set_style_format(style_format),
+
+                    Some(Command::SetTermios(settings)) => {
+                        let _ = channels.control_tx.send(pty::Control::SetTermios(settings)).await;
+                    }
+
+                    Some(Command::CloseStdin) => {
+                        let _ = channels.control_tx.send(pty::Control::CloseStdin).await;
+                    }
+
+                    Some(Command::Completed(fifo_path)) => {
+                        session.emit_command_completed();
+
+                        // Let go of the command once the input sent before its completion is written
+                        let fence = command::fence([&channels.control_tx]).await;
+                        tokio::spawn(async move {
+                            fence.await;
+                            let _ = pty::release_wait_exit(&fifo_path);
+                        });
+                    }
+
+                    Some(Command::Respawn(attempt, delay)) => {
+                        session.emit_respawn(attempt, delay);
+                        enter(&mut session, &mut state, SessionState::Starting);
+                    }
+
+                    Some(Command::Stopped(signal)) => session.emit_stopped(signal),
+
+                    Some(Command::Continued) => session.emit_continued(),
+
+                    Some(Command::Flush(reply)) => {
+                        let fence = command::fence([&channels.control_tx]).await;
+                        flushes.push_back(async move {
+                            fence.await;
+                            reply
+                        });
+                    }
+
+                    Some(Command::Debug(message)) => session.emit_debug_event(&message),
+
+                    Some(Command::Pane(id, _)) => session.emit_debug_event(&format!("unknownPane:{id}")),
+
+                    Some(Command::Exit) | None => break,
+                }
+            }
+
+            Some(client) = channels.clients_rx.recv() => client.accept(&mut session),
+
+            _ = shutdown.triggered() => break,
+        }
+    }
+
+    session.flush_output();
+    enter(&mut session, &mut state, SessionState::Closed);
+    // The PTY driver hangs up on the command, if it hasn't exited
+    shutdown.trigger();
+    drop(channels);
+    drop(runtime_dir);
+}
+
+fn report_exit(session: &mut Session, state: &mut SessionState, exit_tx: &watch::Sender<Option<pty::ExitStatus>>, status: pty::ExitStatus) {
+    session.emit_exit_code(status.clone());
+    enter(session, state, SessionState::ChildExited);
+    exit_tx.send_replace(Some(status));
+}
+
+fn enter(session: &mut Session, state: &mut SessionState, next: SessionState) {
+    if state.can_become(next) {
+        *state = next;
+        session.emit_state(next);
+    }
+}
+
+async fn sleep_until_std(deadline: Option<std::time::Instant>) {
+    if let Some(deadline) = deadline {
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Htty;
+    use crate::error::HttyError;
+    use crate::session::{Event, SessionState};
+    use futures_util::{FutureExt, StreamExt};
+    use crate::shutdown::Shutdown;
+    use nix::libc;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn runs_command_to_completion() {
+        let htty = Htty::builder().size(20, 5).command(["echo", "hello"]).spawn().unwrap();
+        let status = timeout(Duration::from_secs(5), htty.wait_exit()).await.unwrap().unwrap();
+        assert_eq!(status.code, 0);
+
+        let snapshot = htty.snapshot().await.unwrap();
+        assert_eq!((snapshot.cols, snapshot.rows), (20, 5));
+        assert!(snapshot.text.starts_with("hello"), "{:?}", snapshot.text);
+
+        assert_eq!(htty.close().await.unwrap().map(|s| s.code), Some(0));
+    }
+
+    #[tokio::test]
+    async fn passes_arguments_untouched() {
+        let htty = Htty::builder().size(20, 5).command(["printf", "%s|%s", "a  b", "c;d $HOME"]).spawn().unwrap();
+        timeout(Duration::from_secs(5), htty.wait_exit()).await.unwrap();
+
+        let snapshot = htty.snapshot().await.unwrap();
+        assert!(snapshot.text.starts_with("a  b|c;d $HOME"), "{:?}", snapshot.text);
+    }
+
+    #[tokio::test]
+    async fn sends_keys() {
+        let htty = Htty::builder().size(20, 5).command(["cat"]).spawn().unwrap();
+        htty.send_keys(["hi", "Enter"]).await.unwrap();
+
+        timeout(Duration::from_secs(5), async {
+            while !htty.snapshot().await.unwrap().text.lines().map(str::trim_end).eq(["hi", "hi", "", "", ""]) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        htty.send_keys(["C-d"]).await.unwrap();
+        let status = timeout(Duration::from_secs(5), htty.wait_exit()).await.unwrap().unwrap();
+        assert_eq!(status.code, 0);
+
+        let error = htty.send_keys(["late"]).await.unwrap_err();
+        assert!(matches!(error, HttyError::InputDiscarded(4)), "{error:?}");
+    }
+
+    #[tokio::test]
+    async fn reports_state() {
+        let htty = Htty::builder().size(20, 5).command(["cat"]).spawn().unwrap();
+        let mut events = htty.events().await.unwrap();
+        htty.send_keys(["C-d"]).await.unwrap();
+
+        let exited = timeout(Duration::from_secs(5), async {
+            while let Some(event) = events.next().await {
+                match event {
+                    Event::ExitCode(..) => return true,
+                    Event::State(_, SessionState::ChildExited) => return false,
+                    _ => (),
+                }
+            }
+
+            false
+        });
+
+        assert!(exited.await.unwrap(), "the state changed before the exit code arrived");
+        assert!(matches!(events.next().await, Some(Event::State(_, SessionState::ChildExited))));
+    }
+
+    #[tokio::test]
+    async fn shared_shutdown() {
+        let shutdown = Shutdown::new();
+        let builder = Htty::builder().size(20, 5).command(["sleep", "999"]).shutdown(&shutdown);
+        let first = builder.clone().spawn().unwrap();
+        let second = builder.spawn().unwrap();
+
+        timeout(Duration::from_secs(5), first.close()).await.unwrap().unwrap();
+        assert!(!shutdown.is_triggered());
+        assert!(second.snapshot().await.is_ok(), "closing one session ended the other");
+        assert!(second.wait_exit().now_or_never().is_none());
+
+        shutdown.trigger();
+        timeout(Duration::from_secs(5), second.wait_exit()).await.unwrap();
+        assert!(matches!(second.snapshot().await, Err(HttyError::SessionEnded)));
+    }
+
+    #[tokio::test]
+    async fn missing_program() {
+        let htty = Htty::builder().size(80, 5).command(["/nonexistent/prog", "--version"]).spawn().unwrap();
+        let status = timeout(Duration::from_secs(5), htty.wait_exit()).await.unwrap().unwrap();
+        assert_eq!(status.code, 127);
+
+        let snapshot = htty.snapshot().await.unwrap();
+        assert!(snapshot.text.starts_with("ht: cannot start '/nonexistent/prog' '--version': No such file"), "{:?}", snapshot.text);
+    }
+
+    #[tokio::test]
+    async fn failed_setup() {
+        let mut builder = Htty::builder().size(80, 5).command(["echo", "hello"]);
+        builder.options.cgroup = Some("/nonexistent/cgroup".into());
+        let htty = builder.spawn().unwrap();
+
+        let status = timeout(Duration::from_secs(5), htty.wait_exit()).await.unwrap().unwrap();
+        assert_eq!(status.code, 127);
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let error = Htty::builder().spawn().err().unwrap();
+        assert!(matches!(error, HttyError::SpawnFailed { .. }), "{error:?}");
+
+        let htty = Htty::builder().size(20, 5).command(["echo", "bye"]).spawn().unwrap();
+        let error = htty.expect("hello", Duration::from_secs(5)).await.unwrap_err();
+
+        match error {
+            HttyError::ChildExited { status, message } => {
+                assert_eq!(status.code, 0);
+                assert!(message.starts_with("expected the screen to contain \"hello\"\nbye"), "{message}");
+            }
+
+            error => panic!("{error:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn expects_last_output() {
+        // Subscribed while the command runs, so its exit code comes in on the follower's events
+        let htty = Htty::builder().size(20, 5).command(["sh", "-c", "sleep 0.2; echo bye"]).spawn().unwrap();
+        htty.expect("bye", Duration::from_secs(5)).await.unwrap();
+        assert_eq!(htty.close().await.unwrap().map(|s| s.code), Some(0));
+    }
+
+    #[tokio::test]
+    async fn output_after_hangup() {
+        // The command leaves the path of its terminal behind, then exits, hanging it up
+        let path = std::env::temp_dir().join(format!("htty-hangup-test-{}", std::process::id()));
+        let script = format!("tty > '{}'", path.display());
+        let mut builder = Htty::builder().size(20, 5).command(["sh", "-c", &script]);
+        builder.options.survive_hangup = true;
+        let htty = builder.spawn().unwrap();
+        timeout(Duration::from_secs(5), htty.wait_exit()).await.unwrap();
+
+        // Something else reopens it by that path
+        let tty = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut tty = OpenOptions::new().write(true).custom_flags(libc::O_NOCTTY).open(tty.trim()).unwrap();
+        tty.write_all(b"back\n").unwrap();
+
+        timeout(Duration::from_secs(5), async {
+            while !htty.snapshot().await.unwrap().text.contains("back") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        timeout(Duration::from_secs(5), htty.close()).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn child_exited_shows_last_output() {
+        let htty = Htty::builder().size(20, 5).command(["sh", "-c", "sleep 0.2; echo bye; exit 3"]).spawn().unwrap();
+        let error = htty.expect("hello", Duration::from_secs(5)).await.unwrap_err();
+
+        match error {
+            HttyError::ChildExited { status, message } => {
+                assert_eq!(status.code, 3);
+                assert!(message.starts_with("expected the screen to contain \"hello\"\nbye"), "{message}");
+            }
+
+            error => panic!("{error:?}"),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Errors returned by the library's API, for callers that need to tell failures apart.
+//!
+//! The `ht` binary, and the modules only it uses, report errors through anyhow; every
+//! `HttyError` converts into an `anyhow::Error` with `?`.
+
+use crate::status::ExitStatus;
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum HttyError {
+    /// The command couldn't be started in a terminal.
+    #[error("cannot start {command:?}")]
+    SpawnFailed {
+        command: String,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    /// Reading from or writing to the terminal failed.
+    #[error("terminal I/O failed")]
+    PtyIo(#[from] io::Error),
+
+    /// The session answered with something other than what was asked of it.
+    #[allow(dead_code)] // only the embedded API asks the session for replies
+    #[error("unexpected reply from the session: {0}")]
+    ProtocolError(String),
+
+    /// The screen didn't show what was expected, or the session didn't answer, in time.
+    #[error("{0}")]
+    Timeout(String),
+
+    /// The command exited while waiting for the screen to show something. `message` says
+    /// what was expected, and what the screen showed instead.
+    #[allow(dead_code)] // only the embedded API waits on the screen
+    #[error("{message}")]
+    ChildExited { status: ExitStatus, message: String },
+
+    /// Keys were sent after the command had exited, so there was nothing to write them to.
+    #[allow(dead_code)] // only the embedded API reports this as an error
+    #[error("the command has exited, so {0} bytes of input were discarded")]
+    InputDiscarded(usize),
+
+    /// The session had ended, or been shut down, before it could do what was asked.
+    #[error("the session has ended")]
+    SessionEnded,
+}
+
+pub type Result<T> = std::result::Result<T, HttyError>;
@@ -0,0 +1,260 @@
+//! Scripts for `ht exec`, one step per line:
+//!
+//! ```text
+//! # comments and blank lines are skipped
+//! type echo hello      sends the rest of the line as is
+//! send Enter C-c       sends each key, named as in sendKeys
+//! wait 500             waits that many milliseconds
+//! expect hello         waits for the screen to contain the rest of the line
+//! expect-re hel+o      waits for the screen to match the regular expression
+//! snapshot             prints the screen to stdout
+//! ```
+//!
+//! An expectation that isn't met within the timeout fails, and the script carries on.
+
+use crate::cli::ScreenFormat;
+use crate::command::{Command, InputSeq};
+use crate::keys;
+use crate::session::{self, Event};
+use crate::snapshot::{self, Screen};
+use crate::timing::Timing;
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::{FutureExt, Stream, StreamExt};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Instant};
+
+#[derive(Debug, Clone)]
+pub enum Step {
+    Type(String),
+    Send(Vec<String>),
+    Wait(Duration),
+    Expect(String),
+    ExpectRegex(Regex),
+    Snapshot,
+}
+
+/// The steps of a script, with the line each one is on.
+#[derive(Debug, Clone)]
+pub struct Script(Vec<(usize, Step)>);
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("cannot read script {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("invalid script {}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut steps = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let arg = arg.trim_start();
+
+            steps.push((i + 1, Step::parse(name, arg).map_err(|e| anyhow!("line {}: {e}", i + 1))?));
+        }
+
+        Ok(Self(steps))
+    }
+}
+
+impl Step {
+    fn parse(name: &str, arg: &str) -> Result<Self> {
+        let step = match name {
+            "type" => Step::Type(arg.to_string()),
+            "send" => Step::Send(arg.split_whitespace().map(str::to_string).collect()),
+            "wait" => Step::Wait(Duration::from_millis(arg.parse().map_err(|_| anyhow!("invalid wait: {arg}. Expected milliseconds"))?)),
+            "expect" => Step::Expect(arg.to_string()),
+            "expect-re" => Step::ExpectRegex(Regex::new(arg)?),
+            "snapshot" => Step::Snapshot,
+            _ => bail!("unknown step: {name}. Valid steps: type, send, wait, expect, expect-re, snapshot"),
+        };
+
+        match &step {
+            Step::Send(keys) if keys.is_empty() => bail!("send needs at least one key"),
+            Step::Expect(text) if text.is_empty() => bail!("expect needs some text to look for"),
+            Step::Snapshot if !arg.is_empty() => bail!("snapshot takes no arguments"),
+            _ => Ok(step),
+        }
+    }
+}
+
+/// Stand-in for the stdio API used by `ht exec`: runs `script` against the command.
+///
+/// Failed expectations are reported on stderr along with the screen. Once the script is done ht is
+/// told to exit, and whether every expectation was met is sent to `passed_tx`.
+#[allow(clippy::too_many_arguments)]
+pub async fn start(
+    command_tx: mpsc::Sender<Command>,
+    clients_tx: mpsc::Sender<session::Client>,
+    script: Script,
+    timeout: Duration,
+    keys: HashMap<String, String>,
+    passed_tx: oneshot::Sender<bool>,
+    timing: Timing,
+) -> Result<()> {
+    let failures = run(&command_tx, &clients_tx, script, timeout, &keys, timing).await;
+
+    // Exit even if the script couldn't be finished, e.g. because stdout was closed
+    command_tx.send(Command::Exit).await?;
+    let _ = passed_tx.send(matches!(failures, Ok(0)));
+
+    failures.map(|_| ())
+}
+
+/// Run the steps of `script`, returning how many expectations weren't met.
+async fn run(
+    command_tx: &mpsc::Sender<Command>,
+    clients_tx: &mpsc::Sender<session::Client>,
+    script: Script,
+    timeout: Duration,
+    keys: &HashMap<String, String>,
+    timing: Timing,
+) -> Result<usize> {
+    let mut events = session::stream(clients_tx, timing.subscription_timeout).await?;
+    let mut follower = Follower::new(command_tx.clone());
+    let mut failures = 0;
+
+    for (line, step) in script.0 {
+        match step {
+            Step::Type(text) => command_tx.send(Command::Input(vec![InputSeq::Standard(text)], None)).await?,
+
+            Step::Send(names) => command_tx.send(Command::Input(keys::key_seqs(names, keys), None)).await?,
+
+            Step::Wait(duration) => {
+                follower.until(&mut events, Instant::now() + duration, |_| false).await;
+            }
+
+            Step::Expect(text) => {
+                if !follower.until(&mut events, Instant::now() + timeout, |screen| screen.contains(&text)).await {
+                    failures += 1;
+                    eprintln!("line {line}: expected the screen to contain {text:?}\n{}", follower.screen.text());
+                }
+            }
+
+            Step::ExpectRegex(pattern) => {
+                if !follower.until(&mut events, Instant::now() + timeout, |screen| pattern.is_match(screen)).await {
+                    failures += 1;
+                    eprintln!("line {line}: expected the screen to match {:?}\n{}", pattern.as_str(), follower.screen.text());
+                }
+            }
+
+            Step::Snapshot => snapshot::print(command_tx, ScreenFormat::Text).await?,
+        }
+    }
+
+    Ok(failures)
+}
+
+/// Keeps the screen up to date, and notices when the command has exited.
+pub struct Follower {
+    pub screen: Screen,
+    exited: bool,
+    command_tx: mpsc::Sender<Command>,
+}
+
+impl Follower {
+    /// Follow the screen of the session that `command_tx` sends commands to.
+    pub fn new(command_tx: mpsc::Sender<Command>) -> Self {
+        Self {
+            screen: Screen::default(),
+            exited: false,
+            command_tx,
+        }
+    }
+
+    /// Follow `events` until the screen text satisfies `done`, returning false if it doesn't by
+    /// `deadline` (or, since the screen won't change any more, if the command has exited).
+    pub async fn until(
+        &mut self,
+        events: &mut (impl Stream<Item = Event> + Unpin),
+        deadline: Instant,
+        done: impl Fn(&str) -> bool,
+    ) -> bool {
+        loop {
+            if done(&self.screen.text()) {
+                return true;
+            }
+
+            if self.exited {
+                return false;
+            }
+
+            tokio::select! {
+                event = events.next() => match event {
+                    Some(Event::ExitCode(..)) => {
+                        self.exited = true;
+                        self.catch_up(events, deadline).await;
+                    }
+
+                    Some(event) => {
+                        self.screen.apply(&event);
+                    }
+
+                    None => self.exited = true,
+                },
+
+                _ = time::sleep_until(deadline) => return false,
+            }
+        }
+    }
+
+    /// Apply whatever the session has sent by the time a flush comes back, so that the screen
+    /// isn't judged on before output that was still on its way when the command exited.
+    async fn catch_up(&mut self, events: &mut (impl Stream<Item = Event> + Unpin), deadline: Instant) {
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        // A session that has ended answers no flush, but its events end instead
+        if self.command_tx.send(Command::Flush(Some(reply_tx))).await.is_ok() {
+            let _ = time::timeout_at(deadline, reply_rx).await;
+        }
+
+        while let Some(Some(event)) = events.next().now_or_never() {
+            self.screen.apply(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Script, Step};
+    use std::time::Duration;
+
+    #[test]
+    fn parse_script() {
+        let script = Script::parse("# start\n\ntype echo  hi\nsend Enter C-c\nwait 250\nexpect  hi there\nexpect-re ^h\\w+\nsnapshot\n").unwrap();
+        let steps: Vec<_> = script.0.iter().map(|(line, step)| (*line, step)).collect();
+
+        assert!(matches!(steps[0], (3, Step::Type(text)) if text == "echo  hi"));
+        assert!(matches!(steps[1], (4, Step::Send(keys)) if keys == &["Enter", "C-c"]));
+        assert!(matches!(steps[2], (5, Step::Wait(d)) if *d == Duration::from_millis(250)));
+        assert!(matches!(steps[3], (6, Step::Expect(text)) if text == "hi there"));
+        assert!(matches!(steps[4], (7, Step::ExpectRegex(re)) if re.as_str() == "^h\\w+"));
+        assert!(matches!(steps[5], (8, Step::Snapshot)));
+    }
+
+    #[test]
+    fn parse_script_invalid() {
+        for (script, error) in [
+            ("press Enter", "line 1: unknown step: press"),
+            ("\nwait soon", "line 2: invalid wait: soon"),
+            ("send", "line 1: send needs at least one key"),
+            ("expect", "line 1: expect needs some text"),
+            ("expect-re (", "line 1: regex parse error"),
+            ("snapshot now", "line 1: snapshot takes no arguments"),
+        ] {
+            let e = Script::parse(script).expect_err(script).to_string();
+            assert!(e.starts_with(error), "{script:?} gave {e:?}");
+        }
+    }
+}
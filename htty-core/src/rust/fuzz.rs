@@ -0,0 +1,186 @@
+//! `ht fuzz`: feeding the files in a corpus to a session as though a command had written them
+//! to the terminal, or as keys to send it, with no command or PTY involved, to shake out panics
+//! in the terminal emulation and the key parsing behind it.
+//!
+//! Each file is one case. As output ([`Feed::Output`]), its bytes are decoded as a command's
+//! output would be, `chunk` bytes at a time so that escape sequences and characters get split
+//! across reads too, and then the screen is snapshotted. As input ([`Feed::Input`]), each of its
+//! lines is a key name, as `sendKeys` and `ht keys` take them, and the keys are encoded into the
+//! bytes the command would read. A JSON line is printed per case:
+//!
+//! ```text
+//! {"file":"corpus/cursor-moves","snapshot":{"cols":80,"rows":24,"seq":"...","text":"..."}}
+//! {"file":"corpus/huge-csi","panic":"attempt to subtract with overflow"}
+//! {"file":"corpus/arrows","input":"\u001b[A\u001b[B"}
+//! ```
+
+use crate::keys;
+use crate::session::{Event, Session};
+use crate::utf8;
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// How a case's file is fed to the session.
+#[derive(Debug, Clone, Copy)]
+pub enum Feed<'a> {
+    /// As a command's output, this many bytes at a time.
+    Output(usize),
+    /// As key names, one per line, looked up in these custom keys before the standard ones.
+    Input(&'a HashMap<String, String>),
+}
+
+/// Run every file in `corpus` (or `corpus` itself, if it's a file) as a case on a session of
+/// `cols` by `rows`, printing each case's outcome. Returns how many of them panicked.
+pub fn run(corpus: &Path, cols: usize, rows: usize, feed: Feed) -> Result<usize> {
+    let mut stdout = io::stdout();
+    let mut panicked = 0;
+
+    for file in cases(corpus)? {
+        let data = fs::read(&file).with_context(|| format!("cannot read {}", file.display()))?;
+        let name = file.to_string_lossy();
+
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| match feed {
+            Feed::Output(chunk) => serde_json::to_value(screen_after(&data, cols, rows, chunk).message())
+                .map(|message| json!({ "file": name, "snapshot": message["data"] })),
+
+            Feed::Input(custom) => {
+                let input = input_for(&data, cols, rows, custom);
+                Ok(json!({ "file": name, "input": String::from_utf8_lossy(&input) }))
+            }
+        }));
+
+        let line = match outcome {
+            Ok(line) => line?,
+
+            Err(payload) => {
+                panicked += 1;
+                json!({ "file": name, "panic": panic_message(&*payload) })
+            }
+        };
+
+        writeln!(stdout, "{line}")?;
+    }
+
+    Ok(panicked)
+}
+
+/// Feed `data` to a new session of `cols` by `rows` as a command's output, `chunk` bytes at a
+/// time, and take a snapshot of the screen it leaves.
+pub fn screen_after(data: &[u8], cols: usize, rows: usize, chunk: usize) -> Event {
+    let mut session = Session::new(cols, rows);
+    let mut decoder = utf8::Decoder::default();
+    let snapshot = Arc::new(Mutex::new(None));
+
+    session.on_event({
+        let snapshot = snapshot.clone();
+
+        move |event| {
+            if let Event::Snapshot(..) = event {
+                *snapshot.lock().unwrap_or_else(PoisonError::into_inner) = Some(event.clone());
+            }
+        }
+    });
+
+    for data in data.chunks(chunk.max(1)) {
+        session.output(decoder.decode(data));
+    }
+
+    session.snapshot();
+    let snapshot = snapshot.lock().unwrap_or_else(PoisonError::into_inner).take();
+    snapshot.expect("snapshots reach listeners straight away")
+}
+
+/// Send the keys named by the lines of `data` to a new session of `cols` by `rows`, looking
+/// them up in `custom` first, and return the bytes the command would read.
+pub fn input_for(data: &[u8], cols: usize, rows: usize, custom: &HashMap<String, String>) -> Vec<u8> {
+    let session = Session::new(cols, rows);
+    let names = String::from_utf8_lossy(data).lines().map(str::to_string).collect();
+    session.encode_input(&keys::key_seqs(names, custom))
+}
+
+/// The files making up `corpus`, in order of their names.
+fn cases(corpus: &Path) -> Result<Vec<PathBuf>> {
+    if corpus.is_file() {
+        return Ok(vec![corpus.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(corpus).with_context(|| format!("cannot read corpus {}", corpus.display()))? {
+        let path = entry?.path();
+
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("panicked")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cases, input_for, panic_message, screen_after};
+    use crate::session::Event;
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[test]
+    fn screen_after_output() {
+        let data = "hello \u{1b}[1mwörld\u{1b}[0m\r\n\u{1b}[2;3Hx".as_bytes();
+
+        // However the output is split up, it leaves the same screen behind
+        for chunk in [1, 2, 7, data.len()] {
+            let Event::Snapshot(_, cols, rows, _, text, _) = screen_after(data, 20, 3, chunk) else {
+                panic!("not a snapshot");
+            };
+
+            assert_eq!((cols, rows), (20, 3));
+            assert_eq!(text.lines().map(str::trim_end).collect::<Vec<_>>(), ["hello wörld", "  x", ""]);
+        }
+    }
+
+    #[test]
+    fn input_for_keys() {
+        let custom = HashMap::from([("greet".to_string(), "hi".to_string())]);
+        let input = input_for(b"C-a\nUp\ngreet\nhello", 20, 3, &custom);
+        assert_eq!(input, b"\x01\x1b[Ahihello");
+    }
+
+    #[test]
+    fn corpus_files_in_order() {
+        let dir = std::env::temp_dir().join(format!("htty-fuzz-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("b"), "").unwrap();
+        fs::write(dir.join("a"), "").unwrap();
+
+        assert_eq!(cases(&dir).unwrap(), [dir.join("a"), dir.join("b")]);
+        assert_eq!(cases(&dir.join("a")).unwrap(), [dir.join("a")]);
+        assert!(cases(&dir.join("missing")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn panic_messages() {
+        let payload = std::panic::catch_unwind(|| panic!("with {}", "args")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "with args");
+
+        let payload = std::panic::catch_unwind(|| panic!("plain")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "plain");
+    }
+}
@@ -0,0 +1,382 @@
+use crate::command::{self, InputSeq};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// List the key names `sendKeys` understands, including those from the config file's `keys`.
+pub fn list(keys: &HashMap<String, String>) -> Result<()> {
+    let mut out = io::stdout().lock();
+
+    for names in KEY_NAMES {
+        writeln!(out, "{}", names.join(", "))?;
+    }
+
+    writeln!(out, "C-a .. C-z, ^a .. ^z (control characters, in either case)")?;
+    writeln!(out, "A-<char> (Escape followed by the character)")?;
+
+    let mut custom: Vec<_> = keys.keys().collect();
+    custom.sort();
+
+    for name in custom {
+        writeln!(out, "{name} (from config)")?;
+    }
+
+    Ok(())
+}
+
+/// Show the bytes `sendKeys` would send for each of `names`, with the cursor keys in normal and
+/// in application mode.
+pub fn show(names: &[String], keys: &HashMap<String, String>) -> Result<()> {
+    let mut out = io::stdout().lock();
+
+    for name in names {
+        let seqs = key_seqs(vec![name.clone()], keys);
+
+        if !keys.contains_key(name) && seqs == [InputSeq::Standard(name.clone())] {
+            writeln!(out, "{name} (not a key name, sent as text)")?;
+        } else {
+            writeln!(out, "{name}")?;
+        }
+
+        for (mode, app_mode) in [("normal", false), ("application", true)] {
+            let bytes = command::seqs_to_bytes(&seqs, app_mode);
+            let hex: Vec<_> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+            writeln!(out, "  {mode:<12} {:<24} {}", hex.join(" "), caret(&bytes))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// `bytes` in caret notation, as `cat -v` shows them.
+fn caret(bytes: &[u8]) -> String {
+    let mut out = String::new();
+
+    for &b in bytes {
+        let b = if b >= 0x80 {
+            out.push_str("M-");
+            b - 0x80
+        } else {
+            b
+        };
+
+        match b {
+            0x00..=0x1f => {
+                out.push('^');
+                out.push((b + 0x40) as char);
+            }
+            0x7f => out.push_str("^?"),
+            _ => out.push(b as char),
+        }
+    }
+
+    out
+}
+
+/// The sequences `sendKeys` sends for `names`, looking each one up in `keys` before the
+/// built-in key names. Anything that isn't a key name is sent as is.
+pub fn key_seqs(names: Vec<String>, keys: &HashMap<String, String>) -> Vec<InputSeq> {
+    names
+        .into_iter()
+        .map(|key| match keys.get(&key) {
+            Some(seq) => standard_key(seq),
+            None => parse_key(key),
+        })
+        .collect()
+}
+
+pub fn standard_key<S: ToString>(seq: S) -> InputSeq {
+    InputSeq::Standard(seq.to_string())
+}
+
+pub fn cursor_key<S: ToString>(seq1: S, seq2: S) -> InputSeq {
+    InputSeq::Cursor(seq1.to_string(), seq2.to_string())
+}
+
+/// The key names `sendKeys` understands, each with its aliases. Besides these, `C-a` to `C-z`
+/// and `^a` to `^z` (in either case) send control characters and `A-` followed by any character
+/// sends Escape and then that character.
+pub const KEY_NAMES: &[&[&str]] = &[
+    &["C-@", "C-Space", "^@"],
+    &["C-[", "Escape", "^["],
+    &["C-\\", "^\\"],
+    &["C-]", "^]"],
+    &["C-^", "C-/"],
+    &["C--", "C-_"],
+    &["Tab"],
+    &["Enter"],
+    &["Backspace"],
+    &["Space"],
+    &["Left"],
+    &["Right"],
+    &["Up"],
+    &["Down"],
+    &["C-Left"],
+    &["C-Right"],
+    &["S-Left"],
+    &["S-Right"],
+    &["C-Up"],
+    &["C-Down"],
+    &["S-Up"],
+    &["S-Down"],
+    &["A-Left"],
+    &["A-Right"],
+    &["A-Up"],
+    &["A-Down"],
+    &["C-S-Left", "S-C-Left"],
+    &["C-S-Right", "S-C-Right"],
+    &["C-S-Up", "S-C-Up"],
+    &["C-S-Down", "S-C-Down"],
+    &["C-A-Left", "A-C-Left"],
+    &["C-A-Right", "A-C-Right"],
+    &["C-A-Up", "A-C-Up"],
+    &["C-A-Down", "A-C-Down"],
+    &["A-S-Left", "S-A-Left"],
+    &["A-S-Right", "S-A-Right"],
+    &["A-S-Up", "S-A-Up"],
+    &["A-S-Down", "S-A-Down"],
+    &["C-A-S-Left", "C-S-A-Left", "A-C-S-Left", "S-C-A-Left", "A-S-C-Left", "S-A-C-Left"],
+    &["C-A-S-Right", "C-S-A-Right", "A-C-S-Right", "S-C-A-Right", "A-S-C-Right", "S-A-C-Right"],
+    &["C-A-S-Up", "C-S-A-Up", "A-C-S-Up", "S-C-A-Up", "A-S-C-Up", "S-A-C-Up"],
+    &["C-A-S-Down", "C-S-A-Down", "A-C-S-Down", "S-C-A-Down", "A-S-C-Down", "S-A-C-Down"],
+    &["F1"],
+    &["F2"],
+    &["F3"],
+    &["F4"],
+    &["F5"],
+    &["F6"],
+    &["F7"],
+    &["F8"],
+    &["F9"],
+    &["F10"],
+    &["F11"],
+    &["F12"],
+    &["C-F1"],
+    &["C-F2"],
+    &["C-F3"],
+    &["C-F4"],
+    &["C-F5"],
+    &["C-F6"],
+    &["C-F7"],
+    &["C-F8"],
+    &["C-F9"],
+    &["C-F10"],
+    &["C-F11"],
+    &["C-F12"],
+    &["S-F1"],
+    &["S-F2"],
+    &["S-F3"],
+    &["S-F4"],
+    &["S-F5"],
+    &["S-F6"],
+    &["S-F7"],
+    &["S-F8"],
+    &["S-F9"],
+    &["S-F10"],
+    &["S-F11"],
+    &["S-F12"],
+    &["A-F1"],
+    &["A-F2"],
+    &["A-F3"],
+    &["A-F4"],
+    &["A-F5"],
+    &["A-F6"],
+    &["A-F7"],
+    &["A-F8"],
+    &["A-F9"],
+    &["A-F10"],
+    &["A-F11"],
+    &["A-F12"],
+    &["Home"],
+    &["C-Home"],
+    &["S-Home"],
+    &["A-Home"],
+    &["End"],
+    &["C-End"],
+    &["S-End"],
+    &["A-End"],
+    &["PageUp"],
+    &["C-PageUp"],
+    &["S-PageUp"],
+    &["A-PageUp"],
+    &["PageDown"],
+    &["C-PageDown"],
+    &["S-PageDown"],
+    &["A-PageDown"],
+    &["Insert"],
+    &["C-Insert"],
+    &["S-Insert"],
+    &["A-Insert"],
+    &["Delete"],
+    &["C-Delete"],
+    &["S-Delete"],
+    &["A-Delete"],
+    &["S-Tab"],
+];
+
+fn parse_key(key: String) -> InputSeq {
+    let seq = match key.as_str() {
+        "C-@" | "C-Space" | "^@" => "\x00",
+        "C-[" | "Escape" | "^[" => "\x1b",
+        "C-\\" | "^\\" => "\x1c",
+        "C-]" | "^]" => "\x1d",
+        "C-^" | "C-/" => "\x1e",
+        "C--" | "C-_" => "\x1f",
+        "Tab" => "\x09",   // same as C-i
+        "Enter" => "\x0d", // same as C-m
+        "Backspace" => "\x7f", // DEL character
+        "Space" => " ",
+        "Left" => return cursor_key("\x1b[D", "\x1bOD"),
+        "Right" => return cursor_key("\x1b[C", "\x1bOC"),
+        "Up" => return cursor_key("\x1b[A", "\x1bOA"),
+        "Down" => return cursor_key("\x1b[B", "\x1bOB"),
+        "C-Left" => "\x1b[1;5D",
+        "C-Right" => "\x1b[1;5C",
+        "S-Left" => "\x1b[1;2D",
+        "S-Right" => "\x1b[1;2C",
+        "C-Up" => "\x1b[1;5A",
+        "C-Down" => "\x1b[1;5B",
+        "S-Up" => "\x1b[1;2A",
+        "S-Down" => "\x1b[1;2B",
+        "A-Left" => "\x1b[1;3D",
+        "A-Right" => "\x1b[1;3C",
+        "A-Up" => "\x1b[1;3A",
+        "A-Down" => "\x1b[1;3B",
+        "C-S-Left" | "S-C-Left" => "\x1b[1;6D",
+        "C-S-Right" | "S-C-Right" => "\x1b[1;6C",
+        "C-S-Up" | "S-C-Up" => "\x1b[1;6A",
+        "C-S-Down" | "S-C-Down" => "\x1b[1;6B",
+        "C-A-Left" | "A-C-Left" => "\x1b[1;7D",
+        "C-A-Right" | "A-C-Right" => "\x1b[1;7C",
+        "C-A-Up" | "A-C-Up" => "\x1b[1;7A",
+        "C-A-Down" | "A-C-Down" => "\x1b[1;7B",
+        "A-S-Left" | "S-A-Left" => "\x1b[1;4D",
+        "A-S-Right" | "S-A-Right" => "\x1b[1;4C",
+        "A-S-Up" | "S-A-Up" => "\x1b[1;4A",
+        "A-S-Down" | "S-A-Down" => "\x1b[1;4B",
+        "C-A-S-Left" | "C-S-A-Left" | "A-C-S-Left" | "S-C-A-Left" | "A-S-C-Left" | "S-A-C-Left" => {
+            "\x1b[1;8D"
+        }
+        "C-A-S-Right" | "C-S-A-Right" | "A-C-S-Right" | "S-C-A-Right" | "A-S-C-Right"
+        | "S-A-C-Right" => "\x1b[1;8C",
+        "C-A-S-Up" | "C-S-A-Up" | "A-C-S-Up" | "S-C-A-Up" | "A-S-C-Up" | "S-A-C-Up" => "\x1b[1;8A",
+        "C-A-S-Down" | "C-S-A-Down" | "A-C-S-Down" | "S-C-A-Down" | "A-S-C-Down" | "S-A-C-Down" => {
+            "\x1b[1;8B"
+        }
+        "F1" => "\x1bOP",
+        "F2" => "\x1bOQ",
+        "F3" => "\x1bOR",
+        "F4" => "\x1bOS",
+        "F5" => "\x1b[15~",
+        "F6" => "\x1b[17~",
+        "F7" => "\x1b[18~",
+        "F8" => "\x1b[19~",
+        "F9" => "\x1b[20~",
+        "F10" => "\x1b[21~",
+        "F11" => "\x1b[23~",
+        "F12" => "\x1b[24~",
+        "C-F1" => "\x1b[1;5P",
+        "C-F2" => "\x1b[1;5Q",
+        "C-F3" => "\x1b[1;5R",
+        "C-F4" => "\x1b[1;5S",
+        "C-F5" => "\x1b[15;5~",
+        "C-F6" => "\x1b[17;5~",
+        "C-F7" => "\x1b[18;5~",
+        "C-F8" => "\x1b[19;5~",
+        "C-F9" => "\x1b[20;5~",
+        "C-F10" => "\x1b[21;5~",
+        "C-F11" => "\x1b[23;5~",
+        "C-F12" => "\x1b[24;5~",
+        "S-F1" => "\x1b[1;2P",
+        "S-F2" => "\x1b[1;2Q",
+        "S-F3" => "\x1b[1;2R",
+        "S-F4" => "\x1b[1;2S",
+        "S-F5" => "\x1b[15;2~",
+        "S-F6" => "\x1b[17;2~",
+        "S-F7" => "\x1b[18;2~",
+        "S-F8" => "\x1b[19;2~",
+        "S-F9" => "\x1b[20;2~",
+        "S-F10" => "\x1b[21;2~",
+        "S-F11" => "\x1b[23;2~",
+        "S-F12" => "\x1b[24;2~",
+        "A-F1" => "\x1b[1;3P",
+        "A-F2" => "\x1b[1;3Q",
+        "A-F3" => "\x1b[1;3R",
+        "A-F4" => "\x1b[1;3S",
+        "A-F5" => "\x1b[15;3~",
+        "A-F6" => "\x1b[17;3~",
+        "A-F7" => "\x1b[18;3~",
+        "A-F8" => "\x1b[19;3~",
+        "A-F9" => "\x1b[20;3~",
+        "A-F10" => "\x1b[21;3~",
+        "A-F11" => "\x1b[23;3~",
+        "A-F12" => "\x1b[24;3~",
+        "Home" => return cursor_key("\x1b[H", "\x1bOH"),
+        "C-Home" => "\x1b[1;5H",
+        "S-Home" => "\x1b[1;2H",
+        "A-Home" => "\x1b[1;3H",
+        "End" => return cursor_key("\x1b[F", "\x1bOF"),
+        "C-End" => "\x1b[1;5F",
+        "S-End" => "\x1b[1;2F",
+        "A-End" => "\x1b[1;3F",
+        "PageUp" => "\x1b[5~",
+        "C-PageUp" => "\x1b[5;5~",
+        "S-PageUp" => "\x1b[5;2~",
+        "A-PageUp" => "\x1b[5;3~",
+        "PageDown" => "\x1b[6~",
+        "C-PageDown" => "\x1b[6;5~",
+        "S-PageDown" => "\x1b[6;2~",
+        "A-PageDown" => "\x1b[6;3~",
+        "Insert" => "\x1b[2~",
+        "C-Insert" => "\x1b[2;5~",
+        "S-Insert" => "\x1b[2;2~",
+        "A-Insert" => "\x1b[2;3~",
+        "Delete" => "\x1b[3~",
+        "C-Delete" => "\x1b[3;5~",
+        "S-Delete" => "\x1b[3;2~",
+        "A-Delete" => "\x1b[3;3~",
+        "S-Tab" => "\x1b[Z", // backtab
+
+        k => {
+            let chars: Vec<char> = k.chars().collect();
+
+            match chars.as_slice() {
+                ['C', '-', k @ 'a'..='z'] => {
+                    return standard_key((*k as u8 - 0x60) as char);
+                }
+
+                ['C', '-', k @ 'A'..='Z'] => {
+                    return standard_key((*k as u8 - 0x40) as char);
+                }
+
+                ['^', k @ 'a'..='z'] => {
+                    return standard_key((*k as u8 - 0x60) as char);
+                }
+
+                ['^', k @ 'A'..='Z'] => {
+                    return standard_key((*k as u8 - 0x40) as char);
+                }
+
+                ['A', '-', k] => {
+                    return standard_key(format!("\x1b{}", k));
+                }
+
+                _ => &key,
+            }
+        }
+    };
+
+    standard_key(seq)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_key, standard_key, KEY_NAMES};
+
+    #[test]
+    fn key_names() {
+        for name in KEY_NAMES.iter().flat_map(|names| names.iter()) {
+            assert_ne!(parse_key(name.to_string()), standard_key(name), "{name} is not a key name");
+        }
+    }
+}
@@ -9,18 +9,58 @@ through the `htty` Python package which calls the `ht` binary as a subprocess.
 ## Usage as a library
 
 ```rust
-use htty::{Command, Session, InputSeq};
+use htty_core::{Event, Session, InputSeq};
 
 let mut session = Session::new(80, 24);
 // Simulate terminal output
 session.output("hello\n".to_string());
+// Observe events as they happen, without an async runtime
+session.on_event(|event| {
+    if let Event::Snapshot(_time, _cols, _rows, _seq, text, _styles) = event {
+        assert!(text.starts_with("hello"));
+    }
+});
 // Take a snapshot of the current terminal state
 session.snapshot();
 
-// Create input commands (these would be sent through the command system)
-let _input_command = Command::Input(vec![InputSeq::Standard("hello".to_string())]);
+// Encode input for the PTY, following the cursor key mode the program asked for
+let up = [InputSeq::Cursor("\x1b[A".to_string(), "\x1bOA".to_string())];
+assert_eq!(session.encode_input(&up), b"\x1b[A");
+session.output("\x1b[?1h".to_string());
+assert_eq!(session.encode_input(&up), b"\x1bOA");
 ```
 
+The bytes from `Session::encode_input` are what to write to the PTY the session's output
+comes from.
+
+To run a command in a headless terminal without driving the `ht` binary, see
+[`embed`]:
+
+```no_run
+# async fn example() -> anyhow::Result<()> {
+let htty = htty_core::Htty::builder().size(80, 24).command(["vim"]).spawn()?;
+htty.send_keys([":q", "Enter"]).await?;
+htty.wait_exit().await;
+# Ok(())
+# }
+```
+
+Programs without an async runtime can use [`blocking`] instead.
+
+Both report failures as [`HttyError`]s, which can be matched on to tell them apart.
+
+## Cargo features
+
+All but `wasm` are on by default, and all needed to build `ht`. Programs that only need a
+[`Session`] and the terminal behind it can leave them out with `default-features = false`.
+
+- `pty`: running commands in PTYs, with everything else that needs a Unix host: the
+  command line, [`embed`] and [`blocking`]. Without it the crate builds for wasm32
+- `http`: the HTTP and WebSocket server (`api::http`), and axum with it; implies `pty`
+- `stdio`: the JSON protocol on stdin and stdout (`api::stdio`); implies `pty` and `render`
+- `render`: snapshots as HTML or ANSI (`render`), and asciicast recording and replay
+- `wasm`: terminal emulation exported to JavaScript (`wasm`); implies `render`
+
 ## Python integration
 
 Python integration is provided by the `htty` Python package, which calls the
@@ -29,13 +69,69 @@ Python integration is provided by the `htty` Python package, which calls the
 
 // Re-export the main modules
 pub mod api;
+#[cfg(feature = "render")]
+pub mod asciicast;
+pub mod backend;
+pub mod benchmark;
+#[cfg(feature = "pty")]
+pub mod blocking;
+#[cfg(feature = "pty")]
+pub mod cgroup;
+#[cfg(feature = "pty")]
 pub mod cli;
+pub mod clock;
 pub mod command;
+pub mod config;
+#[cfg(feature = "pty")]
+pub mod embed;
+pub mod error;
+#[cfg(feature = "pty")]
+pub mod exec;
+pub mod fuzz;
+pub mod keys;
+#[cfg(feature = "pty")]
 pub mod locale;
+#[cfg(feature = "pty")]
+pub mod logging;
+#[cfg(feature = "pty")]
+pub mod manifest;
+pub mod message;
+#[cfg(feature = "pty")]
+pub mod namespace;
+#[cfg(feature = "pty")]
 pub mod nbio;
+#[cfg(feature = "pty")]
+pub mod pane;
+#[cfg(feature = "pty")]
 pub mod pty;
+#[cfg(all(feature = "pty", feature = "render"))]
+pub mod record;
+#[cfg(feature = "render")]
+pub mod render;
+#[cfg(feature = "render")]
+pub mod replay;
+#[cfg(feature = "pty")]
+pub mod runtime;
 pub mod session;
+pub mod shutdown;
+pub mod snapshot;
+pub mod status;
+#[cfg(feature = "pty")]
+pub mod termios;
+pub mod timing;
+pub mod utf8;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 // Re-export key types for library users
-pub use command::{Command, InputSeq};
-pub use session::{Event, Session};
+#[cfg(feature = "pty")]
+pub use command::Command;
+pub use command::InputSeq;
+pub use backend::{Cell, Color, Screen, Style, TerminalBackend};
+pub use config::{Size, StyleFormat, StyleMode, Subscription};
+#[cfg(feature = "pty")]
+pub use embed::{Htty, HttyBuilder};
+pub use error::HttyError;
+pub use message::Message;
+pub use session::{Event, Session, SessionState};
+pub use shutdown::Shutdown;
@@ -1,6 +1,62 @@
 use nix::libc::{self, CODESET, LC_ALL};
+use std::collections::HashMap;
 use std::env;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
+use std::str::FromStr;
+
+/// The locale `--locale-policy set` runs the command in, which glibc and musl both provide
+/// without any locales being generated.
+const FALLBACK: &str = "C.UTF-8";
+
+/// What ht does when it's started in a locale whose character encoding is neither ASCII nor UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Policy {
+    /// Run the command with `LC_ALL=C.UTF-8`, if the system has that locale.
+    Set,
+    /// Carry on regardless, replacing whatever isn't valid UTF-8 in the command's output.
+    Warn,
+    /// Refuse to start.
+    #[default]
+    Strict,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "set" => Ok(Policy::Set),
+            "warn" => Ok(Policy::Warn),
+            "strict" => Ok(Policy::Strict),
+            _ => Err(format!("invalid locale policy: {s}. Valid options: set, warn, strict")),
+        }
+    }
+}
+
+/// Check the locale ht was started in, dealing with an unsupported encoding as `policy` says.
+/// With `Policy::Set`, the fallback locale is added to `env`, the command's environment.
+pub fn apply_policy(policy: Policy, env: &mut HashMap<String, String>) -> anyhow::Result<()> {
+    let Err(e) = check_utf8_locale() else {
+        return Ok(());
+    };
+
+    match policy {
+        Policy::Strict => Err(anyhow::anyhow!("{e} Or start ht with --locale-policy set (or warn).")),
+
+        Policy::Warn => {
+            tracing::warn!("{e} Carrying on, with invalid UTF-8 in the command's output replaced.");
+            Ok(())
+        }
+
+        Policy::Set if set_locale(FALLBACK) => {
+            tracing::warn!("{e} Running the command with LC_ALL={FALLBACK} instead.");
+            env.insert("LC_ALL".to_string(), FALLBACK.to_string());
+            Ok(())
+        }
+
+        Policy::Set => Err(anyhow::anyhow!("{e} The {FALLBACK} locale isn't available to fall back on either.")),
+    }
+}
 
 pub fn check_utf8_locale() -> anyhow::Result<()> {
     initialize_from_env();
@@ -26,6 +82,12 @@ pub fn initialize_from_env() {
     };
 }
 
+/// Switch ht itself to `locale`, returning whether the system has it.
+fn set_locale(locale: &str) -> bool {
+    let name = CString::new(locale).expect("locale names have no NUL bytes");
+    !unsafe { libc::setlocale(LC_ALL, name.as_ptr()) }.is_null()
+}
+
 fn get_encoding() -> String {
     let codeset = unsafe { CStr::from_ptr(libc::nl_langinfo(CODESET)) };
 
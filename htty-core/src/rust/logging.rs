@@ -0,0 +1,57 @@
+use crate::cli::LogLevel;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, Layer};
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// Write ht's diagnostics at `level` and above to stderr, one plain line per message.
+///
+/// With a `log_file`, they also go there as timestamped JSON lines, kept apart from the terminal
+/// output and the event stream. The file records debug messages (the same ones sent as `debug`
+/// events) even when stderr is quieter, so it can be used to reconstruct what ht was doing.
+pub fn init(level: LogLevel, log_file: Option<&Path>) -> Result<()> {
+    let level = LevelFilter::from(level);
+
+    let stderr = fmt::layer()
+        .with_writer(io::stderr)
+        .without_time()
+        .with_level(false)
+        .with_target(false)
+        .with_filter(level);
+
+    let file = match log_file {
+        Some(path) => {
+            let file = File::create(path).with_context(|| format!("cannot create log file {}", path.display()))?;
+
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_writer(Mutex::new(file))
+                    .with_filter(level.max(LevelFilter::DEBUG)),
+            )
+        }
+
+        None => None,
+    };
+
+    tracing_subscriber::registry().with(stderr).with(file).init();
+
+    Ok(())
+}
@@ -2,211 +2,554 @@
 // Test comment for build optimization verification
 
 mod api;
+mod asciicast;
+mod backend;
+mod benchmark;
+mod cgroup;
 mod cli;
+mod clock;
 mod command;
+mod config;
+mod error;
+mod exec;
+mod fuzz;
+mod keys;
 mod locale;
+mod logging;
+mod manifest;
+mod message;
+mod namespace;
 mod nbio;
+mod pane;
 mod pty;
+mod record;
+mod render;
+mod replay;
+mod runtime;
 mod session;
+mod shutdown;
+mod snapshot;
+mod status;
+mod termios;
+mod timing;
+mod utf8;
 use anyhow::{Context, Result};
 use command::Command;
-use nix::libc;
-use session::Session;
-use std::io::BufRead;
+use futures_util::future::BoxFuture;
+use futures_util::stream::{FuturesOrdered, StreamExt};
+use session::{Backlog, Event, Session, SessionState};
+use shutdown::Shutdown;
+use std::collections::{BTreeMap, HashMap};
 use std::net::{SocketAddr, TcpListener};
 use std::path::PathBuf;
-use tokio::{sync::mpsc, task::JoinHandle};
+use std::process::ExitCode;
+use timing::Timing;
+use tokio::{sync::{mpsc, oneshot}, task::JoinHandle};
+use tracing::{debug, error, info};
+
+/// What ht exits with after an idle timeout, as `timeout(1)` does.
+const IDLE_TIMEOUT_EXIT_CODE: u8 = 124;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    locale::check_utf8_locale()?;
-    let cli = cli::Cli::new()?;
+async fn main() -> Result<ExitCode> {
+    let start = manifest::Start::now();
+    let mut cli = cli::Cli::new()?;
+    logging::init(cli.log_level, cli.log_file.as_deref())?;
+
+    // The helper only passes a FIFO's contents along, and must not fail where ht was told to
+    // carry on regardless of the locale
+    if let Some(cli::Commands::WaitExit { signal_file, timeout }) = &cli.command {
+        return handle_waitexit(signal_file.clone(), *timeout).await.map(|_| ExitCode::SUCCESS);
+    }
+
+    locale::apply_policy(cli.locale_policy, &mut cli.env)?;
+
+    if let Some(cli::Commands::Completions { shell }) = &cli.command {
+        cli::Cli::print_completions(*shell);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(cli::Commands::Man { output_dir }) = &cli.command {
+        cli::Cli::print_man(output_dir.as_deref())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(cli::Commands::Keys { keys }) = &cli.command {
+        if keys.is_empty() {
+            keys::list(&cli.keys)?;
+        } else {
+            keys::show(keys, &cli.keys)?;
+        }
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(cli::Commands::Replay { file, speed, to_json }) = &cli.command {
+        return replay::run(file, *speed, *to_json).await.map(|_| ExitCode::SUCCESS);
+    }
 
-    // Handle waitexit subcommand
-    if let Some(cli::Commands::WaitExit { signal_file }) = &cli.command {
-        return handle_waitexit(signal_file.clone()).await;
+    if let Some(cli::Commands::Fuzz { corpus, size, chunk, input }) = &cli.command {
+        let feed = if *input { fuzz::Feed::Input(&cli.keys) } else { fuzz::Feed::Output(*chunk) };
+        let panicked = fuzz::run(corpus, size.cols(), size.rows(), feed)?;
+        return Ok(if panicked > 0 { ExitCode::FAILURE } else { ExitCode::SUCCESS });
     }
 
-    let (input_tx, input_rx) = mpsc::channel(1024);
-    let (output_tx, output_rx) = mpsc::channel(1024);
-    let (command_tx, command_rx) = mpsc::channel(1024);
+    let capacity = cli.buffers.channel_capacity;
+    let (input_tx, input_rx) = mpsc::channel(capacity);
+    let (control_tx, control_rx) = mpsc::channel(capacity);
+    let (output_tx, output_rx) = mpsc::channel(capacity);
+    let (stderr_tx, stderr_rx) = mpsc::channel(capacity);
+    let (command_tx, command_rx) = mpsc::channel(capacity);
     let (clients_tx, clients_rx) = mpsc::channel(1);
     let (pid_tx, pid_rx) = mpsc::channel(1);
     let (exit_code_tx, exit_code_rx) = mpsc::channel(1);
+    let (notice_tx, notice_rx) = mpsc::channel(capacity);
+    let runtime_dir = runtime::RuntimeDir::create()?;
+    let cgroup = cli.cgroup.map(cgroup::Cgroup::create).transpose()?;
+    let options = spawn_options(&cli, cgroup.as_ref().map(|c| c.path()));
+    let main_pane = cli.main_pane.unwrap_or_else(|| config::Geometry::full(&cli.size));
+    let shutdown = Shutdown::new();
+    watch_signals(&shutdown, runtime_dir.path())?;
+    shutdown.trigger_on_panic();
+
+    let (passed_tx, passed_rx) = oneshot::channel();
+
+    start_http_api(cli.listen, clients_tx.clone(), cli.timing).await?;
+    let api = match &cli.command {
+        Some(cli::Commands::Record { output }) => tokio::spawn(record::start(command_tx.clone(), clients_tx, output.clone(), cli.timing)),
+        Some(cli::Commands::Snapshot { until_quiet, until, format }) => tokio::spawn(snapshot::start(command_tx.clone(), clients_tx, *until_quiet, until.clone(), *format, cli.timing)),
+        Some(cli::Commands::Exec { script, timeout }) => tokio::spawn(exec::start(command_tx.clone(), clients_tx, script.clone(), *timeout, cli.keys.clone(), passed_tx, cli.timing)),
+        _ => start_stdio_api(command_tx.clone(), clients_tx, cli.subscribe.unwrap_or_default(), cli.keys.clone(), cli.keep_child, cli.timing, shutdown.clone()),
+    };
+    let pty = start_pty(cli.shell_command.clone(), runtime_dir.fifo_path(), &main_pane.size(), options.clone(), input_rx, control_rx, output_tx, stderr_tx, pid_tx, exit_code_tx, command_tx.clone(), shutdown.clone())?;
+    let panes = start_panes(&cli, &runtime_dir, &options, notice_tx, &shutdown)?;
+    let session = build_session(&cli, main_pane);
+    let ending = async {
+        let ending = run_event_loop(output_rx, stderr_rx, notice_rx, input_tx, control_tx, command_rx, clients_rx, pid_rx, exit_code_rx, session, panes, api, &shutdown, &cli).await?;
+        pty.await??;
+        anyhow::Ok(ending)
+    }
+    .await;
+
+    // The runs that failed are the ones CI most needs the artifacts of, so they get a manifest
+    // too, without an exit status
+    let written = match &ending {
+        Ok(ending) => manifest::write(&cli, &start, ending.exit_status.as_ref(), ending.timed_out),
+        Err(_) => manifest::write(&cli, &start, None, false),
+    };
+
+    // The run itself went ahead, so its outcome is still what ht exits with
+    if let Err(e) = written {
+        error!("{e:#}");
+    }
 
-    start_http_api(cli.listen, clients_tx.clone()).await?;
-    let api = start_stdio_api(command_tx.clone(), clients_tx, cli.subscribe.unwrap_or_default());
-    let pty = start_pty(cli.shell_command.clone(), &cli.size, input_rx, output_tx, pid_tx, exit_code_tx, command_tx.clone())?;
-    let session = build_session(&cli.size, cli.style_mode);
-    run_event_loop(output_rx, input_tx, command_rx, clients_rx, pid_rx, exit_code_rx, session, api, &cli).await?;
-    pty.await?
-}
+    let ending = ending?;
 
-async fn handle_waitexit(signal_file: PathBuf) -> Result<()> {
-    // Create the FIFO (step 3 in desired flow)
-    let fifo_path_str = signal_file.to_string_lossy();
-    let fifo_path_cstr = std::ffi::CString::new(fifo_path_str.as_bytes()).unwrap();
-    
-    unsafe {
-        let result = libc::mkfifo(fifo_path_cstr.as_ptr(), 0o600);
-        if result != 0 {
-            return Ok(());
-        }
+    if ending.timed_out {
+        return Ok(ExitCode::from(IDLE_TIMEOUT_EXIT_CODE));
     }
-    
-    // Block reading from FIFO - this signals to parent that command completed (step 4-5)
-    // Parent will detect FIFO existence and know command is done
-    if let Ok(file) = std::fs::File::open(&signal_file) {
-        let reader = std::io::BufReader::new(file);
-        for line in reader.lines().map_while(Result::ok) {
-            if line.trim() == "exit" {
-                break;
-            }
-        }
+
+    // ht exec passes its verdict on, so it can be used in shell tests
+    match (&cli.command, ending.exit_status) {
+        (Some(cli::Commands::Exec { .. }), _) if !passed_rx.await.unwrap_or(false) => Ok(ExitCode::FAILURE),
+        (_, Some(status)) if cli.propagate_exit_code => Ok(ExitCode::from(status.exit_code())),
+        _ => Ok(ExitCode::SUCCESS),
     }
-    
-    Ok(())
 }
 
-fn build_session(size: &cli::Size, style_mode: cli::StyleMode) -> Session {
-    let mut session = Session::new(size.cols(), size.rows());
-    session.set_style_mode(style_mode);
+async fn handle_waitexit(signal_file: PathBuf, timeout: Option<std::time::Duration>) -> Result<()> {
+    // Recorded before the FIFO appears, so ht knows which process to stop once it sees it
+    runtime::record_helper_pid(&signal_file)?;
+
+    // The FIFO turning up tells ht the command completed; the helper then holds the wrapping
+    // shell until ht releases it. Failing either makes ht's completion detection unreliable,
+    // so it's reported rather than passed over, and the shell carries on regardless.
+    let waited = runtime::create_fifo(&signal_file).and_then(|()| runtime::await_release(&signal_file, timeout));
+
+    let _ = std::fs::remove_file(runtime::helper_pid_path(&signal_file));
+    waited
+}
+
+fn build_session(cli: &cli::Cli, main_pane: config::Geometry) -> Session {
+    let mut session = Session::new(cli.size.cols(), cli.size.rows())
+        .with_broadcast_capacity(cli.buffers.broadcast_capacity);
+
+    if !cli.panes.is_empty() {
+        let panes = cli.panes.iter().enumerate().map(|(i, p)| (i + 1, p.geometry));
+        session = session.with_panes(cli.size.cols(), cli.size.rows(), main_pane, panes);
+    }
+
+    if cli.start_on_output {
+        session = session.with_init_on_output();
+    }
+
+    session.set_style_mode(cli.style_mode);
+    session.set_style_format(cli.style_format);
     session
 }
 
+fn start_panes(
+    cli: &cli::Cli,
+    runtime_dir: &runtime::RuntimeDir,
+    options: &pty::Options,
+    notice_tx: mpsc::Sender<pane::Notice>,
+    shutdown: &Shutdown,
+) -> Result<BTreeMap<usize, pane::Handle>> {
+    let mut panes = BTreeMap::new();
+
+    for (i, spec) in cli.panes.iter().enumerate() {
+        let id = i + 1;
+        let fifo_path = runtime_dir.pane_fifo_path(id);
+        let handle = pane::spawn(id, spec, fifo_path, options.clone(), cli.buffers.channel_capacity, notice_tx.clone(), shutdown.clone())?;
+        panes.insert(id, handle);
+    }
+
+    Ok(panes)
+}
+
+fn spawn_options(cli: &cli::Cli, cgroup: Option<&std::path::Path>) -> pty::Options {
+    pty::Options {
+        login: cli.login,
+        session_leader: !cli.no_setsid,
+        controlling_terminal: !cli.no_ctty,
+        job_control: cli.job_control,
+        separate_stderr: cli.separate_stderr,
+        termios: cli.termios,
+        wait_exit: true,
+        wait_exit_helper: cli.wait_exit_helper.clone(),
+        cgroup: cgroup.map(|p| p.to_path_buf()),
+        namespaces: cli.unshare,
+        respawn: cli.respawn,
+        keep_alive: cli.no_exit,
+        survive_hangup: cli.no_exit,
+        env: cli.env.clone(),
+        read_buf_size: cli.buffers.read_size,
+        timing: cli.timing,
+    }
+}
+
 fn start_stdio_api(
     command_tx: mpsc::Sender<Command>,
     clients_tx: mpsc::Sender<session::Client>,
-    sub: api::Subscription,
+    sub: config::Subscription,
+    keys: HashMap<String, String>,
+    keep_child: bool,
+    timing: Timing,
+    shutdown: Shutdown,
 ) -> JoinHandle<Result<()>> {
-    tokio::spawn(api::stdio::start(command_tx, clients_tx, sub))
+    tokio::spawn(api::stdio::start(command_tx, clients_tx, sub, keys, keep_child, timing, shutdown))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn start_pty(
     command: Vec<String>,
-    size: &cli::Size,
+    fifo_path: PathBuf,
+    size: &config::Size,
+    options: pty::Options,
     input_rx: mpsc::Receiver<Vec<u8>>,
+    control_rx: mpsc::Receiver<pty::Control>,
     output_tx: mpsc::Sender<Vec<u8>>,
+    stderr_tx: mpsc::Sender<Vec<u8>>,
     pid_tx: mpsc::Sender<i32>,
-    exit_code_tx: mpsc::Sender<i32>,
+    exit_code_tx: mpsc::Sender<pty::ExitStatus>,
     command_tx: mpsc::Sender<Command>,
-) -> Result<JoinHandle<Result<()>>> {
-    let command = command.join(" ");
-    eprintln!("launching \"{}\" in terminal of size {}", command, size);
+    shutdown: Shutdown,
+) -> Result<JoinHandle<Result<(), error::HttyError>>> {
+    let command = pty::Program::from_args(command);
+    info!("launching \"{}\" in terminal of size {}", command, size);
 
     Ok(tokio::spawn(pty::spawn(
-        command, size, input_rx, output_tx, pid_tx, exit_code_tx, command_tx,
+        command, fifo_path, size, options, input_rx, control_rx, output_tx, stderr_tx, pid_tx, exit_code_tx, command_tx, shutdown,
     )?))
 }
 
+/// Shut down as usual on SIGINT, SIGTERM or SIGHUP, so that the runtime directory and cgroup
+/// are removed on the way out rather than left behind. Another signal while that's under way
+/// (the command ignoring SIGHUP, say) removes the runtime directory and exits there and then.
+fn watch_signals(shutdown: &Shutdown, runtime_dir: &std::path::Path) -> Result<()> {
+    use nix::sys::signal::Signal;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    let shutdown = shutdown.clone();
+    let runtime_dir = runtime_dir.to_path_buf();
+
+    tokio::spawn(async move {
+        loop {
+            let signal = tokio::select! {
+                _ = interrupt.recv() => Signal::SIGINT,
+                _ = terminate.recv() => Signal::SIGTERM,
+                _ = hangup.recv() => Signal::SIGHUP,
+            };
+
+            if !shutdown.is_triggered() {
+                info!(%signal, "shutting down");
+                shutdown.trigger();
+            } else {
+                info!(%signal, "shutting down without waiting for the command");
+                let _ = std::fs::remove_dir_all(&runtime_dir);
+                std::process::exit(128 + signal as i32);
+            }
+        }
+    });
+
+    Ok(())
+}
+
 async fn start_http_api(
     listen_addr: Option<SocketAddr>,
     clients_tx: mpsc::Sender<session::Client>,
+    timing: Timing,
 ) -> Result<()> {
     if let Some(addr) = listen_addr {
         let listener = TcpListener::bind(addr).context("cannot start HTTP listener")?;
-        tokio::spawn(api::http::start(listener, clients_tx).await?);
+        tokio::spawn(api::http::start(listener, clients_tx, timing).await?);
     }
 
     Ok(())
 }
 
+/// What to do once a fence from `command::fence` has completed.
+enum Fenced {
+    Flush(Option<session::Reply>),
+    Exit,
+    /// A command (of a pane, or the main one) completed, and its wait-exit helper can let it exit.
+    Release(PathBuf),
+    /// The PTY (of a pane, if given) was asked for a size, and reported the one it took.
+    Resize(Option<usize>, config::Size, Result<config::Size, String>),
+}
+
+/// How the event loop came to an end.
+struct Ending {
+    /// The last exit status of the command, if it exited.
+    exit_status: Option<pty::ExitStatus>,
+    /// Whether it was cut short by `--idle-timeout`.
+    timed_out: bool,
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn run_event_loop(
     mut output_rx: mpsc::Receiver<Vec<u8>>,
+    mut stderr_rx: mpsc::Receiver<Vec<u8>>,
+    mut notice_rx: mpsc::Receiver<pane::Notice>,
     input_tx: mpsc::Sender<Vec<u8>>,
+    control_tx: mpsc::Sender<pty::Control>,
     mut command_rx: mpsc::Receiver<Command>,
     mut clients_rx: mpsc::Receiver<session::Client>,
     mut pid_rx: mpsc::Receiver<i32>,
-    mut exit_code_rx: mpsc::Receiver<i32>,
+    mut exit_code_rx: mpsc::Receiver<pty::ExitStatus>,
     mut session: Session,
+    mut panes: BTreeMap<usize, pane::Handle>,
     mut api_handle: JoinHandle<Result<()>>,
-    _cli: &cli::Cli,
-) -> Result<()> {
+    shutdown: &Shutdown,
+    cli: &cli::Cli,
+) -> Result<Ending> {
     let mut serving = true;
-    let mut last_command_time = std::time::Instant::now();
-    let mut pending_waitexit: Option<std::path::PathBuf> = None;
-    let mut pending_exit = false;
+    let mut output_open = true;
+    let mut exit_status = None;
+    // An exit status that arrived while output sent ahead of it was still queued
+    let mut held_exit: Option<pty::ExitStatus> = None;
+    let mut stderr_open = true;
     let mut api_completed = false;
-
-    // Timer for checking command channel emptiness
-    let mut emptiness_check_interval = tokio::time::interval(std::time::Duration::from_millis(10));
+    let mut last_output_time = tokio::time::Instant::now();
+    let mut idle = false;
+    let mut last_activity = std::time::Instant::now();
+    let mut timed_out = false;
+    let mut output_decoder = utf8::Decoder::default();
+    let mut stderr_decoder = utf8::Decoder::default();
+    let mut meter = cli.benchmark.then(benchmark::Meter::default);
+    let mut state = SessionState::Starting;
+    session.emit_state(state);
+    let mut output_backlog = Backlog::new("output", cli.buffers.channel_capacity);
+    let mut command_backlog = Backlog::new("command", cli.buffers.channel_capacity);
+    // Completed in the order they were set up, so an exit comes after earlier flushes
+    let mut fences: FuturesOrdered<BoxFuture<'static, Fenced>> = FuturesOrdered::new();
 
     loop {
+        let output_flush_deadline = session.output_flush_deadline();
+        let idle_timeout_deadline = cli.idle_timeout.map(|timeout| last_activity + timeout);
+
         tokio::select! {
-            result = output_rx.recv() => {
+            result = output_rx.recv(), if output_open => {
                 match result {
                     Some(data) => {
+                        let received = std::time::Instant::now();
+                        session.check_backlog(&mut output_backlog, output_rx.len());
+
+                        if idle {
+                            idle = false;
+                            session.emit_active(last_output_time.elapsed());
+                        }
+
+                        last_output_time = tokio::time::Instant::now();
+                        last_activity = received;
                         session.emit_debug_event(&format!("outputReceived:{}bytes", data.len()));
-                        session.output(String::from_utf8_lossy(&data).to_string());
+                        session.raw_output(&data);
+                        let text = output_decoder.decode(&data);
+
+                        if !text.is_empty() {
+                            session.output(text);
+                        }
+
+                        if let Some(meter) = &mut meter {
+                            meter.record(data.len(), received);
+                        }
+
                         session.emit_debug_event("outputProcessed");
+
+                        if output_rx.is_empty() {
+                            if let Some(status) = held_exit.take() {
+                                report_exit(&mut session, &mut state, &mut exit_status, status);
+                            }
+                        }
                     },
 
+                    None if cli.no_exit => {
+                        if let Some(status) = held_exit.take() {
+                            report_exit(&mut session, &mut state, &mut exit_status, status);
+                        }
+
+                        enter(&mut session, &mut state, SessionState::ChildExited);
+                        session.flush_output();
+                        session.emit_debug_event("outputChannelClosed");
+                        info!("Process exited, waiting for an exit command...");
+                        output_open = false;
+                    }
+
                     None => {
+                        // The exit status is sent before the output channel closes, but may not have been picked up yet
+                        if let Some(status) = held_exit.take().or_else(|| exit_code_rx.try_recv().ok()) {
+                            report_exit(&mut session, &mut state, &mut exit_status, status);
+                        }
+
+                        session.flush_output();
                         session.emit_debug_event("outputChannelClosed");
-                        eprintln!("Process exited, shutting down...");
+                        info!("Process exited, shutting down...");
                         break;
                     }
                 }
             }
 
+            data = stderr_rx.recv(), if stderr_open => {
+                match data {
+                    Some(data) => {
+                        let text = stderr_decoder.decode(&data);
+
+                        if !text.is_empty() {
+                            session.stderr(text);
+                        }
+                    }
+                    None => stderr_open = false,
+                }
+            }
+
+            Some(notice) = notice_rx.recv() => {
+                match notice {
+                    pane::Notice::Output(id, data) => {
+                        last_activity = std::time::Instant::now();
+                        session.pane_output(id, data);
+                    }
+
+                    pane::Notice::Pid(id, pid) => session.emit_pane_pid(id, pid),
+
+                    pane::Notice::ExitCode(id, exit_code) => {
+                        set_pane_lifecycle(&mut panes, id, pty::Lifecycle::Ended);
+                        session.emit_pane_exit_code(id, exit_code);
+                    }
+
+                    pane::Notice::Command(id, Command::Completed(fifo_path)) => {
+                        session.emit_pane_command_completed(id);
+                        let fence = command::fence(control_txs(&control_tx, &panes)).await;
+                        fences.push_back(Box::pin(async move {
+                            fence.await;
+                            Fenced::Release(fifo_path)
+                        }));
+                    }
+
+                    pane::Notice::Command(id, Command::Debug(message)) => {
+                        session.emit_debug_event(&format!("pane{id}:{message}"));
+                    }
+
+                    pane::Notice::Command(id, Command::Respawn(attempt, delay)) => {
+                        set_pane_lifecycle(&mut panes, id, pty::Lifecycle::Running);
+                        session.emit_pane_respawn(id, attempt, delay);
+                    }
+
+                    pane::Notice::Command(id, Command::Stopped(signal)) => session.emit_pane_stopped(id, signal),
+
+                    pane::Notice::Command(id, Command::Continued) => session.emit_pane_continued(id),
+
+                    pane::Notice::Command(..) => (),
+                }
+            }
+
+            _ = sleep_until_std(output_flush_deadline), if output_flush_deadline.is_some() => {
+                session.flush_output();
+            }
+
+            _ = tokio::time::sleep_until(last_output_time + cli.idle_after), if !idle => {
+                idle = true;
+                session.emit_idle(last_output_time.elapsed());
+            }
+
+            _ = sleep_until_std(idle_timeout_deadline), if idle_timeout_deadline.is_some() && !timed_out => {
+                info!("nothing happened for {:?}, shutting down...", last_activity.elapsed());
+                session.emit_idle_timeout(last_activity.elapsed());
+                timed_out = true;
+                // Drains like an exit command, so subscribers see the event
+                enter(&mut session, &mut state, SessionState::Draining);
+                let fence = command::fence(control_txs(&control_tx, &panes)).await;
+                fences.push_back(Box::pin(async move {
+                    fence.await;
+                    Fenced::Exit
+                }));
+            }
+
             pid = pid_rx.recv() => {
                 if let Some(pid) = pid {
+                    debug!(pid, "child started");
                     session.emit_pid(pid);
+                    enter(&mut session, &mut state, SessionState::Running);
                 }
             }
 
             exit_code = exit_code_rx.recv() => {
-                if let Some(exit_code) = exit_code {
-                    session.emit_exit_code(exit_code);
+                match exit_code {
+                    // The output the command wrote before exiting was sent first, and comes first
+                    Some(status) if output_open && !output_rx.is_empty() => held_exit = Some(status),
+                    Some(status) => report_exit(&mut session, &mut state, &mut exit_status, status),
+                    None => (),
                 }
             }
 
-            _ = emptiness_check_interval.tick() => {
-                let emptiness_duration = last_command_time.elapsed();
-                
-                // Debug: Show current emptiness duration if we have pending operations
-                if pending_exit || pending_waitexit.is_some() {
-                    session.emit_debug_event(&format!("emptinessCheck:{}ms", emptiness_duration.as_millis()));
-                }
-                
-                // Check if we should signal waitexit due to channel emptiness
-                if let Some(fifo_path) = &pending_waitexit {
-                    if emptiness_duration >= std::time::Duration::from_millis(200) {
-                        // Channel has been empty for 200ms, signal waitexit
-                        session.emit_debug_event("signalingWaitexit");
-                        
-                        if fifo_path.exists() {
-                            if let Ok(mut file) = std::fs::OpenOptions::new()
-                                .write(true)
-                                .open(fifo_path) 
-                            {
-                                use std::io::Write;
-                                let _ = writeln!(file, "exit");
-                                let _ = file.flush();
-                                session.emit_debug_event("exitSignalSent");
-                            } else {
-                                session.emit_debug_event("exitSignalFailed");
-                            }
-                        } else {
-                            session.emit_debug_event("fifoMissingForExit");
-                        }
-                        
-                        pending_waitexit = None; // Clear pending state
+            Some(fenced) = fences.next(), if !fences.is_empty() => {
+                match fenced {
+                    Fenced::Flush(reply) => session.emit_flushed(reply),
+
+                    Fenced::Resize(None, size, reported) => session.follow_pty_resize(size, reported),
+
+                    Fenced::Resize(Some(id), size, reported) => session.pane_follow_pty_resize(id, size, reported),
+
+                    Fenced::Exit => {
+                        session.flush_output();
+                        session.emit_debug_event("exitAfterFence");
+                        break;
                     }
-                }
-                
-                // Check if we should process pending exit due to channel emptiness
-                if pending_exit && emptiness_duration >= std::time::Duration::from_millis(200) {
-                    session.emit_debug_event("exitAfterQuiescence");
-                    break; // Exit the event loop after ensuring command channel is empty
+
+                    Fenced::Release(fifo_path) => signal_waitexit(&mut session, &fifo_path),
                 }
             }
 
             command = command_rx.recv() => {
-                // Update last command time whenever we receive any command
-                last_command_time = std::time::Instant::now();
-                
+                session.check_backlog(&mut command_backlog, command_rx.len());
+
+                // Only commands from a client count as activity, not the ones ht sends itself
+                if !matches!(command, Some(Command::Debug(_) | Command::Completed(_) | Command::Respawn(..) | Command::Stopped(_) | Command::Continued) | None) {
+                    last_activity = std::time::Instant::now();
+                }
+
                 match command {
                     Some(ref cmd) => {
                         session.emit_debug_event(&format!("commandReceived:{:?}", cmd));
@@ -217,25 +560,56 @@ async fn run_event_loop(
                 }
                 
                 match command {
-                    Some(Command::Input(seqs)) => {
-                        let data = command::seqs_to_bytes(&seqs, session.cursor_key_app_mode());
-                        input_tx.send(data).await?;
+                    Some(command) if !command.valid_in(state) => command::reject(&mut session, command, state),
+
+                    Some(Command::Input(seqs, reply)) => {
+                        let data = session.encode_input(&seqs);
+
+                        // The driver lets go of its input once the command has exited, which can happen before the exit status arrives
+                        if let Err(discarded) = command::send_input(&input_tx, &control_tx, data, reply, |event| event).await {
+                            enter(&mut session, &mut state, SessionState::ChildExited);
+                            session.emit_input_discarded(discarded.bytes, discarded.reply);
+                        }
                     }
 
-                    Some(Command::Snapshot) => {
+                    Some(Command::Snapshot(reply)) => {
                         session.emit_debug_event("snapshotCommandReceived");
-                        session.snapshot();
+
+                        match reply {
+                            Some(reply) => session.snapshot_for(reply),
+                            None => session.snapshot(),
+                        }
+
                         session.emit_debug_event("snapshotCommandCompleted");
                     }
 
-                    Some(Command::Resize(cols, rows)) => {
-                        session.resize(cols, rows);
-                    }
+                    Some(Command::Resize(cols, rows)) => match config::Size::checked(cols, rows) {
+                        Ok(size) => {
+                            // The screen follows once the PTY has taken the size and the command has been told
+                            let resized = command::resize(&control_tx, size).await;
+                            fences.push_back(Box::pin(async move { Fenced::Resize(None, size, resized.await) }));
+                        }
+
+                        // Refused, with an error event
+                        Err(_) => session.resize(cols, rows),
+                    },
 
                     Some(Command::SetStyleMode(style_mode)) => {
                         session.set_style_mode(style_mode);
                     }
 
+                    Some(Command::SetStyleFormat(style_format)) => {
+                        session.set_style_format(style_format);
+                    }
+
+                    Some(Command::SetTermios(settings)) => {
+                        send_control(&mut session, &control_tx, state.takes_input(), pty::Control::SetTermios(settings)).await;
+                    }
+
+                    Some(Command::CloseStdin) => {
+                        send_control(&mut session, &control_tx, state.takes_input(), pty::Control::CloseStdin).await;
+                    }
+
                     Some(Command::Debug(message)) => {
                         // Emit all debug messages as debug events
                         session.emit_debug_event(&message);
@@ -243,22 +617,94 @@ async fn run_event_loop(
 
                     Some(Command::Completed(fifo_path)) => {
                         session.emit_command_completed();
-                        // Set up pending waitexit - it will be triggered when channel is empty for 200ms
-                        pending_waitexit = Some(fifo_path);
                         session.emit_debug_event("commandCompletedReceived");
+
+                        // The command is let go once the commands sent before its completion have
+                        // been seen to, so that input typed ahead of it isn't discarded
+                        let fence = command::fence(control_txs(&control_tx, &panes)).await;
+                        fences.push_back(Box::pin(async move {
+                            fence.await;
+                            Fenced::Release(fifo_path)
+                        }));
+                    }
+
+                    Some(Command::Respawn(attempt, delay)) => {
+                        session.emit_respawn(attempt, delay);
+                        enter(&mut session, &mut state, SessionState::Starting);
+                    }
+
+                    Some(Command::Stopped(signal)) => session.emit_stopped(signal),
+
+                    Some(Command::Continued) => session.emit_continued(),
+
+                    Some(Command::Pane(id, command)) => {
+                        match panes.get_mut(&id) {
+                            Some(pane) => match *command {
+                                Command::Input(seqs, reply) => {
+                                    let data = session.pane_encode_input(id, &seqs);
+                                    let in_pane = move |event| Event::Pane(id, Box::new(event));
+
+                                    let sent = match pane.lifecycle {
+                                        pty::Lifecycle::Running => command::send_input(&pane.input_tx, &pane.control_tx, data, reply, in_pane).await,
+                                        pty::Lifecycle::Ended => Err(command::Discarded { bytes: data.len(), reply }),
+                                    };
+
+                                    if let Err(discarded) = sent {
+                                        pane.lifecycle = pty::Lifecycle::Ended;
+                                        session.emit_pane_input_discarded(id, discarded.bytes, discarded.reply);
+                                    }
+                                }
+
+                                Command::Snapshot(Some(reply)) => session.pane_snapshot_for(id, reply),
+
+                                Command::Snapshot(None) => session.pane_snapshot(id),
+
+                                Command::Resize(cols, rows) => match config::Size::checked(cols, rows) {
+                                    Ok(size) => {
+                                        let resized = command::resize(&pane.control_tx, size).await;
+                                        fences.push_back(Box::pin(async move { Fenced::Resize(Some(id), size, resized.await) }));
+                                    }
+
+                                    Err(_) => session.pane_resize(id, cols, rows),
+                                },
+
+                                Command::SetTermios(settings) => {
+                                    send_control(&mut session, &pane.control_tx, pane.lifecycle == pty::Lifecycle::Running, pty::Control::SetTermios(settings)).await;
+                                }
+
+                                Command::CloseStdin => {
+                                    send_control(&mut session, &pane.control_tx, pane.lifecycle == pty::Lifecycle::Running, pty::Control::CloseStdin).await;
+                                }
+
+                                command => session.emit_debug_event(&format!("unsupportedPaneCommand:{id}:{command:?}")),
+                            },
+
+                            None => session.emit_debug_event(&format!("unknownPane:{id}")),
+                        }
                     }
 
+                    Some(Command::Flush(reply)) => {
+                        let fence = command::fence(control_txs(&control_tx, &panes)).await;
+                        fences.push_back(Box::pin(async move {
+                            fence.await;
+                            Fenced::Flush(reply)
+                        }));
+                    }
 
                     Some(Command::Exit) => {
                         session.emit_debug_event("exitCommandReceived");
-                        // Don't exit immediately - wait for command channel to be empty for 200ms
-                        // This ensures any pending commands (like snapshot) are processed first
-                        pending_exit = true;
-                        session.emit_debug_event("exitCommandQueued");
+                        // Closes once the commands sent before have been seen to
+                        enter(&mut session, &mut state, SessionState::Draining);
+                        let fence = command::fence(control_txs(&control_tx, &panes)).await;
+                        fences.push_back(Box::pin(async move {
+                            fence.await;
+                            Fenced::Exit
+                        }));
                     }
 
                     None => {
-                        eprintln!("stdin closed, shutting down...");
+                        session.flush_output();
+                        info!("stdin closed, shutting down...");
                         break;
                     }
                 }
@@ -267,7 +713,7 @@ async fn run_event_loop(
             client = clients_rx.recv(), if serving => {
                 match client {
                     Some(client) => {
-                        client.accept(session.subscribe());
+                        client.accept(&mut session);
                     }
 
                     None => {
@@ -283,8 +729,99 @@ async fn run_event_loop(
                 // Keep processing commands from the buffer - they might already be queued
                 session.emit_debug_event("apiClosedContinuingToProcessCommands");
             }
+
+            _ = shutdown.triggered() => {
+                session.flush_output();
+                break;
+            }
         }
     }
 
-    Ok(())
+    // Tell the API to stop taking commands, and the PTY driver to hang up on the command if
+    // it's still running (or stop holding the session open for snapshots if it isn't)
+    shutdown.trigger();
+
+    match &cli.final_snapshot {
+        Some(cli::FinalSnapshot::Event) => session.snapshot(),
+
+        Some(cli::FinalSnapshot::File(path)) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            session.snapshot_for(reply_tx);
+
+            if let Event::Snapshot(_, _, _, _, text, _) = reply_rx.await? {
+                std::fs::write(path, text).with_context(|| format!("cannot write final snapshot to {}", path.display()))?;
+            }
+        }
+
+        None => (),
+    }
+
+    enter(&mut session, &mut state, SessionState::Closed);
+
+    // Closing the session ends the API's event stream, once it has passed on what's left of it
+    drop(session);
+    drop(command_rx);
+
+    if !api_completed {
+        let _ = tokio::time::timeout(cli.timing.subscription_timeout, api_handle).await;
+    }
+
+    if let Some(meter) = meter {
+        eprintln!("{meter}");
+    }
+
+    Ok(Ending { exit_status, timed_out })
+}
+
+/// The control channels of the main PTY driver and each pane's.
+fn control_txs<'a>(control_tx: &'a mpsc::Sender<pty::Control>, panes: &'a BTreeMap<usize, pane::Handle>) -> impl Iterator<Item = &'a mpsc::Sender<pty::Control>> {
+    std::iter::once(control_tx).chain(panes.values().map(|pane| &pane.control_tx))
+}
+
+/// Move the session on to `next`, if it can get there from where it is, and tell subscribers.
+fn report_exit(session: &mut Session, state: &mut SessionState, exit_status: &mut Option<pty::ExitStatus>, status: pty::ExitStatus) {
+    debug!(code = status.code, signal = ?status.signal, "child exited");
+    session.emit_exit_code(status.clone());
+    *exit_status = Some(status);
+    enter(session, state, SessionState::ChildExited);
+}
+
+fn enter(session: &mut Session, state: &mut SessionState, next: SessionState) {
+    if state.can_become(next) {
+        *state = next;
+        session.emit_state(next);
+    }
+}
+
+/// Pass a control message to a PTY driver, if its child is `running`: otherwise there's
+/// nothing for it to act on and the driver may already have dropped its end of the channel.
+async fn send_control(session: &mut Session, control_tx: &mpsc::Sender<pty::Control>, running: bool, control: pty::Control) {
+    if !running || control_tx.send(control).await.is_err() {
+        session.emit_debug_event("controlDiscarded");
+    }
+}
+
+fn set_pane_lifecycle(panes: &mut BTreeMap<usize, pane::Handle>, id: usize, lifecycle: pty::Lifecycle) {
+    if let Some(pane) = panes.get_mut(&id) {
+        pane.lifecycle = lifecycle;
+    }
+}
+
+fn signal_waitexit(session: &mut Session, fifo_path: &std::path::Path) {
+    session.emit_debug_event("signalingWaitexit");
+
+    if runtime::is_fifo(fifo_path) {
+        match pty::release_wait_exit(fifo_path) {
+            Ok(()) => session.emit_debug_event("exitSignalSent"),
+            Err(_) => session.emit_debug_event("exitSignalFailed"),
+        }
+    } else {
+        session.emit_debug_event("fifoMissingForExit");
+    }
+}
+
+async fn sleep_until_std(deadline: Option<std::time::Instant>) {
+    if let Some(deadline) = deadline {
+        tokio::time::sleep_until(deadline.into()).await;
+    }
 }
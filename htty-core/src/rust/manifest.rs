@@ -0,0 +1,103 @@
+//! A JSON summary of what a run of ht left behind, so CI can collect it without knowing ht.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "command": ["make", "test"],
+//!   "exitCode": 0,
+//!   "signal": null,
+//!   "timedOut": false,
+//!   "startedAt": 1760400000.25,
+//!   "durationMs": 5120,
+//!   "artifacts": [{ "kind": "recording", "path": "/tmp/out/session.cast" }]
+//! }
+//! ```
+
+use crate::cli::{Cli, Commands, FinalSnapshot};
+use crate::pty::ExitStatus;
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Name of the manifest when it's written next to the artifacts.
+const FILE_NAME: &str = "htty-manifest.json";
+
+/// When ht started, for the manifest's timings.
+pub struct Start {
+    time: SystemTime,
+    instant: Instant,
+}
+
+impl Start {
+    pub fn now() -> Self {
+        Self {
+            time: SystemTime::now(),
+            instant: Instant::now(),
+        }
+    }
+}
+
+/// The files ht was asked to produce that it did produce, each with its kind.
+fn artifacts(cli: &Cli) -> Vec<(&'static str, PathBuf)> {
+    let mut artifacts = Vec::new();
+
+    if let Some(Commands::Record { output }) = &cli.command {
+        artifacts.push(("recording", output.join("session.cast")));
+        artifacts.push(("snapshot", output.join("snapshot.txt")));
+        artifacts.push(("exitCode", output.join("exit-code")));
+    }
+
+    if let Some(FinalSnapshot::File(path)) = &cli.final_snapshot {
+        artifacts.push(("snapshot", path.clone()));
+    }
+
+    if let Some(path) = &cli.log_file {
+        artifacts.push(("log", path.clone()));
+    }
+
+    artifacts.retain(|(_, path)| path.exists());
+    artifacts
+}
+
+/// Where the manifest goes: as given with `--manifest`, in the output directory of `ht record`,
+/// or else next to the first artifact. None if there's nothing to list.
+fn path(cli: &Cli, artifacts: &[(&str, PathBuf)]) -> Option<PathBuf> {
+    if let Some(path) = &cli.manifest {
+        return Some(path.clone());
+    }
+
+    if let Some(Commands::Record { output }) = &cli.command {
+        return Some(output.join(FILE_NAME));
+    }
+
+    let (_, first) = artifacts.first()?;
+    Some(first.parent().unwrap_or(Path::new("")).join(FILE_NAME))
+}
+
+/// Write the manifest for this run, if it produced any artifacts or one was asked for.
+pub fn write(cli: &Cli, start: &Start, exit_status: Option<&ExitStatus>, timed_out: bool) -> Result<()> {
+    let artifacts = artifacts(cli);
+
+    let Some(path) = path(cli, &artifacts) else {
+        return Ok(());
+    };
+
+    // Absolute, so the manifest can be read from anywhere
+    let cwd = env::current_dir().unwrap_or_default();
+    let artifacts: Vec<_> = artifacts.iter().map(|(kind, path)| json!({ "kind": kind, "path": cwd.join(path) })).collect();
+
+    let manifest = json!({
+        "version": 1,
+        "command": cli.shell_command,
+        "exitCode": exit_status.map(|status| status.code),
+        "signal": exit_status.and_then(|status| status.signal),
+        "timedOut": timed_out,
+        "startedAt": start.time.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0),
+        "durationMs": start.instant.elapsed().as_millis() as u64,
+        "artifacts": artifacts,
+    });
+
+    fs::write(&path, format!("{manifest:#}\n")).with_context(|| format!("cannot write manifest {}", path.display()))
+}
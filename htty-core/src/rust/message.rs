@@ -0,0 +1,272 @@
+//! Typed form of the JSON events ht writes to stdout and WebSocket clients.
+//!
+//! Every message is an object with a `type` and a `data` field. Events from an additional pane
+//! carry its id in `data.pane`. Serializing borrows from the session's events rather than
+//! copying them, and Rust consumers can deserialize a line of ht's output into a `Message`.
+
+use crate::status::Terminator;
+use crate::config::StyleFormat;
+use crate::session::{PenJson, SessionState, StyleMap};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
+pub enum Message<'a> {
+    Init(Init<'a>),
+    Output(Output<'a>),
+    RawOutput(RawOutput<'a>),
+    Stderr(Output<'a>),
+    Resize(Resize),
+    Snapshot(Snapshot<'a>),
+    InputWritten(InputWritten),
+    InputDiscarded(InputDiscarded),
+    Flushed(Flushed),
+    Pid(Pid),
+    ExitCode(ExitCode<'a>),
+    Debug(Debug<'a>),
+    Error(Error<'a>),
+    CommandCompleted(CommandCompleted),
+    Respawn(Respawn),
+    Stopped(Stopped<'a>),
+    Continued(Continued),
+    Idle(Quiet),
+    Active(Quiet),
+    IdleTimeout(Quiet),
+    State(State),
+    Dropped(Dropped),
+    Backlog(Backlog<'a>),
+    Batch(Batch<'a>),
+}
+
+impl Message<'_> {
+    /// Tag this message as coming from the additional pane `id`.
+    pub fn in_pane(mut self, id: usize) -> Self {
+        self.set_pane(id);
+        self
+    }
+
+    fn set_pane(&mut self, id: usize) {
+        let pane = match self {
+            Message::Init(data) => &mut data.pane,
+            Message::Output(data) | Message::Stderr(data) => &mut data.pane,
+            Message::RawOutput(data) => &mut data.pane,
+            Message::Resize(data) => &mut data.pane,
+            Message::Snapshot(data) => &mut data.pane,
+            Message::InputWritten(data) => &mut data.pane,
+            Message::InputDiscarded(data) => &mut data.pane,
+            Message::Flushed(data) => &mut data.pane,
+            Message::Pid(data) => &mut data.pane,
+            Message::ExitCode(data) => &mut data.pane,
+            Message::Debug(data) => &mut data.pane,
+            Message::Error(data) => &mut data.pane,
+            Message::CommandCompleted(data) => &mut data.pane,
+            Message::Respawn(data) => &mut data.pane,
+            Message::Stopped(data) => &mut data.pane,
+            Message::Continued(data) => &mut data.pane,
+            Message::Idle(data) | Message::Active(data) | Message::IdleTimeout(data) => &mut data.pane,
+            Message::State(data) => &mut data.pane,
+            Message::Dropped(data) => &mut data.pane,
+            Message::Backlog(data) => &mut data.pane,
+
+            Message::Batch(batch) => {
+                batch.events.iter_mut().for_each(|message| message.set_pane(id));
+                return;
+            }
+        };
+
+        *pane = Some(id);
+    }
+}
+
+/// Per-cell styling sent along with screen contents in `styled` mode.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Styles<'a> {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub char_map: Option<Cow<'a, [Vec<char>]>>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub style_map: Option<Cow<'a, StyleMap>>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub style_format: Option<StyleFormat>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub styles: Option<Cow<'a, HashMap<String, PenJson>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Init<'a> {
+    pub cols: usize,
+    pub rows: usize,
+    pub pid: i32,
+    pub seq: Cow<'a, str>,
+    pub text: Cow<'a, str>,
+    #[serde(flatten)]
+    pub styles: Styles<'a>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Output<'a> {
+    pub seq: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RawOutput<'a> {
+    /// The bytes the command wrote, base64-encoded.
+    pub bytes: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Resize {
+    pub cols: usize,
+    pub rows: usize,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot<'a> {
+    pub time: f64,
+    pub cols: usize,
+    pub rows: usize,
+    pub seq: Cow<'a, str>,
+    pub text: Cow<'a, str>,
+    #[serde(flatten)]
+    pub styles: Styles<'a>,
+    /// The screen rendered as HTML, when the takeSnapshot command asked for it.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub html: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputWritten {
+    pub bytes: usize,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputDiscarded {
+    pub bytes: usize,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Flushed {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pid {
+    pub pid: i32,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExitCode<'a> {
+    pub exit_code: i32,
+    pub signal: Option<Cow<'a, str>>,
+    pub core_dumped: bool,
+    pub terminated_by: Terminator,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Debug<'a> {
+    pub message: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Error<'a> {
+    pub message: Cow<'a, str>,
+    /// The line of input that couldn't be taken as a command, for errors about one.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub line: Option<Cow<'a, str>>,
+    /// The `id` the line gave, if it was a JSON object with one.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub id: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandCompleted {
+    pub time: f64,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Respawn {
+    pub attempt: u32,
+    pub delay_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stopped<'a> {
+    pub signal: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Continued {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+/// Data of `idle`, `active` and `idleTimeout` events: how long the terminal had been quiet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quiet {
+    pub quiet_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    pub state: SessionState,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dropped {
+    pub count: u64,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Backlog<'a> {
+    pub channel: Cow<'a, str>,
+    pub queued: usize,
+    pub capacity: usize,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pane: Option<usize>,
+}
+
+/// Several events sent as one frame to a WebSocket client which asked for batching.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Batch<'a> {
+    pub events: Vec<Message<'a>>,
+}
@@ -0,0 +1,175 @@
+use anyhow::bail;
+use std::str::FromStr;
+
+/// Linux namespaces to create for the child, parsed from a list such as `net,pid`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Namespaces {
+    pub net: bool,
+    pub pid: bool,
+    pub ipc: bool,
+    pub uts: bool,
+    pub mount: bool,
+    pub user: bool,
+}
+
+impl Namespaces {
+    pub fn is_empty(&self) -> bool {
+        *self == Namespaces::default()
+    }
+
+    /// Fail early (before forking) when namespaces were requested but this build can't create them.
+    pub fn ensure_supported(&self) -> anyhow::Result<()> {
+        if !self.is_empty() && !cfg!(all(feature = "namespaces", target_os = "linux")) {
+            bail!("--unshare requires ht to be built for Linux with the `namespaces` feature");
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Namespaces {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut namespaces = Namespaces::default();
+
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match name {
+                "net" => namespaces.net = true,
+                "pid" => namespaces.pid = true,
+                "ipc" => namespaces.ipc = true,
+                "uts" => namespaces.uts = true,
+                "mount" | "mnt" => namespaces.mount = true,
+                "user" => namespaces.user = true,
+                _ => {
+                    return Err(format!(
+                        "invalid namespace: {name}. Valid options: net, pid, ipc, uts, mount, user"
+                    ))
+                }
+            }
+        }
+
+        Ok(namespaces)
+    }
+}
+
+#[cfg(all(feature = "namespaces", target_os = "linux"))]
+mod linux {
+    use super::Namespaces;
+    use nix::errno::Errno;
+    use nix::libc;
+    use nix::mount::{mount, MsFlags};
+    use nix::sched::{unshare, CloneFlags};
+    use nix::sys::signal::{self, SigHandler};
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{self, ForkResult, Gid, Uid};
+    use std::fs;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    impl Namespaces {
+        /// Move the calling (forked, not yet exec'd) process into the requested namespaces.
+        ///
+        /// Without root a user namespace is created as well, mapping the current uid/gid onto
+        /// themselves. A new PID namespace only applies to children, so the caller forks once more:
+        /// the new process becomes PID 1 and returns, while the original stays behind to relay its
+        /// exit status and never returns.
+        pub fn enter(&self) -> nix::Result<()> {
+            let uid = unistd::getuid();
+            let gid = unistd::getgid();
+            let user = self.user || !uid.is_root();
+
+            let flags = [
+                (user, CloneFlags::CLONE_NEWUSER),
+                (self.net, CloneFlags::CLONE_NEWNET),
+                (self.pid, CloneFlags::CLONE_NEWPID),
+                (self.ipc, CloneFlags::CLONE_NEWIPC),
+                (self.uts, CloneFlags::CLONE_NEWUTS),
+                (self.mount, CloneFlags::CLONE_NEWNS),
+            ]
+            .into_iter()
+            .filter(|(on, _)| *on)
+            .fold(CloneFlags::empty(), |acc, (_, flag)| acc | flag);
+
+            unshare(flags)?;
+
+            if user {
+                write_id_maps(uid, gid)?;
+            }
+
+            if self.net {
+                bring_up_loopback()?;
+            }
+
+            if self.pid {
+                become_pid_1()?;
+
+                if self.mount {
+                    mount_proc()?;
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    fn write_id_maps(uid: Uid, gid: Gid) -> nix::Result<()> {
+        let write = |file: &str, contents: String| {
+            fs::write(format!("/proc/self/{file}"), contents)
+                .map_err(|e| Errno::from_raw(e.raw_os_error().unwrap_or(libc::EPERM)))
+        };
+
+        write("setgroups", "deny".to_string())?;
+        write("uid_map", format!("{uid} {uid} 1"))?;
+        write("gid_map", format!("{gid} {gid} 1"))
+    }
+
+    /// A fresh network namespace only has a loopback interface, and it starts out down.
+    fn bring_up_loopback() -> nix::Result<()> {
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        let fd = unsafe { OwnedFd::from_raw_fd(Errno::result(fd)?) };
+        let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+
+        for (dst, src) in req.ifr_name.iter_mut().zip(b"lo") {
+            *dst = *src as libc::c_char;
+        }
+
+        Errno::result(unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCGIFFLAGS, &mut req) })?;
+        unsafe { req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short };
+        Errno::result(unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCSIFFLAGS, &req) })?;
+
+        Ok(())
+    }
+
+    fn become_pid_1() -> nix::Result<()> {
+        match unsafe { unistd::fork() }? {
+            ForkResult::Child => {
+                // Don't outlive the relay: whoever signals the pid ht reported takes us down too.
+                Errno::result(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) })?;
+                Ok(())
+            }
+
+            ForkResult::Parent { child } => loop {
+                match waitpid(child, None) {
+                    Ok(WaitStatus::Exited(_, code)) => unsafe { libc::_exit(code) },
+
+                    Ok(WaitStatus::Signaled(_, sig, _)) => {
+                        let _ = unsafe { signal::signal(sig, SigHandler::SigDfl) };
+                        let _ = signal::raise(sig);
+                        unsafe { libc::_exit(128 + sig as i32) }
+                    }
+
+                    Ok(_) | Err(Errno::EINTR) => continue,
+
+                    Err(_) => unsafe { libc::_exit(1) },
+                }
+            },
+        }
+    }
+
+    /// Remount /proc so tools like `ps` only see the new PID namespace.
+    fn mount_proc() -> nix::Result<()> {
+        let none: Option<&str> = None;
+        mount(none, "/", none, MsFlags::MS_REC | MsFlags::MS_PRIVATE, none)?;
+        mount(Some("proc"), "/proc", Some("proc"), MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC, none)
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::{self, ErrorKind, Read};
+use std::io::{self, ErrorKind, IoSlice, Read};
 use std::{io::Write, os::fd::RawFd};
 
 pub fn set_non_blocking(fd: &RawFd) -> Result<(), io::Error> {
@@ -22,14 +22,14 @@ pub fn read<R: Read + ?Sized>(source: &mut R, buf: &mut [u8]) -> io::Result<Opti
             } else if e.raw_os_error().is_some_and(|code| code == 5) {
                 Ok(Some(0))
             } else {
-                return Err(e);
+                Err(e)
             }
         }
     }
 }
 
-pub fn write<W: Write + ?Sized>(sink: &mut W, buf: &[u8]) -> io::Result<Option<usize>> {
-    match sink.write(buf) {
+pub fn write_vectored<W: Write + ?Sized>(sink: &mut W, bufs: &[IoSlice<'_>]) -> io::Result<Option<usize>> {
+    match sink.write_vectored(bufs) {
         Ok(n) => Ok(Some(n)),
 
         Err(e) => {
@@ -38,7 +38,7 @@ pub fn write<W: Write + ?Sized>(sink: &mut W, buf: &[u8]) -> io::Result<Option<u
             } else if e.raw_os_error().is_some_and(|code| code == 5) {
                 Ok(Some(0))
             } else {
-                return Err(e);
+                Err(e)
             }
         }
     }
@@ -0,0 +1,127 @@
+use crate::config::Geometry;
+use crate::command::Command;
+use crate::pty;
+use crate::shutdown::Shutdown;
+use crate::utf8;
+use anyhow::{bail, Result};
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// An additional pane requested on the command line, written as `COLSxROWS+X+Y:COMMAND`.
+#[derive(Debug, Clone)]
+pub struct Spec {
+    pub geometry: Geometry,
+    pub command: String,
+}
+
+impl FromStr for Spec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((geometry, command)) if !command.trim().is_empty() => Ok(Self {
+                geometry: geometry.parse()?,
+                command: command.to_string(),
+            }),
+
+            _ => bail!("invalid pane: {s}. Expected COLSxROWS+X+Y:COMMAND"),
+        }
+    }
+}
+
+/// Something that happened in one of the additional panes, tagged with the pane id.
+#[derive(Debug)]
+pub enum Notice {
+    Output(usize, String),
+    Pid(usize, i32),
+    ExitCode(usize, pty::ExitStatus),
+    Command(usize, Command),
+}
+
+/// The event loop's end of a running pane.
+pub struct Handle {
+    pub input_tx: mpsc::Sender<Vec<u8>>,
+    pub control_tx: mpsc::Sender<pty::Control>,
+    pub lifecycle: pty::Lifecycle,
+}
+
+/// Start `spec.command` in its own PTY, reporting everything it does through `notice_tx`.
+pub fn spawn(
+    id: usize,
+    spec: &Spec,
+    fifo_path: PathBuf,
+    options: pty::Options,
+    capacity: usize,
+    notice_tx: mpsc::Sender<Notice>,
+    shutdown: Shutdown,
+) -> Result<Handle> {
+    let (input_tx, input_rx) = mpsc::channel(capacity);
+    let (control_tx, control_rx) = mpsc::channel(capacity);
+    let (output_tx, mut output_rx) = mpsc::channel::<Vec<u8>>(capacity);
+    let (stderr_tx, _) = mpsc::channel(1);
+    let (pid_tx, mut pid_rx) = mpsc::channel(1);
+    let (exit_code_tx, mut exit_code_rx) = mpsc::channel(1);
+    let (command_tx, mut command_rx) = mpsc::channel(capacity);
+
+    let options = pty::Options {
+        separate_stderr: false,
+        ..options
+    };
+
+    info!(
+        "launching \"{}\" in pane {} of size {}",
+        spec.command,
+        id,
+        spec.geometry.size()
+    );
+
+    tokio::spawn(pty::spawn(
+        pty::Program::Shell(spec.command.clone()),
+        fifo_path,
+        &spec.geometry.size(),
+        options,
+        input_rx,
+        control_rx,
+        output_tx,
+        stderr_tx,
+        pid_tx,
+        exit_code_tx,
+        command_tx,
+        shutdown,
+    )?);
+
+    tokio::spawn(async move {
+        let mut decoder = utf8::Decoder::default();
+
+        loop {
+            let notice = tokio::select! {
+                Some(data) = output_rx.recv() => {
+                    let text = decoder.decode(&data);
+
+                    if text.is_empty() {
+                        continue;
+                    }
+
+                    Notice::Output(id, text)
+                }
+                Some(pid) = pid_rx.recv() => Notice::Pid(id, pid),
+                Some(status) = exit_code_rx.recv() => Notice::ExitCode(id, status),
+                Some(command) = command_rx.recv() => Notice::Command(id, command),
+                _ = notice_tx.closed() => break,
+                else => break,
+            };
+
+            if notice_tx.send(notice).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(Handle {
+        input_tx,
+        control_tx,
+        lifecycle: pty::Lifecycle::Running,
+    })
+}
@@ -1,175 +1,705 @@
+use crate::cgroup;
+use crate::error::HttyError;
+use crate::namespace::Namespaces;
 use crate::nbio;
-use anyhow::Result;
+use crate::runtime;
+use crate::shutdown::Shutdown;
+use crate::termios;
+use crate::timing::Timing;
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
 use nix::libc;
 use nix::pty;
 use nix::sys::signal::{self, SigHandler, Signal};
 use nix::sys::wait;
 use nix::unistd::{self, ForkResult, Pid};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
 use std::env;
 use std::ffi::{CString, NulError};
 use std::fs::File;
 use std::future::Future;
-use std::io::{self};
+use std::io::{self, IoSlice};
+use std::mem::ManuallyDrop;
 use std::os::fd::FromRawFd;
 use std::os::fd::{AsRawFd, OwnedFd};
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use tokio::io::unix::AsyncFd;
-use tokio::sync::mpsc;
+use tokio::io::Interest;
+use tokio::io::AsyncReadExt;
+use tokio::signal::unix::SignalKind;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
 use crate::command::Command;
+use crate::config::Size;
+pub use crate::status::{ExitStatus, Terminator};
 
+/// Settings controlling how the child process is launched.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Run the shell as a login shell (argv[0] prefixed with `-`), so it sources profile files.
+    pub login: bool,
+    /// Start the child in a new session (setsid), detached from ht's controlling terminal.
+    pub session_leader: bool,
+    /// Make the PTY the controlling terminal of the child's session (requires `session_leader`).
+    pub controlling_terminal: bool,
+    /// Run the shell with job control enabled (`sh -m`), so jobs get their own process groups.
+    pub job_control: bool,
+    /// Redirect the child's stderr to a pipe instead of the PTY, delivering it separately.
+    pub separate_stderr: bool,
+    /// Line discipline settings applied to the PTY before the child starts.
+    pub termios: termios::Settings,
+    /// Follow the command with `<helper> wait-exit <fifo>`, holding the PTY open until its
+    /// completion has been reported.
+    pub wait_exit: bool,
+    /// Binary invoked as `<helper> wait-exit <fifo>` after the command finishes (defaults to this executable).
+    pub wait_exit_helper: Option<PathBuf>,
+    /// cgroup v2 directory the child joins before exec, taking all its descendants with it.
+    pub cgroup: Option<PathBuf>,
+    /// Linux namespaces the child is moved into before exec.
+    pub namespaces: Namespaces,
+    /// Relaunch the child in the same session when it exits with a nonzero status.
+    pub respawn: Respawn,
+    /// Bytes read from the PTY (and the stderr pipe) at a time.
+    pub read_buf_size: usize,
+    /// Hold the output channel open after the command's final exit, so that ht keeps serving
+    /// snapshots instead of shutting down.
+    pub keep_alive: bool,
+    /// Keep the PTY's child side open in ht too, so that output carries on when the command's
+    /// side is closed and reopened (by a program that daemonizes and reattaches, say) rather
+    /// than ending at the hangup. Output then only ends with the session, which is why this is
+    /// ignored when relaunching with `respawn`, as that waits for it to end.
+    pub survive_hangup: bool,
+    /// Environment variables set for the command, overriding ht's own.
+    pub env: HashMap<String, String>,
+    /// Polling intervals used while waiting for the command to finish and afterwards.
+    pub timing: Timing,
+}
+
+impl ExitStatus {
+    /// The status the child ended with, as `waitpid` reported it. With `shell_wrapped` the child
+    /// was a shell running the command, which passes on a command killed by a signal as exit
+    /// code `128 + signal`, and which may itself have been killed before it could.
+    fn from_wait(status: wait::WaitStatus, shell_wrapped: bool) -> Self {
+        match status {
+            wait::WaitStatus::Exited(_, code) => Self {
+                code,
+                signal: code
+                    .checked_sub(128)
+                    .filter(|n| shell_wrapped && *n > 0)
+                    .and_then(|n| Signal::try_from(n).ok())
+                    .map(Signal::as_str),
+                core_dumped: false,
+                terminated_by: Terminator::Command,
+            },
+
+            wait::WaitStatus::Signaled(_, signal, core_dumped) => Self {
+                code: 128 + signal as i32,
+                signal: Some(signal.as_str()),
+                core_dumped,
+                terminated_by: if shell_wrapped { Terminator::Shell } else { Terminator::Command },
+            },
+
+            _ => Self::unknown(),
+        }
+    }
+}
+
+/// Whether a PTY driver's child is there to take input, as the event loop driving it sees it.
+///
+/// Input sent once the child has ended is discarded and reported, rather than depending on
+/// whether the driver has let go of its input channel yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Lifecycle {
+    #[default]
+    Running,
+    /// The child exited, and hasn't been respawned (yet), or its driver has stopped.
+    Ended,
+}
+
+/// Requests for the PTY driver which aren't input destined for the child.
+#[derive(Debug)]
+pub enum Control {
+    SetTermios(termios::Settings),
+    /// Signal end-of-input to whatever is reading the terminal.
+    CloseStdin,
+    /// Notify the sender once all input sent before this has been written to the PTY.
+    NotifyWritten(oneshot::Sender<()>),
+    /// Resize the terminal and let the child know, replying with the size the PTY then reports.
+    Resize(Size, oneshot::Sender<io::Result<Size>>),
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            login: false,
+            session_leader: true,
+            controlling_terminal: true,
+            job_control: false,
+            separate_stderr: false,
+            termios: termios::Settings::default(),
+            wait_exit: true,
+            wait_exit_helper: None,
+            cgroup: None,
+            namespaces: Namespaces::default(),
+            respawn: Respawn::default(),
+            read_buf_size: DEFAULT_READ_BUF_SIZE,
+            keep_alive: false,
+            survive_hangup: false,
+            env: HashMap::new(),
+            timing: Timing::default(),
+        }
+    }
+}
+
+/// What the child runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Program {
+    /// A command line for `/bin/sh` to interpret, pipes, expansions and all.
+    Shell(String),
+    /// A program and its arguments, which reach it exactly as they are.
+    Argv(Vec<String>),
+}
+
+impl Program {
+    /// The command given as `args` on ht's command line: a single argument is a command line
+    /// for the shell (as in `ht "make | tee log"`), and several are a program and its arguments.
+    pub fn from_args(mut args: Vec<String>) -> Self {
+        if args.len() > 1 {
+            Program::Argv(args)
+        } else {
+            Program::Shell(args.pop().unwrap_or_default())
+        }
+    }
+
+    /// A command line the shell runs this as, with each argument of a program quoted.
+    fn to_shell(&self) -> String {
+        match self {
+            Program::Shell(line) => line.clone(),
+            Program::Argv(args) => args.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" "),
+        }
+    }
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_shell())
+    }
+}
+
+/// How many times a child that exits with a nonzero status gets relaunched.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Respawn {
+    #[default]
+    Never,
+    Limited(u32),
+    Unlimited,
+}
+
+impl Respawn {
+    fn allows(&self, attempt: u32) -> bool {
+        match self {
+            Respawn::Never => false,
+            Respawn::Limited(limit) => attempt <= *limit,
+            Respawn::Unlimited => true,
+        }
+    }
+
+    /// Exponential backoff between relaunches: 100ms, 200ms, 400ms, ... capped at 5s.
+    fn delay(attempt: u32) -> std::time::Duration {
+        let millis = 100u64.saturating_mul(1 << attempt.saturating_sub(1).min(6));
+        std::time::Duration::from_millis(millis.min(5000))
+    }
+}
+
+/// The PTY a child was started in, as ht holds it.
+struct Terminal {
+    master: OwnedFd,
+    /// The child side, when `survive_hangup` has ht hold it open as well.
+    slave: Option<OwnedFd>,
+}
+
+/// Everything needed to start the child, kept around so it can be started again.
+struct Launch {
+    command: Program,
+    fifo_path: PathBuf,
+    winsize: pty::Winsize,
+    options: Options,
+    wait_exit_helper: Option<PathBuf>,
+    stderr_tx: mpsc::Sender<Vec<u8>>,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn spawn(
-    command: String,
-    winsize: &pty::Winsize,
+    command: Program,
+    fifo_path: PathBuf,
+    size: &Size,
+    options: Options,
     input_rx: mpsc::Receiver<Vec<u8>>,
+    control_rx: mpsc::Receiver<Control>,
     output_tx: mpsc::Sender<Vec<u8>>,
+    stderr_tx: mpsc::Sender<Vec<u8>>,
     pid_tx: mpsc::Sender<i32>,
-    exit_code_tx: mpsc::Sender<i32>,
+    exit_code_tx: mpsc::Sender<ExitStatus>,
     command_tx: mpsc::Sender<Command>,
-) -> Result<impl Future<Output = Result<()>>> {
-    // Generate FIFO path using parent PID (step 1 in desired flow)
-    let fifo_path = format!("/tmp/ht_fifo_{}", std::process::id());
-    let fifo_path_buf = PathBuf::from(&fifo_path);
-
-    let result = unsafe { pty::forkpty(Some(winsize), None) }?;
-
-    match result.fork_result {
-        ForkResult::Parent { child } => {
-            let pid = child.as_raw();
-
-            // Add debug event for FIFO path generation
-            let _ = pid_tx.try_send(pid);
-            
-            let command_tx_clone = command_tx.clone();
-            let fifo_path_debug = fifo_path.clone();
-            tokio::spawn(async move {
-                let _ = command_tx_clone.try_send(Command::Debug(format!("fifoPathGenerated:{}", fifo_path_debug)));
-            });
-
-            Ok(drive_child(child, result.master, input_rx, output_tx, exit_code_tx, command_tx, fifo_path_buf))
+    shutdown: Shutdown,
+) -> Result<impl Future<Output = Result<(), HttyError>>, HttyError> {
+    let spawn_failed = |source| HttyError::SpawnFailed { command: command.to_string(), source };
+
+    let wait_exit_helper = if options.wait_exit {
+        Some(wait_exit_helper(options.wait_exit_helper.as_deref()).map_err(spawn_failed)?)
+    } else {
+        None
+    };
+
+    options.namespaces.ensure_supported().map_err(spawn_failed)?;
+
+    let launch = Launch {
+        command: command.clone(),
+        fifo_path,
+        winsize: pty::Winsize {
+            ws_col: size.cols() as u16,
+            ws_row: size.rows() as u16,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
         },
+        options,
+        wait_exit_helper,
+        stderr_tx,
+    };
+
+    let (child, terminal) = launch.start()?;
+
+    // Add debug event for FIFO path generation
+    let _ = pid_tx.try_send(child.as_raw());
+
+    let command_tx_clone = command_tx.clone();
+    let fifo_path_debug = launch.fifo_path.display().to_string();
+    tokio::spawn(async move {
+        let _ = command_tx_clone.try_send(Command::Debug(format!("fifoPathGenerated:{}", fifo_path_debug)));
+    });
+
+    Ok(drive_child(launch, child, terminal, input_rx, control_rx, output_tx, pid_tx, exit_code_tx, command_tx, shutdown))
+}
+
+impl Launch {
+    /// Whether the child is a shell running the command, rather than the command itself.
+    fn runs_in_shell(&self) -> bool {
+        runs_in_shell(&self.command, self.wait_exit_helper.as_deref(), &self.options)
+    }
+
+    fn start(&self) -> Result<(Pid, Terminal), HttyError> {
+        self.fork().map_err(|source| HttyError::SpawnFailed {
+            command: self.command.to_string(),
+            source,
+        })
+    }
+
+    fn fork(&self) -> Result<(Pid, Terminal)> {
+        if self.wait_exit_helper.is_some() {
+            runtime::ensure_vacant(&self.fifo_path)?;
+        }
+
+        let pty = pty::openpty(Some(&self.winsize), None)?;
+        self.options.termios.apply(&pty.slave)?;
+        let stderr_pipe = if self.options.separate_stderr { Some(unistd::pipe()?) } else { None };
+
+        match unsafe { unistd::fork() }? {
+            ForkResult::Parent { child } => {
+                let slave = (self.options.survive_hangup && self.options.respawn == Respawn::Never).then_some(pty.slave);
+
+                // Held for as long as the session runs, it mustn't leak into anything else ht starts
+                if let Some(slave) = &slave {
+                    if let Err(e) = fcntl(slave.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+                        warn!("cannot set close-on-exec on the child side of the PTY: {e}");
+                    }
+                }
+
+                if let Some((stderr_read, stderr_write)) = stderr_pipe {
+                    drop(stderr_write);
+                    tokio::spawn(forward_stderr(stderr_read, self.stderr_tx.clone(), self.options.read_buf_size));
+                }
+
+                Ok((child, Terminal { master: pty.master, slave }))
+            },
+
+            ForkResult::Child => {
+                drop(pty.master);
+
+                let error = match self.become_command(pty.slave, stderr_pipe) {
+                    Ok(never) => match never {},
+                    Err(error) => error,
+                };
 
-        ForkResult::Child => {
-            exec(command, fifo_path)?;
-            unreachable!();
+                // Returning would carry on running ht's code in this copy of it, down to the
+                // destructors removing the parent's runtime directory and cgroup
+                abandon_child(&format!("cannot start {}: {error:#}", self.command))
+            }
         }
     }
+
+    /// Set up the forked child and exec the command in it, which only returns on failure.
+    fn become_command(&self, slave: OwnedFd, stderr_pipe: Option<(OwnedFd, OwnedFd)>) -> Result<Infallible> {
+        if let Some(path) = &self.options.cgroup {
+            cgroup::join(path)?;
+        }
+
+        #[cfg(all(feature = "namespaces", target_os = "linux"))]
+        self.options.namespaces.enter()?;
+
+        attach_terminal(slave, &self.options)?;
+
+        if let Some((stderr_read, stderr_write)) = stderr_pipe {
+            drop(stderr_read);
+            unistd::dup2(stderr_write.as_raw_fd(), 2)?;
+        }
+
+        Ok(exec(&self.command, self.wait_exit_helper.as_deref(), &self.fifo_path, &self.options)?)
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn drive_child(
-    child: Pid,
-    master: OwnedFd,
-    input_rx: mpsc::Receiver<Vec<u8>>,
+    mut launch: Launch,
+    mut child: Pid,
+    mut terminal: Terminal,
+    mut input_rx: mpsc::Receiver<Vec<u8>>,
+    mut control_rx: mpsc::Receiver<Control>,
     output_tx: mpsc::Sender<Vec<u8>>,
-    exit_code_tx: mpsc::Sender<i32>,
+    pid_tx: mpsc::Sender<i32>,
+    exit_code_tx: mpsc::Sender<ExitStatus>,
     command_tx: mpsc::Sender<Command>,
-    fifo_path: PathBuf,
-) -> Result<()> {
-    // Debug event: Starting coordination
-    let _ = command_tx.try_send(Command::Debug(format!("startingCoordination:{}", fifo_path.display())));
-    
-    // Start a task to monitor FIFO existence (step 4-5 in desired flow)
-    let fifo_command_tx = command_tx.clone();
-    let fifo_path_clone = fifo_path.clone();
-    let _monitor_task = tokio::spawn(async move {
-        let mut interval = tokio::time::interval(std::time::Duration::from_millis(50));
-        
-        // Step 4: Periodically check if FIFO exists
-        let _ = fifo_command_tx.try_send(Command::Debug("startingFifoMonitoring".to_string()));
-        
-        loop {
-            interval.tick().await;
-            
-            // Check if FIFO exists (indicates command completed and waitexit is blocking)
-            if fifo_path_clone.exists() {
-                let _ = fifo_command_tx.try_send(Command::Completed(fifo_path_clone.clone()));
-                break; // Exit monitoring once FIFO is detected
+    shutdown: Shutdown,
+) -> Result<(), HttyError> {
+    let fifo_path = launch.fifo_path.clone();
+    let timing = launch.options.timing;
+    let mut attempt = 0;
+
+    loop {
+        // Debug event: Starting coordination
+        let _ = command_tx.try_send(Command::Debug(format!("startingCoordination:{}", fifo_path.display())));
+
+        // Start a task to monitor FIFO existence (step 4-5 in desired flow)
+        let fifo_command_tx = command_tx.clone();
+        let fifo_path_clone = fifo_path.clone();
+        let monitor_task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(timing.fifo_monitoring_interval);
+
+            // Step 4: Periodically check if FIFO exists
+            let _ = fifo_command_tx.try_send(Command::Debug("startingFifoMonitoring".to_string()));
+
+            loop {
+                interval.tick().await;
+
+                // Check if FIFO exists (indicates command completed and waitexit is blocking)
+                if runtime::is_fifo(&fifo_path_clone) {
+                    let _ = fifo_command_tx.try_send(Command::Completed(fifo_path_clone.clone()));
+                    break; // Exit monitoring once FIFO is detected
+                }
             }
+        });
+
+        // Reap the child as soon as it dies, independently of when the PTY reaches EOF
+        let (status_tx, status_rx) = oneshot::channel();
+        let reaper_command_tx = command_tx.clone();
+        let shell_wrapped = launch.runs_in_shell();
+        let reaper = tokio::spawn(async move {
+            let status = wait_for_exit(child, shell_wrapped, reaper_command_tx).await;
+            let _ = status_tx.send(status.clone());
+            status
+        });
+
+        let mut exit = ExitReport {
+            status_rx: Some(status_rx),
+            exit_code_tx: exit_code_tx.clone(),
+            sent: false,
+        };
+
+        // Process the main command and capture its output, until it's done or ht is shutting down.
+        // With the child side held (`survive_hangup`), output never ends, so this only returns at
+        // shutdown, and everything below (down to coordinationComplete) waits until then
+        let result = tokio::select! {
+            result = do_drive_child(child, terminal, &mut launch.winsize, &mut input_rx, &mut control_rx, output_tx.clone(), &mut exit, launch.options.read_buf_size) => result,
+            _ = shutdown.triggered() => Ok(()),
+        };
+
+        // Step 5: Output capture is complete, but don't signal waitexit yet
+        let _ = command_tx.try_send(Command::Debug("outputCaptureComplete".to_string()));
+
+        if !reaper.is_finished() {
+            info!("sending HUP signal to the child process");
+            unsafe { libc::kill(child.as_raw(), libc::SIGHUP) };
         }
-    });
 
-    // Process the main command and capture its output
-    let _result = do_drive_child(master, input_rx, output_tx.clone()).await;
-    
-    // Step 5: Output capture is complete, but don't signal waitexit yet
-    let _ = command_tx.try_send(Command::Debug("outputCaptureComplete".to_string()));
-    
-    eprintln!("sending HUP signal to the child process");
-    unsafe { libc::kill(child.as_raw(), libc::SIGHUP) };
-    eprintln!("waiting for the child process to exit");
-
-    // After signaling wait-exit, we want to keep the ht process alive for snapshots
-    // So we don't return here - we keep the output_tx alive and just wait
-    
-    // Give waitexit time to process the exit signal and clean up
-    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-
-    // Step 7: waitexit should exit and shell command completes
-    let _ = command_tx.try_send(Command::Debug("coordinationComplete".to_string()));
-
-    tokio::task::spawn_blocking(move || {
-        match wait::waitpid(child, None) {
-            Ok(wait_status) => {
-                let exit_code = match wait_status {
-                    wait::WaitStatus::Exited(_, code) => code,
-                    wait::WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
-                    _ => -1,
-                };
-                let _ = exit_code_tx.try_send(exit_code);
-            }
-            Err(_) => {
-                let _ = exit_code_tx.try_send(-1);
-            }
+        info!("waiting for the child process to exit");
+        let status = reaper.await.unwrap_or_else(|_| ExitStatus::unknown());
+
+        // Output ended before the child was reaped, so there's nothing left to go ahead of this
+        if !exit.sent {
+            let _ = exit_code_tx.send(status.clone()).await;
+        }
+
+        monitor_task.abort();
+        stop_wait_exit_helper(&fifo_path, child, &launch.options);
+
+        // Step 7: waitexit has exited and the shell command completed
+        let _ = command_tx.try_send(Command::Debug("coordinationComplete".to_string()));
+
+        if let Err(e) = result {
+            let _ = command_tx.try_send(Command::Debug("ptyTaskExiting".to_string()));
+            return Err(HttyError::PtyIo(e));
+        }
+
+        attempt += 1;
+
+        if status.code == 0 || !launch.options.respawn.allows(attempt) || shutdown.is_triggered() {
+            break;
+        }
+
+        let delay = Respawn::delay(attempt);
+        info!("child exited with status {}, respawning in {:?}", status.code, delay);
+        let _ = command_tx.send(Command::Respawn(attempt, delay)).await;
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.triggered() => break,
         }
-    })
-    .await
-    .unwrap();
 
-    // Instead of returning the result which would drop output_tx,
-    // we keep the task alive indefinitely to keep ht running for snapshots
-    // The output_tx will be kept alive, preventing the main event loop from exiting
+        // The previous run's wait-exit left its FIFO behind; the next one needs to create it afresh
+        let _ = std::fs::remove_file(&fifo_path);
+        let _ = std::fs::remove_file(runtime::helper_pid_path(&fifo_path));
+        (child, terminal) = launch.start()?;
+        let _ = pid_tx.send(child.as_raw()).await;
+    }
+
+    if !launch.options.keep_alive {
+        let _ = command_tx.try_send(Command::Debug("ptyTaskExiting".to_string()));
+        return Ok(());
+    }
+
+    // Hold on to output_tx, which the event loop would otherwise take as the end of the
+    // session, so that ht stays up for snapshots until it's shut down. Input and control
+    // requests have nothing left to act on, so the event loop is told as much straight away
+    // rather than left waiting for an answer.
+    drop(input_rx);
+    drop(control_rx);
     let _ = command_tx.try_send(Command::Debug("ptyContinuingForSnapshots".to_string()));
-    
-    // Keep this task alive but allow it to exit gracefully when needed
-    // We'll send periodic heartbeats but also check if the main process is shutting down
-    let mut heartbeat_interval = tokio::time::interval(std::time::Duration::from_secs(60));
-    
+    shutdown.triggered().await;
+
+    let _ = command_tx.try_send(Command::Debug("ptyTaskExiting".to_string()));
+    Ok(())
+}
+
+/// Resolves once the child terminates, without a blocking waitpid, reporting it being
+/// stopped (e.g. by Ctrl-Z) and continued along the way.
+///
+/// On Linux the child is watched through a pidfd, which becomes readable exactly when it
+/// exits. Elsewhere, or on kernels without pidfd_open (pre 5.3), every SIGCHLD is a cue to
+/// check again. A pidfd doesn't tell of stops, so SIGCHLD is listened for on Linux too.
+async fn wait_for_exit(child: Pid, shell_wrapped: bool, command_tx: mpsc::Sender<Command>) -> ExitStatus {
+    let mut notifier = match ExitNotifier::new(child) {
+        Ok(notifier) => notifier,
+        Err(_) => return ExitStatus::unknown(),
+    };
+
+    let flags = wait::WaitPidFlag::WNOHANG | wait::WaitPidFlag::WUNTRACED | wait::WaitPidFlag::WCONTINUED;
+
     loop {
-        tokio::select! {
-            _ = heartbeat_interval.tick() => {
-                // Send a periodic heartbeat to show we're still alive
-                if command_tx.try_send(Command::Debug("ptyHeartbeat".to_string())).is_err() {
-                    // If we can't send debug commands, the main process is shutting down
-                    let _ = command_tx.try_send(Command::Debug("ptyExitingDueToChannelClosure".to_string()));
-                    break;
-                }
+        match wait::waitpid(child, Some(flags)) {
+            Ok(status @ (wait::WaitStatus::Exited(..) | wait::WaitStatus::Signaled(..))) => {
+                return ExitStatus::from_wait(status, shell_wrapped);
+            }
+
+            // Reported once each, so there's no need to wait before checking again
+            Ok(wait::WaitStatus::Stopped(_, signal)) => {
+                let _ = command_tx.send(Command::Stopped(signal.as_str())).await;
             }
-            
-            // Add a small delay to prevent busy waiting
-            _ = tokio::time::sleep(std::time::Duration::from_millis(100)) => {
-                // Check if the main command channel is closed (indicating shutdown)
-                if command_tx.is_closed() {
-                    let _ = command_tx.try_send(Command::Debug("ptyExitingDueToMainShutdown".to_string()));
-                    break;
+
+            Ok(wait::WaitStatus::Continued(_)) => {
+                let _ = command_tx.send(Command::Continued).await;
+            }
+
+            Ok(_) => {
+                if !notifier.wait().await {
+                    return ExitStatus::unknown();
                 }
             }
+
+            Err(Errno::EINTR) => (),
+
+            Err(_) => return ExitStatus::unknown(),
         }
     }
+}
 
-    let _ = command_tx.try_send(Command::Debug("ptyTaskExiting".to_string()));
-    Ok(())
+enum ExitNotifier {
+    #[cfg(target_os = "linux")]
+    Pidfd(AsyncFd<OwnedFd>, tokio::signal::unix::Signal),
+    Sigchld(tokio::signal::unix::Signal),
 }
 
-const READ_BUF_SIZE: usize = 128 * 1024;
+impl ExitNotifier {
+    fn new(child: Pid) -> io::Result<Self> {
+        let sigchld = tokio::signal::unix::signal(SignalKind::child())?;
+
+        #[cfg(target_os = "linux")]
+        if let Ok(pidfd) = pidfd_open(child) {
+            return Ok(ExitNotifier::Pidfd(AsyncFd::with_interest(pidfd, Interest::READABLE)?, sigchld));
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        let _ = child;
 
+        Ok(ExitNotifier::Sigchld(sigchld))
+    }
+
+    /// Wait until the child may have changed state. Returns false if that can no longer be observed.
+    async fn wait(&mut self) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            ExitNotifier::Pidfd(pidfd, sigchld) => tokio::select! {
+                readable = pidfd.readable() => match readable {
+                    Ok(mut guard) => {
+                        guard.clear_ready();
+                        true
+                    }
+
+                    Err(_) => false,
+                },
+
+                // Stops and continues, which the pidfd doesn't signal
+                received = sigchld.recv() => received.is_some(),
+            },
+
+            ExitNotifier::Sigchld(sigchld) => sigchld.recv().await.is_some(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pidfd_open(pid: Pid) -> nix::Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    Errno::result(fd).map(|fd| unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+pub const DEFAULT_READ_BUF_SIZE: usize = 128 * 1024;
+
+/// Most chunks handed to a single `writev` call; well under any platform's `IOV_MAX`.
+const MAX_WRITE_CHUNKS: usize = 64;
+
+/// Input waiting to be written to the PTY master, kept in the chunks it arrived in.
+///
+/// Chunks are moved in rather than copied into one buffer, and are written out together
+/// with `writev`, so a large paste split into many commands doesn't turn into a write
+/// syscall (and an allocation) per chunk.
+#[derive(Debug, Default)]
+struct PendingInput {
+    chunks: VecDeque<Vec<u8>>,
+    /// How much of the front chunk has already been written.
+    offset: usize,
+    /// Whether the input so far ends partway through a line.
+    mid_line: bool,
+    /// Bytes queued and written so far, to tell when a notification's input is out.
+    pushed: u64,
+    written: u64,
+    /// Who to notify once `written` reaches each mark, in order.
+    notifications: VecDeque<(u64, oneshot::Sender<()>)>,
+}
+
+impl PendingInput {
+    fn push(&mut self, data: Vec<u8>) {
+        if let Some(last) = data.last() {
+            self.mid_line = !matches!(last, b'\n' | b'\r');
+            self.pushed += data.len() as u64;
+            self.chunks.push_back(data);
+        }
+    }
+
+    /// Queue the terminal's VEOF character `eof`, so that the reader sees end-of-file.
+    fn push_eof(&mut self, eof: u8) {
+        // The line discipline only turns VEOF into end-of-file on an empty line, so a pending
+        // partial line is flushed with one VEOF before sending another.
+        self.push(if self.mid_line { vec![eof, eof] } else { vec![eof] });
+        self.mid_line = false;
+    }
+
+    fn notify_when_written(&mut self, tx: oneshot::Sender<()>) {
+        if self.is_empty() {
+            let _ = tx.send(());
+        } else {
+            self.notifications.push_back((self.pushed, tx));
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    fn slices(&self) -> Vec<IoSlice<'_>> {
+        self.chunks
+            .iter()
+            .take(MAX_WRITE_CHUNKS)
+            .enumerate()
+            .map(|(i, chunk)| IoSlice::new(if i == 0 { &chunk[self.offset..] } else { chunk }))
+            .collect()
+    }
+
+    /// Drop the first `n` bytes, which have made it to the PTY.
+    fn consume(&mut self, n: usize) {
+        self.written += n as u64;
+
+        while self.notifications.front().is_some_and(|(mark, _)| *mark <= self.written) {
+            if let Some((_, tx)) = self.notifications.pop_front() {
+                let _ = tx.send(());
+            }
+        }
+
+        let mut n = n;
+
+        while let Some(front) = self.chunks.front() {
+            let left = front.len() - self.offset;
+
+            if n < left {
+                self.offset += n;
+                return;
+            }
+
+            n -= left;
+            self.offset = 0;
+            self.chunks.pop_front();
+        }
+    }
+}
+
+/// A child's exit status on its way to the event loop, which is held back until the output the
+/// child wrote before exiting has been sent ahead of it.
+struct ExitReport {
+    /// The status from the reaper, until it has been taken.
+    status_rx: Option<oneshot::Receiver<ExitStatus>>,
+    exit_code_tx: mpsc::Sender<ExitStatus>,
+    sent: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn do_drive_child(
-    master: OwnedFd,
-    mut input_rx: mpsc::Receiver<Vec<u8>>,
+    child: Pid,
+    terminal: Terminal,
+    winsize: &mut pty::Winsize,
+    input_rx: &mut mpsc::Receiver<Vec<u8>>,
+    control_rx: &mut mpsc::Receiver<Control>,
     output_tx: mpsc::Sender<Vec<u8>>,
-) -> Result<()> {
-    let mut buf = [0u8; READ_BUF_SIZE];
-    let mut input: Vec<u8> = Vec::with_capacity(READ_BUF_SIZE);
+    exit: &mut ExitReport,
+    read_buf_size: usize,
+) -> io::Result<()> {
+    // Holding the child side, if it's held, until output ends keeps a hangup from ending it
+    let Terminal { master, slave: _slave } = terminal;
+    let mut buf = vec![0u8; read_buf_size];
+    let mut input = PendingInput::default();
     nbio::set_non_blocking(&master.as_raw_fd())?;
-    let mut master_file = unsafe { File::from_raw_fd(master.as_raw_fd()) };
+    // The fd is owned by `master_fd` below; keep this handle from closing it a second time.
+    let mut master_file = ManuallyDrop::new(unsafe { File::from_raw_fd(master.as_raw_fd()) });
     let master_fd = AsyncFd::new(master)?;
 
     loop {
@@ -177,7 +707,9 @@ async fn do_drive_child(
             result = input_rx.recv() => {
                 match result {
                     Some(data) => {
-                        input.extend_from_slice(&data);
+                        // Pick up whatever else has queued up too, so it all goes out in one write
+                        input.push(data);
+                        take_queued(input_rx, &mut input);
                     }
 
                     None => {
@@ -186,17 +718,49 @@ async fn do_drive_child(
                 }
             }
 
+            Some(control) = control_rx.recv() => {
+                // Input sent before this control request is already queued; take it first so
+                // that the request applies after it
+                take_queued(input_rx, &mut input);
+
+                match control {
+                    Control::SetTermios(settings) => {
+                        if let Err(e) = settings.apply(master_fd.get_ref()) {
+                            warn!("cannot apply termios settings: {e}");
+                        }
+                    }
+
+                    Control::CloseStdin => {
+                        // A PTY can't be half-closed; the line discipline turns the VEOF character
+                        // into end-of-file instead.
+                        match termios::eof_char(master_fd.get_ref()) {
+                            Ok(eof) => input.push_eof(eof),
+                            Err(e) => warn!("cannot determine the EOF character: {e}"),
+                        }
+                    }
+
+                    Control::NotifyWritten(tx) => input.notify_when_written(tx),
+
+                    Control::Resize(size, reply) => {
+                        let _ = reply.send(resize(master_fd.get_ref(), child, size, winsize));
+                    }
+                }
+            }
+
             result = master_fd.readable() => {
                 let mut guard = result?;
 
                 loop {
-                    match nbio::read(&mut master_file, &mut buf)? {
+                    match nbio::read(&mut *master_file, &mut buf)? {
                         Some(0) => {
                             return Ok(());
                         }
 
                         Some(n) => {
-                            output_tx.send(buf[0..n].to_vec()).await?;
+                            // Nobody is left to read the output
+                            if output_tx.send(buf[0..n].to_vec()).await.is_err() {
+                                return Ok(());
+                            }
                         }
 
                         None => {
@@ -207,22 +771,34 @@ async fn do_drive_child(
                 }
             }
 
+            status = async { exit.status_rx.as_mut().expect("checked").await }, if exit.status_rx.is_some() => {
+                exit.status_rx = None;
+
+                let Ok(status) = status else {
+                    continue;
+                };
+
+                // Whatever the child wrote before it exited is in the PTY by now
+                while let Some(n @ 1..) = nbio::read(&mut *master_file, &mut buf)? {
+                    if output_tx.send(buf[0..n].to_vec()).await.is_err() {
+                        return Ok(());
+                    }
+                }
+
+                exit.sent = exit.exit_code_tx.send(status).await.is_ok();
+            }
+
             result = master_fd.writable(), if !input.is_empty() => {
                 let mut guard = result?;
-                let mut buf: &[u8] = input.as_ref();
 
-                loop {
-                    match nbio::write(&mut master_file, buf)? {
+                while !input.is_empty() {
+                    match nbio::write_vectored(&mut *master_file, &input.slices())? {
                         Some(0) => {
                             return Ok(());
                         }
 
                         Some(n) => {
-                            buf = &buf[n..];
-
-                            if buf.is_empty() {
-                                break;
-                            }
+                            input.consume(n);
                         }
 
                         None => {
@@ -231,37 +807,255 @@ async fn do_drive_child(
                         }
                     }
                 }
+            }
+        }
+    }
+}
 
-                let left = buf.len();
+/// Set the PTY's window size to `size` and deliver SIGWINCH, returning the size the PTY
+/// reports afterwards, which is also what a respawned child starts with.
+///
+/// The kernel signals the terminal's foreground process group itself, but only when the size
+/// changes, so resizing to the size it already has signals nobody. A child without a
+/// controlling terminal has no such group, so it's signalled directly, whatever the size.
+fn resize(master: &OwnedFd, child: Pid, size: Size, winsize: &mut pty::Winsize) -> io::Result<Size> {
+    let requested = pty::Winsize {
+        ws_col: size.cols() as u16,
+        ws_row: size.rows() as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
 
-                if left == 0 {
-                    input.clear();
-                } else {
-                    input.drain(..input.len() - left);
-                }
-            }
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &requested) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut applied = requested;
+
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCGWINSZ, &mut applied) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    *winsize = applied;
+
+    if unistd::tcgetpgrp(master).is_err() {
+        // Gone already if it has exited, which leaves nobody to tell
+        let _ = signal::kill(child, Signal::SIGWINCH);
+    }
+
+    Ok(Size::new(applied.ws_col, applied.ws_row))
+}
+
+/// Move input that's already waiting in `input_rx` into `input`, without waiting for more.
+fn take_queued(input_rx: &mut mpsc::Receiver<Vec<u8>>, input: &mut PendingInput) {
+    while let Ok(data) = input_rx.try_recv() {
+        input.push(data);
+    }
+}
+
+async fn forward_stderr(stderr: OwnedFd, stderr_tx: mpsc::Sender<Vec<u8>>, read_buf_size: usize) -> Result<()> {
+    let mut pipe = tokio::net::unix::pipe::Receiver::from_owned_fd(stderr)?;
+    let mut buf = vec![0u8; read_buf_size];
+
+    loop {
+        match pipe.read(&mut buf).await? {
+            0 => return Ok(()),
+            n => stderr_tx.send(buf[0..n].to_vec()).await?,
         }
     }
 }
 
-fn exec(command: String, fifo_path: String) -> io::Result<()> {
-    let ht_binary = env::current_exe()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
-        .to_string_lossy()
-        .to_string();
-    
-    // Capture the exit code, run wait-exit, then exit with the original code
-    let final_command = format!("{} ; exit_code=$? ; {} wait-exit {} ; exit $exit_code", command, ht_binary, fifo_path);
+/// Wire the PTY slave up as the child's stdio, optionally as the controlling terminal of a new session.
+fn attach_terminal(slave: OwnedFd, options: &Options) -> io::Result<()> {
+    if options.session_leader {
+        unistd::setsid()?;
 
+        if options.controlling_terminal && unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCSCTTY as _, 0) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
 
-    let shell_path = "/bin/sh";
-    let command = [shell_path.to_owned(), "-c".to_owned(), final_command]
+    for fd in 0..=2 {
+        unistd::dup2(slave.as_raw_fd(), fd)?;
+    }
+
+    if slave.as_raw_fd() <= 2 {
+        // The descriptor is now one of our stdio fds; don't close it.
+        std::mem::forget(slave);
+    }
+
+    Ok(())
+}
+
+/// Locate the binary providing the `wait-exit` subcommand and make sure the child will be able to run it.
+///
+/// This has to happen before forking: a helper that fails in the child would leave the
+/// wrapped command's completion unreported instead of producing an error.
+fn wait_exit_helper(explicit: Option<&Path>) -> Result<PathBuf> {
+    let helper = match explicit {
+        Some(path) => path.to_path_buf(),
+        None => env::current_exe().context("cannot determine the path of the ht executable")?,
+    };
+
+    let metadata = std::fs::metadata(&helper)
+        .with_context(|| format!("wait-exit helper {} is not accessible", helper.display()))?;
+
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        bail!("wait-exit helper {} is not an executable file", helper.display());
+    }
+
+    Ok(helper)
+}
+
+/// Replace the child with `program`: run by the shell if it's a command line, or has to be
+/// followed by `wait-exit`, and otherwise exec'd directly, so that its arguments never pass
+/// through the shell at all.
+fn exec(program: &Program, wait_exit_helper: Option<&Path>, fifo_path: &Path, options: &Options) -> io::Result<Infallible> {
+    let (path, args) = match (wait_exit_helper, program) {
+        // Capture the exit code, run wait-exit, then exit with the original code
+        (Some(helper), program) => shell(
+            format!(
+                "{} ; exit_code=$? ; {} wait-exit --timeout {} {} ; exit $exit_code",
+                program.to_shell(),
+                shell_quote(&helper.to_string_lossy()),
+                options.timing.wait_exit_timeout.as_millis(),
+                shell_quote(&fifo_path.to_string_lossy())
+            ),
+            options,
+        ),
+
+        (None, Program::Argv(args)) if !runs_in_shell(program, None, options) => (args[0].as_str(), args.clone()),
+
+        (None, program) => shell(program.to_shell(), options),
+    };
+
+    let path = CString::new(path)?;
+
+    let args = args
         .iter()
         .map(|s| CString::new(s.as_bytes()))
         .collect::<Result<Vec<CString>, NulError>>()?;
 
     env::set_var("TERM", "xterm-256color");
+
+    for (name, value) in &options.env {
+        env::set_var(name, value);
+    }
+
     unsafe { signal::signal(Signal::SIGPIPE, SigHandler::SigDfl) }?;
-    unistd::execvp(&command[0], &command)?;
-    unsafe { libc::_exit(1) }
+    Ok(unistd::execvp(&path, &args)?)
+}
+
+/// End a forked child that couldn't become the command, with the status a shell gives a
+/// command it can't run, telling whoever is reading its stderr why.
+fn abandon_child(message: &str) -> ! {
+    let message = format!("ht: {message}\n");
+
+    unsafe {
+        libc::write(2, message.as_ptr().cast(), message.len());
+        libc::_exit(127)
+    }
+}
+
+/// Whether `exec` runs `program` by way of the shell.
+fn runs_in_shell(program: &Program, wait_exit_helper: Option<&Path>, options: &Options) -> bool {
+    wait_exit_helper.is_some() || options.login || options.job_control || matches!(program, Program::Shell(_))
+}
+
+/// The shell, and the arguments that have it run `command_line`.
+fn shell(command_line: String, options: &Options) -> (&'static str, Vec<String>) {
+    let shell_path = "/bin/sh";
+    let argv0 = if options.login { "-sh" } else { shell_path };
+    let mut args = vec![argv0.to_owned()];
+
+    if options.job_control {
+        args.push("-m".to_owned());
+    }
+
+    args.push("-c".to_owned());
+    args.push(command_line);
+    (shell_path, args)
+}
+
+/// Stop the wait-exit helper of `fifo_path` if it's still waiting to be released, as it is when
+/// the shell running it was hung up on first. It's then an orphan, which init reaps.
+///
+/// The record outlives a helper that was killed outright, by which time its PID may belong to
+/// some other process, so it's only signalled while still in the session of `child`, the shell
+/// that ran it. In a PID namespace the PID the helper recorded means something else out here,
+/// so it's left alone there, to be killed along with everything else when the namespace's init
+/// exits.
+fn stop_wait_exit_helper(fifo_path: &Path, child: Pid, options: &Options) {
+    if options.namespaces.pid {
+        return;
+    }
+
+    if let Some(pid) = runtime::helper_pid(fifo_path).map(Pid::from_raw) {
+        let session = if options.session_leader { Ok(child) } else { unistd::getsid(None) };
+
+        if unistd::getsid(Some(pid)).ok() == session.ok() {
+            info!(%pid, "stopping the wait-exit helper");
+            let _ = signal::kill(pid, Signal::SIGTERM);
+        } else {
+            info!(%pid, "the wait-exit helper has already gone");
+        }
+
+        let _ = std::fs::remove_file(runtime::helper_pid_path(fifo_path));
+    }
+}
+
+/// Let a command's wait-exit helper finish, by writing `exit` to the FIFO it's blocked on.
+pub fn release_wait_exit(fifo_path: &Path) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut file = std::fs::OpenOptions::new().write(true).open(fifo_path)?;
+    writeln!(file, "exit")?;
+    file.flush()
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{stop_wait_exit_helper, ExitStatus, Options, Terminator};
+    use crate::runtime;
+    use nix::sys::signal::Signal;
+    use nix::sys::wait::WaitStatus;
+    use nix::unistd::Pid;
+
+    #[test]
+    fn exit_status() {
+        let pid = Pid::from_raw(1);
+
+        // A shell passes on the command's death by SIGINT as 130
+        let relayed = ExitStatus::from_wait(WaitStatus::Exited(pid, 130), true);
+        assert_eq!((relayed.code, relayed.signal, relayed.terminated_by), (130, Some("SIGINT"), Terminator::Command));
+
+        // Whereas a program exec'd directly may just exit with 130
+        let exited = ExitStatus::from_wait(WaitStatus::Exited(pid, 130), false);
+        assert_eq!((exited.code, exited.signal, exited.terminated_by), (130, None, Terminator::Command));
+
+        let shell_killed = ExitStatus::from_wait(WaitStatus::Signaled(pid, Signal::SIGKILL, false), true);
+        assert_eq!((shell_killed.code, shell_killed.signal, shell_killed.terminated_by), (137, Some("SIGKILL"), Terminator::Shell));
+
+        let killed = ExitStatus::from_wait(WaitStatus::Signaled(pid, Signal::SIGSEGV, true), false);
+        assert_eq!((killed.code, killed.signal, killed.terminated_by), (139, Some("SIGSEGV"), Terminator::Command));
+        assert!(killed.core_dumped);
+    }
+
+    #[test]
+    fn leaves_reused_helper_pid_alone() {
+        let dir = std::env::temp_dir().join(format!("htty-pty-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fifo_path = dir.join("fifo");
+
+        // A stale record naming this process, which isn't in the shell's session
+        runtime::record_helper_pid(&fifo_path).unwrap();
+        stop_wait_exit_helper(&fifo_path, Pid::from_raw(i32::MAX), &Options::default());
+
+        assert!(runtime::helper_pid(&fifo_path).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,55 @@
+use crate::asciicast::SessionRecorder;
+use crate::command::Command;
+use crate::session::{self, Event};
+use crate::timing::Timing;
+use anyhow::{Context, Result};
+use futures_util::StreamExt;
+use std::fs;
+use std::path::PathBuf;
+use tokio::sync::{mpsc, oneshot};
+
+/// Stand-in for the stdio API used by `ht record`: saves what the command does to `dir`.
+///
+/// The recording of the command's output is written to `session.cast` as it goes. Once the
+/// command has exited, the final screen is written to `snapshot.txt` and the exit code to
+/// `exit-code`, and ht is told to exit. If ht shuts down first (on an idle timeout or a
+/// signal), the recording is still finished, with what there was of it.
+pub async fn start(
+    command_tx: mpsc::Sender<Command>,
+    clients_tx: mpsc::Sender<session::Client>,
+    dir: PathBuf,
+    timing: Timing,
+) -> Result<()> {
+    fs::create_dir_all(&dir).with_context(|| format!("cannot create output directory {}", dir.display()))?;
+
+    let mut events = session::stream(&clients_tx, timing.subscription_timeout).await?;
+    let cast_path = dir.join("session.cast");
+    let mut recorder = SessionRecorder::create(&cast_path)?;
+    let mut exit_status = None;
+
+    // The stream ends when ht shuts down, whether or not the command has exited
+    while let Some(event) = events.next().await {
+        recorder.record(&event).with_context(|| format!("cannot write {}", cast_path.display()))?;
+
+        if let Event::ExitCode(_, status) = event {
+            exit_status = Some(status);
+            break;
+        }
+    }
+
+    recorder.finish()?;
+
+    if let Some(status) = exit_status {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        command_tx.send(Command::Snapshot(Some(reply_tx))).await?;
+
+        if let Event::Snapshot(_, _, _, _, text, _) = reply_rx.await? {
+            fs::write(dir.join("snapshot.txt"), text)?;
+        }
+
+        fs::write(dir.join("exit-code"), format!("{}\n", status.code))?;
+        command_tx.send(Command::Exit).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! Drawing a snapshot's screen with its colors and attributes: as ANSI escape sequences for
+//! terminals, or as HTML.
+
+use crate::backend::{Color, Style, TerminalBackend};
+use crate::config::HtmlTheme;
+use crate::session;
+use crate::snapshot::replay;
+
+/// The visible rows of `vt`, with SGR sequences wherever the style changes and trailing
+/// blanks left out.
+pub fn ansi(vt: &dyn TerminalBackend) -> String {
+    let mut lines = Vec::new();
+
+    for line in vt.view() {
+        let cells: Vec<_> = line.iter().filter(|c| c.width > 0).collect();
+        let blank = cells.iter().rev().take_while(|c| c.ch == ' ' && c.style == Style::default()).count();
+        let mut out = String::new();
+        let mut style = Style::default();
+
+        for cell in &cells[..cells.len() - blank] {
+            if cell.style != style {
+                style = cell.style;
+                out.push_str(&session::sgr(&style));
+            }
+
+            out.push(cell.ch);
+        }
+
+        if style != Style::default() {
+            out.push_str("\x1b[0m");
+        }
+
+        lines.push(out);
+    }
+
+    lines.join("\n")
+}
+
+impl HtmlTheme {
+    fn foreground(self) -> &'static str {
+        match self {
+            HtmlTheme::Dark => "#e5e5e5",
+            HtmlTheme::Light => "#000000",
+        }
+    }
+
+    fn background(self) -> &'static str {
+        match self {
+            HtmlTheme::Dark => "#000000",
+            HtmlTheme::Light => "#ffffff",
+        }
+    }
+}
+
+/// The screen a snapshot's `seq` draws, as a `<pre>` element with the colors and attributes as
+/// inline styles.
+pub fn html(cols: usize, rows: usize, seq: &str, theme: HtmlTheme) -> String {
+    let vt = replay(cols, rows, seq);
+    let (foreground, background) = (theme.foreground(), theme.background());
+    let mut out = format!("<pre style=\"color: {foreground}; background-color: {background}\">");
+
+    for (row, line) in vt.view().iter().enumerate() {
+        if row > 0 {
+            out.push('\n');
+        }
+
+        let cells: Vec<_> = line.iter().filter(|c| c.width > 0).collect();
+        let mut start = 0;
+
+        while start < cells.len() {
+            let cell_style = cells[start].style;
+            let end = cells[start..].iter().position(|c| c.style != cell_style).map_or(cells.len(), |n| start + n);
+            let text: String = cells[start..end].iter().map(|c| c.ch).collect();
+            let text = if end == cells.len() { text.trim_end().to_string() } else { text };
+            let style = css(&cell_style, theme);
+
+            if style.is_empty() {
+                out.push_str(&escape(&text));
+            } else {
+                out.push_str(&format!("<span style=\"{style}\">{}</span>", escape(&text)));
+            }
+
+            start = end;
+        }
+    }
+
+    out.push_str("</pre>");
+    out
+}
+
+fn css(cell_style: &Style, theme: HtmlTheme) -> String {
+    let mut foreground = cell_style.foreground.map(color);
+    let mut background = cell_style.background.map(color);
+
+    if cell_style.inverse {
+        (foreground, background) = (
+            Some(background.unwrap_or_else(|| theme.background().to_string())),
+            Some(foreground.unwrap_or_else(|| theme.foreground().to_string())),
+        );
+    }
+
+    let mut style = Vec::new();
+
+    if let Some(foreground) = foreground {
+        style.push(format!("color: {foreground}"));
+    }
+
+    if let Some(background) = background {
+        style.push(format!("background-color: {background}"));
+    }
+
+    if cell_style.bold {
+        style.push("font-weight: bold".to_string());
+    }
+
+    if cell_style.faint {
+        style.push("opacity: 0.5".to_string());
+    }
+
+    if cell_style.italic {
+        style.push("font-style: italic".to_string());
+    }
+
+    match (cell_style.underline, cell_style.strikethrough) {
+        (true, true) => style.push("text-decoration: underline line-through".to_string()),
+        (true, false) => style.push("text-decoration: underline".to_string()),
+        (false, true) => style.push("text-decoration: line-through".to_string()),
+        (false, false) => (),
+    }
+
+    style.join("; ")
+}
+
+/// CSS color for `color`, using the xterm palette for indexed colors.
+fn color(color: Color) -> String {
+    const BASIC: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00),
+        (0xcd, 0x00, 0x00),
+        (0x00, 0xcd, 0x00),
+        (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee),
+        (0xcd, 0x00, 0xcd),
+        (0x00, 0xcd, 0xcd),
+        (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f),
+        (0xff, 0x00, 0x00),
+        (0x00, 0xff, 0x00),
+        (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff),
+        (0xff, 0x00, 0xff),
+        (0x00, 0xff, 0xff),
+        (0xff, 0xff, 0xff),
+    ];
+
+    let (r, g, b) = match color {
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Indexed(i @ 0..=15) => BASIC[i as usize],
+        Color::Indexed(i @ 16..=231) => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let i = i - 16;
+            (level(i / 36), level(i / 6 % 6), level(i % 6))
+        }
+        Color::Indexed(i) => {
+            let grey = 8 + (i - 232) * 10;
+            (grey, grey, grey)
+        }
+    };
+
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
@@ -0,0 +1,73 @@
+use crate::asciicast::{self, Entry};
+use crate::message::{self, Message};
+use anyhow::Result;
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+/// Play the recording at `path` back on stdout, `speed` times faster than it was recorded.
+///
+/// With `to_json`, its events are instead written straight away as the JSON messages ht
+/// would have sent while recording it: a `resize` to the recorded size, then `output` and
+/// `resize` events.
+pub async fn run(path: &Path, speed: f64, to_json: bool) -> Result<()> {
+    let (header, entries) = asciicast::Reader::open(path)?;
+    let mut stdout = io::stdout();
+
+    if to_json {
+        let resize = Message::Resize(message::Resize {
+            cols: header.width,
+            rows: header.height,
+            pane: None,
+        });
+
+        writeln!(stdout, "{}", serde_json::to_string(&resize)?)?;
+
+        for entry in entries {
+            if let Some(message) = message_for(&entry?) {
+                writeln!(stdout, "{}", serde_json::to_string(&message)?)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    let start = Instant::now();
+
+    for entry in entries {
+        let Entry(time, code, data) = entry?;
+
+        if code != "o" {
+            continue;
+        }
+
+        time::sleep_until(start + Duration::from_secs_f64(time.max(0.0) / speed)).await;
+        stdout.write_all(data.as_bytes())?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+fn message_for(Entry(_, code, data): &Entry) -> Option<Message<'_>> {
+    match code.as_str() {
+        "o" => Some(Message::Output(message::Output {
+            seq: Cow::Borrowed(data),
+            pane: None,
+        })),
+
+        "r" => {
+            let (cols, rows) = data.split_once('x')?;
+
+            Some(Message::Resize(message::Resize {
+                cols: cols.parse().ok()?,
+                rows: rows.parse().ok()?,
+                pane: None,
+            }))
+        }
+
+        _ => None,
+    }
+}
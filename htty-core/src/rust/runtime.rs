@@ -0,0 +1,231 @@
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::stat::Mode;
+use nix::unistd;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::fd::AsFd;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// Per-session directory holding coordination artifacts (FIFOs, sockets, logs).
+///
+/// The directory is created with a random name and mode 0700 under
+/// `$XDG_RUNTIME_DIR` when it is usable, falling back to `$TMPDIR` (or `/tmp`).
+/// It is removed, along with everything inside it, when this value is dropped.
+pub struct RuntimeDir {
+    dir: TempDir,
+}
+
+impl RuntimeDir {
+    pub fn create() -> Result<Self> {
+        let base = base_dir();
+
+        let dir = tempfile::Builder::new()
+            .prefix("htty-")
+            .tempdir_in(&base)
+            .with_context(|| format!("cannot create runtime directory in {}", base.display()))?;
+
+        Ok(Self { dir })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn fifo_path(&self) -> PathBuf {
+        self.path().join("exit.fifo")
+    }
+
+    pub fn pane_fifo_path(&self, id: usize) -> PathBuf {
+        self.path().join(format!("exit-{id}.fifo"))
+    }
+}
+
+/// Make sure nothing is at `path` yet, so that a FIFO turning up there can only be the one
+/// the command's wait-exit helper made.
+pub fn ensure_vacant(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        bail!("coordination file {} already exists", path.display());
+    }
+
+    Ok(())
+}
+
+/// Create the FIFO a wait-exit helper blocks on. Whatever is already at `path` is refused
+/// rather than taken over, as it can only be left from another session, or planted there.
+pub fn create_fifo(path: &Path) -> Result<()> {
+    unistd::mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)
+        .with_context(|| format!("cannot create coordination FIFO {}", path.display()))
+}
+
+/// Wait, as the wait-exit helper, for ht to release it by writing `exit` to the FIFO at
+/// `path` or closing its end. The FIFO is opened without blocking, since a blocking open waits
+/// for ht to open it too, and ht never does if it went away; the wait then fails at `timeout`
+/// instead of going on forever.
+pub fn await_release(path: &Path, timeout: Option<Duration>) -> Result<()> {
+    let mut fifo = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(OFlag::O_NONBLOCK.bits())
+        .open(path)
+        .with_context(|| format!("cannot open coordination FIFO {}", path.display()))?;
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut received = Vec::new();
+    let mut buf = [0u8; 64];
+
+    loop {
+        // Until ht opens its end, the FIFO only polls readable once something is written to it
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let poll_timeout = remaining.map_or(PollTimeout::NONE, |r| PollTimeout::try_from(r).unwrap_or(PollTimeout::MAX));
+
+        match poll(&mut [PollFd::new(fifo.as_fd(), PollFlags::POLLIN)], poll_timeout) {
+            Ok(0) if remaining.is_some_and(|remaining| remaining.is_zero()) => {
+                bail!("ht didn't release the wait-exit helper within {:?}", timeout.unwrap_or_default())
+            }
+
+            // Timeouts are in whole milliseconds, so one can run out just short of the deadline
+            Ok(0) | Err(Errno::EINTR) => continue,
+            Ok(_) => (),
+            Err(e) => return Err(e).with_context(|| format!("cannot wait on coordination FIFO {}", path.display())),
+        }
+
+        match fifo.read(&mut buf) {
+            // ht closed its end
+            Ok(0) => return Ok(()),
+
+            Ok(n) => {
+                received.extend_from_slice(&buf[..n]);
+
+                if received.split(|&b| b == b'\n').any(|line| String::from_utf8_lossy(line).trim() == "exit") {
+                    return Ok(());
+                }
+            }
+
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => (),
+            Err(e) => return Err(e).with_context(|| format!("cannot read coordination FIFO {}", path.display())),
+        }
+    }
+}
+
+/// Where the wait-exit helper blocking on the FIFO at `fifo_path` records its PID.
+pub fn helper_pid_path(fifo_path: &Path) -> PathBuf {
+    fifo_path.with_extension("pid")
+}
+
+/// Record this process as the wait-exit helper of `fifo_path`. Like the FIFO, the record is
+/// never taken over from another helper.
+pub fn record_helper_pid(fifo_path: &Path) -> Result<()> {
+    let path = helper_pid_path(fifo_path);
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| write!(file, "{}", std::process::id()))
+        .with_context(|| format!("cannot record the wait-exit helper's PID in {}", path.display()))
+}
+
+/// The PID of the wait-exit helper of `fifo_path`, if one is still waiting on it.
+pub fn helper_pid(fifo_path: &Path) -> Option<i32> {
+    fs::read_to_string(helper_pid_path(fifo_path)).ok()?.trim().parse().ok()
+}
+
+/// Whether `path` is a FIFO, as opposed to missing, or a file or link of some other kind.
+pub fn is_fifo(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+fn base_dir() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute() && p.is_dir())
+        .unwrap_or_else(env::temp_dir)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{await_release, create_fifo, ensure_vacant, helper_pid, is_fifo, record_helper_pid, RuntimeDir};
+    use std::fs;
+    use std::io::Write;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn refuses_existing_coordination_files() {
+        let dir = RuntimeDir::create().unwrap();
+        let fifo = dir.fifo_path();
+        let planted = dir.pane_fifo_path(1);
+        fs::write(&planted, "exit").unwrap();
+
+        assert!(ensure_vacant(&fifo).is_ok());
+        create_fifo(&fifo).unwrap();
+        assert!(is_fifo(&fifo));
+        assert!(ensure_vacant(&fifo).is_err());
+        assert!(create_fifo(&fifo).is_err());
+
+        assert!(!is_fifo(&planted));
+        assert!(create_fifo(&planted).is_err());
+        assert!(!is_fifo(&dir.pane_fifo_path(2)));
+    }
+
+    #[test]
+    fn records_helper_pid() {
+        let dir = RuntimeDir::create().unwrap();
+        let fifo = dir.fifo_path();
+        assert_eq!(helper_pid(&fifo), None);
+
+        record_helper_pid(&fifo).unwrap();
+        assert_eq!(helper_pid(&fifo), Some(std::process::id() as i32));
+        assert!(record_helper_pid(&fifo).is_err());
+        assert_eq!(helper_pid(&dir.pane_fifo_path(1)), None);
+    }
+
+    #[test]
+    fn awaits_release() {
+        let dir = RuntimeDir::create().unwrap();
+        let fifo = dir.fifo_path();
+        create_fifo(&fifo).unwrap();
+
+        // Opening to write blocks until the helper has the FIFO open
+        let path = fifo.clone();
+        let writer = thread::spawn(move || {
+            let mut file = fs::OpenOptions::new().write(true).open(path).unwrap();
+            file.write_all(b"ex").unwrap();
+            thread::sleep(Duration::from_millis(50));
+            writeln!(file, "it").unwrap();
+            thread::sleep(Duration::from_secs(1));
+        });
+
+        let start = Instant::now();
+        await_release(&fifo, Some(Duration::from_secs(5))).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        writer.join().unwrap();
+
+        // Just closing the FIFO releases the helper too
+        let path = fifo.clone();
+        let writer = thread::spawn(move || drop(fs::OpenOptions::new().write(true).open(path).unwrap()));
+        await_release(&fifo, Some(Duration::from_secs(5))).unwrap();
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn gives_up_on_release() {
+        let dir = RuntimeDir::create().unwrap();
+        let fifo = dir.fifo_path();
+
+        assert!(await_release(&fifo, Some(Duration::from_millis(50))).is_err());
+
+        create_fifo(&fifo).unwrap();
+        let start = Instant::now();
+        let error = await_release(&fifo, Some(Duration::from_millis(100))).unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(error.to_string().contains("didn't release"));
+    }
+}
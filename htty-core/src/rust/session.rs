@@ -1,415 +1,1562 @@
-use crate::cli::StyleMode;
-use anyhow::Result;
-use avt::{Color, Pen};
+use crate::backend::{self, Cell, Color, Style, TerminalBackend};
+use crate::clock::Instant;
+use crate::config::{Size, StyleFormat, StyleMode};
+use crate::command::{self, InputSeq};
+use crate::error::{HttyError, Result};
+use crate::message::{self, Message};
+use crate::config::Geometry;
+use crate::status::ExitStatus;
+use base64::prelude::{Engine, BASE64_STANDARD};
 use futures_util::{stream, Stream, StreamExt};
-use serde::Serialize;
-use serde_json::json;
-use std::collections::HashMap;
-use std::future;
-use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PenJson {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    fg: Option<ColorJson>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    bg: Option<ColorJson>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    attrs: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub fg: Option<ColorJson>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub bg: Option<ColorJson>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub attrs: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ColorJson {
     Indexed { indexed: u8 },
     Rgb { rgb: [u8; 3] },
 }
 
-impl From<&Pen> for PenJson {
-    fn from(pen: &Pen) -> Self {
-        let mut attrs = Vec::new();
-        if pen.is_bold() { attrs.push("bold".to_string()); }
-        if pen.is_faint() { attrs.push("faint".to_string()); }
-        if pen.is_italic() { attrs.push("italic".to_string()); }
-        if pen.is_underline() { attrs.push("underline".to_string()); }
-        if pen.is_strikethrough() { attrs.push("strikethrough".to_string()); }
-        if pen.is_blink() { attrs.push("blink".to_string()); }
-        if pen.is_inverse() { attrs.push("inverse".to_string()); }
+impl From<&Style> for PenJson {
+    fn from(style: &Style) -> Self {
+        let attrs = [
+            (style.bold, "bold"),
+            (style.faint, "faint"),
+            (style.italic, "italic"),
+            (style.underline, "underline"),
+            (style.strikethrough, "strikethrough"),
+            (style.blink, "blink"),
+            (style.inverse, "inverse"),
+        ];
 
         PenJson {
-            fg: pen.foreground().map(|c| match c {
-                Color::Indexed(i) => ColorJson::Indexed { indexed: i },
-                Color::RGB(rgb) => ColorJson::Rgb { rgb: [rgb.r, rgb.g, rgb.b] },
-            }),
-            bg: pen.background().map(|c| match c {
-                Color::Indexed(i) => ColorJson::Indexed { indexed: i },
-                Color::RGB(rgb) => ColorJson::Rgb { rgb: [rgb.r, rgb.g, rgb.b] },
-            }),
-            attrs,
+            fg: style.foreground.map(ColorJson::from),
+            bg: style.background.map(ColorJson::from),
+            attrs: attrs.into_iter().filter(|(on, _)| *on).map(|(_, name)| name.to_string()).collect(),
+        }
+    }
+}
+
+impl From<Color> for ColorJson {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Indexed(i) => ColorJson::Indexed { indexed: i },
+            Color::Rgb(r, g, b) => ColorJson::Rgb { rgb: [r, g, b] },
         }
     }
 }
 
 pub struct Session {
-    vt: avt::Vt,
+    vt: Box<dyn TerminalBackend>,
+    backend: backend::Factory,
     broadcast_tx: broadcast::Sender<Event>,
+    broadcast_backlog: Backlog,
+    listeners: Vec<Listener>,
     stream_time: f64,
     start_time: Instant,
     last_event_time: Instant,
     pending_pid: Option<i32>,
+    /// The state last emitted, repeated to each new subscriber.
+    state: Option<SessionState>,
     style_mode: StyleMode,
+    style_format: StyleFormat,
+    layout: Option<Layout>,
+    pending_output: Option<PendingOutput>,
+    styles: Arc<Mutex<StyleCache>>,
+    /// Whether subscribers are still waiting for the first output to get their init event.
+    init_held: bool,
+    /// Held by each subscription that asked for raw output, which is broadcast along with the
+    /// text it was decoded from while any of them are left.
+    raw_subscribers: Arc<()>,
+}
+
+/// Called with each event as it's broadcast. See `Session::on_event`.
+type Listener = Box<dyn Fn(&Event) + Send>;
+
+/// Output fed to the terminal but not yet broadcast, accumulated so that bursts of
+/// small PTY reads turn into a few larger output events.
+struct PendingOutput {
+    time: f64,
+    since: Instant,
+    data: String,
+    /// The bytes `data` was decoded from, when raw output is on.
+    bytes: Vec<u8>,
+}
+
+/// Pending output is broadcast once it grows this large...
+const OUTPUT_COALESCE_BYTES: usize = 64 * 1024;
+
+/// ...or once it has been waiting this long, whichever comes first.
+const OUTPUT_COALESCE_DELAY: Duration = Duration::from_millis(5);
+
+/// Arrangement of the main terminal and any additional panes on a shared virtual screen.
+struct Layout {
+    cols: usize,
+    rows: usize,
+    main: Geometry,
+    panes: BTreeMap<usize, Pane>,
+}
+
+struct Pane {
+    geometry: Geometry,
+    vt: Box<dyn TerminalBackend>,
+    styles: Arc<Mutex<StyleCache>>,
+}
+
+/// Style ids handed out so far and the rows of the previous snapshot.
+///
+/// A style keeps its id from one snapshot to the next, and only rows that differ from the
+/// previous snapshot get their char and style maps rebuilt.
+struct StyleCache {
+    ids: HashMap<Style, usize>,
+    styles: Vec<Style>,
+    rows: Vec<StyledRow>,
+}
+
+struct StyledRow {
+    line: Vec<Cell>,
+    chars: Vec<char>,
+    styles: Vec<usize>,
+}
+
+/// Once this many distinct styles have been seen, ids are handed out afresh, so that
+/// programs cycling through many colors can't grow the cache without bound.
+const STYLE_CACHE_LIMIT: usize = 4096;
+
+impl Default for StyleCache {
+    fn default() -> Self {
+        Self {
+            // Id 0 is reserved for the default style
+            ids: HashMap::from([(Style::default(), 0)]),
+            styles: vec![Style::default()],
+            rows: Vec::new(),
+        }
+    }
+}
+
+impl StyleCache {
+    fn style_data(&mut self, vt: &dyn TerminalBackend, format: StyleFormat) -> StyleData {
+        if self.styles.len() > STYLE_CACHE_LIMIT {
+            *self = Self::default();
+        }
+
+        let (cols, _rows) = vt.size();
+        let view = vt.view();
+        self.rows.truncate(view.len());
+
+        for (i, line) in view.iter().enumerate() {
+            if self.rows.get(i).is_some_and(|row| row.line == *line) {
+                continue;
+            }
+
+            let row = self.style_row(line, cols);
+
+            match self.rows.get_mut(i) {
+                Some(cached) => *cached = row,
+                None => self.rows.push(row),
+            }
+        }
+
+        let used: BTreeSet<usize> = std::iter::once(0)
+            .chain(self.rows.iter().flat_map(|row| row.styles.iter().copied()))
+            .collect();
+
+        StyleData {
+            char_map: self.rows.iter().map(|row| row.chars.clone()).collect(),
+            style_map: match format {
+                StyleFormat::Cells => StyleMap::Cells(self.rows.iter().map(|row| row.styles.clone()).collect()),
+                StyleFormat::Rle => StyleMap::Rle(self.rows.iter().map(|row| runs(&row.styles)).collect()),
+            },
+            styles: used
+                .into_iter()
+                .map(|id| (id.to_string(), PenJson::from(&self.styles[id])))
+                .collect(),
+        }
+    }
+
+    fn style_row(&mut self, line: &[Cell], cols: usize) -> StyledRow {
+        let mut chars = Vec::with_capacity(cols);
+        let mut styles = Vec::with_capacity(cols);
+
+        for cell in line {
+            chars.push(cell.ch);
+            styles.push(self.id(cell.style));
+        }
+
+        // Ensure we have exactly cols entries, padding with blanks in the default style
+        chars.resize(cols, ' ');
+        styles.resize(cols, 0);
+
+        StyledRow {
+            line: line.to_vec(),
+            chars,
+            styles,
+        }
+    }
+
+    fn id(&mut self, style: Style) -> usize {
+        let next_id = self.styles.len();
+
+        *self.ids.entry(style).or_insert_with(|| {
+            self.styles.push(style);
+            next_id
+        })
+    }
+}
+
+/// Group consecutive cells of a row with the same style id into `(id, count)` runs.
+fn runs(styles: &[usize]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+
+    for &id in styles {
+        match runs.last_mut() {
+            Some((last, count)) if *last == id => *count += 1,
+            _ => runs.push((id, 1)),
+        }
+    }
+
+    runs
 }
 
 #[derive(Clone, Debug)]
 pub struct StyleData {
     char_map: Vec<Vec<char>>,
-    style_map: Vec<Vec<usize>>,
+    style_map: StyleMap,
     styles: HashMap<String, PenJson>,
 }
 
+/// The style id of every cell on screen, row by row, encoded as requested with `StyleFormat`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StyleMap {
+    Cells(Vec<Vec<usize>>),
+    Rle(Vec<Vec<(usize, usize)>>),
+}
+
+impl StyleMap {
+    pub fn format(&self) -> StyleFormat {
+        match self {
+            StyleMap::Cells(_) => StyleFormat::Cells,
+            StyleMap::Rle(_) => StyleFormat::Rle,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Event {
     Init(f64, usize, usize, i32, String, String, Option<StyleData>),
     Output(f64, String),
+    RawOutput(f64, Vec<u8>),
+    Stderr(f64, String),
     Resize(f64, usize, usize),
-    Snapshot(usize, usize, String, String, Option<StyleData>),
+    Snapshot(f64, usize, usize, String, String, Option<StyleData>),
+    /// Input of this many bytes has been written to the PTY. Only ever sent as a reply.
+    InputWritten(usize),
+    /// Input of this many bytes was dropped, because the command had exited. Sent as a reply
+    /// if the client asked for one, or to every client otherwise.
+    InputDiscarded(usize),
+    /// Every command sent before a flush has been seen to. Sent as a reply if the client
+    /// asked for one, or to every client otherwise.
+    Flushed,
     Pid(f64, i32),
-    ExitCode(f64, i32),
+    ExitCode(f64, ExitStatus),
     Debug(f64, String),
+    /// A command was refused, for the reason given. Sent to every client.
+    Error(f64, String),
     Completed(f64),
+    Respawn(f64, u32, Duration),
+    /// The command was stopped by the named signal, e.g. `SIGTSTP` from Ctrl-Z.
+    Stopped(f64, &'static str),
+    /// The command was continued after being stopped.
+    Continued(f64),
+    Idle(f64, Duration),
+    Active(f64, Duration),
+    IdleTimeout(f64, Duration),
+    State(f64, SessionState),
+    Pane(usize, Box<Event>),
+    Dropped(f64, u64),
+    /// One of ht's internal channels is filling up: the first count of items is waiting in
+    /// it, out of the second it has room for. Sent to every client.
+    Backlog(f64, &'static str, usize, usize),
+}
+
+/// Where a session is in its life, as its event loop sees it, which decides what commands
+/// it still acts on.
+///
+/// A session normally goes from `Starting` to `Closed` in order, but can skip states (an exit
+/// command while the command is running goes straight to `Draining`), and goes back to
+/// `Starting` when the command is respawned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SessionState {
+    /// The command is being launched, and input is buffered until it's there to read it.
+    #[default]
+    Starting,
+    Running,
+    /// The command has exited, and the session is kept open for snapshots (or a respawn).
+    /// Input and termios changes are discarded.
+    ChildExited,
+    /// An exit command or idle timeout has been taken, and the session is seeing to the
+    /// commands already sent before closing. Further input and exit commands are discarded.
+    Draining,
+    /// The session takes no more commands.
+    Closed,
+}
+
+impl SessionState {
+    /// Whether the command is (or is about to be) there to take input.
+    pub fn takes_input(self) -> bool {
+        matches!(self, SessionState::Starting | SessionState::Running)
+    }
+
+    /// Whether the session is on its way out, and won't go back to running a command.
+    pub fn is_ending(self) -> bool {
+        matches!(self, SessionState::Draining | SessionState::Closed)
+    }
+
+    /// Whether a session can go from this state to `next`.
+    pub fn can_become(self, next: SessionState) -> bool {
+        use SessionState::*;
+
+        matches!(
+            (self, next),
+            (Starting, Running)
+                | (Starting | Running, ChildExited)
+                | (ChildExited, Starting)
+                | (Starting | Running | ChildExited, Draining)
+                | (Starting | Running | ChildExited | Draining, Closed)
+        )
+    }
+}
+
+impl std::fmt::Display for SessionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SessionState::Starting => "starting",
+            SessionState::Running => "running",
+            SessionState::ChildExited => "childExited",
+            SessionState::Draining => "draining",
+            SessionState::Closed => "closed",
+        };
+
+        f.write_str(name)
+    }
+}
+
+pub struct Client {
+    tx: oneshot::Sender<Subscription>,
+    raw_output: bool,
 }
 
-pub struct Client(oneshot::Sender<Subscription>);
+/// Where to send an event meant for a single client, such as the snapshot it asked for.
+pub type Reply = oneshot::Sender<Event>;
 
 pub struct Subscription {
-    init: Event,
+    /// Left out while the init event is held back, in which case it's broadcast later.
+    init: Option<PendingInit>,
+    /// The state the session is in, for this subscriber alone, as the others have already
+    /// been told of it.
+    state: Option<Event>,
     broadcast_rx: broadcast::Receiver<Event>,
+    /// Keeps raw output broadcast until the subscriber is done with it.
+    raw_interest: Option<Arc<()>>,
+}
+
+/// The `Init` event of a new subscription, rendered by the subscriber rather than the session.
+struct PendingInit {
+    pid: i32,
+    capture: Capture,
+}
+
+impl PendingInit {
+    fn into_event(self) -> Event {
+        let time = self.capture.time;
+        let (cols, rows, seq, text, style_data) = self.capture.render();
+        Event::Init(time, cols, rows, self.pid, seq, text, style_data)
+    }
+}
+
+/// A copy of a screen, taken on the event loop, from which a snapshot or init event gets built.
+///
+/// Taking it costs a dump of the terminal. The text view and, in styled mode, the style maps
+/// are worked out from a replay of that dump, which can happen on another thread so that a
+/// large styled screen doesn't hold up input handling.
+struct Capture {
+    /// When the screen was copied, which is the moment the snapshot shows.
+    time: f64,
+    cols: usize,
+    rows: usize,
+    seq: String,
+    styles: Option<Styling>,
+    backend: backend::Factory,
+}
+
+/// The style cache to build a snapshot's style maps with, and how to encode them.
+type Styling = (Arc<Mutex<StyleCache>>, StyleFormat);
+
+impl Capture {
+    fn new(time: f64, screen: &dyn TerminalBackend, styles: Option<Styling>, backend: &backend::Factory) -> Self {
+        let (cols, rows) = screen.size();
+
+        Self {
+            time,
+            cols,
+            rows,
+            seq: screen.dump(),
+            styles,
+            backend: backend.clone(),
+        }
+    }
+
+    fn render(self) -> (usize, usize, String, String, Option<StyleData>) {
+        let mut screen = (self.backend)(self.cols, self.rows);
+        screen.feed_str(&self.seq);
+
+        let style_data = self.styles.map(|(cache, format)| {
+            cache.lock().unwrap_or_else(PoisonError::into_inner).style_data(&*screen, format)
+        });
+
+        (self.cols, self.rows, self.seq, text_view(&*screen), style_data)
+    }
+
+    fn into_snapshot(self) -> Event {
+        let time = self.time;
+        let (cols, rows, seq, text, style_data) = self.render();
+        Event::Snapshot(time, cols, rows, seq, text, style_data)
+    }
+}
+
+/// How full one of ht's channels is, so a backlog building up in it is reported once, rather
+/// than on every item queued while it lasts.
+///
+/// The backlog is reported when three quarters of the channel is taken, and again only after
+/// it has drained to half.
+#[derive(Debug, Clone, Copy)]
+pub struct Backlog {
+    channel: &'static str,
+    capacity: usize,
+    reported: bool,
+}
+
+impl Backlog {
+    pub fn new(channel: &'static str, capacity: usize) -> Self {
+        Self { channel, capacity, reported: false }
+    }
+
+    /// Whether `queued` items waiting in the channel make a backlog that hasn't been reported yet.
+    fn check(&mut self, queued: usize) -> bool {
+        if self.reported {
+            self.reported = queued > self.capacity / 2;
+            false
+        } else {
+            self.reported = queued * 4 >= self.capacity * 3;
+            self.reported
+        }
+    }
 }
 
 impl Session {
     pub fn new(cols: usize, rows: usize) -> Self {
         let (broadcast_tx, _) = broadcast::channel(1024);
         let now = Instant::now();
+        let backend = backend::avt();
 
         Self {
-            vt: build_vt(cols, rows),
+            vt: backend(cols, rows),
+            backend,
             broadcast_tx,
+            broadcast_backlog: Backlog::new("broadcast", 1024),
+            listeners: Vec::new(),
             stream_time: 0.0,
             start_time: now,
             last_event_time: now,
             pending_pid: None,
+            state: None,
             style_mode: StyleMode::Plain,
+            style_format: StyleFormat::Cells,
+            layout: None,
+            pending_output: None,
+            styles: Arc::default(),
+            init_held: false,
+            raw_subscribers: Arc::new(()),
+        }
+    }
+
+    /// Hold back the init event until the command's first output (or its exit, if there is
+    /// none), then broadcast it to every subscriber with that output already on screen.
+    pub fn with_init_on_output(mut self) -> Self {
+        self.init_held = true;
+        self
+    }
+
+    /// Emulate the terminal (and any panes) with terminals built by `backend` instead of avt.
+    #[allow(dead_code)] // ht itself always uses avt
+    pub fn with_backend(
+        mut self,
+        backend: impl Fn(usize, usize) -> Box<dyn TerminalBackend> + Send + Sync + 'static,
+    ) -> Self {
+        let backend: backend::Factory = Arc::new(backend);
+        let (cols, rows) = self.vt.size();
+        self.vt = backend(cols, rows);
+
+        if let Some(layout) = &mut self.layout {
+            for pane in layout.panes.values_mut() {
+                pane.vt = backend(pane.geometry.cols, pane.geometry.rows);
+            }
+        }
+
+        self.backend = backend;
+        self
+    }
+
+    /// Let each subscriber fall up to `capacity` events behind before it starts missing them.
+    pub fn with_broadcast_capacity(mut self, capacity: usize) -> Self {
+        self.broadcast_tx = broadcast::channel(capacity).0;
+        self.broadcast_backlog = Backlog::new("broadcast", capacity);
+        self
+    }
+
+    /// Split a `cols`x`rows` screen between the main terminal, placed at `main`, and `panes`.
+    pub fn with_panes(
+        mut self,
+        cols: usize,
+        rows: usize,
+        main: Geometry,
+        panes: impl IntoIterator<Item = (usize, Geometry)>,
+    ) -> Self {
+        let panes = panes
+            .into_iter()
+            .map(|(id, geometry)| {
+                let vt = (self.backend)(geometry.cols, geometry.rows);
+                let styles = Arc::default();
+                (id, Pane { geometry, vt, styles })
+            })
+            .collect();
+
+        self.vt = (self.backend)(main.cols, main.rows);
+        self.layout = Some(Layout { cols, rows, main, panes });
+        self
+    }
+
+    /// The bytes read from the PTY, given before the text decoded from them goes to `output`.
+    /// They're broadcast as a `RawOutput` event along with that text, if a subscriber wants them.
+    pub fn raw_output(&mut self, data: &[u8]) {
+        // Until then the init event, with its screen, stands in for the output
+        if Arc::strong_count(&self.raw_subscribers) == 1 || self.init_held {
+            return;
         }
+
+        self.pending_output().bytes.extend_from_slice(data);
     }
 
     pub fn output(&mut self, data: String) {
         self.vt.feed_str(&data);
+
+        if self.init_held {
+            self.release_init();
+            return;
+        }
+
+        let pending = self.pending_output();
+        pending.data.push_str(&data);
+
+        if pending.data.len().max(pending.bytes.len()) >= OUTPUT_COALESCE_BYTES {
+            self.flush_output();
+        }
+    }
+
+    fn pending_output(&mut self) -> &mut PendingOutput {
+        self.pending_output.get_or_insert_with(|| PendingOutput {
+            time: self.start_time.elapsed().as_secs_f64(),
+            since: Instant::now(),
+            data: String::new(),
+            bytes: Vec::new(),
+        })
+    }
+
+    /// When pending output has to be broadcast, if there is any.
+    pub fn output_flush_deadline(&self) -> Option<Instant> {
+        self.pending_output.as_ref().map(|p| p.since + OUTPUT_COALESCE_DELAY)
+    }
+
+    /// Broadcast pending output now. Every other event (debug aside) flushes first, so
+    /// subscribers never see events out of order.
+    pub fn flush_output(&mut self) {
+        if let Some(pending) = self.pending_output.take() {
+            // A read can end partway through a character, leaving no text to go with its bytes
+            if !pending.data.is_empty() {
+                self.broadcast(Event::Output(pending.time, pending.data));
+            }
+
+            if !pending.bytes.is_empty() {
+                self.broadcast(Event::RawOutput(pending.time, pending.bytes));
+            }
+
+            self.stream_time = pending.time;
+            self.last_event_time = Instant::now();
+        }
+    }
+
+    pub fn stderr(&mut self, data: String) {
+        self.flush_output();
+
         let time = self.start_time.elapsed().as_secs_f64();
-        let _ = self.broadcast_tx.send(Event::Output(time, data));
+        self.broadcast(Event::Stderr(time, data));
         self.stream_time = time;
         self.last_event_time = Instant::now();
     }
 
+    /// Resize the screen, or emit an error event instead if the size is out of bounds.
     pub fn resize(&mut self, cols: usize, rows: usize) {
-        resize_vt(&mut self.vt, cols, rows);
+        if let Err(e) = Size::checked(cols, rows) {
+            self.emit_error(&e.to_string());
+            return;
+        }
+
+        self.flush_output();
+        self.vt.resize(cols, rows);
+
+        if self.vt.size() != (cols, rows) {
+            let (actual_cols, actual_rows) = self.vt.size();
+            self.emit_error(&format!("screen was resized to {actual_cols}x{actual_rows} rather than {cols}x{rows}"));
+            return;
+        }
+
+        if let Some(layout) = &mut self.layout {
+            layout.main.cols = cols;
+            layout.main.rows = rows;
+        }
+
         let time = self.start_time.elapsed().as_secs_f64();
-        let _ = self.broadcast_tx.send(Event::Resize(time, cols, rows));
+        self.broadcast(Event::Resize(time, cols, rows));
         self.stream_time = time;
         self.last_event_time = Instant::now();
     }
 
-    pub fn snapshot(&self) {
-        let (cols, rows) = self.vt.size();
-        let style_data = match self.style_mode {
-            StyleMode::Styled => {
-                let (pen_to_id, styles) = self.build_style_palette();
-                Some(StyleData {
-                    char_map: self.build_char_map(),
-                    style_map: self.build_style_map(&pen_to_id),
-                    styles,
-                })
+    /// Resize the screen to follow the PTY, once it has been asked for `requested` and has
+    /// `reported` the size it then had.
+    ///
+    /// The screen takes whatever size the PTY has, as that's what the command draws for, but
+    /// anything other than `requested` is reported as an error event too.
+    pub fn follow_pty_resize(&mut self, requested: Size, reported: std::result::Result<Size, String>) {
+        if let Some(size) = self.check_pty_resize(None, requested, reported) {
+            self.resize(size.cols(), size.rows());
+        }
+    }
+
+    pub fn snapshot(&mut self) {
+        let snapshot = self.capture().into_snapshot();
+        self.broadcast(snapshot);
+    }
+
+    /// Send a snapshot to `reply` only, rather than to every subscriber.
+    ///
+    /// The snapshot is built on tokio's blocking pool, so this must be called from within a runtime.
+    pub fn snapshot_for(&mut self, reply: Reply) {
+        let capture = self.capture();
+
+        tokio::task::spawn_blocking(move || {
+            let _ = reply.send(capture.into_snapshot());
+        });
+    }
+
+    fn capture(&mut self) -> Capture {
+        self.flush_output();
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.with_screen(|screen| Capture::new(time, screen, self.styles_for(&self.styles), &self.backend))
+    }
+
+    pub fn pane_output(&mut self, id: usize, data: String) {
+        if let Some(pane) = self.pane_mut(id) {
+            pane.vt.feed_str(&data);
+            self.emit_pane_event(id, |time| Event::Output(time, data));
+        }
+    }
+
+    pub fn pane_resize(&mut self, id: usize, cols: usize, rows: usize) {
+        if let Err(e) = Size::checked(cols, rows) {
+            let message = e.to_string();
+            self.emit_pane_event(id, |time| Event::Error(time, message));
+            return;
+        }
+
+        if let Some(pane) = self.pane_mut(id) {
+            pane.vt.resize(cols, rows);
+
+            if pane.vt.size() != (cols, rows) {
+                let (actual_cols, actual_rows) = pane.vt.size();
+                let message = format!("screen was resized to {actual_cols}x{actual_rows} rather than {cols}x{rows}");
+                self.emit_pane_event(id, |time| Event::Error(time, message));
+                return;
             }
-            StyleMode::Plain => None,
+
+            pane.geometry.cols = cols;
+            pane.geometry.rows = rows;
+            self.emit_pane_event(id, |time| Event::Resize(time, cols, rows));
+        }
+    }
+
+    /// Like `follow_pty_resize`, for pane `id`.
+    pub fn pane_follow_pty_resize(&mut self, id: usize, requested: Size, reported: std::result::Result<Size, String>) {
+        if let Some(size) = self.check_pty_resize(Some(id), requested, reported) {
+            self.pane_resize(id, size.cols(), size.rows());
+        }
+    }
+
+    /// The size a PTY (of `pane`, if given) reported after being asked for `requested`, if it
+    /// reported one, emitting an error event unless it's the size asked for.
+    fn check_pty_resize(&mut self, pane: Option<usize>, requested: Size, reported: std::result::Result<Size, String>) -> Option<Size> {
+        let (cols, rows) = (requested.cols(), requested.rows());
+
+        let (size, message) = match reported {
+            Ok(size) if size == requested => return Some(size),
+            Ok(size) => (Some(size), format!("terminal was resized to {}x{} rather than {cols}x{rows}", size.cols(), size.rows())),
+            Err(e) => (None, format!("cannot resize the terminal to {cols}x{rows}: {e}")),
         };
 
-        let _ = self.broadcast_tx.send(Event::Snapshot(
-            cols,
-            rows,
-            self.vt.dump(),
-            self.text_view(),
-            style_data,
-        ));
+        match pane {
+            Some(id) => self.emit_pane_event(id, |time| Event::Error(time, message)),
+            None => self.emit_error(&message),
+        }
+
+        size
+    }
+
+    pub fn pane_snapshot(&mut self, id: usize) {
+        if let Some(capture) = self.pane_capture(id) {
+            let snapshot = Event::Pane(id, Box::new(capture.into_snapshot()));
+            self.broadcast(snapshot);
+        }
+    }
+
+    /// Send a snapshot of pane `id` to `reply` only, rather than to every subscriber.
+    ///
+    /// Like `snapshot_for`, this must be called from within a runtime.
+    pub fn pane_snapshot_for(&mut self, id: usize, reply: Reply) {
+        if let Some(capture) = self.pane_capture(id) {
+            tokio::task::spawn_blocking(move || {
+                let _ = reply.send(Event::Pane(id, Box::new(capture.into_snapshot())));
+            });
+        }
+    }
+
+    fn pane_capture(&mut self, id: usize) -> Option<Capture> {
+        self.flush_output();
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.pane(id).map(|pane| Capture::new(time, &*pane.vt, self.styles_for(&pane.styles), &self.backend))
+    }
+
+    pub fn emit_pane_pid(&mut self, id: usize, pid: i32) {
+        self.emit_pane_event(id, |time| Event::Pid(time, pid));
+    }
+
+    pub fn emit_pane_exit_code(&mut self, id: usize, exit_code: ExitStatus) {
+        self.emit_pane_event(id, |time| Event::ExitCode(time, exit_code));
+    }
+
+    pub fn emit_pane_command_completed(&mut self, id: usize) {
+        self.emit_pane_event(id, Event::Completed);
+    }
+
+    pub fn emit_pane_respawn(&mut self, id: usize, attempt: u32, delay: Duration) {
+        self.emit_pane_event(id, |time| Event::Respawn(time, attempt, delay));
+    }
+
+    pub fn emit_pane_stopped(&mut self, id: usize, signal: &'static str) {
+        self.emit_pane_event(id, |time| Event::Stopped(time, signal));
+    }
+
+    pub fn emit_pane_continued(&mut self, id: usize) {
+        self.emit_pane_event(id, Event::Continued);
+    }
+
+    pub fn pane_cursor_key_app_mode(&self, id: usize) -> bool {
+        self.pane(id).is_some_and(|p| p.vt.cursor_key_app_mode())
+    }
+
+    /// Like `encode_input`, for the given pane's cursor key mode.
+    pub fn pane_encode_input(&self, id: usize, seqs: &[InputSeq]) -> Vec<u8> {
+        command::seqs_to_bytes(seqs, self.pane_cursor_key_app_mode(id))
     }
 
     pub fn emit_pid(&mut self, pid: i32) {
+        self.flush_output();
         self.pending_pid = Some(pid);
 
         let time = self.start_time.elapsed().as_secs_f64();
-        let _ = self.broadcast_tx.send(Event::Pid(time, pid));
+        self.broadcast(Event::Pid(time, pid));
         self.stream_time = time;
         self.last_event_time = Instant::now();
     }
 
-    pub fn emit_exit_code(&mut self, exit_code: i32) {
+    pub fn emit_exit_code(&mut self, exit_code: ExitStatus) {
+        self.release_init();
+        self.flush_output();
+
         let time = self.start_time.elapsed().as_secs_f64();
-        let _ = self.broadcast_tx.send(Event::ExitCode(time, exit_code));
+        self.broadcast(Event::ExitCode(time, exit_code));
         self.stream_time = time;
         self.last_event_time = Instant::now();
     }
 
+    /// Report input that the command wasn't there to take.
+    pub fn emit_input_discarded(&mut self, bytes: usize, reply: Option<Reply>) {
+        match reply {
+            Some(reply) => {
+                let _ = reply.send(Event::InputDiscarded(bytes));
+            }
+
+            None => self.broadcast(Event::InputDiscarded(bytes)),
+        }
+    }
+
+    pub fn emit_pane_input_discarded(&mut self, id: usize, bytes: usize, reply: Option<Reply>) {
+        let event = Event::Pane(id, Box::new(Event::InputDiscarded(bytes)));
+
+        match reply {
+            Some(reply) => {
+                let _ = reply.send(event);
+            }
+
+            None => self.broadcast(event),
+        }
+    }
+
+    /// Answer a flush whose fence has completed.
+    pub fn emit_flushed(&mut self, reply: Option<Reply>) {
+        match reply {
+            Some(reply) => {
+                let _ = reply.send(Event::Flushed);
+            }
+
+            None => self.broadcast(Event::Flushed),
+        }
+    }
+
     pub fn emit_command_completed(&mut self) {
+        self.flush_output();
+
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.broadcast(Event::Completed(time));
+        self.stream_time = time;
+        self.last_event_time = Instant::now();
+    }
+
+    pub fn emit_respawn(&mut self, attempt: u32, delay: Duration) {
+        self.flush_output();
+
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.broadcast(Event::Respawn(time, attempt, delay));
+        self.stream_time = time;
+        self.last_event_time = Instant::now();
+    }
+
+    /// The command was stopped by `signal`, so it won't produce output until it's continued.
+    pub fn emit_stopped(&mut self, signal: &'static str) {
+        self.flush_output();
+
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.broadcast(Event::Stopped(time, signal));
+        self.stream_time = time;
+        self.last_event_time = Instant::now();
+    }
+
+    pub fn emit_continued(&mut self) {
+        self.flush_output();
+
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.broadcast(Event::Continued(time));
+        self.stream_time = time;
+        self.last_event_time = Instant::now();
+    }
+
+    /// No output has arrived for `quiet`.
+    pub fn emit_idle(&mut self, quiet: Duration) {
+        self.flush_output();
+
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.broadcast(Event::Idle(time, quiet));
+        self.stream_time = time;
+        self.last_event_time = Instant::now();
+    }
+
+    /// Output resumed after a silence lasting `quiet`.
+    pub fn emit_active(&mut self, quiet: Duration) {
+        self.flush_output();
+
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.broadcast(Event::Active(time, quiet));
+        self.stream_time = time;
+        self.last_event_time = Instant::now();
+    }
+
+    /// Neither output nor commands have arrived for `quiet`, so the session is shutting down.
+    pub fn emit_idle_timeout(&mut self, quiet: Duration) {
+        self.flush_output();
+
         let time = self.start_time.elapsed().as_secs_f64();
-        let _ = self.broadcast_tx.send(Event::Completed(time));
+        self.broadcast(Event::IdleTimeout(time, quiet));
+        self.stream_time = time;
+        self.last_event_time = Instant::now();
+    }
+
+    /// The session has moved on to `state`.
+    pub fn emit_state(&mut self, state: SessionState) {
+        self.flush_output();
+        self.state = Some(state);
+
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.broadcast(Event::State(time, state));
         self.stream_time = time;
         self.last_event_time = Instant::now();
     }
 
     pub fn emit_debug_event(&mut self, message: &str) {
+        tracing::debug!("{message}");
         let time = self.start_time.elapsed().as_secs_f64();
-        let _ = self.broadcast_tx.send(Event::Debug(time, message.to_string()));
+        self.broadcast(Event::Debug(time, message.to_string()));
         self.stream_time = time;
         self.last_event_time = Instant::now();
     }
 
+    /// Tell every client that a command was refused, and why.
+    pub fn emit_error(&mut self, message: &str) {
+        tracing::warn!("{message}");
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.broadcast(Event::Error(time, message.to_string()));
+    }
+
+    /// Tell every client about a backlog in a channel, if `queued` items waiting in it make one.
+    pub fn check_backlog(&mut self, backlog: &mut Backlog, queued: usize) {
+        if backlog.check(queued) {
+            self.emit_backlog(*backlog, queued);
+        }
+    }
+
+    fn emit_backlog(&mut self, Backlog { channel, capacity, .. }: Backlog, queued: usize) {
+        tracing::warn!(channel, queued, capacity, "{channel} channel is backing up");
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.broadcast(Event::Backlog(time, channel, queued, capacity));
+    }
+
     pub fn cursor_key_app_mode(&self) -> bool {
         self.vt.cursor_key_app_mode()
     }
 
+    /// The bytes to write to the PTY for `seqs`, with cursor keys encoded for the mode the
+    /// terminal is currently in (`ESC [ A` normally, `ESC O A` in application mode).
+    pub fn encode_input(&self, seqs: &[InputSeq]) -> Vec<u8> {
+        command::seqs_to_bytes(seqs, self.cursor_key_app_mode())
+    }
+
     pub fn set_style_mode(&mut self, style_mode: StyleMode) {
         self.style_mode = style_mode;
     }
 
-    pub fn subscribe(&self) -> Subscription {
-        let (cols, rows) = self.vt.size();
-        let style_data = match self.style_mode {
-            StyleMode::Styled => {
-                let (pen_to_id, styles) = self.build_style_palette();
-                Some(StyleData {
-                    char_map: self.build_char_map(),
-                    style_map: self.build_style_map(&pen_to_id),
-                    styles,
-                })
-            }
-            StyleMode::Plain => None,
-        };
+    pub fn set_style_format(&mut self, style_format: StyleFormat) {
+        self.style_format = style_format;
+    }
 
-        let init = Event::Init(
-            self.elapsed_time(),
-            cols,
-            rows,
-            self.pending_pid.unwrap_or(0),
-            self.vt.dump(),
-            self.text_view(),
-            style_data,
-        );
+    pub fn subscribe(&mut self) -> Subscription {
+        self.flush_output();
+
+        let init = (!self.init_held).then(|| self.pending_init());
 
         let broadcast_rx = self.broadcast_tx.subscribe();
 
         if let Some(pid) = self.pending_pid {
             let time = self.elapsed_time();
-            let _ = self.broadcast_tx.send(Event::Pid(time, pid));
+            self.broadcast(Event::Pid(time, pid));
+        }
+
+        let state = self.state.map(|state| Event::State(self.elapsed_time(), state));
+
+        Subscription { init, state, broadcast_rx, raw_interest: None }
+    }
+
+    /// Like `subscribe`, but with output also broadcast as the raw bytes it was decoded from,
+    /// for as long as the subscription lasts. Only then, as it doubles the output events.
+    pub fn subscribe_raw(&mut self) -> Subscription {
+        let mut sub = self.subscribe();
+        sub.raw_interest = Some(self.raw_subscribers.clone());
+        sub
+    }
+
+    /// Call `listener` with every event from now on, starting with `Event::Init`, on
+    /// whichever thread drives the session.
+    ///
+    /// Unlike `subscribe`, this needs no async runtime. The listener runs before the event
+    /// reaches subscribers, so it shouldn't block.
+    #[allow(dead_code)] // ht itself only has async subscribers
+    pub fn on_event(&mut self, listener: impl Fn(&Event) + Send + 'static) {
+        self.flush_output();
+
+        if !self.init_held {
+            listener(&self.pending_init().into_event());
+        }
+
+        if let Some(state) = self.state {
+            listener(&Event::State(self.elapsed_time(), state));
+        }
+
+        self.listeners.push(Box::new(listener));
+    }
+
+    fn broadcast(&mut self, event: Event) {
+        for listener in &self.listeners {
+            listener(&event);
+        }
+
+        let _ = self.broadcast_tx.send(event);
+
+        let queued = self.broadcast_tx.len();
+
+        if self.broadcast_backlog.check(queued) {
+            self.emit_backlog(self.broadcast_backlog, queued);
         }
+    }
 
-        Subscription { init, broadcast_rx }
+    fn pending_init(&self) -> PendingInit {
+        let time = self.elapsed_time();
+
+        PendingInit {
+            pid: self.pending_pid.unwrap_or(0),
+            capture: self.with_screen(|screen| Capture::new(time, screen, self.styles_for(&self.styles), &self.backend)),
+        }
+    }
+
+    /// Give the subscribers that have been waiting for it their init event.
+    fn release_init(&mut self) {
+        if self.init_held {
+            self.init_held = false;
+            self.broadcast(self.pending_init().into_event());
+            self.stream_time = self.elapsed_time();
+            self.last_event_time = Instant::now();
+        }
     }
 
     fn elapsed_time(&self) -> f64 {
         self.stream_time + self.last_event_time.elapsed().as_secs_f64()
     }
 
-    fn text_view(&self) -> String {
-        self.vt
-            .view()
-            .iter()
-            .map(|l| l.text())
-            .collect::<Vec<_>>()
-            .join("\n")
-    }
-
-    fn build_style_palette(&self) -> (HashMap<String, usize>, HashMap<String, PenJson>) {
-        let mut pen_to_id = HashMap::new();
-        let mut styles = HashMap::new();
-        // Reserve ID 0 for default pen
-        let default_pen = Pen::default();
-        let default_key = self.pen_to_key(&default_pen);
-        pen_to_id.insert(default_key, 0);
-        styles.insert("0".to_string(), PenJson::from(&default_pen));
-        let mut next_id = 1;
-
-        for line in self.vt.view() {
-            for cell in line.cells() {
-                if cell.width() > 0 {
-                    let pen = *cell.pen();
-                    let pen_key = self.pen_to_key(&pen);
-                    if let std::collections::hash_map::Entry::Vacant(e) = pen_to_id.entry(pen_key) {
-                        e.insert(next_id);
-                        styles.insert(next_id.to_string(), PenJson::from(&pen));
-                        next_id += 1;
+    fn emit_pane_event(&mut self, id: usize, event: impl FnOnce(f64) -> Event) {
+        self.flush_output();
+
+        let time = self.start_time.elapsed().as_secs_f64();
+        self.broadcast(Event::Pane(id, Box::new(event(time))));
+        self.stream_time = time;
+        self.last_event_time = Instant::now();
+    }
+
+    fn pane(&self, id: usize) -> Option<&Pane> {
+        self.layout.as_ref().and_then(|l| l.panes.get(&id))
+    }
+
+    fn pane_mut(&mut self, id: usize) -> Option<&mut Pane> {
+        self.layout.as_mut().and_then(|l| l.panes.get_mut(&id))
+    }
+
+    /// The whole virtual screen: the main terminal alone, or every pane composited into place.
+    fn with_screen<R>(&self, f: impl FnOnce(&dyn TerminalBackend) -> R) -> R {
+        let Some(layout) = &self.layout else {
+            return f(&*self.vt);
+        };
+
+        let mut screen = (self.backend)(layout.cols, layout.rows);
+        let mut seq = String::new();
+        let regions = std::iter::once((&layout.main, &self.vt))
+            .chain(layout.panes.values().map(|p| (&p.geometry, &p.vt)));
+
+        for (geometry, vt) in regions {
+            let width = geometry.cols.min(layout.cols.saturating_sub(geometry.x));
+            let height = geometry.rows.min(layout.rows.saturating_sub(geometry.y));
+
+            for (row, line) in vt.view().iter().take(height).enumerate() {
+                seq.push_str(&format!("\x1b[{};{}H", geometry.y + row + 1, geometry.x + 1));
+                let mut col = 0;
+
+                for cell in line.iter().filter(|c| c.width > 0) {
+                    if col + cell.width > width {
+                        break;
                     }
+
+                    seq.push_str(&sgr(&cell.style));
+                    seq.push(cell.ch);
+                    col += cell.width;
                 }
             }
         }
 
-        (pen_to_id, styles)
-    }
+        seq.push_str("\x1b[0m");
+        let cursor = self.vt.cursor();
+        seq.push_str(&format!("\x1b[{};{}H", layout.main.y + cursor.row + 1, layout.main.x + cursor.col + 1));
 
-    fn pen_to_key(&self, pen: &Pen) -> String {
-        // Create a unique string key for the pen
-        format!("{:?}", pen)
+        if !cursor.visible {
+            seq.push_str("\x1b[?25l");
+        }
+
+        screen.feed_str(&seq);
+        f(&*screen)
     }
 
-    fn build_char_map(&self) -> Vec<Vec<char>> {
-        let (cols, _rows) = self.vt.size();
-        self.vt
-            .view()
-            .iter()
-            .map(|line| {
-                let mut char_row = Vec::with_capacity(cols);
-                for cell in line.cells() {
-                    char_row.push(cell.char());
-                }
-                // Ensure we have exactly cols characters, pad with spaces if needed
-                char_row.resize(cols, ' ');
-                char_row
-            })
-            .collect()
-    }
-
-    fn build_style_map(&self, pen_to_id: &HashMap<String, usize>) -> Vec<Vec<usize>> {
-        let (cols, _rows) = self.vt.size();
-        self.vt
-            .view()
-            .iter()
-            .map(|line| {
-                let mut style_row = Vec::with_capacity(cols);
-                for cell in line.cells() {
-                    let pen_key = self.pen_to_key(cell.pen());
-                    style_row.push(*pen_to_id.get(&pen_key).unwrap_or(&0));
-                }
-                // Ensure we have exactly cols style IDs, pad with default style if needed
-                style_row.resize(cols, 0);
-                style_row
-            })
-            .collect()
+    fn styles_for(&self, cache: &Arc<Mutex<StyleCache>>) -> Option<Styling> {
+        match self.style_mode {
+            StyleMode::Styled => Some((cache.clone(), self.style_format)),
+            StyleMode::Plain => None,
+        }
     }
 }
 
 impl Event {
-    pub fn to_json(&self) -> serde_json::Value {
+    /// The wire form of this event, borrowing its contents.
+    pub fn message(&self) -> Message<'_> {
         match self {
-            Event::Init(_time, cols, rows, pid, seq, text, style_data) => {
-                let mut data = json!({
-                    "cols": cols,
-                    "rows": rows,
-                    "pid": pid,
-                    "seq": seq,
-                    "text": text,
-                });
-
-                if let Some(style_data) = style_data {
-                    let data_obj = data.as_object_mut().unwrap();
-                    data_obj.insert("charMap".to_string(), json!(style_data.char_map));
-                    data_obj.insert("styleMap".to_string(), json!(style_data.style_map));
-                    data_obj.insert("styles".to_string(), json!(style_data.styles));
-                }
+            Event::Init(_time, cols, rows, pid, seq, text, style_data) => Message::Init(message::Init {
+                cols: *cols,
+                rows: *rows,
+                pid: *pid,
+                seq: seq.into(),
+                text: text.into(),
+                styles: styles_message(style_data),
+                pane: None,
+            }),
 
-                json!({
-                    "type": "init",
-                    "data": data
-                })
-            },
+            Event::Output(_time, seq) => Message::Output(message::Output {
+                seq: seq.into(),
+                pane: None,
+            }),
 
-            Event::Output(_time, seq) => json!({
-                "type": "output",
-                "data": json!({
-                    "seq": seq
-                })
+            Event::RawOutput(_time, bytes) => Message::RawOutput(message::RawOutput {
+                bytes: BASE64_STANDARD.encode(bytes).into(),
+                pane: None,
             }),
 
-            Event::Resize(_time, cols, rows) => json!({
-                "type": "resize",
-                "data": json!({
-                    "cols": cols,
-                    "rows": rows,
-                })
+            Event::Stderr(_time, seq) => Message::Stderr(message::Output {
+                seq: seq.into(),
+                pane: None,
             }),
 
-            Event::Snapshot(cols, rows, seq, text, style_data) => {
-                let mut data = json!({
-                    "cols": cols,
-                    "rows": rows,
-                    "seq": seq,
-                    "text": text,
-                });
-
-                if let Some(style_data) = style_data {
-                    let data_obj = data.as_object_mut().unwrap();
-                    data_obj.insert("charMap".to_string(), json!(style_data.char_map));
-                    data_obj.insert("styleMap".to_string(), json!(style_data.style_map));
-                    data_obj.insert("styles".to_string(), json!(style_data.styles));
-                }
+            Event::Resize(_time, cols, rows) => Message::Resize(message::Resize {
+                cols: *cols,
+                rows: *rows,
+                pane: None,
+            }),
 
-                json!({
-                    "type": "snapshot",
-                    "data": data
-                })
-            },
+            Event::Snapshot(time, cols, rows, seq, text, style_data) => Message::Snapshot(message::Snapshot {
+                time: *time,
+                cols: *cols,
+                rows: *rows,
+                seq: seq.into(),
+                text: text.into(),
+                styles: styles_message(style_data),
+                html: None,
+                pane: None,
+            }),
+
+            Event::InputWritten(bytes) => Message::InputWritten(message::InputWritten {
+                bytes: *bytes,
+                pane: None,
+            }),
+
+            Event::InputDiscarded(bytes) => Message::InputDiscarded(message::InputDiscarded {
+                bytes: *bytes,
+                pane: None,
+            }),
+
+            Event::Flushed => Message::Flushed(message::Flushed { pane: None }),
+
+            Event::Pid(_time, pid) => Message::Pid(message::Pid { pid: *pid, pane: None }),
+
+            Event::ExitCode(_time, status) => Message::ExitCode(message::ExitCode {
+                exit_code: status.code,
+                signal: status.signal.map(Into::into),
+                core_dumped: status.core_dumped,
+                terminated_by: status.terminated_by,
+                pane: None,
+            }),
+
+            Event::Debug(_time, message) => Message::Debug(message::Debug {
+                message: message.into(),
+                pane: None,
+            }),
+
+            Event::Error(_time, message) => Message::Error(message::Error {
+                message: message.into(),
+                line: None,
+                id: None,
+                pane: None,
+            }),
+
+            Event::Completed(time) => Message::CommandCompleted(message::CommandCompleted {
+                time: *time,
+                pane: None,
+            }),
+
+            Event::Respawn(_time, attempt, delay) => Message::Respawn(message::Respawn {
+                attempt: *attempt,
+                delay_ms: delay.as_millis() as u64,
+                pane: None,
+            }),
 
-            Event::Pid(_time, pid) => json!({
-                "type": "pid",
-                "data": json!({
-                    "pid": pid
-                })
+            Event::Stopped(_time, signal) => Message::Stopped(message::Stopped {
+                signal: Cow::Borrowed(signal),
+                pane: None,
             }),
 
-            Event::ExitCode(_time, exit_code) => json!({
-                "type": "exitCode",
-                "data": json!({
-                    "exitCode": exit_code
-                })
+            Event::Continued(_time) => Message::Continued(message::Continued { pane: None }),
+
+            Event::Idle(_time, quiet) => Message::Idle(message::Quiet {
+                quiet_ms: quiet.as_millis() as u64,
+                pane: None,
+            }),
+
+            Event::Active(_time, quiet) => Message::Active(message::Quiet {
+                quiet_ms: quiet.as_millis() as u64,
+                pane: None,
+            }),
+
+            Event::IdleTimeout(_time, quiet) => Message::IdleTimeout(message::Quiet {
+                quiet_ms: quiet.as_millis() as u64,
+                pane: None,
+            }),
+
+            Event::State(_time, state) => Message::State(message::State {
+                state: *state,
+                pane: None,
             }),
 
-            Event::Debug(_time, message) => json!({
-                "type": "debug",
-                "data": json!({
-                    "message": message
-                })
+            Event::Dropped(_time, count) => Message::Dropped(message::Dropped {
+                count: *count,
+                pane: None,
             }),
 
-            Event::Completed(time) => json!({
-                "type": "commandCompleted",
-                "data": json!({
-                    "time": time
-                })
+            Event::Backlog(_time, channel, queued, capacity) => Message::Backlog(message::Backlog {
+                channel: Cow::Borrowed(channel),
+                queued: *queued,
+                capacity: *capacity,
+                pane: None,
             }),
+
+            Event::Pane(id, event) => event.message().in_pane(*id),
         }
     }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.message()).expect("events always serialize")
+    }
+}
+
+fn styles_message(style_data: &Option<StyleData>) -> message::Styles<'_> {
+    match style_data {
+        Some(style_data) => message::Styles {
+            char_map: Some(Cow::Borrowed(&style_data.char_map)),
+            style_map: Some(Cow::Borrowed(&style_data.style_map)),
+            style_format: Some(style_data.style_map.format()),
+            styles: Some(Cow::Borrowed(&style_data.styles)),
+        },
+
+        None => message::Styles::default(),
+    }
 }
 
-fn build_vt(cols: usize, rows: usize) -> avt::Vt {
-    avt::Vt::builder().size(cols, rows).build()
+fn text_view(vt: &dyn TerminalBackend) -> String {
+    vt.view()
+        .iter()
+        .map(|l| backend::text(l))
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
-fn resize_vt(vt: &mut avt::Vt, cols: usize, rows: usize) {
-    vt.feed_str(&format!("\x1b[8;{rows};{cols}t"));
+/// SGR sequence selecting exactly `style`, starting from the default rendition.
+pub fn sgr(style: &Style) -> String {
+    let mut params = vec!["0".to_string()];
+
+    for (on, code) in [
+        (style.bold, "1"),
+        (style.faint, "2"),
+        (style.italic, "3"),
+        (style.underline, "4"),
+        (style.blink, "5"),
+        (style.inverse, "7"),
+        (style.strikethrough, "9"),
+    ] {
+        if on {
+            params.push(code.to_string());
+        }
+    }
+
+    for (color, base) in [(style.foreground, 38), (style.background, 48)] {
+        match color {
+            Some(Color::Indexed(i)) => params.push(format!("{base};5;{i}")),
+            Some(Color::Rgb(r, g, b)) => params.push(format!("{base};2;{r};{g};{b}")),
+            None => (),
+        }
+    }
+
+    format!("\x1b[{}m", params.join(";"))
 }
 
 impl Client {
-    pub fn accept(self, subscription: Subscription) {
-        let _ = self.0.send(subscription);
+    pub fn accept(self, session: &mut Session) {
+        let subscription = if self.raw_output { session.subscribe_raw() } else { session.subscribe() };
+        let _ = self.tx.send(subscription);
     }
 }
 
-pub async fn stream(
+/// Events for a single client, starting with `Init`.
+///
+/// A client that falls too far behind loses the oldest events it hasn't read yet. When that
+/// happens the stream yields `Dropped` with the number of lost events, then resubscribes and
+/// carries on from a fresh `Init`, so the client can rebuild its view of the screen.
+pub async fn stream(clients_tx: &mpsc::Sender<Client>, timeout: Duration) -> Result<impl Stream<Item = Event> + Unpin> {
+    subscription_stream(clients_tx, timeout, false).await
+}
+
+/// Like `stream`, for a client that only wants the events `sub` includes. Raw output, which
+/// doubles the output events, is only broadcast while such a client asks for it.
+pub async fn stream_for(
     clients_tx: &mpsc::Sender<Client>,
-) -> Result<impl Stream<Item = Result<Event, BroadcastStreamRecvError>>> {
-    let (sub_tx, sub_rx) = oneshot::channel();
-    clients_tx.send(Client(sub_tx)).await?;
-    let sub = tokio::time::timeout(Duration::from_secs(5), sub_rx).await??;
-    let init = stream::once(future::ready(Ok(sub.init)));
+    timeout: Duration,
+    sub: &crate::config::Subscription,
+) -> Result<impl Stream<Item = Event> + Unpin> {
+    subscription_stream(clients_tx, timeout, sub.raw_output).await
+}
+
+async fn subscription_stream(
+    clients_tx: &mpsc::Sender<Client>,
+    timeout: Duration,
+    raw_output: bool,
+) -> Result<impl Stream<Item = Event> + Unpin> {
+    let (first, sub) = subscribe(clients_tx, timeout, raw_output).await?;
+    let pending = VecDeque::from(first);
     let events = BroadcastStream::new(sub.broadcast_rx);
+    let raw_interest = sub.raw_interest;
+
+    Ok(stream::unfold(
+        (clients_tx.clone(), pending, events, raw_interest),
+        move |(clients_tx, mut pending, mut events, mut raw_interest)| async move {
+            loop {
+                if let Some(event) = pending.pop_front() {
+                    return Some((event, (clients_tx, pending, events, raw_interest)));
+                }
 
-    Ok(init.chain(events))
+                match events.next().await? {
+                    Ok(event) => return Some((event, (clients_tx, pending, events, raw_interest))),
+
+                    Err(BroadcastStreamRecvError::Lagged(count)) => {
+                        let (first, sub) = subscribe(&clients_tx, timeout, raw_output).await.ok()?;
+                        let time = match first.first() {
+                            Some(Event::Init(time, ..)) => *time,
+                            _ => 0.0,
+                        };
+
+                        pending.push_back(Event::Dropped(time, count));
+                        pending.extend(first);
+                        events = BroadcastStream::new(sub.broadcast_rx);
+                        raw_interest = sub.raw_interest;
+                    }
+                }
+            }
+        },
+    )
+    .boxed())
+}
+
+/// Ask the session for a new subscription, then build its init event on the blocking pool.
+/// Returns the events the subscription starts with, ahead of those broadcast, along with what's
+/// left of the subscription.
+async fn subscribe(clients_tx: &mpsc::Sender<Client>, timeout: Duration, raw_output: bool) -> Result<(Vec<Event>, Subscription)> {
+    let (tx, sub_rx) = oneshot::channel();
+    clients_tx.send(Client { tx, raw_output }).await.map_err(|_| HttyError::SessionEnded)?;
+
+    let mut sub = tokio::time::timeout(timeout, sub_rx)
+        .await
+        .map_err(|_| HttyError::Timeout(format!("the session didn't accept a subscription within {timeout:?}")))?
+        .map_err(|_| HttyError::SessionEnded)?;
+
+    // Only fails if the runtime is shutting down, taking the session with it
+    let pending_init = sub.init.take();
+    let init = tokio::task::spawn_blocking(move || pending_init.map(PendingInit::into_event))
+        .await
+        .map_err(|_| HttyError::SessionEnded)?;
+
+    Ok((init.into_iter().chain(sub.state.take()).collect(), sub))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Backlog, Event, Session, SessionState};
+    use crate::config::Size;
+    use std::sync::{Arc, Mutex};
+
+    /// Every event `session` emits from now on, starting with `Init`.
+    fn recorded(session: &mut Session) -> Arc<Mutex<Vec<Event>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        session.on_event(move |event| log.lock().unwrap().push(event.clone()));
+        events
+    }
+
+    #[test]
+    fn on_event() {
+        let mut session = Session::new(10, 2);
+        session.output("a".to_string());
+
+        let events = recorded(&mut session);
+
+        session.output("b".to_string());
+        session.resize(20, 4);
+
+        let events = events.lock().unwrap();
+        assert!(matches!(&events[..], [Event::Init(_, 10, 2, _, _, text, _), Event::Output(_, b), Event::Resize(_, 20, 4)]
+            if text.starts_with('a') && b == "b"));
+    }
+
+    #[test]
+    fn refuses_out_of_bounds_resize() {
+        let mut session = Session::new(10, 2);
+        let events = recorded(&mut session);
+
+        session.resize(0, 4);
+        session.resize(20, 10_001);
+        session.resize(20, 4);
+
+        let events = events.lock().unwrap();
+        assert!(matches!(&events[..], [Event::Init(..), Event::Error(_, a), Event::Error(_, b), Event::Resize(_, 20, 4)]
+            if a.contains("0x4") && b.contains("20x10001")));
+    }
+
+    #[test]
+    fn screen_follows_pty_resize() {
+        let mut session = Session::new(10, 2);
+        let events = recorded(&mut session);
+
+        session.follow_pty_resize(Size::new(20, 4), Ok(Size::new(20, 4)));
+        session.follow_pty_resize(Size::new(30, 6), Ok(Size::new(30, 5)));
+        session.follow_pty_resize(Size::new(40, 8), Err("Invalid argument".to_string()));
+
+        let events = events.lock().unwrap();
+        assert!(matches!(&events[..], [Event::Init(..), Event::Resize(_, 20, 4), Event::Error(_, a), Event::Resize(_, 30, 5), Event::Error(_, b)]
+            if a.contains("30x5 rather than 30x6") && b.contains("40x8: Invalid argument")));
+    }
+
+    #[test]
+    fn raw_output() {
+        let mut session = Session::new(10, 2);
+        let events = recorded(&mut session);
+        let plain = session.subscribe();
+        let raw = session.subscribe_raw();
+
+        // "é" split across reads, then bytes that aren't text at all
+        session.raw_output(b"a\xc3");
+        session.output("a".to_string());
+        session.raw_output(b"\xa9\x00\xff");
+        session.output("é\0\u{fffd}".to_string());
+        session.flush_output();
+
+        // With only subscribers that didn't ask for it left, raw output stops
+        drop(raw);
+        session.raw_output(b"b");
+        session.output("b".to_string());
+        session.flush_output();
+        drop(plain);
+
+        let events = events.lock().unwrap();
+        assert!(matches!(&events[..], [Event::Init(..), Event::Output(_, text), Event::RawOutput(_, bytes), Event::Output(_, b)]
+            if text == "aé\0\u{fffd}" && bytes == b"a\xc3\xa9\x00\xff" && b == "b"));
+        assert_eq!(events[2].to_json(), r#"{"type":"rawOutput","data":{"bytes":"YcOpAP8="}}"#);
+    }
+
+    #[test]
+    fn snapshot_is_timestamped() {
+        let mut session = Session::new(10, 2);
+        let events = recorded(&mut session);
+
+        session.output("a".to_string());
+        session.snapshot();
+
+        let events = events.lock().unwrap();
+        let [Event::Init(..), Event::Output(output_time, _), snapshot @ Event::Snapshot(time, ..)] = &events[..] else {
+            panic!("unexpected events: {events:?}");
+        };
+        assert!(time >= output_time);
+        assert!(snapshot.to_json().contains(&format!(r#""time":{time}"#)));
+    }
+
+    #[test]
+    fn backlog_reported_once_until_drained() {
+        let mut backlog = Backlog::new("output", 8);
+        let reports: Vec<_> = [1, 5, 6, 8, 7, 5, 4, 6].into_iter().map(|queued| backlog.check(queued)).collect();
+        assert_eq!(reports, [false, false, true, false, false, false, false, true]);
+    }
+
+    #[test]
+    fn reports_broadcast_backlog() {
+        let mut session = Session::new(10, 2).with_broadcast_capacity(4);
+        let _slow = session.broadcast_tx.subscribe();
+        let events = recorded(&mut session);
+
+        for cols in 11..16 {
+            session.resize(cols, 2);
+        }
+
+        let events = events.lock().unwrap();
+        let backlogs: Vec<_> = events.iter().filter(|e| matches!(e, Event::Backlog(..))).collect();
+        assert!(matches!(&backlogs[..], [Event::Backlog(_, "broadcast", 3, 4)]));
+        assert!(backlogs[0].to_json().contains(r#""type":"backlog""#));
+    }
+
+    #[test]
+    fn stop_and_continue() {
+        let mut session = Session::new(10, 2);
+        let events = recorded(&mut session);
+
+        session.emit_stopped("SIGSTOP");
+        session.emit_continued();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events[1].to_json(), r#"{"type":"stopped","data":{"signal":"SIGSTOP"}}"#);
+        assert_eq!(events[2].to_json(), r#"{"type":"continued","data":{}}"#);
+    }
+
+    #[test]
+    fn state_transitions() {
+        use SessionState::*;
+
+        assert!(Starting.can_become(Running) && Running.can_become(ChildExited) && ChildExited.can_become(Draining));
+        assert!(ChildExited.can_become(Starting) && Running.can_become(Draining) && Draining.can_become(Closed));
+        assert!(!Draining.can_become(Running) && !Draining.can_become(ChildExited) && !Closed.can_become(Draining));
+
+        assert!(Running.takes_input() && !ChildExited.takes_input() && !Draining.takes_input());
+        assert_eq!(Event::State(0.0, ChildExited).to_json(), r#"{"type":"state","data":{"state":"childExited"}}"#);
+    }
+
+    #[test]
+    fn state_for_new_subscribers() {
+        let mut session = Session::new(10, 2);
+        session.emit_state(SessionState::Running);
+
+        let mut first = session.subscribe();
+        let second = session.subscribe();
+
+        assert!(matches!(first.state, Some(Event::State(_, SessionState::Running))));
+        assert!(matches!(second.state, Some(Event::State(_, SessionState::Running))));
+        assert!(first.broadcast_rx.try_recv().is_err(), "a new subscriber made the session announce its state again");
+    }
 }
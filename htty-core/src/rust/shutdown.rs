@@ -0,0 +1,82 @@
+//! Telling the PTY driver, the API servers and the event loop that the session is ending.
+//!
+//! Each task takes a clone of the same [`Shutdown`] and stops once it's triggered, rather
+//! than inferring that from channels closing under it.
+
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(CancellationToken);
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask every task holding a clone of this to stop. Triggering it again does nothing.
+    pub fn trigger(&self) {
+        self.0.cancel();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.is_cancelled()
+    }
+
+    /// Trigger this whenever a thread panics, once the panic has been reported as usual.
+    ///
+    /// tokio catches a panic in a spawned task and only passes it to whatever awaits the task,
+    /// so otherwise the rest of the session would carry on without it (or wait on it forever)
+    /// and never get to the cleanup done on the way out, of the runtime directory and cgroup.
+    pub fn trigger_on_panic(&self) {
+        let shutdown = self.clone();
+        let report = std::panic::take_hook();
+
+        std::panic::set_hook(Box::new(move |info| {
+            report(info);
+            shutdown.trigger();
+        }));
+    }
+
+    /// A shutdown of its own, which is also triggered along with this one.
+    #[allow(dead_code)] // ht itself only ever has the one
+    pub fn child(&self) -> Self {
+        Self(self.0.child_token())
+    }
+
+    /// Resolves once the shutdown is triggered, immediately if it already has been.
+    pub async fn triggered(&self) {
+        self.0.cancelled().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Shutdown;
+
+    #[tokio::test]
+    async fn reaches_every_clone() {
+        let shutdown = Shutdown::new();
+        let task = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.triggered().await }
+        });
+
+        assert!(!shutdown.is_triggered());
+        shutdown.trigger();
+        task.await.unwrap();
+        assert!(shutdown.is_triggered());
+    }
+
+    #[tokio::test]
+    async fn triggered_by_panic() {
+        let shutdown = Shutdown::new();
+        shutdown.trigger_on_panic();
+
+        let task = tokio::spawn(async { panic!("deliberately") });
+        assert!(task.await.unwrap_err().is_panic());
+        assert!(shutdown.is_triggered());
+
+        // Back to the default hook, which reports the other tests' panics without triggering this
+        let _ = std::panic::take_hook();
+    }
+}
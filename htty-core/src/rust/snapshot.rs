@@ -0,0 +1,157 @@
+use crate::backend::{self, TerminalBackend};
+use crate::session::Event;
+#[cfg(feature = "pty")]
+use crate::cli::ScreenFormat;
+#[cfg(feature = "pty")]
+use crate::command::Command;
+#[cfg(feature = "pty")]
+use crate::session;
+#[cfg(feature = "pty")]
+use crate::timing::Timing;
+#[cfg(feature = "pty")]
+use anyhow::Result;
+#[cfg(feature = "pty")]
+use futures_util::StreamExt;
+#[cfg(feature = "pty")]
+use regex::Regex;
+#[cfg(feature = "pty")]
+use std::io::{self, Write};
+#[cfg(feature = "pty")]
+use std::time::Duration;
+#[cfg(feature = "pty")]
+use tokio::sync::{mpsc, oneshot};
+#[cfg(feature = "pty")]
+use tokio::time::{self, Instant};
+#[cfg(all(feature = "pty", feature = "render"))]
+use crate::config::HtmlTheme;
+#[cfg(all(feature = "pty", feature = "render"))]
+use crate::render;
+#[cfg(all(feature = "pty", not(feature = "render")))]
+use anyhow::bail;
+
+/// Stand-in for the stdio API used by `ht snapshot`: prints the final screen to stdout.
+///
+/// The screen is taken when the command exits, or earlier once the terminal has been quiet
+/// for `until_quiet` or its text matches `until`. ht is then told to exit.
+#[cfg(feature = "pty")]
+pub async fn start(
+    command_tx: mpsc::Sender<Command>,
+    clients_tx: mpsc::Sender<session::Client>,
+    until_quiet: Option<Duration>,
+    until: Option<Regex>,
+    format: ScreenFormat,
+    timing: Timing,
+) -> Result<()> {
+    let mut events = session::stream(&clients_tx, timing.subscription_timeout).await?;
+    let mut screen = Screen::default();
+    let mut quiet_deadline = until_quiet.map(|d| Instant::now() + d);
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else { break };
+
+                if let Event::ExitCode(..) = event {
+                    break;
+                }
+
+                if let Event::Output(..) = event {
+                    quiet_deadline = until_quiet.map(|d| Instant::now() + d);
+                }
+
+                if screen.apply(&event) && until.as_ref().is_some_and(|p| p.is_match(&screen.text())) {
+                    break;
+                }
+            }
+
+            _ = sleep_until(quiet_deadline), if quiet_deadline.is_some() => break,
+        }
+    }
+
+    // Exit even if the screen couldn't be printed, e.g. because stdout was closed
+    let printed = print(&command_tx, format).await;
+    command_tx.send(Command::Exit).await?;
+
+    printed
+}
+
+/// Take a snapshot and print it to stdout in `format`, leaving out blank rows at the bottom.
+#[cfg(feature = "pty")]
+#[cfg_attr(not(feature = "render"), allow(unused_variables))]
+pub async fn print(command_tx: &mpsc::Sender<Command>, format: ScreenFormat) -> Result<()> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    command_tx.send(Command::Snapshot(Some(reply_tx))).await?;
+
+    if let Event::Snapshot(_, cols, rows, seq, text, _) = reply_rx.await? {
+        let screen = match format {
+            ScreenFormat::Text => trim_lines(&text).trim_end_matches('\n').to_string(),
+            #[cfg(feature = "render")]
+            ScreenFormat::Ansi => render::ansi(&*replay(cols, rows, &seq)).trim_end_matches('\n').to_string(),
+            #[cfg(feature = "render")]
+            ScreenFormat::Html => render::html(cols, rows, &seq, HtmlTheme::default()),
+            #[cfg(not(feature = "render"))]
+            format => bail!("{format:?} snapshots need htty_core's render feature"),
+        };
+
+        let mut stdout = io::stdout();
+        writeln!(stdout, "{screen}")?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+/// The main screen as it's built up by a session's events, for matching against as they come.
+///
+/// Output is only seen once the session flushes it, so this can lag a little behind a snapshot.
+#[derive(Default)]
+pub struct Screen(Option<Box<dyn TerminalBackend>>);
+
+impl Screen {
+    /// Update the screen with `event`, returning whether it changed.
+    pub fn apply(&mut self, event: &Event) -> bool {
+        match (event, &mut self.0) {
+            (Event::Init(_, cols, rows, _pid, seq, _text, _), vt) => {
+                *vt = Some(replay(*cols, *rows, seq));
+                true
+            }
+
+            (Event::Output(_, seq), Some(vt)) => {
+                vt.feed_str(seq);
+                true
+            }
+
+            (Event::Resize(_, cols, rows), Some(vt)) => {
+                vt.resize(*cols, *rows);
+                true
+            }
+
+            _ => false,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match &self.0 {
+            Some(vt) => trim_lines(&vt.screen().text()),
+            None => String::new(),
+        }
+    }
+}
+
+fn trim_lines(text: &str) -> String {
+    text.lines().map(str::trim_end).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(feature = "pty")]
+async fn sleep_until(deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        time::sleep_until(deadline).await;
+    }
+}
+
+/// Rebuild the screen a snapshot's `seq` draws, to read its cells back.
+pub(crate) fn replay(cols: usize, rows: usize, seq: &str) -> Box<dyn TerminalBackend> {
+    let mut vt = backend::build_avt(cols, rows);
+    vt.feed_str(seq);
+    vt
+}
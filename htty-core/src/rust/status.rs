@@ -0,0 +1,46 @@
+//! How a command went away, as reported by the PTY driver and passed on to clients.
+
+use serde::{Deserialize, Serialize};
+
+/// Which process's termination an `ExitStatus` describes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Terminator {
+    /// The command ended: exec'd directly, or wrapped by a shell which relayed its status.
+    Command,
+    /// The shell wrapping the command was itself terminated (e.g. killed by a signal) before
+    /// it could relay a status.
+    Shell,
+}
+
+/// How the child went away.
+///
+/// `code` keeps the historic `128 + signal` mapping for signal deaths. For statuses relayed by
+/// a shell wrapper the signal is inferred from that same convention, since `$?` is all the
+/// wrapper gets to see; core dumps are then only known when the wrapper itself was terminated.
+/// A command exec'd directly is seen dying of its signal, so its exit code is taken as it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExitStatus {
+    pub code: i32,
+    /// The name of the signal the command died of, such as `SIGTERM`.
+    pub signal: Option<&'static str>,
+    pub core_dumped: bool,
+    pub terminated_by: Terminator,
+}
+
+impl ExitStatus {
+    pub fn unknown() -> Self {
+        Self {
+            code: -1,
+            signal: None,
+            core_dumped: false,
+            terminated_by: Terminator::Shell,
+        }
+    }
+
+    /// The code for ht to exit with when passing this status on: the command's own exit code,
+    /// `128 + signal` when it was killed by a signal, or 1 when the status is unknown.
+    pub fn exit_code(&self) -> u8 {
+        u8::try_from(self.code).unwrap_or(1)
+    }
+}
@@ -0,0 +1,108 @@
+use nix::sys::termios::{self, InputFlags, LocalFlags, OutputFlags, SetArg, SpecialCharacterIndices};
+use std::os::fd::AsFd;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Raw,
+    Cooked,
+}
+
+/// Line discipline adjustments for the PTY.
+///
+/// Parsed from a comma-separated list such as `raw,-echo,ixon`, where a leading `-`
+/// turns a flag off. Anything not mentioned is left as the PTY currently has it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Settings {
+    pub mode: Option<Mode>,
+    pub echo: Option<bool>,
+    pub isig: Option<bool>,
+    pub ixon: Option<bool>,
+    pub ixoff: Option<bool>,
+}
+
+impl Settings {
+    pub fn is_empty(&self) -> bool {
+        *self == Settings::default()
+    }
+
+    pub fn apply<Fd: AsFd>(&self, fd: Fd) -> nix::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let mut attrs = termios::tcgetattr(fd.as_fd())?;
+
+        match self.mode {
+            Some(Mode::Raw) => termios::cfmakeraw(&mut attrs),
+
+            Some(Mode::Cooked) => {
+                attrs.local_flags |= LocalFlags::ICANON
+                    | LocalFlags::ECHO
+                    | LocalFlags::ECHOE
+                    | LocalFlags::ECHOK
+                    | LocalFlags::ISIG
+                    | LocalFlags::IEXTEN;
+                attrs.input_flags |= InputFlags::ICRNL | InputFlags::BRKINT;
+                attrs.output_flags |= OutputFlags::OPOST | OutputFlags::ONLCR;
+            }
+
+            None => (),
+        }
+
+        if let Some(on) = self.echo {
+            attrs.local_flags.set(LocalFlags::ECHO, on);
+        }
+
+        if let Some(on) = self.isig {
+            attrs.local_flags.set(LocalFlags::ISIG, on);
+        }
+
+        if let Some(on) = self.ixon {
+            attrs.input_flags.set(InputFlags::IXON, on);
+        }
+
+        if let Some(on) = self.ixoff {
+            attrs.input_flags.set(InputFlags::IXOFF, on);
+        }
+
+        termios::tcsetattr(fd.as_fd(), SetArg::TCSANOW, &attrs)
+    }
+}
+
+/// The character the line discipline currently treats as end-of-file (usually ^D).
+pub fn eof_char<Fd: AsFd>(fd: Fd) -> nix::Result<u8> {
+    let attrs = termios::tcgetattr(fd.as_fd())?;
+    Ok(attrs.control_chars[SpecialCharacterIndices::VEOF as usize])
+}
+
+impl FromStr for Settings {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = Settings::default();
+
+        for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (name, on) = match item.strip_prefix('-') {
+                Some(name) => (name, false),
+                None => (item, true),
+            };
+
+            match name {
+                "raw" if on => settings.mode = Some(Mode::Raw),
+                "cooked" if on => settings.mode = Some(Mode::Cooked),
+                "echo" => settings.echo = Some(on),
+                "isig" => settings.isig = Some(on),
+                "ixon" => settings.ixon = Some(on),
+                "ixoff" => settings.ixoff = Some(on),
+                _ => {
+                    return Err(format!(
+                        "invalid termios setting: {item}. Valid options: raw, cooked, [-]echo, [-]isig, [-]ixon, [-]ixoff"
+                    ))
+                }
+            }
+        }
+
+        Ok(settings)
+    }
+}
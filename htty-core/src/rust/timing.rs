@@ -0,0 +1,60 @@
+use anyhow::{bail, Result};
+use std::env;
+use std::time::Duration;
+
+/// Delays and polling intervals used while coordinating with the child, the PTY and clients.
+///
+/// Each one defaults to its compiled-in value (see `nix/lib/constants.nix`) and can be
+/// overridden at startup through the environment variable named after it, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    /// How often the PTY task looks for the FIFO created by the wait-exit helper.
+    pub fifo_monitoring_interval: Duration,
+    /// How long a client waits for the session to accept its subscription.
+    pub subscription_timeout: Duration,
+    /// How long a write to stdout or to a WebSocket client may take before its reader is
+    /// taken to have stopped reading. Minutes rather than seconds, so that a reader that's
+    /// merely slow (or paused in a debugger) doesn't end the session.
+    pub write_timeout: Duration,
+    /// How long the wait-exit helper waits to be released before giving up on ht.
+    pub wait_exit_timeout: Duration,
+}
+
+const MAX_MILLIS: u64 = 60 * 60 * 1000;
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            fifo_monitoring_interval: Duration::from_millis(50),
+            subscription_timeout: Duration::from_millis(5000),
+            write_timeout: Duration::from_millis(300_000),
+            wait_exit_timeout: Duration::from_millis(30000),
+        }
+    }
+}
+
+impl Timing {
+    pub fn from_env() -> Result<Self> {
+        let mut timing = Self::default();
+
+        for (var, value) in [
+            ("HTTY_FIFO_MONITORING_INTERVAL_MS", &mut timing.fifo_monitoring_interval),
+            ("HTTY_SUBSCRIPTION_TIMEOUT_MS", &mut timing.subscription_timeout),
+            ("HTTY_WRITE_TIMEOUT_MS", &mut timing.write_timeout),
+            ("HTTY_WAIT_EXIT_TIMEOUT_MS", &mut timing.wait_exit_timeout),
+        ] {
+            if let Ok(s) = env::var(var) {
+                *value = parse_millis(var, &s)?;
+            }
+        }
+
+        Ok(timing)
+    }
+}
+
+fn parse_millis(name: &str, s: &str) -> Result<Duration> {
+    match s.trim().parse() {
+        Ok(millis) if (1..=MAX_MILLIS).contains(&millis) => Ok(Duration::from_millis(millis)),
+        _ => bail!("invalid value for {name}: {s}. Expected a number of milliseconds between 1 and {MAX_MILLIS}"),
+    }
+}
@@ -0,0 +1,72 @@
+/// Incremental UTF-8 decoder for output that arrives in arbitrary chunks.
+///
+/// A multi-byte character split across two reads is held back until the rest of it
+/// arrives, instead of being turned into replacement characters on both sides of the
+/// split. Bytes that can never form valid UTF-8 still decode to U+FFFD.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    pending: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn decode(&mut self, data: &[u8]) -> String {
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(data);
+
+        let mut text = String::with_capacity(bytes.len());
+        let mut rest = bytes.as_slice();
+
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    break;
+                }
+
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &invalid[len..];
+                        }
+
+                        None => {
+                            // An incomplete sequence at the very end: wait for more input
+                            self.pending = invalid.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Decoder;
+
+    #[test]
+    fn split_sequence() {
+        let mut decoder = Decoder::default();
+        let bytes = "é€😀".as_bytes();
+
+        let text: String = bytes.iter().map(|b| decoder.decode(&[*b])).collect();
+
+        assert_eq!(text, "é€😀");
+    }
+
+    #[test]
+    fn invalid_bytes() {
+        let mut decoder = Decoder::default();
+
+        assert_eq!(decoder.decode(b"a\xffb\xe2\x82"), "a\u{fffd}b");
+        assert_eq!(decoder.decode(b"\xacc"), "€c");
+        assert_eq!(decoder.decode(b"\xe2x"), "\u{fffd}x");
+    }
+}
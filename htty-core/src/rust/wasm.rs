@@ -0,0 +1,219 @@
+//! Terminal emulation for JavaScript, from a wasm32 build with the `wasm` feature, so that
+//! recordings can be drawn in a browser by the same emulator that produced them.
+//!
+//! ```sh
+//! cargo build --lib --target wasm32-unknown-unknown --no-default-features --features wasm
+//! ```
+//!
+//! There's no PTY: output is fed in as bytes, and screens read back as text, ANSI or HTML.
+//! Strings cross the boundary as UTF-8 in the module's memory. Input is copied into a buffer
+//! from `htty_alloc`, and each call returning a string returns a pointer to the terminal's
+//! own copy, valid until its next call, whose length `htty_terminal_output_len` gives:
+//!
+//! ```js
+//! const { memory, htty_alloc, htty_dealloc, htty_terminal_new, htty_terminal_feed,
+//!         htty_terminal_html, htty_terminal_output_len } = instance.exports;
+//!
+//! const term = htty_terminal_new(80, 24);
+//! const bytes = new TextEncoder().encode("\x1b[1mhello\x1b[0m");
+//! const ptr = htty_alloc(bytes.length);
+//! new Uint8Array(memory.buffer, ptr, bytes.length).set(bytes);
+//! htty_terminal_feed(term, ptr, bytes.length);
+//! htty_dealloc(ptr, bytes.length);
+//!
+//! const html = htty_terminal_html(term, 0);
+//! const len = htty_terminal_output_len(term);
+//! document.body.innerHTML = new TextDecoder().decode(new Uint8Array(memory.buffer, html, len));
+//! ```
+//!
+//! Each terminal is a [`Session`] without a PTY, so screens read back here match the
+//! snapshots `ht` takes. Its events are timed by [`crate::clock`], which stands still unless
+//! `htty_clock_set` moves it on.
+
+#![allow(clippy::missing_safety_doc)]
+
+use crate::config::{HtmlTheme, Size};
+use crate::render;
+use crate::session::{Event, Session};
+use crate::snapshot;
+use crate::utf8;
+use std::sync::{Arc, Mutex};
+
+/// A terminal fed output by hand.
+pub struct Terminal {
+    session: Session,
+    decoder: utf8::Decoder,
+    // Where the session's listener leaves each snapshot, for `screen` to pick up
+    snapshot: Arc<Mutex<Option<Event>>>,
+    // The last string handed to JavaScript, kept alive until the next one
+    output: String,
+}
+
+/// A snapshot's size, the sequences which redraw it, and its text.
+struct Screen {
+    cols: usize,
+    rows: usize,
+    seq: String,
+    text: String,
+}
+
+impl Terminal {
+    /// A blank screen of `cols` by `rows`, brought within the sizes a terminal may have.
+    pub fn new(cols: usize, rows: usize) -> Self {
+        let size = Size::clamped(cols, rows);
+        let mut session = Session::new(size.cols(), size.rows());
+        let snapshot = Arc::new(Mutex::new(None));
+        let taken = snapshot.clone();
+
+        session.on_event(move |event| {
+            if let Event::Snapshot(..) = event {
+                *taken.lock().unwrap() = Some(event.clone());
+            }
+        });
+
+        Self { session, decoder: utf8::Decoder::default(), snapshot, output: String::new() }
+    }
+
+    /// Process output from a program, which may split UTF-8 sequences between calls.
+    pub fn feed(&mut self, data: &[u8]) {
+        let text = self.decoder.decode(data);
+        self.session.output(text);
+        self.session.flush_output();
+    }
+
+    /// Resize the screen, to within the sizes a terminal may have, as `new`.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let size = Size::clamped(cols, rows);
+        self.session.resize(size.cols(), size.rows());
+    }
+
+    /// The characters on the screen, one line per row.
+    pub fn text(&mut self) -> String {
+        self.screen().text
+    }
+
+    /// The screen with SGR sequences for its colors and attributes, as `ht snapshot --format ansi`.
+    pub fn ansi(&mut self) -> String {
+        let screen = self.screen();
+        render::ansi(&*snapshot::replay(screen.cols, screen.rows, &screen.seq))
+    }
+
+    /// The screen as a `<pre>` element, as `ht snapshot --format html`.
+    pub fn html(&mut self, theme: HtmlTheme) -> String {
+        let screen = self.screen();
+        render::html(screen.cols, screen.rows, &screen.seq, theme)
+    }
+
+    /// Escape sequences which, fed to a fresh terminal of the same size, redraw this one.
+    pub fn seq(&mut self) -> String {
+        self.screen().seq
+    }
+
+    fn screen(&mut self) -> Screen {
+        self.session.snapshot();
+
+        match self.snapshot.lock().unwrap().take() {
+            Some(Event::Snapshot(_, cols, rows, seq, text, _)) => Screen { cols, rows, seq, text },
+            _ => unreachable!("the session's listener is given every snapshot"),
+        }
+    }
+
+    fn hand_out(&mut self, output: String) -> *const u8 {
+        self.output = output;
+        self.output.as_ptr()
+    }
+}
+
+/// Move the clock that times every terminal's events on to `ms` milliseconds from its start.
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn htty_clock_set(ms: f64) {
+    crate::clock::set(std::time::Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+}
+
+/// `len` bytes for JavaScript to copy input into.
+#[no_mangle]
+pub extern "C" fn htty_alloc(len: usize) -> *mut u8 {
+    let mut buf = Vec::<u8>::with_capacity(len);
+    let ptr = buf.as_mut_ptr();
+    std::mem::forget(buf);
+    ptr
+}
+
+/// Free `len` bytes from `htty_alloc`.
+#[no_mangle]
+pub unsafe extern "C" fn htty_dealloc(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+#[no_mangle]
+pub extern "C" fn htty_terminal_new(cols: u32, rows: u32) -> *mut Terminal {
+    Box::into_raw(Box::new(Terminal::new(cols as usize, rows as usize)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn htty_terminal_free(terminal: *mut Terminal) {
+    drop(Box::from_raw(terminal));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn htty_terminal_feed(terminal: *mut Terminal, ptr: *const u8, len: usize) {
+    let terminal = &mut *terminal;
+    terminal.feed(std::slice::from_raw_parts(ptr, len));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn htty_terminal_resize(terminal: *mut Terminal, cols: u32, rows: u32) {
+    let terminal = &mut *terminal;
+    terminal.resize(cols as usize, rows as usize);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn htty_terminal_text(terminal: *mut Terminal) -> *const u8 {
+    let terminal = &mut *terminal;
+    let output = terminal.text();
+    terminal.hand_out(output)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn htty_terminal_ansi(terminal: *mut Terminal) -> *const u8 {
+    let terminal = &mut *terminal;
+    let output = terminal.ansi();
+    terminal.hand_out(output)
+}
+
+/// The screen as HTML, with the dark theme, or the light one if `light` isn't 0.
+#[no_mangle]
+pub unsafe extern "C" fn htty_terminal_html(terminal: *mut Terminal, light: u32) -> *const u8 {
+    let theme = if light == 0 { HtmlTheme::Dark } else { HtmlTheme::Light };
+    let terminal = &mut *terminal;
+    let output = terminal.html(theme);
+    terminal.hand_out(output)
+}
+
+/// The length in bytes of the string the terminal last returned.
+#[no_mangle]
+pub unsafe extern "C" fn htty_terminal_output_len(terminal: *const Terminal) -> usize {
+    let terminal = &*terminal;
+    terminal.output.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::Terminal;
+    use crate::config::HtmlTheme;
+
+    #[test]
+    fn feed() {
+        let mut terminal = Terminal::new(6, 2);
+        let bytes = "\x1b[1mhé\x1b[0m!".as_bytes();
+        let (first, rest) = bytes.split_at(6);
+
+        terminal.feed(first);
+        terminal.feed(rest);
+
+        assert_eq!(terminal.text(), "hé!   \n      ");
+        assert_eq!(terminal.ansi(), "\x1b[0;1mhé\x1b[0m!\n");
+        assert!(terminal.html(HtmlTheme::Dark).contains("<span style=\"font-weight: bold\">hé</span>!"));
+    }
+}
@@ -71,6 +71,12 @@ assert cleared.text == '\\n'.join([
 ```
 It's a good idea to `expect` something before you take a snapshot, otherwise the snapshot might happen before the child process has fully arrived at the state you're trying to capture.
 
+From async code, use `htty.asyncio.run` instead. It returns the same kind of object, but its methods are coroutines, and `events()` lets you follow along with what ht sends.
+
+In pytest tests, the `ht_session` fixture (see `htty.pytest_plugin`) starts sessions that are cleaned up when the test ends, and saves their screens and recordings when it fails. To save starting a fresh `ht` for each test, take sessions from a `SessionPool` (see `htty.pool`) instead.
+
+When something goes wrong htty raises an `HttyError` (see `htty.errors`): `HttyProcessError` if the command exited while htty was waiting on it, `HttyTimeoutError` if it's still running but didn't get there in time, and `HttyProtocolError` if ht itself went wrong.
+
 # Command Line Usage
 
 Unlike the `htty` python library, the `htty` command accepts all of its instructions before it starts.
@@ -135,8 +141,40 @@ ___/~\\___/          |_D__D__D_|  |_D__D__D_|
 Warning: if you don't include an `--expect`, it's likely that your first snapshot will be empty because it happens before the command can get around to producing any output.
 """
 
+import htty.errors as errors
+import htty.events as events
 import htty.keys as keys
+from htty.errors import HttyError, HttyProcessError, HttyProtocolError, HttyTimeoutError
+from htty.events import (
+    ActiveEvent,
+    BacklogEvent,
+    CommandCompletedEvent,
+    ContinuedEvent,
+    DebugEvent,
+    DroppedEvent,
+    ErrorEvent,
+    Event,
+    ExitCodeEvent,
+    FlushedEvent,
+    IdleEvent,
+    IdleTimeoutEvent,
+    InitEvent,
+    InputDiscardedEvent,
+    InputWrittenEvent,
+    OutputEvent,
+    PidEvent,
+    RawOutputEvent,
+    ResizeEvent,
+    RespawnEvent,
+    SnapshotEvent,
+    StateEvent,
+    StderrEvent,
+    StoppedEvent,
+    UnknownEvent,
+)
 from htty.ht import (
+    EventStream,
+    ExpectResult,
     HtWrapper,
     ProcessController,
     SnapshotResult,
@@ -144,7 +182,8 @@ from htty.ht import (
     terminal_session,
 )
 from htty.keys import Press
-from htty_core import StyleMode
+from htty.pool import SessionPool
+from htty_core import HtmlTheme, StyleMode
 
 # [[[cog
 # import os
@@ -158,9 +197,44 @@ __all__ = [
     "run",
     "HtWrapper",
     "ProcessController",
+    "SessionPool",
     "SnapshotResult",
+    "ExpectResult",
+    "EventStream",
+    "Event",
+    "InitEvent",
+    "OutputEvent",
+    "RawOutputEvent",
+    "StderrEvent",
+    "ResizeEvent",
+    "SnapshotEvent",
+    "InputWrittenEvent",
+    "InputDiscardedEvent",
+    "FlushedEvent",
+    "PidEvent",
+    "ExitCodeEvent",
+    "DebugEvent",
+    "ErrorEvent",
+    "CommandCompletedEvent",
+    "RespawnEvent",
+    "StoppedEvent",
+    "ContinuedEvent",
+    "IdleEvent",
+    "ActiveEvent",
+    "IdleTimeoutEvent",
+    "StateEvent",
+    "DroppedEvent",
+    "BacklogEvent",
+    "UnknownEvent",
+    "HttyError",
+    "HttyTimeoutError",
+    "HttyProcessError",
+    "HttyProtocolError",
     "StyleMode",
+    "HtmlTheme",
     "Press",
     "keys",
+    "events",
+    "errors",
     "__version__",
 ]
@@ -0,0 +1,552 @@
+"""
+An asyncio counterpart to `htty.run`, for async test frameworks and for driving several terminals at once.
+
+```python
+import asyncio
+
+import htty.asyncio
+from htty import OutputEvent, Press
+
+
+async def main():
+    async with await htty.asyncio.run("vim", rows=20, cols=50) as vim:
+        await vim.send_keys(["ihello", Press.ESCAPE])
+        await vim.expect("hello")
+
+        async with vim.events() as events:
+            await vim.send_keys(":q!")
+            async for event in events:
+                if isinstance(event, OutputEvent):
+                    break
+
+        snapshot = await vim.snapshot()
+
+
+asyncio.run(main())
+```
+
+Nothing here blocks the event loop: events are read from ht by a task rather than a thread, and waiting on them
+doesn't sleep in between checks.
+"""
+
+import asyncio
+import json
+import logging
+import os
+import re
+import signal
+from collections import deque
+from contextlib import suppress
+from typing import Any, Optional, Union
+
+from htty_core import Cols, Command, HtArgs, HtEvent, HtmlTheme, Rows, StyleMode
+
+from .constants import (
+    DEFAULT_EXIT_TIMEOUT,
+    DEFAULT_EXPECT_TIMEOUT,
+    DEFAULT_SLEEP_AFTER_KEYS,
+    DEFAULT_SNAPSHOT_TIMEOUT,
+    DEFAULT_SUBPROCESS_WAIT_TIMEOUT,
+)
+from .errors import HttyError, HttyProcessError, HttyProtocolError, HttyTimeoutError
+from .events import (
+    ErrorEvent,
+    Event,
+    ExitCodeEvent,
+    FlushedEvent,
+    InputDiscardedEvent,
+    InputWrittenEvent,
+    OutputEvent,
+    ResizeEvent,
+    UnknownEvent,
+    parse_event,
+)
+from .ht import (
+    Cwd,
+    Env,
+    ExpectResult,
+    ExtraEnv,
+    ExtraSubscribes,
+    Logger,
+    NoExit,
+    SnapshotResult,
+    check_size,
+    command_env,
+)
+from .html_utils import simple_ansi_to_html
+from .keys import KeyInput, keys_to_strings
+
+__all__ = ["AsyncHtWrapper", "AsyncEventStream", "run"]
+
+default_logger = logging.getLogger(__name__)
+
+# Snapshots of large terminals make for long lines
+STREAM_LIMIT = 16 * 1024 * 1024
+
+
+class AsyncEventStream:
+    """
+    The events ht sends, as they arrive. Returned by `AsyncHtWrapper.events`.
+    """
+
+    def __init__(self, subscribers: list[asyncio.Queue[Optional[Event]]], closed: bool) -> None:
+        """
+        @private
+        """
+        self._subscribers = subscribers
+        self._events: asyncio.Queue[Optional[Event]] = asyncio.Queue()
+        self._subscribers.append(self._events)
+
+        if closed:
+            self._events.put_nowait(None)
+
+    def __aiter__(self) -> "AsyncEventStream":
+        return self
+
+    async def __anext__(self) -> Event:
+        event = await self._events.get()
+
+        if event is None:
+            self.close()
+            raise StopAsyncIteration
+
+        return event
+
+    async def __aenter__(self) -> "AsyncEventStream":
+        return self
+
+    async def __aexit__(self, *exc_info: object) -> None:
+        self.close()
+
+    def close(self) -> None:
+        """Stop collecting events."""
+        if self._events in self._subscribers:
+            self._subscribers.remove(self._events)
+
+
+class AsyncHtWrapper:
+    """
+    A running `ht` process, driven with coroutines. Create one with `htty.asyncio.run`.
+
+    Use it with `async with` to make sure the command and ht are terminated once you're done.
+    """
+
+    def __init__(self, ht_proc: asyncio.subprocess.Process, logger: Optional[logging.Logger] = None) -> None:
+        """
+        @private
+        Users are not expected to create these directly, they should use `htty.asyncio.run(...)`
+        """
+        self._ht_proc = ht_proc
+        self._logger = logger or default_logger
+        self._snapshots: deque[asyncio.Future[SnapshotResult]] = deque()
+        self._subscribers: list[asyncio.Queue[Optional[Event]]] = []
+        # The init and pid ones are also set if ht goes away first, so that startup doesn't wait for them in vain
+        self._init_received = asyncio.Event()
+        self._pid_received = asyncio.Event()
+        self._exit_received = asyncio.Event()
+        self._latest_snapshot: Optional[SnapshotResult] = None
+        self.pid: Optional[int] = None
+        """PID of the shell that wraps the command (`ht`'s child process)"""
+        self.exit_code: Optional[int] = None
+        """Exit code of the command, once it has exited"""
+
+        self._readers = [
+            asyncio.create_task(self._read_events()),
+            asyncio.create_task(self._read_stderr()),
+        ]
+
+    async def __aenter__(self) -> "AsyncHtWrapper":
+        return self
+
+    async def __aexit__(self, *exc_info: object) -> None:
+        await self.exit()
+
+    @property
+    def ht_pid(self) -> int:
+        """PID of the ht process"""
+        return self._ht_proc.pid
+
+    async def send_keys(
+        self, keys: Union[KeyInput, list[KeyInput]], wait: bool = True, timeout: float = DEFAULT_SNAPSHOT_TIMEOUT
+    ) -> None:
+        """
+        Send keys to the terminal, like `HtWrapper.send_keys`.
+
+        Raises:
+            HttyProtocolError: If ht has exited, the command has and the keys were discarded, or ht
+                doesn't report the keys as written within the timeout
+        """
+        if not wait:
+            await self._send({"type": "sendKeys", "keys": keys_to_strings(keys)})
+            await asyncio.sleep(DEFAULT_SLEEP_AFTER_KEYS)
+            return
+
+        async def written() -> bool:
+            async for event in events:
+                if isinstance(event, InputWrittenEvent) and event.pane is None:
+                    return True
+                if isinstance(event, InputDiscardedEvent) and event.pane is None:
+                    raise HttyProtocolError(f"the command has exited, so {event.bytes} bytes of keys were discarded")
+
+            return False
+
+        # Subscribed before sending, so the acknowledgement can't be missed
+        async with self.events() as events:
+            await self._send({"type": "sendKeys", "keys": keys_to_strings(keys), "ack": True})
+
+            try:
+                acknowledged = await asyncio.wait_for(written(), timeout)
+            except asyncio.TimeoutError:
+                acknowledged = False
+
+        if not acknowledged:
+            self._check_running()
+            raise HttyProtocolError(f"ht didn't report the keys as written within {timeout} seconds")
+
+    async def resize(
+        self, cols: int, rows: int, snapshot: bool = False, timeout: float = DEFAULT_SNAPSHOT_TIMEOUT
+    ) -> Optional[SnapshotResult]:
+        """
+        Resize the terminal, like `HtWrapper.resize`.
+        """
+        check_size(cols, rows)
+
+        self._check_running()
+
+        async def resized() -> bool:
+            async for event in events:
+                if isinstance(event, ResizeEvent) and event.pane is None and (event.cols, event.rows) == (cols, rows):
+                    return True
+                if isinstance(event, ErrorEvent) and event.pane is None and event.line is None:
+                    raise HttyProtocolError(f"ht refused to resize the terminal: {event.message}")
+
+            return False
+
+        # Subscribed before sending, so the resize event can't be missed
+        async with self.events() as events:
+            await self._send({"type": "resize", "cols": cols, "rows": rows})
+
+            try:
+                reported = await asyncio.wait_for(resized(), timeout)
+            except asyncio.TimeoutError:
+                reported = False
+
+        if not reported:
+            self._check_running()
+            raise HttyProtocolError(
+                f"ht didn't report the terminal being resized to {cols}x{rows} within {timeout} seconds"
+            )
+
+        return await self.snapshot() if snapshot else None
+
+    async def flush(self, timeout: float = DEFAULT_SNAPSHOT_TIMEOUT) -> None:
+        """
+        Return once ht has seen to every command sent before, like `HtWrapper.flush`.
+        """
+        self._check_running()
+
+        async def flushed() -> bool:
+            async for event in events:
+                if isinstance(event, FlushedEvent):
+                    return True
+
+            return False
+
+        # Subscribed before sending, so the flushed event can't be missed
+        async with self.events() as events:
+            await self._send({"type": "flush"})
+
+            try:
+                reported = await asyncio.wait_for(flushed(), timeout)
+            except asyncio.TimeoutError:
+                reported = False
+
+        if not reported:
+            self._check_running()
+            raise HttyProtocolError(f"ht didn't report the flush within {timeout} seconds")
+
+    async def snapshot(
+        self, timeout: float = DEFAULT_SNAPSHOT_TIMEOUT, html_theme: HtmlTheme = HtmlTheme.DARK
+    ) -> SnapshotResult:
+        """
+        Take a snapshot of the terminal, like `HtWrapper.snapshot`.
+
+        Raises:
+            HttyProcessError: If ht has exited because the command did
+            HttyProtocolError: If ht has exited otherwise, or doesn't answer within the timeout
+        """
+        self._check_running()
+
+        future: asyncio.Future[SnapshotResult] = asyncio.get_running_loop().create_future()
+        self._snapshots.append(future)
+        await self._send({"type": "takeSnapshot", "htmlTheme": str(html_theme)})
+
+        try:
+            self._latest_snapshot = await asyncio.wait_for(future, timeout)
+        except asyncio.TimeoutError as e:
+            raise HttyProtocolError(f"Failed to receive snapshot event within {timeout} seconds") from e
+
+        return self._latest_snapshot
+
+    async def expect(self, pattern: str, timeout: float = DEFAULT_EXPECT_TIMEOUT) -> ExpectResult:
+        """
+        Wait for a regex pattern to appear on the terminal's screen, like `HtWrapper.expect`.
+
+        Raises:
+            HttyTimeoutError: If the pattern doesn't appear within the timeout period
+            HttyProcessError: If the command exits without the pattern having appeared
+            HttyProtocolError: If ht has exited or stops answering
+        """
+        try:
+            regex = re.compile(pattern, re.MULTILINE)
+        except re.error as e:
+            raise ValueError(f"Invalid regex pattern '{pattern}': {e}") from e
+
+        async def search() -> ExpectResult:
+            # Subscribe before the first look, so output arriving in between isn't missed
+            async with self.events() as events:
+                while True:
+                    # Checked before the snapshot, so that it's known to show everything the command wrote
+                    finished = self._exit_received.is_set()
+                    snapshot = await self.snapshot()
+                    match = regex.search(snapshot.text)
+                    if match:
+                        return ExpectResult(match, snapshot)
+
+                    if finished:
+                        raise HttyProcessError(
+                            f"Pattern '{pattern}' not found before the command exited with code {self.exit_code}",
+                            exit_code=self.exit_code,
+                            snapshot=snapshot,
+                        )
+
+                    async for event in events:
+                        if isinstance(event, (OutputEvent, ExitCodeEvent)):
+                            break
+
+        try:
+            return await asyncio.wait_for(search(), timeout)
+        except asyncio.TimeoutError as e:
+            raise HttyTimeoutError(f"Pattern '{pattern}' not found within {timeout} seconds") from e
+
+    def events(self) -> "AsyncEventStream":
+        """
+        Iterate over the events ht sends from now on, as typed objects (see `htty.events`), until it exits.
+
+        Events are collected from the moment this is called, not from when iteration starts. Each stream sees every
+        event, so several can be used at once. Close a stream you're done with, or use it with `async with`.
+        """
+        return AsyncEventStream(self._subscribers, closed=self._readers[0].done())
+
+    async def wait_for_startup(self, timeout: float) -> None:
+        """
+        @private
+        Wait until ht has sent the init event and the command's PID, like `HtWrapper.wait_for_startup`.
+        """
+
+        async def started() -> None:
+            await self._init_received.wait()
+            await self._pid_received.wait()
+
+        with suppress(asyncio.TimeoutError):
+            await asyncio.wait_for(started(), timeout)
+
+        if self._pid_received.is_set() and self.pid is not None:
+            return
+
+        if self._readers[0].done():
+            with suppress(asyncio.TimeoutError):
+                await asyncio.wait_for(self._ht_proc.wait(), DEFAULT_SUBPROCESS_WAIT_TIMEOUT)
+            raise HttyProtocolError(
+                f"ht process exited with code {self._ht_proc.returncode} before starting the command"
+            )
+
+        raise HttyProtocolError(
+            f"ht process {self.ht_pid} did not report starting the command within {timeout} seconds"
+        )
+
+    async def wait(self, timeout: Optional[float] = None) -> Optional[int]:
+        """
+        Wait for the command to exit, returning its exit code, or None if it's still running after the timeout.
+        """
+        with suppress(asyncio.TimeoutError):
+            await asyncio.wait_for(self._exit_received.wait(), timeout)
+
+        return self.exit_code
+
+    async def exit(self, timeout: float = DEFAULT_EXIT_TIMEOUT) -> int:
+        """
+        Shut ht down, returning its exit code.
+
+        If the command has already exited ht is asked to exit, otherwise the command and ht are terminated.
+        """
+        if self._ht_proc.returncode is None:
+            if self._exit_received.is_set():
+                with suppress(BrokenPipeError, ConnectionResetError):
+                    await self._send({"type": "exit"})
+            else:
+                if self.pid is not None:
+                    with suppress(OSError):
+                        os.kill(self.pid, signal.SIGTERM)
+                    await self.wait(DEFAULT_SUBPROCESS_WAIT_TIMEOUT)
+
+                with suppress(ProcessLookupError):
+                    self._ht_proc.terminate()
+
+            try:
+                await asyncio.wait_for(self._ht_proc.wait(), timeout)
+            except asyncio.TimeoutError:
+                self._logger.warning(f"ht process {self.ht_pid} did not exit within {timeout} seconds, killing it")
+                with suppress(ProcessLookupError):
+                    self._ht_proc.kill()
+                await self._ht_proc.wait()
+
+        await asyncio.gather(*self._readers, return_exceptions=True)
+        return self._ht_proc.returncode if self._ht_proc.returncode is not None else -1
+
+    def _check_running(self) -> None:
+        if (returncode := self._ht_proc.returncode) is None:
+            return
+
+        if self._exit_received.is_set():
+            raise HttyProcessError(
+                f"ht process has exited with code {returncode}, after the command exited with code {self.exit_code}",
+                exit_code=self.exit_code,
+                snapshot=self._latest_snapshot,
+            )
+
+        raise HttyProtocolError(f"ht process has exited with code {returncode}")
+
+    async def _send(self, message: dict[str, Any]) -> None:
+        if self._ht_proc.stdin is None:
+            raise HttyProtocolError("ht process stdin is not available")
+
+        self._logger.debug(f"Sending: {message}")
+        self._ht_proc.stdin.write((json.dumps(message) + "\n").encode())
+        await self._ht_proc.stdin.drain()
+
+    async def _read_events(self) -> None:
+        assert self._ht_proc.stdout is not None
+
+        while line := await self._ht_proc.stdout.readline():
+            try:
+                event = json.loads(line)
+            except json.JSONDecodeError as e:
+                self._logger.warning(f"ht process {self.ht_pid} non-JSON stdout: {line!r} (error: {e})")
+                continue
+
+            self._logger.debug(f"ht event: {event}")
+            data = event.get("data", {})
+
+            if event["type"] == "snapshot":
+                while self._snapshots:
+                    future = self._snapshots.popleft()
+                    if not future.done():
+                        raw_seq = data["seq"]
+                        html = data.get("html") or simple_ansi_to_html(raw_seq)
+                        future.set_result(SnapshotResult(data["text"], html, raw_seq))
+                        break
+            elif event["type"] == "init" and "pane" not in data:
+                # The command may have been started before we subscribed, but init has its PID too
+                if data.get("pid"):
+                    self.pid = data["pid"]
+                    self._pid_received.set()
+                self._init_received.set()
+            elif event["type"] == "pid" and "pane" not in data:
+                self.pid = data.get("pid")
+                self._pid_received.set()
+            elif event["type"] == "exitCode" and "pane" not in data:
+                self.exit_code = data.get("exitCode")
+                self._exit_received.set()
+            elif event["type"] == "respawn" and "pane" not in data:
+                # The command is running again, until its next exitCode event
+                self.exit_code = None
+                self._exit_received.clear()
+
+            if self._subscribers:
+                try:
+                    typed = parse_event(event)
+                except KeyError as e:
+                    self._logger.warning(f"ht event is missing {e}: {event}")
+                    typed = UnknownEvent(type=event.get("type", ""), data=data, raw=event)
+
+                for subscriber in self._subscribers:
+                    subscriber.put_nowait(typed)
+
+        self._logger.debug(f"ht process {self.ht_pid} stdout closed")
+        self._init_received.set()
+        self._pid_received.set()
+
+        for future in self._snapshots:
+            if not future.done():
+                future.set_exception(HttyProtocolError("ht process exited before sending the snapshot"))
+
+        for subscriber in self._subscribers:
+            subscriber.put_nowait(None)
+
+    async def _read_stderr(self) -> None:
+        assert self._ht_proc.stderr is not None
+
+        while line := await self._ht_proc.stderr.readline():
+            self._logger.debug(f"ht stderr: {line.decode(errors='replace').rstrip()}")
+
+
+async def run(
+    command: Command,
+    rows: Rows = None,
+    cols: Cols = None,
+    no_exit: NoExit = True,
+    logger: Logger = None,
+    extra_subscribes: ExtraSubscribes = None,
+    style_mode: Optional[StyleMode] = None,
+    env: Env = None,
+    extra_env: ExtraEnv = None,
+    cwd: Cwd = None,
+) -> AsyncHtWrapper:
+    """
+    Start `command` under ht, like `htty.run`, and return once ht has reported the command's PID.
+
+    Raises:
+        HttyProtocolError: If ht exits or doesn't report starting the command in time
+    """
+    subscribes = [
+        HtEvent.INIT,
+        HtEvent.SNAPSHOT,
+        HtEvent.OUTPUT,
+        HtEvent.RESIZE,
+        HtEvent.PID,
+        HtEvent.EXIT_CODE,
+        HtEvent.COMMAND_COMPLETED,
+        *(extra_subscribes or []),
+    ]
+
+    ht_args = HtArgs(
+        command=command,
+        subscribes=subscribes,
+        rows=rows,
+        cols=cols,
+        style_mode=style_mode,
+        no_exit=no_exit,
+        env=command_env(env, extra_env),
+        cwd=cwd,
+    )
+
+    ht_proc = await asyncio.create_subprocess_exec(
+        *ht_args.get_command(),
+        stdin=asyncio.subprocess.PIPE,
+        stdout=asyncio.subprocess.PIPE,
+        stderr=asyncio.subprocess.PIPE,
+        limit=STREAM_LIMIT,
+        env=ht_args.env,
+        cwd=ht_args.cwd,
+    )
+
+    wrapper = AsyncHtWrapper(ht_proc, logger)
+
+    try:
+        await wrapper.wait_for_startup(timeout=DEFAULT_SNAPSHOT_TIMEOUT)
+    except HttyError:
+        await wrapper.exit()
+        raise
+
+    return wrapper
@@ -1,6 +1,8 @@
 # Auto-generated constants from nix/lib/constants.nix
 # DO NOT EDIT THE GENERATED SECTIONS MANUALLY
 
+import os
+
 # [[[cog
 # import os
 # # Terminal configuration
@@ -15,10 +17,10 @@
 # default_exit_timeout = int(os.environ['HTTY_DEFAULT_EXIT_TIMEOUT_MS']) / 1000.0
 # default_graceful_termination_timeout = int(os.environ['HTTY_DEFAULT_GRACEFUL_TERMINATION_TIMEOUT_MS']) / 1000.0
 # default_expect_timeout = int(os.environ['HTTY_DEFAULT_EXPECT_TIMEOUT_MS']) / 1000.0
-# snapshot_retry_timeout = int(os.environ['HTTY_SNAPSHOT_RETRY_TIMEOUT_MS']) / 1000.0
 #
 # # Retry counts and thresholds
 # max_snapshot_retries = int(os.environ['HTTY_MAX_SNAPSHOT_RETRIES'])
+# max_events = int(os.environ['HTTY_MAX_EVENTS'])
 # ]]]
 # [[[end]]]
 
@@ -38,7 +40,6 @@ DEFAULT_TERMINAL_ROWS = 30
 # cog.outl(f"DEFAULT_SNAPSHOT_TIMEOUT = {default_snapshot_timeout}")
 # cog.outl(f"DEFAULT_EXIT_TIMEOUT = {default_exit_timeout}")
 # cog.outl(f"DEFAULT_GRACEFUL_TERMINATION_TIMEOUT = {default_graceful_termination_timeout}")
-# cog.outl(f"SNAPSHOT_RETRY_TIMEOUT = {snapshot_retry_timeout}")
 # cog.outl(f"SUBPROCESS_EXIT_DETECTION_DELAY = {subprocess_exit_detection_delay}")
 # cog.outl(f"DEFAULT_EXPECT_TIMEOUT = {default_expect_timeout}")
 # ]]]
@@ -47,7 +48,6 @@ DEFAULT_SUBPROCESS_WAIT_TIMEOUT = 2.0
 DEFAULT_SNAPSHOT_TIMEOUT = 5.0
 DEFAULT_EXIT_TIMEOUT = 5.0
 DEFAULT_GRACEFUL_TERMINATION_TIMEOUT = 5.0
-SNAPSHOT_RETRY_TIMEOUT = 0.1
 SUBPROCESS_EXIT_DETECTION_DELAY = 0.2
 DEFAULT_EXPECT_TIMEOUT = 5.0
 # [[[end]]]
@@ -55,20 +55,59 @@ DEFAULT_EXPECT_TIMEOUT = 5.0
 # Retry counts and thresholds
 # [[[cog
 # cog.outl(f"MAX_SNAPSHOT_RETRIES = {max_snapshot_retries}")
+# cog.outl(f"DEFAULT_MAX_EVENTS = {max_events}")
 # ]]]
 MAX_SNAPSHOT_RETRIES = 10
+DEFAULT_MAX_EVENTS = 10000
 # [[[end]]]
 
+# The fewest and most columns or rows ht accepts for a terminal
+MIN_TERMINAL_SIDE = 2
+MAX_TERMINAL_SIDE = 10000
+
+_MAX_MILLIS = 60 * 60 * 1000
+
+
+def _seconds_from_env(name: str, default: float) -> float:
+    """Read an `HTTY_<NAME>_MS` override for a timing constant, falling back to the compiled-in value."""
+    var = f"HTTY_{name}_MS"
+    value = os.environ.get(var)
+    if value is None:
+        return default
+    try:
+        millis = int(value.strip())
+    except ValueError:
+        millis = 0
+    if not 1 <= millis <= _MAX_MILLIS:
+        raise ValueError(
+            f"invalid value for {var}: {value}. Expected a number of milliseconds between 1 and {_MAX_MILLIS}"
+        )
+    return millis / 1000.0
+
+
+# Timing constants may be overridden at startup, e.g. HTTY_DEFAULT_EXPECT_TIMEOUT_MS=30000
+DEFAULT_SLEEP_AFTER_KEYS = _seconds_from_env("DEFAULT_SLEEP_AFTER_KEYS", DEFAULT_SLEEP_AFTER_KEYS)
+DEFAULT_SUBPROCESS_WAIT_TIMEOUT = _seconds_from_env("DEFAULT_SUBPROCESS_WAIT_TIMEOUT", DEFAULT_SUBPROCESS_WAIT_TIMEOUT)
+DEFAULT_SNAPSHOT_TIMEOUT = _seconds_from_env("DEFAULT_SNAPSHOT_TIMEOUT", DEFAULT_SNAPSHOT_TIMEOUT)
+DEFAULT_EXIT_TIMEOUT = _seconds_from_env("DEFAULT_EXIT_TIMEOUT", DEFAULT_EXIT_TIMEOUT)
+DEFAULT_GRACEFUL_TERMINATION_TIMEOUT = _seconds_from_env(
+    "DEFAULT_GRACEFUL_TERMINATION_TIMEOUT", DEFAULT_GRACEFUL_TERMINATION_TIMEOUT
+)
+SUBPROCESS_EXIT_DETECTION_DELAY = _seconds_from_env("SUBPROCESS_EXIT_DETECTION_DELAY", SUBPROCESS_EXIT_DETECTION_DELAY)
+DEFAULT_EXPECT_TIMEOUT = _seconds_from_env("DEFAULT_EXPECT_TIMEOUT", DEFAULT_EXPECT_TIMEOUT)
+
 __all__ = [
     "DEFAULT_TERMINAL_COLS",
     "DEFAULT_TERMINAL_ROWS",
+    "MIN_TERMINAL_SIDE",
+    "MAX_TERMINAL_SIDE",
     "DEFAULT_SLEEP_AFTER_KEYS",
     "DEFAULT_SUBPROCESS_WAIT_TIMEOUT",
     "DEFAULT_SNAPSHOT_TIMEOUT",
     "DEFAULT_EXIT_TIMEOUT",
     "DEFAULT_GRACEFUL_TERMINATION_TIMEOUT",
-    "SNAPSHOT_RETRY_TIMEOUT",
     "SUBPROCESS_EXIT_DETECTION_DELAY",
     "DEFAULT_EXPECT_TIMEOUT",
     "MAX_SNAPSHOT_RETRIES",
+    "DEFAULT_MAX_EVENTS",
 ]
@@ -0,0 +1,59 @@
+"""
+The exceptions htty raises, so that a command which crashed can be told apart from htty (or ht) misbehaving.
+
+```python
+from htty import HttyProcessError, HttyTimeoutError, terminal_session
+
+with terminal_session("./my-app", rows=20, cols=80) as proc:
+    try:
+        proc.expect("ready")
+    except HttyProcessError as e:
+        print(f"my-app exited with code {e.exit_code}, leaving this on the screen:")
+        print(e.snapshot.text if e.snapshot else "(nothing)")
+    except HttyTimeoutError:
+        print("my-app is still running, but it never got ready")
+```
+
+Each of them also derives from the builtin exception htty used to raise in its place (`TimeoutError` or
+`RuntimeError`), so code that catches those keeps working.
+"""
+
+from typing import TYPE_CHECKING, Optional
+
+if TYPE_CHECKING:
+    from .ht import SnapshotResult
+
+__all__ = [
+    "HttyError",
+    "HttyTimeoutError",
+    "HttyProcessError",
+    "HttyProtocolError",
+]
+
+
+class HttyError(Exception):
+    """Base class of the exceptions htty raises."""
+
+
+class HttyTimeoutError(HttyError, TimeoutError):
+    """Something didn't happen in time, while the command was still running."""
+
+
+class HttyProcessError(HttyError, RuntimeError):
+    """The command exited while htty was waiting on it, so what it was waiting for can't happen anymore."""
+
+    def __init__(
+        self,
+        message: str,
+        exit_code: Optional[int] = None,
+        snapshot: Optional["SnapshotResult"] = None,
+    ) -> None:
+        super().__init__(message)
+        self.exit_code = exit_code
+        """The command's exit code, if ht reported one"""
+        self.snapshot = snapshot
+        """The last snapshot of the terminal, if one could be taken"""
+
+
+class HttyProtocolError(HttyError, RuntimeError):
+    """ht went away, stopped answering, or sent something htty didn't expect."""
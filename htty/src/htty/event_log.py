@@ -0,0 +1,60 @@
+import threading
+from collections import deque
+from typing import Any, Optional
+
+from .constants import DEFAULT_MAX_EVENTS
+
+
+class EventLog:
+    """
+    A bounded record of the events received from `ht`.
+
+    Once `max_events` events have been recorded, each new event pushes out the oldest one, so a long-running
+    session doesn't grow without limit. Events are also indexed by type, which keeps `latest` and `of_type` from
+    having to scan the whole log. Pass `max_events=None` to keep everything.
+    """
+
+    def __init__(self, max_events: Optional[int] = DEFAULT_MAX_EVENTS) -> None:
+        if max_events is not None and max_events < 1:
+            raise ValueError(f"max_events must be at least 1, got {max_events}")
+
+        self.max_events = max_events
+        self.total = 0
+        """How many events have been recorded, including any that have since been discarded."""
+
+        self._events: deque[dict[str, Any]] = deque()
+        self._by_type: dict[str, deque[dict[str, Any]]] = {}
+        self._lock = threading.Lock()  # appended to by the reader thread, read by the caller's thread
+
+    def append(self, event: dict[str, Any]) -> None:
+        with self._lock:
+            if self.max_events is not None and len(self._events) >= self.max_events:
+                # The oldest event overall is also the oldest of its type
+                oldest = self._events.popleft()
+                of_type = self._by_type[oldest.get("type", "")]
+                of_type.popleft()
+                if not of_type:
+                    del self._by_type[oldest.get("type", "")]
+
+            self._events.append(event)
+            self._by_type.setdefault(event.get("type", ""), deque()).append(event)
+            self.total += 1
+
+    def latest(self, event_type: str) -> Optional[dict[str, Any]]:
+        """The most recent event of the given type that's still in the log, if any."""
+        with self._lock:
+            of_type = self._by_type.get(event_type)
+            return of_type[-1] if of_type else None
+
+    def of_type(self, event_type: str) -> list[dict[str, Any]]:
+        """All events of the given type that are still in the log, oldest first."""
+        with self._lock:
+            return list(self._by_type.get(event_type, ()))
+
+    def all(self) -> list[dict[str, Any]]:
+        """Every event still in the log, oldest first."""
+        with self._lock:
+            return list(self._events)
+
+    def __len__(self) -> int:
+        return len(self._events)
@@ -0,0 +1,327 @@
+"""
+Typed forms of the JSON events `ht` writes to its stdout, one class per event type.
+
+See `htty_core.HtEvent` for what each event means. Every event keeps the JSON it was parsed from in `raw`, and
+events from an additional pane (see `ht --pane`) carry the pane's id in `pane`.
+
+```python
+from htty import ExitCodeEvent, OutputEvent, terminal_session
+
+with terminal_session("make test") as proc, proc.events(timeout=60) as events:
+    for event in events:
+        if isinstance(event, OutputEvent):
+            print(event.seq, end="")
+        elif isinstance(event, ExitCodeEvent):
+            assert event.exit_code == 0
+            break
+```
+"""
+
+import base64
+from dataclasses import dataclass, field
+from typing import Any, Optional
+
+__all__ = [
+    "Event",
+    "InitEvent",
+    "OutputEvent",
+    "RawOutputEvent",
+    "StderrEvent",
+    "ResizeEvent",
+    "SnapshotEvent",
+    "InputWrittenEvent",
+    "InputDiscardedEvent",
+    "FlushedEvent",
+    "PidEvent",
+    "ExitCodeEvent",
+    "DebugEvent",
+    "ErrorEvent",
+    "CommandCompletedEvent",
+    "RespawnEvent",
+    "StoppedEvent",
+    "ContinuedEvent",
+    "IdleEvent",
+    "ActiveEvent",
+    "IdleTimeoutEvent",
+    "StateEvent",
+    "DroppedEvent",
+    "BacklogEvent",
+    "UnknownEvent",
+    "parse_event",
+]
+
+
+@dataclass(frozen=True, kw_only=True)
+class Event:
+    """Base class of the events ht sends."""
+
+    pane: Optional[int] = None
+    """Which additional pane the event is from, or None for the main one"""
+
+    raw: dict[str, Any] = field(default_factory=dict, repr=False, compare=False)
+    """The JSON object the event was parsed from"""
+
+
+@dataclass(frozen=True, kw_only=True)
+class InitEvent(Event):
+    """The terminal as it was when the subscription started."""
+
+    cols: int
+    rows: int
+    pid: int
+    seq: str
+    text: str
+
+
+@dataclass(frozen=True, kw_only=True)
+class OutputEvent(Event):
+    """Something the command wrote to the terminal."""
+
+    seq: str
+
+
+@dataclass(frozen=True, kw_only=True)
+class RawOutputEvent(Event):
+    """The exact bytes the command wrote to the terminal, binary data and all, with `rawOutput`."""
+
+    data: bytes
+
+
+@dataclass(frozen=True, kw_only=True)
+class StderrEvent(Event):
+    """Something the command wrote to stderr, with `--separate-stderr`."""
+
+    seq: str
+
+
+@dataclass(frozen=True, kw_only=True)
+class ResizeEvent(Event):
+    """The terminal was resized."""
+
+    cols: int
+    rows: int
+
+
+@dataclass(frozen=True, kw_only=True)
+class SnapshotEvent(Event):
+    """The terminal's contents, in answer to a takeSnapshot command."""
+
+    time: float
+    """Seconds since the session started, when the screen was copied"""
+    cols: int
+    rows: int
+    seq: str
+    text: str
+
+
+@dataclass(frozen=True, kw_only=True)
+class InputWrittenEvent(Event):
+    """Keys sent with `ack` have been written to the terminal."""
+
+    bytes: int
+
+
+@dataclass(frozen=True, kw_only=True)
+class InputDiscardedEvent(Event):
+    """Keys arrived after the command exited, so there was nothing to write them to."""
+
+    bytes: int
+
+
+@dataclass(frozen=True, kw_only=True)
+class FlushedEvent(Event):
+    """ht has seen to every command sent before a flush."""
+
+
+@dataclass(frozen=True, kw_only=True)
+class PidEvent(Event):
+    """The PID of the shell that wraps the command."""
+
+    pid: int
+
+
+@dataclass(frozen=True, kw_only=True)
+class ExitCodeEvent(Event):
+    """The command exited."""
+
+    exit_code: int
+    signal: Optional[str] = None
+    """Name of the signal that terminated the command, if one did"""
+
+    core_dumped: bool = False
+    terminated_by: str = "command"
+    """`command` if the status is the command's, `shell` if the wrapping shell was terminated first"""
+
+
+@dataclass(frozen=True, kw_only=True)
+class DebugEvent(Event):
+    """Diagnostics from ht."""
+
+    message: str
+
+
+@dataclass(frozen=True, kw_only=True)
+class ErrorEvent(Event):
+    """ht refused a command, e.g. a resize to a size a terminal can't have, or couldn't make one out of a line."""
+
+    message: str
+    line: Optional[str] = None
+    """The line sent to ht that wasn't a command it knows, for errors about one"""
+
+    request_id: Any = None
+    """The `id` that line gave, if it had one"""
+
+
+@dataclass(frozen=True, kw_only=True)
+class CommandCompletedEvent(Event):
+    """The command finished, and ht is about to let the wrapping shell exit."""
+
+    time: float
+
+
+@dataclass(frozen=True, kw_only=True)
+class RespawnEvent(Event):
+    """The command is about to be started again, with `--respawn`."""
+
+    attempt: int
+    delay_ms: int
+
+
+@dataclass(frozen=True, kw_only=True)
+class StoppedEvent(Event):
+    """The command was stopped by `signal` (e.g. `SIGSTOP` or `SIGTSTP`), and won't run until it's continued."""
+
+    signal: str
+
+
+@dataclass(frozen=True, kw_only=True)
+class ContinuedEvent(Event):
+    """The command was continued after being stopped."""
+
+
+@dataclass(frozen=True, kw_only=True)
+class IdleEvent(Event):
+    """The terminal has gone quiet."""
+
+    quiet_ms: int
+
+
+@dataclass(frozen=True, kw_only=True)
+class ActiveEvent(Event):
+    """Output resumed after the terminal had been quiet."""
+
+    quiet_ms: int
+
+
+@dataclass(frozen=True, kw_only=True)
+class IdleTimeoutEvent(Event):
+    """Nothing happened for `--idle-timeout`, so ht is giving up on the command."""
+
+    quiet_ms: int
+
+
+@dataclass(frozen=True, kw_only=True)
+class StateEvent(Event):
+    """The session moved on to `state`: `starting`, `running`, `childExited`, `draining` or `closed`."""
+
+    state: str
+
+
+@dataclass(frozen=True, kw_only=True)
+class DroppedEvent(Event):
+    """A slow reader missed this many events."""
+
+    count: int
+
+
+@dataclass(frozen=True, kw_only=True)
+class BacklogEvent(Event):
+    """One of ht's channels (`output`, `command` or `broadcast`) is filling up, and may soon hold things up."""
+
+    channel: str
+    queued: int
+    capacity: int
+
+
+@dataclass(frozen=True, kw_only=True)
+class UnknownEvent(Event):
+    """An event of a type this version of htty doesn't know about."""
+
+    type: str
+    data: dict[str, Any]
+
+
+def parse_event(message: dict[str, Any]) -> Event:
+    """
+    The typed form of an event, given the JSON object ht sent for it.
+
+    Raises:
+        KeyError: If the event is missing a field its type requires
+    """
+    kind = message.get("type", "")
+    data = message.get("data") or {}
+    common: dict[str, Any] = {"pane": data.get("pane"), "raw": message}
+
+    if kind == "init":
+        return InitEvent(
+            cols=data["cols"], rows=data["rows"], pid=data["pid"], seq=data["seq"], text=data["text"], **common
+        )
+    elif kind == "output":
+        return OutputEvent(seq=data["seq"], **common)
+    elif kind == "rawOutput":
+        return RawOutputEvent(data=base64.b64decode(data["bytes"]), **common)
+    elif kind == "stderr":
+        return StderrEvent(seq=data["seq"], **common)
+    elif kind == "resize":
+        return ResizeEvent(cols=data["cols"], rows=data["rows"], **common)
+    elif kind == "snapshot":
+        return SnapshotEvent(
+            time=data.get("time", 0.0),
+            cols=data["cols"],
+            rows=data["rows"],
+            seq=data["seq"],
+            text=data["text"],
+            **common,
+        )
+    elif kind == "inputWritten":
+        return InputWrittenEvent(bytes=data["bytes"], **common)
+    elif kind == "inputDiscarded":
+        return InputDiscardedEvent(bytes=data["bytes"], **common)
+    elif kind == "flushed":
+        return FlushedEvent(**common)
+    elif kind == "pid":
+        return PidEvent(pid=data["pid"], **common)
+    elif kind == "exitCode":
+        return ExitCodeEvent(
+            exit_code=data["exitCode"],
+            signal=data.get("signal"),
+            core_dumped=data.get("coreDumped", False),
+            terminated_by=data.get("terminatedBy", "command"),
+            **common,
+        )
+    elif kind == "debug":
+        return DebugEvent(message=data.get("message", ""), **common)
+    elif kind == "error":
+        return ErrorEvent(message=data["message"], line=data.get("line"), request_id=data.get("id"), **common)
+    elif kind == "commandCompleted":
+        return CommandCompletedEvent(time=data.get("time", 0.0), **common)
+    elif kind == "respawn":
+        return RespawnEvent(attempt=data["attempt"], delay_ms=data["delayMs"], **common)
+    elif kind == "stopped":
+        return StoppedEvent(signal=data["signal"], **common)
+    elif kind == "continued":
+        return ContinuedEvent(**common)
+    elif kind == "idle":
+        return IdleEvent(quiet_ms=data["quietMs"], **common)
+    elif kind == "active":
+        return ActiveEvent(quiet_ms=data["quietMs"], **common)
+    elif kind == "idleTimeout":
+        return IdleTimeoutEvent(quiet_ms=data["quietMs"], **common)
+    elif kind == "state":
+        return StateEvent(state=data["state"], **common)
+    elif kind == "dropped":
+        return DroppedEvent(count=data["count"], **common)
+    elif kind == "backlog":
+        return BacklogEvent(channel=data["channel"], queued=data["queued"], capacity=data["capacity"], **common)
+    else:
+        return UnknownEvent(type=kind, data=data, **common)
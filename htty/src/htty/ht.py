@@ -4,10 +4,11 @@ import os
 import queue
 import re
 import shlex
+import signal
 import subprocess
 import threading
 import time
-from collections.abc import Iterator
+from collections.abc import Callable, Iterator, Mapping
 from contextlib import contextmanager, suppress
 from typing import Annotated, Any, Optional, TypeAlias, Union
 
@@ -16,19 +17,36 @@ from htty_core import (
     Command,
     HtArgs,
     HtEvent,
+    HtmlTheme,
     Rows,
     StyleMode,
     run as htty_core_run,
 )
-from tenacity import retry, retry_if_exception_type, stop_after_delay, wait_fixed
 
 from .constants import (
     DEFAULT_EXIT_TIMEOUT,
     DEFAULT_EXPECT_TIMEOUT,
+    DEFAULT_GRACEFUL_TERMINATION_TIMEOUT,
+    DEFAULT_MAX_EVENTS,
     DEFAULT_SLEEP_AFTER_KEYS,
     DEFAULT_SNAPSHOT_TIMEOUT,
     DEFAULT_SUBPROCESS_WAIT_TIMEOUT,
-    SNAPSHOT_RETRY_TIMEOUT,
+    MAX_TERMINAL_SIDE,
+    MIN_TERMINAL_SIDE,
+)
+from .errors import HttyError, HttyProcessError, HttyProtocolError, HttyTimeoutError
+from .event_log import EventLog
+from .events import (
+    ErrorEvent,
+    Event,
+    FlushedEvent,
+    InitEvent,
+    InputDiscardedEvent,
+    InputWrittenEvent,
+    OutputEvent,
+    ResizeEvent,
+    UnknownEvent,
+    parse_event,
 )
 from .html_utils import simple_ansi_to_html
 from .keys import KeyInput, keys_to_strings
@@ -38,15 +56,11 @@ from .proc import CmdProcess, HtProcess, ProcessController
 default_logger = logging.getLogger(__name__)
 
 
-class SnapshotNotReady(Exception):
-    """Exception raised when snapshot is not yet available from the queue."""
-
-    pass
-
-
 __all__ = [
     "terminal_session",
     "SnapshotResult",
+    "ExpectResult",
+    "EventStream",
     "HtWrapper",
     "ProcessController",
     "run",
@@ -64,19 +78,127 @@ Logger: TypeAlias = Annotated[Optional[logging.Logger], "callers can override th
 
 ExtraSubscribes: TypeAlias = Annotated[Optional[list[HtEvent]], "additional event types to subscribe to"]
 
+MaxEvents: TypeAlias = Annotated[
+    Optional[int],
+    "how many output events to keep before discarding the oldest ones (None keeps them all)",
+]
+
+Record: TypeAlias = Annotated[
+    bool,
+    "whether to keep all of the session's output and resizes, for `cast_events` and `save_cast`",
+]
+
+Env: TypeAlias = Annotated[
+    Optional[Mapping[str, str]],
+    "the whole environment to run the command in, instead of this process's",
+]
+
+ExtraEnv: TypeAlias = Annotated[
+    Optional[Mapping[str, str]],
+    "variables to set on top of the environment the command runs in",
+]
+
+Cwd: TypeAlias = Annotated[
+    Optional[Union[str, "os.PathLike[str]"]],
+    "the directory to run the command in, instead of this process's working directory",
+]
+
+
+def command_env(env: Env, extra_env: ExtraEnv) -> Optional[dict[str, str]]:
+    """
+    @private
+    The environment to start ht (and so the command) with, or None for this process's own.
+    """
+    if env is None and not extra_env:
+        return None
+
+    return {**(os.environ if env is None else env), **(extra_env or {})}
+
+
+def check_size(cols: int, rows: int) -> None:
+    """
+    @private
+    Raise ValueError unless ht would accept a terminal of `cols` by `rows`.
+    """
+    sides = range(MIN_TERMINAL_SIDE, MAX_TERMINAL_SIDE + 1)
+
+    if cols not in sides or rows not in sides:
+        raise ValueError(
+            f"Invalid terminal size {cols}x{rows}: columns and rows must each be between "
+            f"{MIN_TERMINAL_SIDE} and {MAX_TERMINAL_SIDE}"
+        )
+
 
 class SnapshotResult:
     """Result of taking a terminal snapshot"""
 
     def __init__(self, text: str, html: str, raw_seq: str):
         self.text = text
+        """The screen's text, one line per row"""
         self.html = html
+        """The screen as a `<pre>` element, with its colors and attributes as inline styles"""
         self.raw_seq = raw_seq
+        """Escape sequences that redraw the screen"""
 
     def __repr__(self):
         return f"SnapshotResult(text={self.text!r}, html=<{len(self.html)} chars>, raw_seq=<{len(self.raw_seq)} chars>)"
 
 
+class ExpectResult:
+    """What `HtWrapper.expect` found: the match, and the snapshot of the screen it was found in"""
+
+    def __init__(self, match: "re.Match[str]", snapshot: SnapshotResult):
+        self.match = match
+        self.snapshot = snapshot
+
+    def __repr__(self):
+        return f"ExpectResult(match={self.match.group(0)!r}, snapshot={self.snapshot!r})"
+
+
+class EventStream:
+    """
+    The events ht sends, as they arrive. Returned by `HtWrapper.events`.
+    """
+
+    def __init__(self, wrapper: "HtWrapper", timeout: Optional[float]) -> None:
+        """
+        @private
+        """
+        self._wrapper = wrapper
+        self._events: queue.Queue[Optional[Event]] = queue.Queue()
+        self._deadline = None if timeout is None else time.monotonic() + timeout
+        wrapper.subscribe_events(self._events)
+
+    def __iter__(self) -> "EventStream":
+        return self
+
+    def __next__(self) -> Event:
+        remaining = None if self._deadline is None else self._deadline - time.monotonic()
+
+        try:
+            if remaining is not None and remaining <= 0:
+                raise queue.Empty
+            event = self._events.get(block=True, timeout=remaining)
+        except queue.Empty:
+            event = None
+
+        if event is None:
+            self.close()
+            raise StopIteration
+
+        return event
+
+    def __enter__(self) -> "EventStream":
+        return self
+
+    def __exit__(self, *exc_info: object) -> None:
+        self.close()
+
+    def close(self) -> None:
+        """Stop collecting events."""
+        self._wrapper.unsubscribe_events(self._events)
+
+
 class HtWrapper:
     """
     A wrapper around a process started with the 'ht' tool that provides
@@ -104,6 +226,8 @@ class HtWrapper:
         cols: Optional[int] = None,
         no_exit: bool = False,
         logger: Optional[logging.Logger] = None,
+        max_events: Optional[int] = DEFAULT_MAX_EVENTS,
+        record: bool = False,
     ) -> None:
         """
         @private
@@ -111,12 +235,13 @@ class HtWrapper:
         They should use `with terminal_session(...)` or `run(...)`
         """
         self._ht_proc = ht_proc  # The ht process itself
-        self._cmd_process = CmdProcess(pid)
+        self._cmd_finished = threading.Event()  # Set once the command has exited, or ht has gone away
+        self._cmd_process = CmdProcess(self._cmd_finished, pid, self)
         self._event_queue = event_queue
         self._command = command
-        self._output_events: list[dict[str, Any]] = []
+        self._output_events = EventLog(max_events)
         self._unknown_events: list[dict[str, Any]] = []
-        self._latest_snapshot: Optional[str] = None
+        self._latest_snapshot: Optional[SnapshotResult] = None
         self._start_time = time.time()
         self._exit_code: Optional[int] = None
         self._rows = rows
@@ -125,14 +250,90 @@ class HtWrapper:
         self._subprocess_exited = False
         self._subprocess_completed = False  # Set earlier when command completion is detected
 
+        # Set by the reader thread, so callers can block until something happens instead of polling. The init and pid
+        # ones are also set if ht goes away first, so that startup doesn't wait for them in vain
+        self._init_received = threading.Event()
+        self._pid_received = threading.Event()
+        self._exit_received = threading.Event()
+
+        # Queues of the open `events()` streams and the callbacks registered with `on()`, which the reader thread
+        # hands each event to
+        self._subscribers: list[queue.Queue[Optional[Event]]] = []
+        self._callbacks: dict[str, list[Callable[[Event], None]]] = {}
+        self._subscribers_lock = threading.Lock()
+        self._stdout_closed = False
+
+        # The main pane's output and resizes as asciicast events, timed from the start of the session (guarded by
+        # the subscribers lock), if the session is recorded
+        self._cast_start = time.monotonic()
+        self._cast_size = (cols, rows)
+        self._cast: Optional[list[tuple[float, str, str]]] = [] if record else None
+
         # Use provided logger or fall back to default
         self._logger = logger or default_logger
         self._logger.debug(f"HTProcess created: ht_proc.pid={ht_proc.pid}, command={command}")
 
+        # Threads reading ht's stdout and stderr, which shutdown() waits for
+        self._reader_threads: list[threading.Thread] = []
+
         # Create the public interface objects
         self.ht: ProcessController = HtProcess(ht_proc, self)
         self.cmd: ProcessController = self._cmd_process
 
+    def __enter__(self) -> "HtWrapper":
+        return self
+
+    def __exit__(self, *exc_info: object) -> None:
+        """
+        Terminate the command and the ht process, and wait for ht to be reaped, whether or not the body of the `with`
+        block raised:
+
+        ```python
+        with run("vim") as proc:
+            proc.send_keys(":q!")
+            raise RuntimeError("oops")  # vim and ht still get cleaned up
+        ```
+        """
+        with suppress(HttyProtocolError):
+            self.shutdown(grace=DEFAULT_SUBPROCESS_WAIT_TIMEOUT)
+
+    def shutdown(self, grace: float = DEFAULT_GRACEFUL_TERMINATION_TIMEOUT) -> int:
+        """
+        Stop the command and ht, escalating as needed, and return ht's exit code once it has been reaped and the
+        threads reading from it are done.
+
+        The command is sent SIGTERM, and SIGKILL if it hasn't exited `grace` seconds later. Then ht is asked to exit
+        (or sent SIGTERM if it never reported the command exiting), and killed if it's still running `grace` seconds
+        after that. `cmd.shutdown()` and `ht.shutdown()` do the same.
+
+        Raises:
+            HttyProtocolError: If ht is still running even after SIGKILL
+        """
+        pid = self._cmd_process.pid
+        if pid is not None and not self._cmd_finished.is_set():
+            self._logger.debug(f"Sending SIGTERM to subprocess {pid}")
+            with suppress(OSError):
+                os.kill(pid, signal.SIGTERM)
+
+            if not self._cmd_finished.wait(grace):
+                self._logger.warning(f"Subprocess {pid} did not exit {grace} seconds after SIGTERM, sending SIGKILL")
+                with suppress(OSError):
+                    os.kill(pid, signal.SIGKILL)
+                self._cmd_finished.wait(DEFAULT_SUBPROCESS_WAIT_TIMEOUT)
+
+        if self._ht_proc.poll() is None:
+            exit_code = self._graceful_exit(grace) if self._exit_received.is_set() else self._forced_exit(grace)
+        else:
+            exit_code = self._ht_proc.returncode
+            self._exit_code = exit_code
+
+        # ht's pipes are closed now that it's gone, so the readers are only finishing up what's left in them
+        for thread in self._reader_threads:
+            if thread is not threading.current_thread():
+                thread.join(grace)
+
+        return exit_code
+
     def __del__(self):
         """Destructor to warn about uncleaned processes."""
         if hasattr(self, "_ht_proc") and self._ht_proc and self._ht_proc.poll() is None:
@@ -144,10 +345,205 @@ class HtWrapper:
             with suppress(Exception):
                 self._ht_proc.terminate()
 
+    @property
+    def rows(self) -> Optional[int]:
+        """Height of the terminal, as last reported by ht"""
+        return self._rows
+
+    @property
+    def cols(self) -> Optional[int]:
+        """Width of the terminal, as last reported by ht"""
+        return self._cols
+
+    def cast_events(self) -> list[tuple[float, str, str]]:
+        """
+        The session so far as [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) events: `(time, "o",
+        output)` for output and `(time, "r", "COLSxROWS")` for resizes, with times in seconds since `run`.
+
+        Unlike `get_output`, this keeps the whole session, however long it gets, so it's only kept for sessions
+        started with `record=True`.
+
+        Raises:
+            RuntimeError: If the session isn't being recorded
+        """
+        with self._subscribers_lock:
+            if self._cast is None:
+                raise RuntimeError("the session isn't being recorded, start it with record=True")
+
+            return list(self._cast)
+
+    def save_cast(self, path: Union[str, "os.PathLike[str]"]) -> None:
+        """
+        Write the session so far to `path` as an asciicast, to replay later with `ht replay` or `asciinema play`.
+        The session must have been started with `record=True`.
+
+        ```python
+        try:
+            proc.expect("All tests passed")
+        except HttyError:
+            proc.save_cast("failed-session.cast")
+            raise
+        ```
+        """
+        events = self.cast_events()
+        cols, rows = self._cast_size
+        header = {
+            "version": 2,
+            "width": cols or 0,
+            "height": rows or 0,
+            "timestamp": int(self._start_time),
+            "command": self._command,
+        }
+
+        with open(path, "w") as f:
+            f.write(json.dumps(header) + "\n")
+            for event in events:
+                f.write(json.dumps(list(event)) + "\n")
+
     def get_output(self) -> list[dict[str, Any]]:
         """
-        Return list of [output](./htty-core/htty_core.html#HtEvent.OUTPUT) events."""
-        return [event for event in self._output_events if event.get("type") == "output"]
+        Return list of [output](./htty-core/htty_core.html#HtEvent.OUTPUT) events.
+
+        Only the most recent `max_events` events are kept (see `run`), so in a long session the earliest output
+        may no longer be available."""
+        return self._output_events.of_type("output")
+
+    def events(self, timeout: Optional[float] = None) -> EventStream:
+        """
+        Iterate over the events ht sends from now on, as typed objects (see `htty.events`).
+
+        This is how to make assertions about what happened along the way rather than only about the screen at the
+        end:
+
+        ```python
+        with proc.events(timeout=10) as events:
+            proc.send_keys(["make", Press.ENTER])
+            for event in events:
+                if isinstance(event, OutputEvent) and "warning" in event.seq:
+                    raise AssertionError("make printed a warning")
+                if isinstance(event, ExitCodeEvent):
+                    break
+        ```
+
+        Events are collected from the moment this is called, not from when iteration starts. Each stream sees every
+        event, independently of `get_output` and of other streams. Iteration stops once ht exits, or when `timeout`
+        seconds have passed since the call. Close a stream you're done with, or use it with `with`.
+        """
+        return EventStream(self, timeout)
+
+    def on(self, event_type: Union[HtEvent, str], callback: Callable[[Event], None]) -> Callable[[], None]:
+        """
+        Call `callback` with each event of the given type (see `htty.events`) as it arrives, returning a function
+        that unregisters it.
+
+        This is for reacting to the session without polling it, like answering a prompt whenever it shows up:
+
+        ```python
+        def answer(event: OutputEvent) -> None:
+            if "Overwrite? [y/N]" in event.seq:
+                proc.send_keys(["y", Press.ENTER])
+
+        stop = proc.on("output", answer)
+        ...
+        stop()
+        ```
+
+        Callbacks run on the thread that reads from ht, one at a time and in the order the events arrived, so they
+        should return quickly. They may send keys (`send_keys` doesn't wait for ht when called from a callback), but
+        mustn't wait on ht (with `snapshot`, `expect` or `cmd.wait`) since ht's answer can't be read until they
+        return. An exception raised by a callback is logged and
+        otherwise ignored.
+        """
+        event_type = str(event_type)
+
+        with self._subscribers_lock:
+            self._callbacks.setdefault(event_type, []).append(callback)
+
+        def unregister() -> None:
+            with self._subscribers_lock:
+                with suppress(ValueError):
+                    self._callbacks.get(event_type, []).remove(callback)
+
+        return unregister
+
+    def subscribe_events(self, events: queue.Queue[Optional[Event]]) -> None:
+        """
+        @private
+        Start handing events to an `EventStream`'s queue (for internal use by `EventStream`).
+        """
+        with self._subscribers_lock:
+            self._subscribers.append(events)
+            if self._stdout_closed:
+                events.put(None)
+
+    def unsubscribe_events(self, events: queue.Queue[Optional[Event]]) -> None:
+        """
+        @private
+        Stop handing events to an `EventStream`'s queue (for internal use by `EventStream`).
+        """
+        with self._subscribers_lock:
+            if events in self._subscribers:
+                self._subscribers.remove(events)
+
+    def publish_event(self, message: Optional[dict[str, Any]]) -> None:
+        """
+        @private
+        Hand an event to the open event streams and the callbacks for its type, or tell the streams there are no
+        more with None (for internal use by reader thread).
+        """
+        event: Optional[Event] = None
+
+        if message is not None:
+            try:
+                event = parse_event(message)
+            except KeyError as e:
+                self._logger.warning(f"ht event is missing {e}: {message}")
+                event = UnknownEvent(type=message.get("type", ""), data=message.get("data") or {}, raw=message)
+
+        if message is None:
+            # ht is gone, so no exitCode event is coming for anyone waiting on the command
+            self._cmd_finished.set()
+            self._init_received.set()
+            self._pid_received.set()
+        elif isinstance(event, (InitEvent, ResizeEvent)) and event.pane is None:
+            self._rows, self._cols = event.rows, event.cols
+
+        elapsed = round(time.monotonic() - self._cast_start, 6)
+
+        with self._subscribers_lock:
+            if message is None:
+                self._stdout_closed = True
+            elif self._cast is None or event is None or event.pane is not None:
+                pass
+            elif isinstance(event, InitEvent) and not self._cast:
+                self._cast_size = (event.cols, event.rows)
+                if event.seq:
+                    self._cast.append((elapsed, "o", event.seq))
+            elif isinstance(event, OutputEvent):
+                self._cast.append((elapsed, "o", event.seq))
+            elif isinstance(event, ResizeEvent):
+                self._cast.append((elapsed, "r", f"{event.cols}x{event.rows}"))
+
+            for events in self._subscribers:
+                events.put(event)
+
+            callbacks = list(self._callbacks.get(message["type"], [])) if message is not None else []
+
+        if event is None:
+            return
+
+        for callback in callbacks:
+            try:
+                callback(event)
+            except Exception:
+                self._logger.exception(f"Callback {callback!r} for {message['type']} event failed")
+
+    def add_reader_thread(self, thread: threading.Thread) -> None:
+        """
+        @private
+        Have `shutdown` wait for a thread reading from ht (for internal use by `run`).
+        """
+        self._reader_threads.append(thread)
 
     def add_output_event(self, event: dict[str, Any]) -> None:
         """
@@ -159,9 +555,59 @@ class HtWrapper:
     def set_subprocess_exited(self, exited: bool) -> None:
         """
         @private
-        Set subprocess exited flag (for internal use by reader thread).
+        Set subprocess exited flag, which a respawn clears again (for internal use by reader thread).
         """
         self._subprocess_exited = exited
+        if exited:
+            self._exit_received.set()
+            self._cmd_finished.set()
+        else:
+            self._exit_received.clear()
+            self._cmd_finished.clear()
+
+    def set_subprocess_pid(self, pid: int) -> None:
+        """
+        @private
+        Record the subprocess PID (for internal use by reader thread).
+        """
+        self._cmd_process.pid = pid
+        self._pid_received.set()
+
+    def set_initialized(self) -> None:
+        """
+        @private
+        Record that ht has sent the init event (for internal use by reader thread).
+        """
+        self._init_received.set()
+
+    def wait_for_startup(self, timeout: float) -> None:
+        """
+        @private
+        Block until ht has sent the init event and the command's PID.
+
+        Raises:
+            HttyProtocolError: If ht exits first, or they don't arrive within the timeout
+        """
+        deadline = time.monotonic() + timeout
+        started = self._init_received.wait(timeout) and self._pid_received.wait(max(0.0, deadline - time.monotonic()))
+
+        if started and self._cmd_process.pid is not None:
+            self._logger.debug(f"ht process {self._ht_proc.pid} started subprocess {self._cmd_process.pid}")
+            return
+
+        with self._subscribers_lock:
+            stdout_closed = self._stdout_closed
+
+        if stdout_closed:
+            with suppress(subprocess.TimeoutExpired):
+                self._ht_proc.wait(timeout=DEFAULT_SUBPROCESS_WAIT_TIMEOUT)
+            raise HttyProtocolError(
+                f"ht process exited with code {self._ht_proc.returncode} before starting {self._command!r}"
+            )
+
+        raise HttyProtocolError(
+            f"ht process {self._ht_proc.pid} did not report starting {self._command!r} within {timeout} seconds"
+        )
 
     def set_subprocess_completed(self, completed: bool) -> None:
         """
@@ -171,12 +617,23 @@ class HtWrapper:
         self._subprocess_completed = completed
         self._cmd_process.set_completed(completed)
 
-    def send_keys(self, keys: Union[KeyInput, list[KeyInput]]) -> None:
+    def send_keys(
+        self, keys: Union[KeyInput, list[KeyInput]], wait: bool = True, timeout: float = DEFAULT_SNAPSHOT_TIMEOUT
+    ) -> None:
         """
         Send keys to the terminal.  Accepts strings, `Press` objects, and lists of strings or `Press` objects.
         For keys that you can `Press`, see
         [keys.py](https://github.com/MatrixManAtYrService/htty/blob/main/htty/src/htty/keys.py).
 
+        Unless `wait` is False, this returns once ht reports that the keys have been written to the terminal, so a
+        snapshot taken afterwards is taken after the application could have read them (though it may not have
+        reacted yet, `expect` is for that). With `wait=False` the keys are sent without waiting for ht, followed by a
+        short pause.
+
+        Called from an `on` callback, this never waits, whatever `wait` says: the callback runs on the thread that
+        reads ht's answer, so waiting for it there would block until the timeout. Nor does it pause, so as not to
+        hold up the events behind it.
+
         ```python
         from htty import Press, terminal_session
 
@@ -210,10 +667,39 @@ class HtWrapper:
         ```
 
         If this behavior is problematic for you, consider submitting an issue.
+
+        Raises:
+            HttyProtocolError: If ht has exited, the command has and the keys were discarded, or ht
+                doesn't report the keys as written within the timeout
         """
         key_strings = keys_to_strings(keys)
-        message = json.dumps({"type": "sendKeys", "keys": key_strings})
 
+        if self._on_reader_thread():
+            self._send_keys_message(json.dumps({"type": "sendKeys", "keys": key_strings}))
+            return
+
+        if not wait:
+            self._send_keys_message(json.dumps({"type": "sendKeys", "keys": key_strings}))
+            time.sleep(DEFAULT_SLEEP_AFTER_KEYS)
+            return
+
+        # Subscribed before sending, so the acknowledgement can't be missed
+        with self.events(timeout=timeout) as events:
+            self._send_keys_message(json.dumps({"type": "sendKeys", "keys": key_strings, "ack": True}))
+
+            for event in events:
+                if isinstance(event, InputWrittenEvent) and event.pane is None:
+                    break
+                if isinstance(event, InputDiscardedEvent) and event.pane is None:
+                    raise HttyProtocolError(f"the command has exited, so {event.bytes} bytes of keys were discarded")
+            else:
+                self._check_running()
+                raise HttyProtocolError(f"ht didn't report the keys as written within {timeout} seconds")
+
+    def _on_reader_thread(self) -> bool:
+        return threading.current_thread() in self._reader_threads
+
+    def _send_keys_message(self, message: str) -> None:
         self._logger.debug(f"Sending keys: {message}")
 
         if self._ht_proc.stdin is not None:
@@ -224,20 +710,136 @@ class HtWrapper:
             except (BrokenPipeError, OSError) as e:
                 self._logger.error(f"Failed to send keys: {e}")
                 self._logger.error(f"ht process poll result: {self._ht_proc.poll()}")
-                raise
+                self._check_running()
+                raise HttyProtocolError(f"Cannot send keys to ht process: {e}") from e
+        else:
+            self._logger.error("ht process stdin is None")
+
+    def close_stdin(self) -> None:
+        """
+        Signal end-of-input to the program reading the terminal, so things like `cat > file` can finish.
+
+        A terminal can't really be closed for writing, so `ht` sends the terminal's EOF character
+        (usually Ctrl-D) instead, twice if there's unterminated input on the current line.
+        Programs that put the terminal in raw mode will just see that character.
+        """
+        message = json.dumps({"type": "closeStdin"})
+        self._logger.debug(f"Closing stdin: {message}")
+
+        if self._ht_proc.stdin is not None:
+            try:
+                self._ht_proc.stdin.write(message + "\n")
+                self._ht_proc.stdin.flush()
+            except (BrokenPipeError, OSError) as e:
+                self._logger.error(f"Failed to close stdin: {e}")
+                self._check_running()
+                raise HttyProtocolError(f"Cannot send closeStdin to ht process: {e}") from e
         else:
             self._logger.error("ht process stdin is None")
 
         time.sleep(DEFAULT_SLEEP_AFTER_KEYS)
 
-    def snapshot(self, timeout: float = DEFAULT_SNAPSHOT_TIMEOUT) -> SnapshotResult:
+    def resize(
+        self, cols: int, rows: int, snapshot: bool = False, timeout: float = DEFAULT_SNAPSHOT_TIMEOUT
+    ) -> Optional[SnapshotResult]:
+        """
+        Resize the terminal, returning once ht reports the new size, along with a snapshot taken afterwards if
+        `snapshot` is set.
+
+        ```python
+        proc.resize(40, 10)
+        proc.expect("narrow layout")
+        ```
+
+        Raises:
+            ValueError: If either dimension is outside the sizes a terminal may have (2 to 10000)
+            HttyProtocolError: If ht refuses the size, has exited, or doesn't report the new size within the timeout
+        """
+        check_size(cols, rows)
+
+        self._check_running()
+
+        message = json.dumps({"type": "resize", "cols": cols, "rows": rows})
+        self._logger.debug(f"Resizing: {message}")
+
+        # Subscribed before sending, so the resize event can't be missed
+        with self.events(timeout=timeout) as events:
+            if self._ht_proc.stdin is None:
+                raise HttyProtocolError("ht process stdin is not available")
+
+            try:
+                self._ht_proc.stdin.write(message + "\n")
+                self._ht_proc.stdin.flush()
+            except (BrokenPipeError, OSError) as e:
+                self._check_running()
+                raise HttyProtocolError(f"Cannot send resize to ht process: {e}") from e
+
+            for event in events:
+                if isinstance(event, ResizeEvent) and event.pane is None and (event.cols, event.rows) == (cols, rows):
+                    break
+                if isinstance(event, ErrorEvent) and event.pane is None and event.line is None:
+                    raise HttyProtocolError(f"ht refused to resize the terminal: {event.message}")
+            else:
+                self._check_running()
+                raise HttyProtocolError(
+                    f"ht didn't report the terminal being resized to {cols}x{rows} within {timeout} seconds"
+                )
+
+        return self.snapshot() if snapshot else None
+
+    def flush(self, timeout: float = DEFAULT_SNAPSHOT_TIMEOUT) -> None:
+        """
+        Return once ht has seen to every command sent before, with their keys written to the terminal and their
+        snapshots sent, which keys sent with `wait=False` otherwise don't promise.
+
+        ```python
+        proc.send_keys("ls", wait=False)
+        proc.send_keys(Press.ENTER, wait=False)
+        proc.flush()
+        ```
+
+        Raises:
+            HttyProtocolError: If ht has exited, or doesn't report the flush within the timeout
+        """
+        self._check_running()
+
+        message = json.dumps({"type": "flush"})
+        self._logger.debug(f"Flushing: {message}")
+
+        # Subscribed before sending, so the flushed event can't be missed
+        with self.events(timeout=timeout) as events:
+            if self._ht_proc.stdin is None:
+                raise HttyProtocolError("ht process stdin is not available")
+
+            try:
+                self._ht_proc.stdin.write(message + "\n")
+                self._ht_proc.stdin.flush()
+            except (BrokenPipeError, OSError) as e:
+                self._check_running()
+                raise HttyProtocolError(f"Cannot send flush to ht process: {e}") from e
+
+            for event in events:
+                if isinstance(event, FlushedEvent):
+                    break
+            else:
+                self._check_running()
+                raise HttyProtocolError(f"ht didn't report the flush within {timeout} seconds")
+
+    def snapshot(
+        self, timeout: float = DEFAULT_SNAPSHOT_TIMEOUT, html_theme: HtmlTheme = HtmlTheme.DARK
+    ) -> SnapshotResult:
         """
         Take a snapshot of the terminal output.
+
+        `html_theme` sets the colors of the snapshot's `html` where the screen doesn't set its own.
+
+        Raises:
+            HttyProcessError: If ht has exited because the command did
+            HttyProtocolError: If ht has exited otherwise, or doesn't answer within the timeout
         """
-        if self._ht_proc.poll() is not None:
-            raise RuntimeError(f"ht process has exited with code {self._ht_proc.returncode}")
+        self._check_running()
 
-        message = json.dumps({"type": "takeSnapshot"})
+        message = json.dumps({"type": "takeSnapshot", "htmlTheme": str(html_theme)})
         self._logger.debug(f"Taking snapshot: {message}")
 
         try:
@@ -246,78 +848,64 @@ class HtWrapper:
                 self._ht_proc.stdin.flush()
                 self._logger.debug("Snapshot request sent successfully")
             else:
-                raise RuntimeError("ht process stdin is not available")
+                raise HttyProtocolError("ht process stdin is not available")
         except BrokenPipeError as e:
             self._logger.error(f"Failed to send snapshot request: {e}")
             self._logger.error(f"ht process poll result: {self._ht_proc.poll()}")
-            raise RuntimeError(
+            self._check_running()
+            raise HttyProtocolError(
                 f"Cannot communicate with ht process (broken pipe). "
                 f"Process may have exited. Poll result: {self._ht_proc.poll()}"
             ) from e
 
-        time.sleep(DEFAULT_SLEEP_AFTER_KEYS)
-
-        # Use tenacity to retry getting the snapshot
         return self._wait_for_snapshot(timeout)
 
     def _wait_for_snapshot(self, timeout: float) -> SnapshotResult:
         """
-        Wait for snapshot response from the event queue with timeout and retries.
+        Block on the event queue until the snapshot response arrives or the timeout runs out.
         """
+        deadline = time.monotonic() + timeout
+        deferred: list[dict[str, Any]] = []
 
-        @retry(
-            stop=stop_after_delay(timeout),
-            wait=wait_fixed(SNAPSHOT_RETRY_TIMEOUT),
-            retry=retry_if_exception_type(SnapshotNotReady),
-        )
-        def _get_snapshot() -> SnapshotResult:
-            try:
-                event = self._event_queue.get(block=True, timeout=SNAPSHOT_RETRY_TIMEOUT)
-            except queue.Empty as e:
-                raise SnapshotNotReady("No events available in queue") from e
-
-            if event["type"] == "snapshot":
-                data = event["data"]
-                snapshot_text = data["text"]
-                raw_seq = data["seq"]
-
-                # Convert to HTML with ANSI color support
-                html = simple_ansi_to_html(raw_seq)
-
-                return SnapshotResult(
-                    text=snapshot_text,
-                    html=html,
-                    raw_seq=raw_seq,
-                )
-            elif event["type"] == "output":
-                self._output_events.append(event)
-            elif event["type"] == "resize":
-                data = event.get("data", {})
-                if "rows" in data:
-                    self._rows = data["rows"]
-                if "cols" in data:
-                    self._cols = data["cols"]
-            elif event["type"] == "init":
-                pass
-            else:
-                # Put non-snapshot events back in queue for reader thread to handle
+        try:
+            while (remaining := deadline - time.monotonic()) > 0:
+                try:
+                    event = self._event_queue.get(block=True, timeout=remaining)
+                except queue.Empty:
+                    break
+
+                if event["type"] == "snapshot":
+                    data = event["data"]
+                    raw_seq = data["seq"]
+
+                    self._latest_snapshot = SnapshotResult(
+                        text=data["text"],
+                        # Rendered by ht, unless it's an older one that doesn't know how
+                        html=data.get("html") or simple_ansi_to_html(raw_seq),
+                        raw_seq=raw_seq,
+                    )
+                    return self._latest_snapshot
+                elif event["type"] == "output":
+                    self._output_events.append(event)
+                elif event["type"] == "resize":
+                    data = event.get("data", {})
+                    if "rows" in data:
+                        self._rows = data["rows"]
+                    if "cols" in data:
+                        self._cols = data["cols"]
+                elif event["type"] in ("init", "inputWritten"):
+                    pass
+                else:
+                    # Hand non-snapshot events back once we're done, rather than picking them straight up again
+                    deferred.append(event)
+        finally:
+            for event in deferred:
                 self._event_queue.put(event)
 
-            # If we get here, we didn't find a snapshot, so retry
-            raise SnapshotNotReady(f"Received {event['type']} event, waiting for snapshot")
-
-        try:
-            return _get_snapshot()
-        except Exception as e:
-            # Handle both direct SnapshotNotReady and tenacity RetryError
-            from tenacity import RetryError
-
-            if isinstance(e, (SnapshotNotReady, RetryError)):
-                raise RuntimeError(
-                    f"Failed to receive snapshot event within {timeout} seconds. "
-                    f"ht process may have exited or stopped responding."
-                ) from e
-            raise
+        raise HttyProtocolError(
+            f"Failed to receive snapshot event within {timeout} seconds. "
+            f"ht process may have exited or stopped responding."
+        )
 
     def exit(self, timeout: float = DEFAULT_EXIT_TIMEOUT) -> int:
         """
@@ -337,12 +925,9 @@ class HtWrapper:
             self._logger.debug("Subprocess has not exited yet, checking current state")
 
             # Give a brief moment for any pending exitCode event to arrive
-            brief_wait_start = time.time()
-            while time.time() - brief_wait_start < 0.5:  # Wait up to 500ms
-                if self._subprocess_exited:
-                    self._logger.debug("Subprocess exited during brief wait, attempting graceful shutdown")
-                    return self._graceful_exit(timeout)
-                time.sleep(0.01)
+            if self._exit_received.wait(0.5):
+                self._logger.debug("Subprocess exited during brief wait, attempting graceful shutdown")
+                return self._graceful_exit(timeout)
 
             self._logger.debug("Subprocess still running after brief wait, using forced termination")
             return self._forced_exit(timeout)
@@ -351,7 +936,7 @@ class HtWrapper:
         """
         Graceful exit: subprocess has completed, so we can send exit command to ht process.
         """
-        # Send exit command to ht process
+        # Send exit command to ht process, which exits as soon as it has seen to the commands before it
         message = json.dumps({"type": "exit"})
         self._logger.debug(f"Sending exit command to ht process {self._ht_proc.pid}: {message}")
 
@@ -371,20 +956,19 @@ class HtWrapper:
             pass
 
         # Wait for the ht process to finish gracefully
-        start_time = time.time()
-        while self._ht_proc.poll() is None:
-            if time.time() - start_time > timeout:
-                # Graceful exit timed out, fall back to forced termination
-                self._logger.warning(
-                    f"ht process {self._ht_proc.pid} did not exit gracefully within timeout, "
-                    f"falling back to forced termination"
-                )
-                return self._forced_exit(timeout)
-            time.sleep(DEFAULT_SLEEP_AFTER_KEYS)
+        try:
+            self._ht_proc.wait(timeout=timeout)
+        except subprocess.TimeoutExpired:
+            # Graceful exit timed out, fall back to forced termination
+            self._logger.warning(
+                f"ht process {self._ht_proc.pid} did not exit gracefully within timeout, "
+                f"falling back to forced termination"
+            )
+            return self._forced_exit(timeout)
 
         self._exit_code = self._ht_proc.returncode
         if self._exit_code is None:
-            raise RuntimeError("Failed to determine ht process exit code")
+            raise HttyProtocolError("Failed to determine ht process exit code")
 
         self._logger.debug(f"HTProcess exited gracefully: exit_code={self._exit_code}")
         return self._exit_code
@@ -421,54 +1005,75 @@ class HtWrapper:
             self._logger.debug(f"Failed to send SIGTERM to ht process {self._ht_proc.pid}: {e}")
 
         # Wait for termination
-        start_time = time.time()
-        while self._ht_proc.poll() is None:
-            if time.time() - start_time > timeout:
-                # SIGTERM timeout, try SIGKILL
-                self._logger.warning(
-                    f"ht process {self._ht_proc.pid} did not terminate with SIGTERM within timeout, sending SIGKILL"
-                )
-                try:
-                    self._ht_proc.kill()
-                    self._logger.debug(f"Sent SIGKILL to ht process {self._ht_proc.pid}")
-                except Exception as e:
-                    self._logger.debug(f"Failed to send SIGKILL to ht process {self._ht_proc.pid}: {e}")
-
-                # Wait for SIGKILL to take effect
-                kill_start_time = time.time()
-                while self._ht_proc.poll() is None:
-                    if time.time() - kill_start_time > timeout:
-                        self._logger.error(f"ht process {self._ht_proc.pid} did not respond to SIGKILL within timeout")
-                        break
-                    time.sleep(DEFAULT_SLEEP_AFTER_KEYS)
-                break
-            time.sleep(DEFAULT_SLEEP_AFTER_KEYS)
+        try:
+            self._ht_proc.wait(timeout=timeout)
+        except subprocess.TimeoutExpired:
+            # SIGTERM timeout, try SIGKILL
+            self._logger.warning(
+                f"ht process {self._ht_proc.pid} did not terminate with SIGTERM within timeout, sending SIGKILL"
+            )
+            try:
+                self._ht_proc.kill()
+                self._logger.debug(f"Sent SIGKILL to ht process {self._ht_proc.pid}")
+            except Exception as e:
+                self._logger.debug(f"Failed to send SIGKILL to ht process {self._ht_proc.pid}: {e}")
+
+            # Wait for SIGKILL to take effect
+            try:
+                self._ht_proc.wait(timeout=timeout)
+            except subprocess.TimeoutExpired:
+                self._logger.error(f"ht process {self._ht_proc.pid} did not respond to SIGKILL within timeout")
 
         self._exit_code = self._ht_proc.returncode
         if self._exit_code is None:
-            raise RuntimeError("Failed to determine ht process exit code")
+            raise HttyProtocolError("Failed to determine ht process exit code")
 
         self._logger.debug(f"HTProcess exited via forced termination: exit_code={self._exit_code}")
         return self._exit_code
 
-    def expect(self, pattern: str, timeout: float = DEFAULT_EXPECT_TIMEOUT) -> None:
+    def _check_running(self) -> None:
+        """
+        Raise if ht has exited, blaming the command if its exit is what ended ht.
+        """
+        if (returncode := self._ht_proc.poll()) is None:
+            return
+
+        if self._subprocess_exited:
+            raise HttyProcessError(
+                f"ht process has exited with code {returncode}, after the command exited with code "
+                f"{self.cmd.exit_code}",
+                exit_code=self.cmd.exit_code,
+                snapshot=self._latest_snapshot,
+            )
+
+        raise HttyProtocolError(f"ht process has exited with code {returncode}")
+
+    def expect(self, pattern: str, timeout: float = DEFAULT_EXPECT_TIMEOUT) -> "ExpectResult":
         """
-        Wait for a regex pattern to appear in the terminal output.
+        Wait for a regex pattern to appear on the terminal's screen.
 
-        This method efficiently waits for output by monitoring the output events from
-        the ht process rather than polling with snapshots. It checks both the current
-        terminal state (via snapshot) and any new output that arrives.
+        The screen is checked straight away, and again each time more output arrives, so the match is always against
+        what's actually on screen rather than against a chunk of raw output.
+
+        ```python
+        result = proc.expect(r"(\\d+) files? copied")
+        assert result.match.group(1) == "3"
+        assert "done" in result.snapshot.text
+        ```
 
         Args:
             pattern: The regex pattern to look for in the terminal output
             timeout: Maximum time to wait in seconds (default: 5.0)
 
+        Returns:
+            An `ExpectResult` holding the match and the snapshot it was found in
+
         Raises:
-            TimeoutError: If the pattern doesn't appear within the timeout period
-            RuntimeError: If the ht process has exited
+            HttyTimeoutError: If the pattern doesn't appear within the timeout period
+            HttyProcessError: If the command exits without the pattern having appeared
+            HttyProtocolError: If ht has exited or stops answering
         """
-        if self._ht_proc.poll() is not None:
-            raise RuntimeError(f"ht process has exited with code {self._ht_proc.returncode}")
+        self._check_running()
 
         self._logger.debug(f"Expecting regex pattern: '{pattern}'")
 
@@ -478,51 +1083,56 @@ class HtWrapper:
         except re.error as e:
             raise ValueError(f"Invalid regex pattern '{pattern}': {e}") from e
 
-        # First check current terminal state
-        snapshot = self.snapshot()
-        if regex.search(snapshot.text):
-            self._logger.debug(f"Pattern '{pattern}' found immediately in current terminal state")
-            return
-
-        # Start time for timeout tracking
-        start_time = time.time()
+        deadline = time.monotonic() + timeout
 
-        # Process events until we find the pattern or timeout
         while True:
-            # Check timeout
-            if time.time() - start_time > timeout:
+            # Checked before the snapshot, so that it's known to show everything the command wrote
+            finished = self._cmd_finished.is_set()
+            snapshot = self.snapshot()
+            match = regex.search(snapshot.text)
+            if match:
+                self._logger.debug(f"Pattern '{pattern}' found in terminal output")
+                return ExpectResult(match, snapshot)
+
+            if finished:
+                raise HttyProcessError(
+                    f"Pattern '{pattern}' not found before the command exited with code {self.cmd.exit_code}",
+                    exit_code=self.cmd.exit_code,
+                    snapshot=snapshot,
+                )
+
+            if not self._wait_for_output(deadline):
                 self._logger.debug(f"Pattern '{pattern}' not found in terminal output after {timeout} seconds")
-                raise TimeoutError(f"Pattern '{pattern}' not found within {timeout} seconds")
+                raise HttyTimeoutError(f"Pattern '{pattern}' not found within {timeout} seconds")
 
-            try:
-                # Wait for next event with a short timeout to allow checking the overall timeout
-                event = self._event_queue.get(block=True, timeout=0.1)
-            except queue.Empty:
-                continue
+    def _wait_for_output(self, deadline: float) -> bool:
+        """
+        Block on the event queue until more output arrives or the command exits, returning False if neither happens
+        before the deadline.
+        """
+        deferred: list[dict[str, Any]] = []
 
-            # Process the event
-            if event["type"] == "output":
-                self._output_events.append(event)
-                # Check if pattern appears in this output
-                if "data" in event and "seq" in event["data"] and regex.search(event["data"]["seq"]):
-                    self._logger.debug(f"Pattern '{pattern}' found in output event")
-                    return
-            elif event["type"] == "exitCode":
-                # Put back in queue for reader thread to handle
+        try:
+            while (remaining := deadline - time.monotonic()) > 0:
+                try:
+                    event = self._event_queue.get(block=True, timeout=remaining)
+                except queue.Empty:
+                    break
+
+                if event["type"] == "output":
+                    # The reader thread has already added it to the output events
+                    return True
+                elif event["type"] == "exitCode":
+                    deferred.append(event)
+                    return True
+
+                # Hand other events back once we're done, for whoever is waiting on them
+                deferred.append(event)
+        finally:
+            for event in deferred:
                 self._event_queue.put(event)
-                # Don't raise here - the process might have exited after outputting what we want
-            elif event["type"] == "snapshot":
-                # If we get a snapshot event, check its content
-                if "data" in event and "text" in event["data"] and regex.search(event["data"]["text"]):
-                    self._logger.debug(f"Pattern '{pattern}' found in snapshot event")
-                    return
-
-            # Take a new snapshot periodically to catch any missed output
-            if len(self._output_events) % 10 == 0:  # Every 10 events
-                snapshot = self.snapshot()
-                if regex.search(snapshot.text):
-                    self._logger.debug(f"Pattern '{pattern}' found in periodic snapshot")
-                    return
+
+        return False
 
     def expect_absent(self, pattern: str, timeout: float = DEFAULT_EXPECT_TIMEOUT) -> None:
         """
@@ -537,11 +1147,10 @@ class HtWrapper:
             timeout: Maximum time to wait in seconds (default: 5.0)
 
         Raises:
-            TimeoutError: If the pattern doesn't disappear within the timeout period
-            RuntimeError: If the ht process has exited
+            HttyTimeoutError: If the pattern doesn't disappear within the timeout period
+            HttyProtocolError: If ht has exited or stops answering
         """
-        if self._ht_proc.poll() is not None:
-            raise RuntimeError(f"ht process has exited with code {self._ht_proc.returncode}")
+        self._check_running()
 
         self._logger.debug(f"Expecting regex pattern to disappear: '{pattern}'")
 
@@ -564,7 +1173,7 @@ class HtWrapper:
             # Check timeout
             if time.time() - start_time > timeout:
                 self._logger.debug(f"Pattern '{pattern}' still present in terminal output after {timeout} seconds")
-                raise TimeoutError(f"Pattern '{pattern}' still present after {timeout} seconds")
+                raise HttyTimeoutError(f"Pattern '{pattern}' still present after {timeout} seconds")
 
             # Wait for next event with a short timeout
             try:
@@ -588,6 +1197,11 @@ def terminal_session(
     cols: Cols = None,
     logger: Logger = None,
     extra_subscribes: ExtraSubscribes = None,
+    max_events: MaxEvents = DEFAULT_MAX_EVENTS,
+    record: Record = False,
+    env: Env = None,
+    extra_env: ExtraEnv = None,
+    cwd: Cwd = None,
 ) -> Iterator[HtWrapper]:
     """
     The terminal_session context manager is a wrapper around `run` which ensures that the underlying process
@@ -616,27 +1230,14 @@ def terminal_session(
         no_exit=True,
         logger=logger,
         extra_subscribes=extra_subscribes,
+        max_events=max_events,
+        record=record,
+        env=env,
+        extra_env=extra_env,
+        cwd=cwd,
     )
-    try:
+    with proc:
         yield proc
-    finally:
-        try:
-            if proc.cmd.pid:
-                proc.cmd.terminate()
-                proc.cmd.wait(timeout=DEFAULT_SUBPROCESS_WAIT_TIMEOUT)
-        except Exception:
-            try:
-                if proc.cmd.pid:
-                    proc.cmd.kill()
-            except Exception:
-                pass
-
-        try:
-            proc.ht.terminate()
-            proc.ht.wait(timeout=DEFAULT_SUBPROCESS_WAIT_TIMEOUT)
-        except Exception:
-            with suppress(Exception):
-                proc.ht.kill()
 
 
 def run(
@@ -647,6 +1248,11 @@ def run(
     logger: Logger = None,
     extra_subscribes: ExtraSubscribes = None,
     style_mode: Optional[StyleMode] = None,
+    max_events: MaxEvents = DEFAULT_MAX_EVENTS,
+    record: Record = False,
+    env: Env = None,
+    extra_env: ExtraEnv = None,
+    cwd: Cwd = None,
 ) -> HtWrapper:
     """
     As a user of the htty python library, your code will run in the python process at the root of this
@@ -676,8 +1282,28 @@ def run(
     proc.exit()
     ```
 
-    If you'd rather not risk having a bunch of `ht` processes lying around and wasting CPU cycles,
-    consider using the `terminal_session` instead.
+    If you'd rather not risk having a bunch of `ht` processes lying around and wasting CPU cycles, use the
+    returned `HtWrapper` as a context manager (or use `terminal_session`), which cleans up even if your code raises:
+
+    ```python
+    with run("some command") as proc:
+        # do stuff
+    ```
+
+    Output events are collected as they arrive (see `HtWrapper.get_output`). Only the latest `max_events` of them
+    are kept, so a chatty command can run indefinitely without the collection growing along with it. With
+    `record=True` all of the output is kept besides, for `HtWrapper.save_cast`.
+
+    The command runs in this process's environment and working directory unless told otherwise, so there's no need
+    to change `os.environ` or chdir to give it its own:
+
+    ```python
+    with run("./my-app", env={"PATH": "/usr/bin:/bin"}, extra_env={"MY_APP_DEBUG": "1"}, cwd=tmp_path) as proc:
+        proc.expect("debug mode")
+    ```
+
+    `env` replaces the environment entirely, and `extra_env` is set on top of it (or on top of this process's, if
+    there's no `env`). ht itself runs with the same environment, so its `HTTY_*` settings can be given there too.
 
     For reasons that are documented in
     [htty-core](https://matrixmanatyrservice.github.io/htty/htty-core/htty_core.html#HtEvent.COMMAND_COMPLETED), the
@@ -687,11 +1313,17 @@ def run(
 
     Instead it's something like this:
 
-        sh -c '{command} ; exit_code=$? ; /path/to/ht wait-exit /path/to/tmp/ht_fifo_5432 ; exit $exit_code'
+        sh -c '{command} ; exit_code=$? ; '/path/to/ht' wait-exit --timeout 30000 '$XDG_RUNTIME_DIR/htty-XXXXXX/exit.fifo' ; exit $exit_code'
 
-    Because of this, it's possible to come up with command strings that cause sh to behave in problematic ways (for
-    example: `'`). For now the mitigation for this is: "don't do that." (If you'd like me to prioritize changing this
-    please leave a comment in https://github.com/MatrixManAtYrService/htty/issues/2)
+    A command given as a string is pasted in as `{command}`, so it's up to you to make it a valid command line: one
+    that sh can't parse on its own (an unbalanced `'`, say) breaks the rest of the line too. Give the command as a
+    list instead, and each argument is quoted for you, so the program receives them exactly as they are, without the
+    shell interpreting any of them:
+
+    ```python
+    with run(["printf", "%s", "it's $HOME; not a variable"]) as proc:
+        assert "it's $HOME" in proc.snapshot().text
+    ```
     """
     # Use provided logger or fall back to default
     process_logger = logger or default_logger
@@ -717,16 +1349,19 @@ def run(
             except ValueError:
                 process_logger.warning(f"Unknown subscription event: {sub}")
 
-    # Convert command to string if it's a list, properly escaping shell arguments
+    # How the command reads in logs and errors, with its arguments quoted as the shell would need
     command_str = command if isinstance(command, str) else " ".join(shlex.quote(arg) for arg in command)
 
     # Create HtArgs and use htty_core.run()
     ht_args = HtArgs(
-        command=command_str,  # Use the already-formatted command string
+        command=command,
         subscribes=base_subscribes,
         rows=rows,
         cols=cols,
         style_mode=style_mode,
+        no_exit=no_exit,
+        env=command_env(env, extra_env),
+        cwd=cwd,
     )
 
     # Log the exact command that would be run
@@ -764,22 +1399,39 @@ def run(
                 event = json.loads(line)
                 thread_logger.debug(f"ht event: {event}")
                 queue_obj.put(event)
+                ht_process.publish_event(event)
 
                 if event["type"] == "output":
                     ht_process.add_output_event(event)
+                elif "pane" in event.get("data", {}):
+                    # The command's state is the main pane's; other panes' commands come and go on their own
+                    pass
                 elif event["type"] == "exitCode":
                     thread_logger.debug(
                         f"ht process {ht_proc.pid} subprocess exited with code: {event.get('data', {}).get('exitCode')}"
                     )
-                    ht_process.set_subprocess_exited(True)
                     exit_code = event.get("data", {}).get("exitCode")
                     if exit_code is not None:
                         ht_process.cmd.exit_code = exit_code
+                    # Only once the exit code is in place, since this wakes up `cmd.wait()`
+                    ht_process.set_subprocess_exited(True)
+                elif event["type"] == "respawn":
+                    # ht is relaunching the command, which is no longer exited, until its next exitCode event
+                    ht_process.cmd.exit_code = None
+                    ht_process.set_subprocess_completed(False)
+                    ht_process.set_subprocess_exited(False)
+                elif event["type"] == "init":
+                    # The command may have been started before we subscribed, in which case its pid event has been
+                    # and gone, but init has the PID too
+                    pid = event.get("data", {}).get("pid")
+                    if pid:
+                        ht_process.set_subprocess_pid(pid)
+                    ht_process.set_initialized()
                 elif event["type"] == "pid":
                     thread_logger.debug(f"ht process {ht_proc.pid} subprocess PID: {event.get('data', {}).get('pid')}")
                     pid = event.get("data", {}).get("pid")
                     if pid is not None:
-                        ht_process.cmd.pid = pid
+                        ht_process.set_subprocess_pid(pid)
                 elif event["type"] == "commandCompleted":
                     # Command has completed - this is the reliable signal that subprocess finished
                     ht_process.set_subprocess_completed(True)
@@ -792,6 +1444,7 @@ def run(
                 thread_logger.warning(f"ht process {ht_proc.pid} non-JSON stdout: {line} (error: {e})")
                 pass
 
+        ht_process.publish_event(None)
         thread_logger.debug(f"Reader thread exiting for ht process {ht_proc.pid}")
 
     # Create an HtWrapper instance
@@ -803,6 +1456,8 @@ def run(
         cols=cols,
         no_exit=no_exit,
         logger=process_logger,
+        max_events=max_events,
+        record=record,
     )
 
     # Start the reader thread for stdout
@@ -812,6 +1467,7 @@ def run(
         daemon=True,
     )
     stdout_thread.start()
+    process.add_reader_thread(stdout_thread)
 
     # Start a stderr reader thread
     def stderr_reader_thread(ht_proc: subprocess.Popen[str], thread_logger: logging.Logger) -> None:
@@ -835,18 +1491,15 @@ def run(
 
     stderr_thread = threading.Thread(target=stderr_reader_thread, args=(ht_proc, process_logger), daemon=True)
     stderr_thread.start()
+    process.add_reader_thread(stderr_thread)
 
-    # Wait briefly for the process to initialize and get PID
-    start_time = time.time()
-    while time.time() - start_time < 2:
-        try:
-            event = event_queue.get(block=True, timeout=0.5)
-            if event["type"] == "pid":
-                pid = event["data"]["pid"]
-                process.cmd.pid = pid
-                break
-        except queue.Empty:
-            continue
+    # ht answers the subscription straight away, so waiting on it rather than sleeping means returning as soon as the
+    # command is up
+    try:
+        process.wait_for_startup(timeout=DEFAULT_SNAPSHOT_TIMEOUT)
+    except HttyError:
+        with process:
+            pass
+        raise
 
-    time.sleep(DEFAULT_SLEEP_AFTER_KEYS)
     return process
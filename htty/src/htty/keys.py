@@ -1,6 +1,6 @@
 """
 Key definitions for htty
-Based on the key parsing logic in ht's stdio.rs file.
+Generated from the key parsing logic in ht's stdio.rs file.
 """
 
 from enum import Enum
@@ -10,19 +10,111 @@ from typing import Union
 class Press(Enum):
     """@private"""
 
-    ENTER = "Enter"
+    # Generated from the key names ht understands (KEY_NAMES in htty-core/src/rust/api/stdio.rs), plus the
+    # families of keys its parser builds from a pattern, so the two can't disagree. Rerun cog after changing either.
+    # [[[cog
+    # import re
+    # from pathlib import Path
+    #
+    # root = Path(cog.inFile).resolve().parents[3]
+    # stdio = (root / "htty-core/src/rust/api/stdio.rs").read_text()
+    # table = re.search(r"pub const KEY_NAMES: &\[&\[&str\]\] = &\[\n(.*?)\n\];", stdio, re.S).group(1)
+    # groups = [
+    #     [name.replace("\\\\", "\\") for name in re.findall(r'"((?:[^"\\]|\\.)*)"', line)]
+    #     for line in table.splitlines()
+    # ]
+    #
+    # # The patterns parse_key falls back on
+    # letters = "abcdefghijklmnopqrstuvwxyz"
+    # symbols = {
+    #     " ": "SPACE", "!": "EXCLAMATION", "@": "AT", "#": "HASH", "$": "DOLLAR", "%": "PERCENT", "^": "CARET",
+    #     "&": "AMPERSAND", "*": "ASTERISK", "(": "LPAREN", ")": "RPAREN", "-": "MINUS", "_": "UNDERSCORE",
+    #     "=": "EQUALS", "+": "PLUS", "[": "LBRACKET", "]": "RBRACKET", "{": "LBRACE", "}": "RBRACE",
+    #     "\\": "BACKSLASH", "|": "PIPE", ";": "SEMICOLON", ":": "COLON", "'": "QUOTE", '"': "DOUBLEQUOTE",
+    #     ",": "COMMA", ".": "DOT", "<": "LESS", ">": "GREATER", "/": "SLASH", "?": "QUESTION", "`": "BACKTICK",
+    #     "~": "TILDE",
+    # }
+    # groups += [[f"C-{c}"] for c in letters + letters.upper()]
+    # groups += [[f"A-{c}"] for c in letters + letters.upper() + "0123456789" + "".join(symbols)]
+    # groups += [[f"^{c}"] for c in letters + letters.upper()]
+    #
+    # modifiers = {"C": "CTRL", "A": "ALT", "S": "SHIFT"}
+    #
+    # def member(key):
+    #     prefix = []
+    #     if key.startswith("^"):
+    #         prefix, key = ["CARET"], key[1:]
+    #     mods = []
+    #     while len(key) > 2 and key[0] in modifiers and key[1] == "-":
+    #         mods, key = mods + [key[0]], key[2:]
+    #     if len(key) == 1 and key.isupper():
+    #         mods, key = mods + ["S"], key.lower()
+    #     mods = [modifiers[m] for m in modifiers if m in mods]
+    #     base = symbols.get(key) or re.sub(r"(?<=[a-z])(?=[A-Z])", "_", key).upper()
+    #     return "_".join(prefix + mods + [base])
+    #
+    # seen = set()
+    # for names in groups:
+    #     members = []
+    #     for name in names:
+    #         if member(name) not in seen:
+    #             seen.add(member(name))
+    #             members.append((member(name), name))
+    #     for m, name in members:
+    #         value = repr(name) if '"' in name else '"' + name.replace("\\", "\\\\") + '"'
+    #         aliases = ", ".join(other for _, other in members if other != name)
+    #         cog.outl(f"{m} = {value}" + (f"  # same as {aliases}" if aliases else ""))
+    # ]]]
+    CTRL_AT = "C-@"  # same as C-Space, ^@
+    CTRL_SPACE = "C-Space"  # same as C-@, ^@
+    CARET_AT = "^@"  # same as C-@, C-Space
+    CTRL_LBRACKET = "C-["  # same as Escape, ^[
+    ESCAPE = "Escape"  # same as C-[, ^[
+    CARET_LBRACKET = "^["  # same as C-[, Escape
+    CTRL_BACKSLASH = "C-\\"  # same as ^\
+    CARET_BACKSLASH = "^\\"  # same as C-\
+    CTRL_RBRACKET = "C-]"  # same as ^]
+    CARET_RBRACKET = "^]"  # same as C-]
+    CTRL_CARET = "C-^"  # same as C-/
+    CTRL_SLASH = "C-/"  # same as C-^
+    CTRL_MINUS = "C--"  # same as C-_
+    CTRL_UNDERSCORE = "C-_"  # same as C--
     TAB = "Tab"
+    ENTER = "Enter"
+    BACKSPACE = "Backspace"
     SPACE = "Space"
-    ESCAPE = "Escape"
     LEFT = "Left"
     RIGHT = "Right"
     UP = "Up"
     DOWN = "Down"
-    HOME = "Home"
-    END = "End"
-    PAGE_UP = "PageUp"
-    PAGE_DOWN = "PageDown"
-    BACKSPACE = "Backspace"
+    CTRL_LEFT = "C-Left"
+    CTRL_RIGHT = "C-Right"
+    SHIFT_LEFT = "S-Left"
+    SHIFT_RIGHT = "S-Right"
+    CTRL_UP = "C-Up"
+    CTRL_DOWN = "C-Down"
+    SHIFT_UP = "S-Up"
+    SHIFT_DOWN = "S-Down"
+    ALT_LEFT = "A-Left"
+    ALT_RIGHT = "A-Right"
+    ALT_UP = "A-Up"
+    ALT_DOWN = "A-Down"
+    CTRL_SHIFT_LEFT = "C-S-Left"
+    CTRL_SHIFT_RIGHT = "C-S-Right"
+    CTRL_SHIFT_UP = "C-S-Up"
+    CTRL_SHIFT_DOWN = "C-S-Down"
+    CTRL_ALT_LEFT = "C-A-Left"
+    CTRL_ALT_RIGHT = "C-A-Right"
+    CTRL_ALT_UP = "C-A-Up"
+    CTRL_ALT_DOWN = "C-A-Down"
+    ALT_SHIFT_LEFT = "A-S-Left"
+    ALT_SHIFT_RIGHT = "A-S-Right"
+    ALT_SHIFT_UP = "A-S-Up"
+    ALT_SHIFT_DOWN = "A-S-Down"
+    CTRL_ALT_SHIFT_LEFT = "C-A-S-Left"
+    CTRL_ALT_SHIFT_RIGHT = "C-A-S-Right"
+    CTRL_ALT_SHIFT_UP = "C-A-S-Up"
+    CTRL_ALT_SHIFT_DOWN = "C-A-S-Down"
     F1 = "F1"
     F2 = "F2"
     F3 = "F3"
@@ -35,6 +127,67 @@ class Press(Enum):
     F10 = "F10"
     F11 = "F11"
     F12 = "F12"
+    CTRL_F1 = "C-F1"
+    CTRL_F2 = "C-F2"
+    CTRL_F3 = "C-F3"
+    CTRL_F4 = "C-F4"
+    CTRL_F5 = "C-F5"
+    CTRL_F6 = "C-F6"
+    CTRL_F7 = "C-F7"
+    CTRL_F8 = "C-F8"
+    CTRL_F9 = "C-F9"
+    CTRL_F10 = "C-F10"
+    CTRL_F11 = "C-F11"
+    CTRL_F12 = "C-F12"
+    SHIFT_F1 = "S-F1"
+    SHIFT_F2 = "S-F2"
+    SHIFT_F3 = "S-F3"
+    SHIFT_F4 = "S-F4"
+    SHIFT_F5 = "S-F5"
+    SHIFT_F6 = "S-F6"
+    SHIFT_F7 = "S-F7"
+    SHIFT_F8 = "S-F8"
+    SHIFT_F9 = "S-F9"
+    SHIFT_F10 = "S-F10"
+    SHIFT_F11 = "S-F11"
+    SHIFT_F12 = "S-F12"
+    ALT_F1 = "A-F1"
+    ALT_F2 = "A-F2"
+    ALT_F3 = "A-F3"
+    ALT_F4 = "A-F4"
+    ALT_F5 = "A-F5"
+    ALT_F6 = "A-F6"
+    ALT_F7 = "A-F7"
+    ALT_F8 = "A-F8"
+    ALT_F9 = "A-F9"
+    ALT_F10 = "A-F10"
+    ALT_F11 = "A-F11"
+    ALT_F12 = "A-F12"
+    HOME = "Home"
+    CTRL_HOME = "C-Home"
+    SHIFT_HOME = "S-Home"
+    ALT_HOME = "A-Home"
+    END = "End"
+    CTRL_END = "C-End"
+    SHIFT_END = "S-End"
+    ALT_END = "A-End"
+    PAGE_UP = "PageUp"
+    CTRL_PAGE_UP = "C-PageUp"
+    SHIFT_PAGE_UP = "S-PageUp"
+    ALT_PAGE_UP = "A-PageUp"
+    PAGE_DOWN = "PageDown"
+    CTRL_PAGE_DOWN = "C-PageDown"
+    SHIFT_PAGE_DOWN = "S-PageDown"
+    ALT_PAGE_DOWN = "A-PageDown"
+    INSERT = "Insert"
+    CTRL_INSERT = "C-Insert"
+    SHIFT_INSERT = "S-Insert"
+    ALT_INSERT = "A-Insert"
+    DELETE = "Delete"
+    CTRL_DELETE = "C-Delete"
+    SHIFT_DELETE = "S-Delete"
+    ALT_DELETE = "A-Delete"
+    SHIFT_TAB = "S-Tab"
     CTRL_A = "C-a"
     CTRL_B = "C-b"
     CTRL_C = "C-c"
@@ -87,55 +240,6 @@ class Press(Enum):
     CTRL_SHIFT_X = "C-X"
     CTRL_SHIFT_Y = "C-Y"
     CTRL_SHIFT_Z = "C-Z"
-    CTRL_AT = "C-@"  # Also equivalent to C-Space
-    CTRL_SPACE = "C-Space"  # Also equivalent to C-@
-    CTRL_LBRACKET = "C-["  # Also equivalent to Escape
-    CTRL_BACKSLASH = "C-\\"
-    CTRL_RBRACKET = "C-]"
-    CTRL_CARET = "C-^"  # Also equivalent to C-/
-    CTRL_SLASH = "C-/"  # Also equivalent to C-^
-    CTRL_MINUS = "C--"  # Also equivalent to C-_
-    CTRL_UNDERSCORE = "C-_"  # Also equivalent to C--
-    CTRL_LEFT = "C-Left"
-    CTRL_RIGHT = "C-Right"
-    CTRL_UP = "C-Up"
-    CTRL_DOWN = "C-Down"
-    CTRL_HOME = "C-Home"
-    CTRL_END = "C-End"
-    CTRL_PAGE_UP = "C-PageUp"
-    CTRL_PAGE_DOWN = "C-PageDown"
-    CTRL_F1 = "C-F1"
-    CTRL_F2 = "C-F2"
-    CTRL_F3 = "C-F3"
-    CTRL_F4 = "C-F4"
-    CTRL_F5 = "C-F5"
-    CTRL_F6 = "C-F6"
-    CTRL_F7 = "C-F7"
-    CTRL_F8 = "C-F8"
-    CTRL_F9 = "C-F9"
-    CTRL_F10 = "C-F10"
-    CTRL_F11 = "C-F11"
-    CTRL_F12 = "C-F12"
-    SHIFT_LEFT = "S-Left"
-    SHIFT_RIGHT = "S-Right"
-    SHIFT_UP = "S-Up"
-    SHIFT_DOWN = "S-Down"
-    SHIFT_HOME = "S-Home"
-    SHIFT_END = "S-End"
-    SHIFT_PAGE_UP = "S-PageUp"
-    SHIFT_PAGE_DOWN = "S-PageDown"
-    SHIFT_F1 = "S-F1"
-    SHIFT_F2 = "S-F2"
-    SHIFT_F3 = "S-F3"
-    SHIFT_F4 = "S-F4"
-    SHIFT_F5 = "S-F5"
-    SHIFT_F6 = "S-F6"
-    SHIFT_F7 = "S-F7"
-    SHIFT_F8 = "S-F8"
-    SHIFT_F9 = "S-F9"
-    SHIFT_F10 = "S-F10"
-    SHIFT_F11 = "S-F11"
-    SHIFT_F12 = "S-F12"
     ALT_A = "A-a"
     ALT_B = "A-b"
     ALT_C = "A-c"
@@ -215,6 +319,8 @@ class Press(Enum):
     ALT_PLUS = "A-+"
     ALT_LBRACKET = "A-["
     ALT_RBRACKET = "A-]"
+    ALT_LBRACE = "A-{"
+    ALT_RBRACE = "A-}"
     ALT_BACKSLASH = "A-\\"
     ALT_PIPE = "A-|"
     ALT_SEMICOLON = "A-;"
@@ -223,49 +329,12 @@ class Press(Enum):
     ALT_DOUBLEQUOTE = 'A-"'
     ALT_COMMA = "A-,"
     ALT_DOT = "A-."
+    ALT_LESS = "A-<"
+    ALT_GREATER = "A->"
     ALT_SLASH = "A-/"
     ALT_QUESTION = "A-?"
     ALT_BACKTICK = "A-`"
     ALT_TILDE = "A-~"
-    ALT_LEFT = "A-Left"
-    ALT_RIGHT = "A-Right"
-    ALT_UP = "A-Up"
-    ALT_DOWN = "A-Down"
-    ALT_HOME = "A-Home"
-    ALT_END = "A-End"
-    ALT_PAGE_UP = "A-PageUp"
-    ALT_PAGE_DOWN = "A-PageDown"
-    ALT_F1 = "A-F1"
-    ALT_F2 = "A-F2"
-    ALT_F3 = "A-F3"
-    ALT_F4 = "A-F4"
-    ALT_F5 = "A-F5"
-    ALT_F6 = "A-F6"
-    ALT_F7 = "A-F7"
-    ALT_F8 = "A-F8"
-    ALT_F9 = "A-F9"
-    ALT_F10 = "A-F10"
-    ALT_F11 = "A-F11"
-    ALT_F12 = "A-F12"
-    CTRL_SHIFT_LEFT = "C-S-Left"
-    CTRL_SHIFT_RIGHT = "C-S-Right"
-    CTRL_SHIFT_UP = "C-S-Up"
-    CTRL_SHIFT_DOWN = "C-S-Down"
-    CTRL_ALT_LEFT = "C-A-Left"
-    CTRL_ALT_RIGHT = "C-A-Right"
-    CTRL_ALT_UP = "C-A-Up"
-    CTRL_ALT_DOWN = "C-A-Down"
-    ALT_SHIFT_LEFT = "A-S-Left"
-    ALT_SHIFT_RIGHT = "A-S-Right"
-    ALT_SHIFT_UP = "A-S-Up"
-    ALT_SHIFT_DOWN = "A-S-Down"
-    CTRL_ALT_SHIFT_LEFT = "C-A-S-Left"
-    CTRL_ALT_SHIFT_RIGHT = "C-A-S-Right"
-    CTRL_ALT_SHIFT_UP = "C-A-S-Up"
-    CTRL_ALT_SHIFT_DOWN = "C-A-S-Down"
-
-    # ===== CARET NOTATION (alternative syntax for Control keys) =====
-    # ht supports both C-a and ^a for the same keys
     CARET_A = "^a"
     CARET_B = "^b"
     CARET_C = "^c"
@@ -318,10 +387,7 @@ class Press(Enum):
     CARET_SHIFT_X = "^X"
     CARET_SHIFT_Y = "^Y"
     CARET_SHIFT_Z = "^Z"
-    CARET_AT = "^@"
-    CARET_LBRACKET = "^["
-    CARET_BACKSLASH = "^\\"
-    CARET_RBRACKET = "^]"
+    # [[[end]]]
 
 
 KeyInput = Union[Press, str]
@@ -0,0 +1,190 @@
+"""
+A pool of htty sessions running the same command, for test suites where starting a fresh ht for every test takes
+longer than the tests themselves.
+
+```python
+import pytest
+
+from htty import Press
+from htty.pool import SessionPool
+
+
+@pytest.fixture(scope="session")
+def shells():
+    with SessionPool("sh -i", size=4, rows=10, cols=40) as pool:
+        yield pool
+
+
+def test_echo(shells):
+    with shells.session() as sh:
+        sh.send_keys(["echo hi", Press.ENTER])
+        sh.expect("^hi")
+```
+
+The pool keeps `size` sessions running at most, starting spares in the background so that there's one ready
+whenever a test asks for it. A session that's handed back is ended and replaced, unless the pool was given a `reset`
+function that puts it back the way a new one would be (and returns True), in which case it's handed out again as is.
+A reused session keeps what it recorded before, like its output events, asciicast and `on()` callbacks.
+"""
+
+import logging
+import queue
+from collections.abc import Callable, Iterator
+from concurrent.futures import ThreadPoolExecutor
+from contextlib import contextmanager, suppress
+from typing import Any, Optional, Union
+
+from htty_core import Command
+
+from .errors import HttyProtocolError, HttyTimeoutError
+from .ht import HtWrapper, Logger, run
+
+__all__ = ["SessionPool"]
+
+default_logger = logging.getLogger(__name__)
+
+
+class SessionPool:
+    """
+    Up to `size` sessions of `command`, handed out one test at a time.
+
+    Takes the same keyword arguments as `htty.run` for starting the sessions.
+    """
+
+    def __init__(
+        self,
+        command: Command,
+        size: int = 4,
+        reset: Optional[Callable[[HtWrapper], bool]] = None,
+        logger: Logger = None,
+        **run_kwargs: Any,
+    ) -> None:
+        if size < 1:
+            raise ValueError(f"A session pool needs room for at least one session, not {size}")
+
+        self._command = command
+        self._reset = reset
+        self._logger = logger or default_logger
+        self._run_kwargs: dict[str, Any] = {"logger": logger, **run_kwargs}
+
+        # Started sessions that nobody is using, or the exception that stopped one from starting
+        self._ready: queue.Queue[Union[HtWrapper, Exception]] = queue.Queue()
+        self._closed = False
+        self._executor = ThreadPoolExecutor(max_workers=size, thread_name_prefix="htty-pool")
+
+        for _ in range(size):
+            self._executor.submit(self._start)
+
+    def __enter__(self) -> "SessionPool":
+        return self
+
+    def __exit__(self, *exc_info: object) -> None:
+        self.close()
+
+    def acquire(self, timeout: Optional[float] = None) -> HtWrapper:
+        """
+        Take a session from the pool, waiting up to `timeout` seconds for one to be free. Hand it back with `release`.
+
+        Raises:
+            HttyTimeoutError: If every session is still in use after the timeout
+            HttyProtocolError: If a session couldn't be started
+        """
+        while True:
+            if self._closed:
+                raise RuntimeError("The session pool has been closed")
+
+            try:
+                proc = self._ready.get(timeout=timeout)
+            except queue.Empty as e:
+                raise HttyTimeoutError(f"No session became free within {timeout} seconds") from e
+
+            if isinstance(proc, Exception):
+                # Try again for whoever asks next
+                with suppress(RuntimeError):
+                    self._executor.submit(self._start)
+                raise HttyProtocolError(f"Failed to start a session of {self._command!r}: {proc}") from proc
+
+            if proc.ht.poll() is None and proc.cmd.poll() is None:
+                return proc
+
+            self._logger.debug(f"Pooled session of {self._command!r} has exited, replacing it")
+            self._replace_later(proc)
+
+    def release(self, proc: HtWrapper) -> None:
+        """
+        Hand back a session taken with `acquire`, to be reset and reused or else replaced.
+        """
+        if self._closed:
+            self._end(proc)
+        elif self._reusable(proc):
+            self._ready.put(proc)
+        else:
+            self._replace_later(proc)
+
+    @contextmanager
+    def session(self, timeout: Optional[float] = None) -> Iterator[HtWrapper]:
+        """
+        A session from the pool for the duration of a `with` block, handed back afterwards even if the block raises.
+        """
+        proc = self.acquire(timeout)
+        try:
+            yield proc
+        finally:
+            self.release(proc)
+
+    def close(self) -> None:
+        """
+        End every session, waiting for spares that are still starting. Sessions in use are ended once they're
+        released.
+        """
+        self._closed = True
+        self._executor.shutdown(wait=True)
+
+        while True:
+            try:
+                proc = self._ready.get_nowait()
+            except queue.Empty:
+                break
+
+            if isinstance(proc, HtWrapper):
+                self._end(proc)
+
+    def _reusable(self, proc: HtWrapper) -> bool:
+        if self._reset is None or proc.ht.poll() is not None or proc.cmd.poll() is not None:
+            return False
+
+        try:
+            return self._reset(proc)
+        except Exception:
+            self._logger.exception(f"Resetting a session of {self._command!r} failed, replacing it")
+            return False
+
+    def _start(self) -> None:
+        if self._closed:
+            return
+
+        try:
+            proc = run(self._command, **self._run_kwargs)
+        except Exception as e:
+            self._ready.put(e)
+            return
+
+        if self._closed:
+            self._end(proc)
+        else:
+            self._ready.put(proc)
+
+    def _replace_later(self, proc: HtWrapper) -> None:
+        try:
+            self._executor.submit(self._replace, proc)
+        except RuntimeError:
+            # The pool was closed in the meantime
+            self._end(proc)
+
+    def _replace(self, proc: HtWrapper) -> None:
+        self._end(proc)
+        self._start()
+
+    def _end(self, proc: HtWrapper) -> None:
+        with suppress(Exception), proc:
+            pass
@@ -4,17 +4,18 @@ Process manipulation protocol and implementations for HTProcess wrapper.
 
 import os
 import signal
-import time
+import threading
 from contextlib import suppress
 from typing import TYPE_CHECKING, Optional, Protocol
 
+from .constants import DEFAULT_GRACEFUL_TERMINATION_TIMEOUT
+from .errors import HttyProtocolError, HttyTimeoutError
+
 if TYPE_CHECKING:
     from subprocess import Popen
 
     from .ht import HtWrapper
 
-DEFAULT_SLEEP_AFTER_KEYS = 0.1
-
 
 class ProcessController(Protocol):
     """Protocol for process manipulation operations."""
@@ -31,6 +32,10 @@ class ProcessController(Protocol):
         """Force kill the process."""
         ...
 
+    def shutdown(self, grace: float = DEFAULT_GRACEFUL_TERMINATION_TIMEOUT) -> Optional[int]:
+        """Stop the command and ht, escalating from SIGTERM to SIGKILL after `grace` seconds."""
+        ...
+
     def wait(self, timeout: Optional[float] = None) -> Optional[int]:
         """Wait for the process to finish."""
         ...
@@ -100,6 +105,17 @@ class HtProcess(ProcessController):
         with suppress(Exception):
             self._ht_proc.kill()
 
+    def shutdown(self, grace: float = DEFAULT_GRACEFUL_TERMINATION_TIMEOUT) -> Optional[int]:
+        """Stop the command and the ht process (see `HtWrapper.shutdown`), returning ht's exit code."""
+        if self._wrapper is not None:
+            return self._wrapper.shutdown(grace)
+
+        self.terminate()
+        if self.wait(timeout=grace) is None:
+            self.kill()
+            self.wait(timeout=grace)
+        return self._exit_code
+
     def wait(self, timeout: Optional[float] = None) -> Optional[int]:
         """Wait for the ht process to finish."""
         try:
@@ -127,7 +143,9 @@ class HtProcess(ProcessController):
 
     @property
     def exit_code(self) -> Optional[int]:
-        """Get the exit code of the process."""
+        """Get the exit code of the process, including when it exited without being waited for."""
+        if self._exit_code is None:
+            self._exit_code = self._ht_proc.poll()
         return self._exit_code
 
     @exit_code.setter
@@ -144,11 +162,16 @@ class HtProcess(ProcessController):
 class CmdProcess(ProcessController):
     """Controller for the subprocess being monitored by ht (merges SubprocessController functionality)."""
 
-    def __init__(self, pid: Optional[int] = None):
+    def __init__(self, finished: threading.Event, pid: Optional[int] = None, wrapper: Optional["HtWrapper"] = None):
+        """
+        `finished` is set once the command's exitCode event has been seen, or once ht has gone away without
+        sending one.
+        """
+        self._wrapper = wrapper
         self._pid = pid
         self._exit_code: Optional[int] = None
-        self._termination_initiated = False
         self._completed = False
+        self._finished = finished
 
     @property
     def pid(self) -> Optional[int]:
@@ -180,13 +203,13 @@ class CmdProcess(ProcessController):
         self._completed = completed
 
     def exit(self, timeout: Optional[float] = 5.0) -> int:
-        """Exit the subprocess (same as wait for subprocesses)."""
+        """Exit the subprocess (same as wait for subprocesses, but returning 0 if there's no exit code)."""
         result = self.wait(timeout)
         return result if result is not None else 0
 
     def poll(self) -> Optional[int]:
-        """Check if the subprocess is still running."""
-        if self._pid is None:
+        """Check if the subprocess is still running, returning its exit code if it isn't."""
+        if self._finished.is_set() or self._pid is None:
             return self._exit_code
         try:
             os.kill(self._pid, 0)
@@ -197,62 +220,52 @@ class CmdProcess(ProcessController):
     def terminate(self) -> None:
         """Terminate the subprocess."""
         if self._pid is None:
-            raise RuntimeError("No subprocess PID available")
+            raise HttyProtocolError("No subprocess PID available")
         with suppress(OSError):
-            self._termination_initiated = True
             os.kill(self._pid, signal.SIGTERM)
 
     def kill(self) -> None:
         """Force kill the subprocess."""
         if self._pid is None:
-            raise RuntimeError("No subprocess PID available")
+            raise HttyProtocolError("No subprocess PID available")
         with suppress(OSError):
-            self._termination_initiated = True
             os.kill(self._pid, signal.SIGKILL)
 
+    def shutdown(self, grace: float = DEFAULT_GRACEFUL_TERMINATION_TIMEOUT) -> Optional[int]:
+        """
+        Stop the subprocess and the ht process (see `HtWrapper.shutdown`), returning the subprocess's exit code, or
+        None if ht went away without reporting one.
+
+        Unlike `terminate` and `kill`, this doesn't leave ht running once the subprocess is gone.
+        """
+        if self._wrapper is None:
+            raise HttyProtocolError("No ht process to shut down")
+
+        self._wrapper.shutdown(grace)
+        return self._exit_code
+
     def wait(self, timeout: Optional[float] = 5.0) -> Optional[int]:
         """
         Wait for the subprocess to finish.
 
+        The command isn't a child of this process (it's a grandchild, by way of ht), so rather than reaping it this
+        waits for ht to report its exit.
+
         Args:
-            timeout: Maximum time to wait (in seconds). Defaults to 5.0 seconds.
+            timeout: Maximum time to wait (in seconds), or None to wait for as long as it takes. Defaults to 5.0
+                seconds.
 
         Returns:
-            The exit code of the subprocess, or None if timeout reached
+            The exit code of the subprocess, or None if ht went away without reporting one
+
+        Raises:
+            HttyTimeoutError: If the subprocess is still running after the timeout
+            HttyProtocolError: If ht never reported the subprocess's PID
         """
         if self._pid is None:
-            raise RuntimeError("No subprocess PID available")
+            raise HttyProtocolError("No subprocess PID available")
 
-        start_time = time.time()
-        while True:
-            try:
-                os.kill(self._pid, 0)  # Check if process is still running
-
-                # Check timeout
-                if timeout is not None and (time.time() - start_time) > timeout:
-                    return None  # Timeout reached
-
-                time.sleep(DEFAULT_SLEEP_AFTER_KEYS)
-            except OSError:
-                # Process has exited
-                # Try to get the actual exit code
-                try:
-                    pid_result, status = os.waitpid(self._pid, os.WNOHANG)
-                    if pid_result == self._pid:
-                        if os.WIFEXITED(status):
-                            self._exit_code = os.WEXITSTATUS(status)
-                        elif os.WIFSIGNALED(status):
-                            signal_num = os.WTERMSIG(status)
-                            self._exit_code = 128 + signal_num
-                        else:
-                            self._exit_code = 1
-                    else:
-                        # Process was already reaped, use stored exit code or default
-                        if self._exit_code is None:
-                            self._exit_code = 0 if not self._termination_initiated else 137
-                except OSError:
-                    # Couldn't get exit code, use reasonable default
-                    if self._exit_code is None:
-                        self._exit_code = 0 if not self._termination_initiated else 137
+        if not self._finished.wait(timeout):
+            raise HttyTimeoutError(f"Subprocess {self._pid} did not exit within {timeout} seconds")
 
-                return self._exit_code
+        return self._exit_code
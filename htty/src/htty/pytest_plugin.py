@@ -0,0 +1,186 @@
+"""
+A pytest plugin for testing terminal applications with htty. It's registered when htty is installed, so there's
+nothing to configure:
+
+```python
+def test_vim(ht_session):
+    vim = ht_session("vim", rows=20, cols=50)
+    vim.send_keys(["ihello", Press.ESCAPE])
+    vim.expect("hello")
+```
+
+`ht_session` starts a session (it takes the same arguments as `htty.run`, but records it unless given
+`record=False`) and cleans it up once the test is over.
+
+When a test fails, the final screen of each of its sessions is added to the report, and saved along with an
+[asciicast](https://docs.asciinema.org/manual/asciicast/v2/) recording of the session (replay it with
+`ht replay` or `asciinema play`) under `htty-artifacts/<test>/`. Set the directory with `--htty-artifacts` or the
+`htty_artifacts` ini option. The paths are also recorded as user properties, so they end up in JUnit XML reports.
+
+A test's sessions can be given a time limit with `@pytest.mark.htty_timeout(seconds)`, or a default one with
+`--htty-timeout` or the `htty_timeout` ini option. Once it runs out the screens are captured and the sessions are
+ended, so a hung application makes the test fail instead of hanging the test run.
+"""
+
+import re
+import threading
+from collections.abc import Callable, Generator
+from pathlib import Path
+from typing import Any, Optional
+
+import pytest
+
+from .errors import HttyError, HttyTimeoutError
+from .ht import HtWrapper, SnapshotResult, run
+
+__all__ = ["HtSession"]
+
+_sessions_key = pytest.StashKey[list["_Session"]]()
+
+
+class _Session:
+    """A session started by `ht_session`, and what's needed to capture it when its test fails."""
+
+    def __init__(self, proc: HtWrapper, command: str, recorded: bool) -> None:
+        self.proc = proc
+        self.command = command
+        self.recorded = recorded
+        self.final: Optional[SnapshotResult] = None
+
+    def capture(self) -> Optional[SnapshotResult]:
+        """The screen as it is now, or as it was when the session was ended."""
+        if self.final is None:
+            try:
+                self.final = self.proc.snapshot()
+            except HttyError:
+                pass
+
+        return self.final
+
+    def save(self, directory: Path, name: str) -> list[Path]:
+        """Write the final screen and the recording (if there is one) to `directory`, returning the files written."""
+        directory.mkdir(parents=True, exist_ok=True)
+        written: list[Path] = []
+
+        if (snapshot := self.capture()) is not None:
+            text = directory / f"{name}.txt"
+            text.write_text(snapshot.text + "\n")
+            html = directory / f"{name}.html"
+            html.write_text(snapshot.html)
+            written += [text, html]
+
+        if self.recorded:
+            cast = directory / f"{name}.cast"
+            self.proc.save_cast(cast)
+            written.append(cast)
+
+        return written
+
+    def end(self) -> None:
+        with self.proc:
+            pass
+
+
+HtSession = Callable[..., HtWrapper]
+"""The type of the `ht_session` fixture: call it like `htty.run` to start a session."""
+
+
+def pytest_addoption(parser: pytest.Parser) -> None:
+    group = parser.getgroup("htty")
+    group.addoption(
+        "--htty-artifacts",
+        metavar="DIR",
+        help="where to save the screens and recordings of htty sessions in failed tests (default: htty-artifacts)",
+    )
+    group.addoption(
+        "--htty-timeout",
+        type=float,
+        metavar="SECS",
+        help="end the htty sessions of a test that runs for longer than this, failing it",
+    )
+    parser.addini("htty_artifacts", "default for --htty-artifacts", default="htty-artifacts")
+    parser.addini("htty_timeout", "default for --htty-timeout", default="")
+
+
+def pytest_configure(config: pytest.Config) -> None:
+    config.addinivalue_line(
+        "markers", "htty_timeout(seconds): end the test's htty sessions after this long, failing the test"
+    )
+
+
+@pytest.hookimpl(hookwrapper=True)
+def pytest_runtest_makereport(item: pytest.Item, call: pytest.CallInfo[None]) -> Generator[None, Any, None]:
+    outcome = yield
+    report: pytest.TestReport = outcome.get_result()
+    sessions = item.stash.get(_sessions_key, [])
+
+    # Capture while the sessions are still running, before the fixture's teardown ends them
+    if report.when != "call" or not report.failed or not sessions:
+        return
+
+    directory = Path(item.config.getoption("htty_artifacts") or item.config.getini("htty_artifacts"))
+    directory = item.config.rootpath / directory / re.sub(r"[^\w.-]+", "_", item.nodeid)
+
+    for i, session in enumerate(sessions, start=1):
+        name = f"session-{i}"
+        files = session.save(directory, name)
+
+        snapshot = session.final.text if session.final is not None else "(no snapshot: ht has exited)"
+        report.sections.append((f"htty {name}: {session.command}", snapshot))
+        report.user_properties += [(f"htty {name} {path.suffix[1:]}", str(path)) for path in files]
+
+    report.sections.append(("htty artifacts", str(directory)))
+
+
+def _timeout(request: pytest.FixtureRequest) -> Optional[float]:
+    marker = request.node.get_closest_marker("htty_timeout")
+    if marker is not None:
+        return float(marker.args[0])
+
+    value = request.config.getoption("htty_timeout") or request.config.getini("htty_timeout")
+    return float(value) if value else None
+
+
+@pytest.fixture
+def ht_session(request: pytest.FixtureRequest) -> Generator[HtSession, None, None]:
+    """
+    Start htty sessions which are cleaned up when the test is over, and captured if it fails.
+
+    Takes the same arguments as `htty.run`, with `record` defaulting to True so that a failed test's recording can
+    be saved.
+    """
+    sessions: list[_Session] = []
+    request.node.stash[_sessions_key] = sessions
+    timed_out = threading.Event()
+    timeout = _timeout(request)
+
+    def expire() -> None:
+        timed_out.set()
+        for session in sessions:
+            session.capture()
+            session.end()
+
+    timer = threading.Timer(timeout, expire) if timeout is not None else None
+    if timer is not None:
+        timer.daemon = True
+        timer.start()
+
+    def start(command: Any, *args: Any, **kwargs: Any) -> HtWrapper:
+        if timed_out.is_set():
+            raise HttyTimeoutError(f"htty_timeout of {timeout} seconds has run out")
+
+        kwargs.setdefault("record", True)
+        proc = run(command, *args, **kwargs)
+        sessions.append(_Session(proc, command if isinstance(command, str) else " ".join(command), kwargs["record"]))
+        return proc
+
+    yield start
+
+    if timer is not None:
+        timer.cancel()
+
+    for session in sessions:
+        session.end()
+
+    if timed_out.is_set():
+        pytest.fail(f"htty sessions were ended after running for longer than htty_timeout ({timeout} seconds)")
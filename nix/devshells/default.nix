@@ -15,6 +15,8 @@ let
   # Rust toolchain with extensions
   rustToolchain = pkgsWithRust.rust-bin.stable.latest.default.override {
     extensions = [ "rust-src" "clippy" "rustfmt" ];
+    # For checking the wasm build of htty_core
+    targets = [ "wasm32-unknown-unknown" ];
   };
 
   # Load the test workspace to get pytest and test dependencies
@@ -0,0 +1,16 @@
+# Nightly Rust and cargo-fuzz, for building and running htty-core's fuzz targets
+{ inputs, system, ... }:
+
+let
+  pkgsWithRust = import inputs.nixpkgs {
+    inherit system;
+    overlays = [ inputs.rust-overlay.overlays.default ];
+  };
+in
+
+pkgsWithRust.mkShell {
+  buildInputs = with pkgsWithRust; [
+    (rust-bin.selectLatestNightlyWith (toolchain: toolchain.default))
+    cargo-fuzz
+  ];
+}
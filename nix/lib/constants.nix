@@ -22,12 +22,8 @@
     # Used as: Delay to detect if subprocess has exited before attempting forced termination
     subprocess_exit_detection_delay_ms = 200;
 
-    # Referenced in: htty-core/src/rust/main.rs:173,198, htty-core/src/rust/pty.rs:104
-    # Used as: Delay for coordination between processes and channel emptiness checks
-    coordination_delay_ms = 200;
-
     # Referenced in: htty-core/src/rust/pty.rs:148, multiple ht.py timeout contexts
-    # Used as: General short sleep interval for polling loops and heartbeat checks
+    # Used as: General short sleep interval for polling loops
     general_sleep_interval_ms = 100;
 
     # Timeout values
@@ -52,45 +48,33 @@
     # Used as: Default timeout for waiting for patterns to appear/disappear in terminal output
     default_expect_timeout_ms = 5000;
 
-    # Referenced in: htty/src/htty/ht.py:47, used in snapshot() retry logic
-    # Used as: Time between snapshot retry attempts when first attempt fails
-    snapshot_retry_timeout_ms = 100;
-
     # Referenced in: htty-core/src/rust/session.rs:169 (as Duration::from_secs(5))
     # Used as: Maximum time to wait for subscription acknowledgment from ht process
     subscription_timeout_ms = 5000;
 
-    # Monitoring intervals
+    # Referenced in: htty-core/src/rust/timing.rs
+    # Used as: How long a write to stdout or a WebSocket client may block before its reader is dropped
+    write_timeout_ms = 300000;
 
-    # Referenced in: htty-core/src/rust/main.rs:131
-    # Used as: Frequency to check if channels are empty during process coordination
-    emptiness_check_interval_ms = 10;
+    # Referenced in: htty-core/src/rust/timing.rs
+    # Used as: How long the wait-exit helper waits to be released before giving up on ht
+    wait_exit_timeout_ms = 30000;
+
+    # Monitoring intervals
 
     # Referenced in: htty-core/src/rust/pty.rs:74
     # Used as: Polling interval for FIFO monitoring in PTY management
     fifo_monitoring_interval_ms = 50;
-
-    # Referenced in: htty-core/src/rust/pty.rs:134 (as Duration::from_secs(60))
-    # Used as: Interval for PTY heartbeat to keep connection alive
-    pty_heartbeat_interval_ms = 60000; # 60 seconds
-
-    # Referenced in: htty-core/src/rust/pty.rs:148
-    # Used as: Delay between heartbeat checks in PTY management loop
-    heartbeat_check_delay_ms = 100;
-
-    # Referenced in: htty-core/src/rust/api/stdio.rs:54
-    # Used as: Delay when checking command channel for new messages
-    command_channel_check_delay_ms = 100;
   };
 
   # Buffer sizes and limits
   buffers = {
-    # Referenced in: htty-core/src/rust/pty.rs:162 (as const READ_BUF_SIZE: usize = 128 * 1024)
-    # Used as: Size of buffer for reading PTY output, affects performance and memory usage
+    # Referenced in: htty-core/src/rust/pty.rs (as const DEFAULT_READ_BUF_SIZE: usize = 128 * 1024)
+    # Used as: Default size of buffer for reading PTY output (ht --read-buffer-size / HTTY_READ_BUF_SIZE)
     read_buf_size = 131072; # 128 * 1024
 
     # Referenced in: htty-core/src/rust/main.rs:30, htty-core/src/rust/session.rs (multiple channels)
-    # Used as: Buffer size for mpsc channels carrying events and data between threads
+    # Used as: Default capacity of mpsc channels carrying events and data between threads (ht --channel-capacity / HTTY_CHANNEL_BUFFER_SIZE)
     channel_buffer_size = 1024;
 
     # Referenced in: htty-core/src/rust/main.rs (multiple single-slot channels)
@@ -98,7 +82,7 @@
     single_slot_channel_size = 1;
 
     # Referenced in: htty-core/src/rust/session.rs:39 (broadcast::channel(1024))
-    # Used as: Buffer size for broadcast channels distributing events to multiple subscribers
+    # Used as: Default capacity of the broadcast channel distributing events to subscribers (ht --broadcast-capacity / HTTY_BROADCAST_CHANNEL_SIZE)
     broadcast_channel_size = 1024;
   };
 
@@ -107,5 +91,9 @@
     # Referenced in: htty/src/htty/ht.py:49, used in snapshot() method retry loop
     # Used as: Maximum number of attempts to retrieve a snapshot before giving up
     max_snapshot_retries = 10;
+
+    # Referenced in: htty/src/htty/event_log.py, used as the default for HtWrapper's max_events
+    # Used as: Number of events kept before the oldest ones are discarded
+    max_events = 10000;
   };
 }
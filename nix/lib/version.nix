@@ -49,7 +49,7 @@ in
 
   # Version information for different package formats
   cargo = {
-    # Referenced in: htty-core/Cargo.toml
+    # Referenced in: htty-core/Cargo.toml, htty-core/ffi/Cargo.toml
     # Used as: Rust package version
     inherit version;
   };
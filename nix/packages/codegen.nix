@@ -20,6 +20,7 @@ let
     inherit flake;
     files = [
       "htty-core/Cargo.toml"
+      "htty-core/ffi/Cargo.toml"
       "htty-core/pyproject.toml"
       "htty/pyproject.toml"
       "htty-core/src/rust/cli.rs"
@@ -57,6 +58,26 @@ let
       done
     '';
   };
+
+  # generate htty's Press constants from the key names in htty-core's stdio.rs
+  generateKeysCheck = makeCheck {
+    name = "generate-keys";
+    description = "Generate the Press key constants in keys.py using Cog from ht's key names";
+    dependencies = with pkgs; [ python3 python3Packages.cogapp ];
+    command = ''
+      cog -r htty/src/htty/keys.py
+    '';
+    verboseCommand = ''
+      cp htty/src/htty/keys.py htty/src/htty/keys.py.bak
+      cog -r htty/src/htty/keys.py
+      if ! diff -u htty/src/htty/keys.py.bak htty/src/htty/keys.py; then
+        echo "Changes made to htty/src/htty/keys.py"
+      else
+        echo "No changes to htty/src/htty/keys.py"
+      fi
+      rm htty/src/htty/keys.py.bak
+    '';
+  };
 in
 createAnalysisPackage {
   name = "codegen";
@@ -66,5 +87,6 @@ createAnalysisPackage {
     generate-constants = generateConstantsCheck;
     generate-version = generateVersionCheck;
     generate-tool-configs = generateToolConfigsCheck;
+    generate-keys = generateKeysCheck;
   };
 }
@@ -39,6 +39,7 @@ let
       "src/rust"
       "src/python"
       "assets"
+      "ffi"
       "Cargo.toml"
       "Cargo.lock"
       "pyproject.toml"
@@ -6,6 +6,9 @@ from textwrap import dedent
 
 import pytest
 
+# For testing htty's own pytest plugin
+pytest_plugins = ["pytester"]
+
 # Configure logging for htty tests
 logging.basicConfig(level=logging.INFO, format="%(asctime)s - %(name)s - %(levelname)s - %(message)s")
 
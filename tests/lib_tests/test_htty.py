@@ -3,16 +3,43 @@ Simple test using the htty module to make assertions about terminal output.
 Adapted from the original htty test suite.
 """
 
+import asyncio
+import json
 import logging
+import os
+import signal
+import subprocess
 import sys
 import time
 from collections.abc import Generator
+from pathlib import Path
 from textwrap import dedent
 from time import sleep
 
 import pytest
 
-from htty import HtWrapper, Press, SnapshotResult, run, terminal_session
+import htty.asyncio
+from htty import (
+    ContinuedEvent,
+    Event,
+    ExitCodeEvent,
+    FlushedEvent,
+    HtWrapper,
+    HtmlTheme,
+    HttyProcessError,
+    HttyProtocolError,
+    HttyTimeoutError,
+    InputWrittenEvent,
+    OutputEvent,
+    Press,
+    SessionPool,
+    SnapshotResult,
+    StoppedEvent,
+    run,
+    terminal_session,
+)
+from htty.pytest_plugin import HtSession
+from htty_core import HtEvent
 
 
 @pytest.fixture
@@ -121,33 +148,31 @@ def test_enum_keys_interface(hello_world_script: str) -> None:
     proc.ht.exit()  # Clean up the ht process
 
 
-@pytest.mark.skip(reason="HTML color functionality will be replaced with new --style-mode parameter")
-@pytest.mark.htty
-def test_html_snapshot_with_colors(colored_hello_world_script: str) -> None:
-    """Test that the new SnapshotResult provides HTML with color information."""
-    cmd = f"{sys.executable} {colored_hello_world_script}"
-    proc = run(cmd, rows=4, cols=8)
-
-    snapshot = proc.snapshot()
-
-    # Test that HTML contains the expected CSS and span for red text
-    assert ".ansi31 { color: #aa0000; }" in snapshot.html
-    assert '<span class="ansi31">hello</span>' in snapshot.html
+def test_press_keys_known_to_ht() -> None:
+    """Test that every Press constant is a key ht understands, rather than text it would type out."""
+    from htty_core import find_ht_binary
 
-    # Continue script to get green "world"
-    proc.send_keys(Press.ENTER)
+    keys = [key.value for key in Press]
+    result = subprocess.run([str(find_ht_binary()), "keys", "--no-config", *keys], capture_output=True, text=True)
+    assert result.returncode == 0, result.stderr
+    assert "not a key name" not in result.stdout
 
-    snapshot2 = proc.snapshot()
 
-    # Test that we now have green color styling too
-    assert ".ansi32 { color: #00aa00; }" in snapshot2.html
-    assert '<span class="ansi32">world</span>' in snapshot2.html
+@pytest.mark.htty
+def test_html_snapshot_with_colors(colored_hello_world_script: str, test_logger: logging.Logger) -> None:
+    """Test that snapshots carry the screen as HTML, with its colors and the chosen theme."""
+    cmd = f"{sys.executable} {colored_hello_world_script}"
+    with terminal_session(cmd, rows=4, cols=8, logger=test_logger) as proc:
+        proc.expect("hello")
+        snapshot = proc.snapshot()
+        assert snapshot.html.startswith('<pre style="color: #e5e5e5; background-color: #000000">')
+        assert '<span style="color: #cd0000">hello</span>' in snapshot.html
 
-    # Clean up
-    proc.cmd.terminate()
-    proc.cmd.wait(timeout=1.0)
-    proc.ht.terminate()
-    proc.ht.wait(timeout=2.0)
+        proc.send_keys(Press.ENTER)
+        proc.expect("world")
+        snapshot = proc.snapshot(html_theme=HtmlTheme.LIGHT)
+        assert snapshot.html.startswith('<pre style="color: #000000; background-color: #ffffff">')
+        assert '<span style="color: #00cd00">world</span>' in snapshot.html
 
 
 @pytest.mark.htty
@@ -163,6 +188,18 @@ def test_context_manager(hello_world_script: str) -> None:
         assert "world" in snapshot.text
 
 
+@pytest.mark.htty
+def test_run_context_manager_cleans_up_on_error(hello_world_script: str) -> None:
+    """Test that using run() as a context manager terminates ht even when the body raises."""
+    cmd = f"{sys.executable} {hello_world_script}"
+
+    with pytest.raises(RuntimeError, match="boom"), run(cmd, rows=3, cols=8) as proc:
+        proc.expect("hello")
+        raise RuntimeError("boom")
+
+    assert proc.ht.poll() is not None
+
+
 @pytest.mark.htty
 def test_exit_while_subprocess_running(hello_world_script: str) -> None:
     """Test that exit() works reliably even when subprocess is still running."""
@@ -210,9 +247,54 @@ def test_exit_after_subprocess_finished(hello_world_script: str) -> None:
     assert proc.ht.poll() is not None, "ht process should have exited"
 
 
+@pytest.mark.htty
+def test_send_keys_after_subprocess_finished(hello_world_script: str) -> None:
+    """Test that keys sent once the command has exited are reported as discarded, and ht carries on."""
+    cmd = f"{sys.executable} {hello_world_script}"
+    proc = run(cmd, rows=4, cols=8, no_exit=True)
+
+    proc.send_keys(Press.ENTER)
+    proc.send_keys(Press.ENTER)
+    proc.cmd.wait(timeout=3.0)
+
+    with pytest.raises(HttyProtocolError, match="discarded"):
+        proc.send_keys("late")
+
+    assert "goodbye" in proc.snapshot().text
+    assert proc.ht.exit(timeout=5.0) == 0
+
+
 # CLI Example Tests - These translate CLI examples to Python API usage
 
 
+@pytest.mark.htty
+def test_cmd_wait(hello_world_script: str, test_logger: logging.Logger) -> None:
+    """Test that cmd.wait times out while the command runs, then returns its exit code."""
+    cmd = f"{sys.executable} {hello_world_script}"
+    with terminal_session(cmd, rows=4, cols=8, logger=test_logger) as proc:
+        with pytest.raises(TimeoutError):
+            proc.cmd.wait(timeout=0.2)
+        assert proc.cmd.exit_code is None
+
+        proc.send_keys(Press.ENTER)
+        proc.send_keys(Press.ENTER)
+        assert proc.cmd.wait(timeout=5) == 0
+        assert proc.cmd.exit_code == 0
+
+
+@pytest.mark.htty
+def test_exit_codes_without_waiting(test_logger: logging.Logger) -> None:
+    """Test that the command's and ht's exit codes show up as they exit, without being waited for."""
+    with run("sleep 0.2; exit 3", no_exit=False, logger=test_logger) as proc:
+        deadline = time.monotonic() + 5
+        while (proc.cmd.exit_code is None or proc.ht.exit_code is None) and time.monotonic() < deadline:
+            sleep(0.05)
+
+        assert proc.cmd.exit_code == 3
+        assert proc.cmd.poll() == 3
+        assert proc.ht.exit_code == 0
+
+
 @pytest.mark.htty
 def test_vim_startup_screen(vim_cmd: list[str]) -> None:
     """Test equivalent to: htty --snapshot -- vim | grep "VIM - Vi IMproved" """
@@ -349,6 +431,292 @@ def test_expect_regex(colored_hello_world_script: str, test_logger: logging.Logg
         proc.expect("goodbye")  # goodbye
 
 
+@pytest.mark.htty
+def test_expect_result(hello_world_script: str, test_logger: logging.Logger) -> None:
+    """Test that expect returns the match along with the snapshot it was found in."""
+    cmd = f"{sys.executable} {hello_world_script}"
+    with terminal_session(cmd, rows=4, cols=8, logger=test_logger) as proc:
+        proc.send_keys(Press.ENTER)
+        result = proc.expect("^(w)orld")
+        assert result.match.group(1) == "w"
+        assert "hello" in result.snapshot.text
+        assert "world" in result.snapshot.text
+
+
+@pytest.mark.htty
+def test_events(hello_world_script: str, test_logger: logging.Logger) -> None:
+    """Test following the typed events of a session as they arrive."""
+    cmd = f"{sys.executable} {hello_world_script}"
+    with terminal_session(cmd, rows=4, cols=8, logger=test_logger) as proc:
+        proc.expect("hello")
+
+        with proc.events(timeout=5) as events:
+            proc.send_keys(Press.ENTER)
+            proc.send_keys(Press.ENTER)
+
+            output = ""
+            for event in events:
+                if isinstance(event, OutputEvent):
+                    output += event.seq
+                elif isinstance(event, ExitCodeEvent):
+                    assert event.exit_code == 0
+                    break
+            else:
+                raise AssertionError("no exitCode event")
+
+        assert "world" in output
+        assert "goodbye" in output
+
+
+@pytest.mark.htty
+def test_on_callbacks(hello_world_script: str, test_logger: logging.Logger) -> None:
+    """Test that callbacks can answer prompts as the output arrives, and see the exit code."""
+    cmd = f"{sys.executable} {hello_world_script}"
+    with terminal_session(cmd, rows=4, cols=8, logger=test_logger) as proc:
+        exit_codes: list[int] = []
+        proc.on("exitCode", lambda event: exit_codes.append(event.exit_code))
+
+        # send_keys can't wait for ht's acknowledgement from a callback, so it mustn't try to
+        send_durations: list[float] = []
+
+        def answer(event: Event) -> None:
+            if isinstance(event, OutputEvent) and ("hello" in event.seq or "world" in event.seq):
+                start = time.monotonic()
+                proc.send_keys(Press.ENTER)
+                send_durations.append(time.monotonic() - start)
+
+        stop = proc.on(HtEvent.OUTPUT, answer)
+        proc.send_keys(Press.ENTER)  # in case "hello" was printed before the callback was registered
+
+        assert proc.cmd.wait(timeout=5) == 0
+        assert exit_codes == [0]
+        assert send_durations and max(send_durations) < 1
+        stop()
+
+
+@pytest.mark.htty
+def test_asyncio(hello_world_script: str, test_logger: logging.Logger) -> None:
+    """Test driving a session with coroutines, and following its events."""
+    cmd = f"{sys.executable} {hello_world_script}"
+
+    async def session() -> None:
+        async with await htty.asyncio.run(cmd, rows=4, cols=8, logger=test_logger) as proc:
+            await proc.expect("hello")
+            async with proc.events() as events:
+                await proc.send_keys(Press.ENTER)
+                await proc.expect("world")
+
+                async for event in events:
+                    if isinstance(event, OutputEvent):
+                        break
+
+            assert "world" in (await proc.snapshot()).text
+            await proc.send_keys(Press.ENTER)
+            assert await proc.wait(timeout=5) == 0
+
+    asyncio.run(session())
+
+
+@pytest.mark.htty
+def test_resize(hello_world_script: str, test_logger: logging.Logger) -> None:
+    """Test that resize waits for ht to report the new size, and can return a snapshot at that size."""
+    cmd = f"{sys.executable} {hello_world_script}"
+    with terminal_session(cmd, rows=4, cols=8, logger=test_logger) as proc:
+        proc.expect("hello")
+        assert proc.resize(12, 5) is None
+        assert (proc.cols, proc.rows) == (12, 5)
+
+        snapshot = proc.resize(20, 2, snapshot=True)
+        assert snapshot is not None
+        assert [len(line) for line in snapshot.text.split("\n")] == [20, 20]
+        assert snapshot.text.startswith("hello")
+
+        for cols, rows in [(0, 2), (20, 1), (10001, 5)]:
+            with pytest.raises(ValueError):
+                proc.resize(cols, rows)
+
+
+@pytest.mark.htty
+def test_resize_reaches_command(test_logger: logging.Logger) -> None:
+    """Test that the command is signalled when the terminal is resized, and sees the new size."""
+    cmd = "sh -c 'trap \"stty size\" WINCH; echo ready; while :; do sleep 0.05; done'"
+    with terminal_session(cmd, rows=4, cols=20, logger=test_logger) as proc:
+        proc.expect("ready")
+        proc.resize(30, 6)
+        proc.expect("^6 30")
+
+        proc.resize(25, 5)
+        proc.expect("^5 25")
+
+
+@pytest.mark.htty
+def test_startup_reports_pid(hello_world_script: str, test_logger: logging.Logger) -> None:
+    """Test that run() returns once ht has started the command, with its PID and the terminal size known."""
+    cmd = f"{sys.executable} {hello_world_script}"
+    with terminal_session(cmd, rows=4, cols=8, logger=test_logger) as proc:
+        assert proc.cmd.pid is not None
+        assert (proc.cols, proc.rows) == (8, 4)
+        proc.expect("hello")
+
+
+@pytest.mark.htty
+def test_send_keys_waits_for_ack(test_logger: logging.Logger) -> None:
+    """Test that send_keys returns once ht reports the keys written, so no sleep is needed before a snapshot."""
+    with terminal_session("cat", rows=4, cols=20, logger=test_logger) as proc:
+        with proc.events(timeout=5) as events:
+            proc.send_keys("hello")
+            assert any(isinstance(event, InputWrittenEvent) and event.bytes == 5 for event in events)
+
+        proc.send_keys(Press.ENTER, wait=False)
+        proc.expect(r"hello\s+hello")
+
+
+@pytest.mark.htty
+def test_flush(test_logger: logging.Logger) -> None:
+    """Test that flush returns once keys sent without waiting have been written."""
+    with terminal_session("cat", rows=4, cols=20, logger=test_logger) as proc:
+        with proc.events(timeout=5) as events:
+            proc.send_keys("hello", wait=False)
+            proc.flush()
+            assert any(isinstance(event, FlushedEvent) for event in events)
+
+        proc.send_keys(Press.ENTER)
+        proc.expect(r"hello\s+hello")
+
+
+@pytest.mark.htty
+def test_shutdown(test_logger: logging.Logger) -> None:
+    """Test that cmd.shutdown() stops the command and takes ht down with it, rather than leaving ht running."""
+    proc = run("sh -c 'trap \"\" TERM; echo ready; sleep 999'", rows=4, cols=20, logger=test_logger)
+    proc.expect("ready")
+
+    start = time.monotonic()
+    proc.cmd.shutdown(grace=0.5)
+    assert time.monotonic() - start < 5
+
+    assert proc.cmd.poll() is not None
+    assert proc.ht.poll() is not None
+
+
+@pytest.mark.htty
+def test_env_and_cwd(test_logger: logging.Logger, tmp_path: Path, monkeypatch: pytest.MonkeyPatch) -> None:
+    """Test that the command gets the environment and working directory it's given, rather than ours."""
+    monkeypatch.setenv("HTTY_TEST_INHERITED", "inherited")
+    cmd = "sh -c 'echo \"[$HTTY_TEST_INHERITED][$HTTY_TEST_EXTRA]\"; pwd'"
+
+    with terminal_session(cmd, rows=4, cols=80, extra_env={"HTTY_TEST_EXTRA": "extra"}, cwd=tmp_path) as proc:
+        proc.expect(r"\[inherited\]\[extra\]")
+        proc.expect(f"^{tmp_path.resolve()}")
+
+    env = {"PATH": os.environ["PATH"], "HTTY_TEST_EXTRA": "replaced"}
+    with terminal_session(cmd, rows=4, cols=80, env=env, logger=test_logger) as proc:
+        proc.expect(r"\[\]\[replaced\]")
+
+
+@pytest.mark.htty
+def test_command_arguments(test_logger: logging.Logger) -> None:
+    """Test that a command given as a list reaches the program untouched, while a string is a shell command line."""
+    with terminal_session(["printf", "%s|%s\\n", "a  b", "c;d $HOME"], rows=4, cols=40, logger=test_logger) as proc:
+        proc.expect(r"^a  b\|c;d \$HOME")
+
+    with terminal_session("echo one; echo two | tr a-z A-Z", rows=4, cols=40, logger=test_logger) as proc:
+        proc.expect("^one")
+        proc.expect("^TWO")
+
+
+@pytest.mark.htty
+def test_save_cast(hello_world_script: str, test_logger: logging.Logger, tmp_path: Path) -> None:
+    """Test that the session is recorded as an asciicast which can be saved."""
+    cmd = f"{sys.executable} {hello_world_script}"
+    with terminal_session(cmd, rows=4, cols=8, logger=test_logger, record=True) as proc:
+        proc.expect("hello")
+        proc.send_keys(Press.ENTER)
+        proc.expect("world")
+
+        output = "".join(data for _time, code, data in proc.cast_events() if code == "o")
+        assert "hello" in output
+        assert "world" in output
+
+        proc.save_cast(tmp_path / "session.cast")
+
+    header, *events = [json.loads(line) for line in (tmp_path / "session.cast").read_text().splitlines()]
+    assert (header["version"], header["width"], header["height"]) == (2, 8, 4)
+    assert header["command"] == cmd
+    assert [tuple(event) for event in events] == proc.cast_events()
+
+    # Only recorded sessions keep all of their output
+    with terminal_session(cmd, rows=4, cols=8, logger=test_logger) as proc:
+        proc.expect("hello")
+        with pytest.raises(RuntimeError, match="record=True"):
+            proc.save_cast(tmp_path / "unrecorded.cast")
+
+    assert not (tmp_path / "unrecorded.cast").exists()
+
+
+@pytest.mark.htty
+def test_ht_session_fixture(ht_session: HtSession, hello_world_script: str, test_logger: logging.Logger) -> None:
+    """Test that the pytest plugin's fixture starts sessions, and ends them when the test is over."""
+    proc = ht_session(f"{sys.executable} {hello_world_script}", rows=4, cols=8, logger=test_logger)
+    proc.expect("hello")
+    assert (proc.cols, proc.rows) == (8, 4)
+
+    proc.send_keys(Press.ENTER)
+    proc.expect("world")
+
+
+@pytest.mark.htty
+def test_ht_session_failure_artifacts(pytester: pytest.Pytester) -> None:
+    """Test that when a test fails, the plugin saves its sessions' screens and recordings, and reports them."""
+    pytester.makepyfile(
+        """
+        def test_fails(ht_session):
+            proc = ht_session("echo hello", rows=4, cols=20)
+            proc.expect("hello")
+            assert False
+        """
+    )
+    result = pytester.runpytest("--htty-artifacts", "artifacts")
+    result.assert_outcomes(failed=1)
+    result.stdout.fnmatch_lines(["*htty session-1: echo hello*", "hello*", "*htty artifacts*"])
+
+    directory = pytester.path / "artifacts" / "test_ht_session_failure_artifacts.py_test_fails"
+    assert (directory / "session-1.txt").read_text().startswith("hello")
+    assert "hello" in (directory / "session-1.html").read_text()
+    header, *events = [json.loads(line) for line in (directory / "session-1.cast").read_text().splitlines()]
+    assert (header["width"], header["height"]) == (20, 4)
+    assert any("hello" in data for _time, code, data in events if code == "o")
+
+
+@pytest.mark.htty
+def test_session_pool(test_logger: logging.Logger) -> None:
+    """Test that a pool hands out ready sessions, reusing the ones it can reset and replacing the rest."""
+
+    def reset(sh: HtWrapper) -> bool:
+        sh.send_keys(["clear", Press.ENTER])
+        sh.expect_absent("echo")
+        return True
+
+    with SessionPool("sh -i", size=1, reset=reset, rows=4, cols=20, logger=test_logger) as pool:
+        with pool.session(timeout=5) as sh:
+            sh.send_keys(["echo one", Press.ENTER])
+            sh.expect("^one")
+            first = sh.ht.pid
+
+            with pytest.raises(HttyTimeoutError):
+                pool.acquire(timeout=0.2)
+
+        with pool.session(timeout=5) as sh:
+            assert sh.ht.pid == first
+            assert "one" not in sh.snapshot().text
+            sh.send_keys(["exit", Press.ENTER])
+            assert sh.cmd.wait(timeout=5) == 0
+
+        with pool.session(timeout=5) as sh:
+            assert sh.ht.pid != first
+            sh.send_keys(["echo two", Press.ENTER])
+            sh.expect("^two")
+
+
 @pytest.mark.htty
 def test_expect_timeout(colored_hello_world_script: str, test_logger: logging.Logger) -> None:
     """Test that expect times out if pattern is not found."""
@@ -363,6 +731,19 @@ def test_expect_timeout(colored_hello_world_script: str, test_logger: logging.Lo
         proc.expect("nonexistent", timeout=1.0)  # Pattern that will never appear
 
 
+@pytest.mark.htty
+def test_expect_process_error(test_logger: logging.Logger) -> None:
+    """Test that expect gives up as soon as the command exits, with its exit code and final screen."""
+    with terminal_session("sh -c 'echo bye; exit 3'", rows=3, cols=8, logger=test_logger) as proc:
+        with pytest.raises(HttyProcessError, match="exited with code 3") as excinfo:
+            proc.expect("nonexistent", timeout=30)
+
+        assert excinfo.value.exit_code == 3
+        assert excinfo.value.snapshot is not None
+        assert "bye" in excinfo.value.snapshot.text
+        assert not isinstance(excinfo.value, HttyTimeoutError)
+
+
 @pytest.mark.htty
 def test_expect_absent_timeout(colored_hello_world_script: str, test_logger: logging.Logger) -> None:
     """Test that expect_absent times out if pattern doesn't disappear."""
@@ -415,3 +796,44 @@ def test_fg(test_logger: logging.Logger) -> None:
 
     assert "foo" not in terminated.text.splitlines()[-1].strip()
     assert "$" in terminated.text.splitlines()[-1].strip()
+
+
+@pytest.mark.htty
+def test_stop_and_continue(test_logger: logging.Logger) -> None:
+    """Test that stopping and continuing the command's process group is reported as events."""
+    subscribes = [HtEvent.STOPPED, HtEvent.CONTINUED]
+    with terminal_session("sleep 999", rows=4, cols=20, extra_subscribes=subscribes, logger=test_logger) as proc:
+        pid = proc.cmd.pid
+        assert pid is not None
+
+        with proc.events(timeout=5) as events:
+            # The command runs in a session of its own, so its process group has its PID
+            os.killpg(pid, signal.SIGSTOP)
+            assert any(isinstance(event, StoppedEvent) and event.signal == "SIGSTOP" for event in events)
+
+            os.killpg(pid, signal.SIGCONT)
+            assert any(isinstance(event, ContinuedEvent) for event in events)
+
+
+@pytest.mark.htty
+def test_close_stdin(test_logger: logging.Logger) -> None:
+    with terminal_session("sh -c 'wc -l; echo done'", rows=4, cols=40, logger=test_logger) as proc:
+        proc.send_keys(["one", Press.ENTER, "two", Press.ENTER, "three"])
+        proc.close_stdin()
+        proc.expect("done")
+
+        # the unterminated third line still counts as input, just not as a line
+        lines = [line.strip() for line in proc.snapshot().text.splitlines()]
+        assert lines[lines.index("done") - 1] == "three2"
+
+
+@pytest.mark.htty
+def test_max_events(test_logger: logging.Logger) -> None:
+    cmd = "sh -c 'for i in $(seq 1 50); do echo line$i; sleep 0.01; done'"
+    with terminal_session(cmd, rows=4, cols=20, max_events=5, logger=test_logger) as proc:
+        proc.expect("line50")
+        proc.cmd.wait()
+
+        output = proc.get_output()
+        assert 0 < len(output) <= 5
+        assert "line50" in "".join(event["data"]["seq"] for event in output)
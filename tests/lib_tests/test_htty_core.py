@@ -39,6 +39,13 @@ def test_htargs_string_command():
     assert args.cols is None
 
 
+def test_htargs_command_arguments():
+    """Test that a string command reaches ht as one shell command line, and a list as the program's arguments."""
+    assert HtArgs(command="echo 'a  b' | cat").get_command("ht")[-2:] == ["--", "echo 'a  b' | cat"]
+    assert HtArgs(command=["printf", "a  b", "c;d"]).get_command("ht")[-4:] == ["--", "printf", "a  b", "c;d"]
+    assert HtArgs(command=["my program"]).get_command("ht")[-2:] == ["--", "'my program'"]
+
+
 def test_find_ht_binary():
     """Test that find_ht_binary can locate the bundled binary."""
     from htty_core import find_ht_binary
@@ -64,3 +71,49 @@ def test_run_basic():
     # Clean up
     proc.terminate()
     proc.wait()
+
+
+def test_malformed_input_reports_error():
+    """Test that lines ht can't take as commands are answered with error events rather than dropped."""
+    import json
+
+    from htty_core import run
+
+    proc = run(HtArgs(command="true", subscribes=[HtEvent.PID]))
+    stdout, _ = proc.communicate('nonsense\n{"type": "bogus", "id": 7}\n', timeout=10)
+    errors = [event["data"] for event in map(json.loads, stdout.splitlines()) if event["type"] == "error"]
+
+    assert [error["line"] for error in errors] == ["nonsense", '{"type": "bogus", "id": 7}']
+    assert "id" not in errors[0]
+    assert errors[1]["id"] == 7
+    assert "bogus" in errors[1]["message"]
+
+
+def test_sigterm_removes_runtime_dir(tmp_path):
+    """Test that ht killed with SIGTERM still removes the directory holding its FIFOs."""
+    import os
+    import time
+
+    from htty_core import run
+
+    proc = run(HtArgs(command="sleep 60", env={**os.environ, "XDG_RUNTIME_DIR": str(tmp_path)}))
+    deadline = time.monotonic() + 10
+    while not any(tmp_path.iterdir()) and time.monotonic() < deadline:
+        time.sleep(0.05)
+    assert any(tmp_path.iterdir())
+
+    proc.terminate()
+    proc.communicate(timeout=10)
+    assert not any(tmp_path.iterdir())
+
+
+def test_manifest_failure_keeps_exit_code(tmp_path):
+    """Test that a manifest that can't be written is reported, without hiding the command's exit code."""
+    from htty_core import run
+
+    manifest = tmp_path / "missing" / "htty-manifest.json"
+    proc = run(HtArgs(command="exit 3", propagate_exit_code=True, manifest=str(manifest)))
+    _, stderr = proc.communicate("", timeout=10)
+
+    assert proc.returncode == 3
+    assert "cannot write manifest" in stderr